@@ -3,9 +3,10 @@
 use log::{LogLevel, LogLevelFilter, LogMetadata, LogRecord, SetLoggerError, set_logger, Log};
 use time;
 use term;
-use term::{StderrTerminal, color};
+use term::{StderrTerminal, color, Attr};
 use std::sync::Mutex;
 use super::SharedLogger;
+use zwreec::utils::error::DIAGNOSTIC_TARGET;
 
 /// The TermLogger struct. Provides a stderr based Logger implementation
 pub struct TermLogger {
@@ -61,6 +62,12 @@ impl Log for TermLogger {
 
             let mut stderr_lock = self.stderr.lock().unwrap();
 
+            if record.target() == DIAGNOSTIC_TARGET {
+                write_diagnostic(&mut stderr_lock, &format!("{}", record.args()));
+                stderr_lock.flush().unwrap();
+                return;
+            }
+
             let cur_time = time::now();
 
             if self.level() <= LogLevel::Warn {
@@ -166,6 +173,57 @@ impl Log for TermLogger {
     }
 }
 
+/// Writes a diagnostic of the compiler with highlighted source snippet.
+///
+/// The diagnostic is formatted like
+///
+/// ```text
+/// parser error: expected `>>` to close `<<print>>`, found number `2`
+///  --> story.twee:2:11
+///   |
+/// 2 | <<print 1 2>>
+///   |           ^
+/// ```
+fn write_diagnostic(stderr_lock: &mut Box<StderrTerminal>, text: &str) {
+    for (i, line) in text.lines().enumerate() {
+        if i == 0 {
+            // "<kind> error: <message>"
+            let split = line.find(": ").map(|pos| pos + 1).unwrap_or(0);
+            stderr_lock.fg(color::RED).unwrap();
+            let _ = stderr_lock.attr(Attr::Bold);
+            write!(stderr_lock, "{}", &line[..split]).unwrap();
+            let _ = stderr_lock.reset();
+            let _ = stderr_lock.attr(Attr::Bold);
+            write!(stderr_lock, "{}", &line[split..]).unwrap();
+            let _ = stderr_lock.reset();
+        } else if let Some(pos) = line.find("--> ") {
+            stderr_lock.fg(color::BLUE).unwrap();
+            let _ = stderr_lock.attr(Attr::Bold);
+            write!(stderr_lock, "{}", &line[..pos + 3]).unwrap();
+            let _ = stderr_lock.reset();
+            write!(stderr_lock, "{}", &line[pos + 3..]).unwrap();
+        } else if let Some(pos) = line.find('|') {
+            stderr_lock.fg(color::BLUE).unwrap();
+            let _ = stderr_lock.attr(Attr::Bold);
+            write!(stderr_lock, "{}", &line[..pos + 1]).unwrap();
+            let _ = stderr_lock.reset();
+
+            let rest = &line[pos + 1..];
+            if rest.trim() == "^" {
+                stderr_lock.fg(color::RED).unwrap();
+                let _ = stderr_lock.attr(Attr::Bold);
+                write!(stderr_lock, "{}", rest).unwrap();
+                let _ = stderr_lock.reset();
+            } else {
+                write!(stderr_lock, "{}", rest).unwrap();
+            }
+        } else {
+            write!(stderr_lock, "{}", line).unwrap();
+        }
+        writeln!(stderr_lock, "").unwrap();
+    }
+}
+
 impl SharedLogger for TermLogger {
 
     fn level(&self) -> LogLevelFilter {
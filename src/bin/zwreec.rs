@@ -54,7 +54,7 @@ fn usage(verbose: bool) {
         short_options()
     };
 
-    let brief = format!("Usage: zwreec [-hV] [-vqwf] [-l [LOGFILE]] [-o OUTPUT] INPUT");
+    let brief = format!("Usage: zwreec [-hV] [-vqwf] [-l [LOGFILE]] [-o OUTPUT] INPUT...");
 
     println!("{}", config::zwreec_usage(verbose, options, &brief));
 }
@@ -160,31 +160,43 @@ fn parse_arguments(args: Vec<String>, opts: getopts::Options) -> (getopts::Match
     (matches, cfg)
 }
 
-fn parse_input(matches: &getopts::Matches) -> Option<Box<Read>> {
-    if matches.free.len() == 1 {
-        let path = Path::new(&matches.free[0]);
-        match File::open(path) {
-            Err(why) => {
-                error!("Couldn't open {}: {}",
-                    path.display(), Error::description(&why));
-                None
-            },
-            Ok(file) => {
-                info!("Opened input: {}", path.display());
-                Some(Box::new(file))
+/// Opens the input files together with their names, the passages of all
+/// files form one story. Without input files the story is read from stdin.
+fn parse_input(matches: &getopts::Matches) -> Option<Vec<(String, Box<Read>)>> {
+    if !matches.free.is_empty() {
+        let mut inputs: Vec<(String, Box<Read>)> = Vec::new();
+        for name in matches.free.iter() {
+            let path = Path::new(name);
+            match File::open(path) {
+                Err(why) => {
+                    error!("Couldn't open {}: {}",
+                        path.display(), Error::description(&why));
+                    return None;
+                },
+                Ok(file) => {
+                    info!("Opened input: {}", path.display());
+                    inputs.push((name.clone(), Box::new(file)));
+                }
             }
         }
+        Some(inputs)
     } else if unsafe { libc::isatty(libc::STDIN_FILENO as i32) } == 0 {
         // Not connected to a terminal, assuming safe to read from stdin
         info!("Reading input from stdin");
-        Some(Box::new(std::io::stdin()))
+        Some(vec![("<input>".to_string(), Box::new(std::io::stdin()))])
     } else {
         None
     }
 }
 
-fn parse_output(matches: &getopts::Matches) -> Option<Box<Write>> {
-    let name = matches.opt_str("o").unwrap_or("a.z8".to_string());
+/// Opens the output file, without a name it is called after the version of
+/// the Z-Machine (a.z8, a.z5, ...) or a.zblorb for Blorb files
+fn parse_output(matches: &getopts::Matches, cfg: &Config) -> Option<Box<Write>> {
+    let default_name = match cfg.output_format {
+        config::OutputFormat::Zcode => format!("a.z{}", cfg.zversion),
+        config::OutputFormat::Blorb => "a.zblorb".to_string(),
+    };
+    let name = matches.opt_str("o").unwrap_or(default_name.clone());
 
     if name == "-" {
         // tty requested
@@ -205,7 +217,7 @@ fn parse_output(matches: &getopts::Matches) -> Option<Box<Write>> {
         // opening file
         let path = Path::new(&name);
 
-        if name == "a.z8" {
+        if name == default_name {
             debug!("No output file specified, using {}", path.display());
         }
 
@@ -242,18 +254,19 @@ fn main() {
         config::zwreec_options(short_options())
     );
 
-    let mut input = parse_input(&matches);
-    let mut output = parse_output(&matches);
+    let inputs = parse_input(&matches);
+    let mut output = parse_output(&matches, &cfg);
 
     debug!("Parsed command line options");
     info!("Main started");
 
     // call library
     if !cfg.test_cases.is_empty() {
+        let mut input = inputs.and_then(|mut inputs| inputs.pop()).map(|(_, input)| input);
         zwreec::test_library(cfg, &mut input, &mut output);
     } else {
         // unwrap input and output
-        let mut _input = match input {
+        let _inputs = match inputs {
             Some(i) => i,
             None => panic!("Missing input file! Compile aborted")
         };
@@ -261,7 +274,13 @@ fn main() {
             Some(o) => o,
             None => panic!("Missing output file! Compile aborted")
         };
-        zwreec::compile(cfg, &mut _input, &mut _output);
+        match zwreec::compile_inputs(cfg, _inputs, &mut _output) {
+            Ok(report) => info!("Wrote {} bytes", report.bytes_written),
+            Err(errors) => {
+                error!("Compile aborted due to {} previous error(s)", errors.len());
+                exit(1);
+            }
+        }
     }
 
     info!("Main finished");
@@ -0,0 +1,274 @@
+//! Packs a story into a Blorb file.
+//!
+//! A Blorb (`.zblorb`) is an IFF file that archives use to distribute a story
+//! together with its metadata. Zwreec writes these chunks:
+//!
+//! * `RIdx` the index of the resources
+//! * `IFmd` the iFiction record with the IFID, the title and the author
+//! * `Fspc` the number of the cover image, if there is one
+//! * `ZCOD` the story itself
+//! * `PNG ` or `JPEG` the cover image
+//!
+//! The IFID is taken from the `StoryData` passage. Without it the IFID is
+//! built from the header of the story, as the Treaty of Babel does for legacy
+//! Z-Machine stories (`ZCODE-release-serial-checksum`). The serial number is
+//! the date of the compilation, so stories compiled on different days get
+//! different IFIDs.
+//!
+//! # Example
+//!
+//! ```
+//! use zwreec::backend::blorb;
+//! use zwreec::backend::zcode::zfile::StoryInfo;
+//!
+//! let zcode: Vec<u8> = vec![0; 64];
+//! let bytes = blorb::blorb(&zcode, &StoryInfo::default(), None).unwrap();
+//! assert_eq!(&bytes[0..4], b"FORM");
+//! assert_eq!(&bytes[8..12], b"IFRS");
+//! ```
+
+use std::error::Error;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use backend::zcode::zfile::StoryInfo;
+
+
+/// A chunk of the Blorb file
+struct Chunk {
+    id: &'static str,
+    data: Vec<u8>,
+}
+
+/// Returns the Blorb file with the story, its metadata and the cover image.
+///
+/// The cover has to be a PNG or a JPEG image.
+pub fn blorb(zcode: &[u8], story: &StoryInfo, cover: Option<&[u8]>) -> Result<Vec<u8>, String> {
+    let cover_id: Option<&'static str> = match cover {
+        Some(image) => Some(try!(image_chunk_id(image))),
+        None => None,
+    };
+
+    let mut chunks: Vec<Chunk> = Vec::new();
+    chunks.push(Chunk { id: "IFmd", data: ifiction(zcode, story, cover.is_some()).into_bytes() });
+    if cover.is_some() {
+        // the cover is the picture resource 1
+        chunks.push(Chunk { id: "Fspc", data: vec![0, 0, 0, 1] });
+    }
+    chunks.push(Chunk { id: "ZCOD", data: zcode.to_vec() });
+    if let (Some(id), Some(image)) = (cover_id, cover) {
+        chunks.push(Chunk { id: id, data: image.to_vec() });
+    }
+
+    // the resource index comes first and points to the story and the cover
+    let mut resources: Vec<(&str, u32, &str)> = vec![("Exec", 0, "ZCOD")];
+    if let Some(id) = cover_id {
+        resources.push(("Pict", 1, id));
+    }
+    let index_length = 4 + 12 * resources.len();
+    let mut offsets: Vec<(&str, usize)> = Vec::new();
+    let mut offset = 12 + 8 + index_length;
+    for chunk in chunks.iter() {
+        offsets.push((chunk.id, offset));
+        offset += 8 + chunk.data.len() + chunk.data.len() % 2;
+    }
+
+    let mut index: Vec<u8> = Vec::new();
+    append_u32(&mut index, resources.len() as u32);
+    for &(usage, number, id) in resources.iter() {
+        let start = offsets.iter().find(|&&(chunk_id, _)| chunk_id == id).map(|&(_, start)| start).unwrap();
+        index.extend(usage.bytes());
+        append_u32(&mut index, number);
+        append_u32(&mut index, start as u32);
+    }
+    chunks.insert(0, Chunk { id: "RIdx", data: index });
+
+    let mut form: Vec<u8> = Vec::new();
+    form.extend("IFRS".bytes());
+    for chunk in chunks.iter() {
+        form.extend(chunk.id.bytes());
+        append_u32(&mut form, chunk.data.len() as u32);
+        form.extend(chunk.data.iter().cloned());
+        // chunks start at even offsets
+        if chunk.data.len() % 2 == 1 {
+            form.push(0);
+        }
+    }
+
+    let mut bytes: Vec<u8> = Vec::new();
+    bytes.extend("FORM".bytes());
+    append_u32(&mut bytes, form.len() as u32);
+    bytes.extend(form.into_iter());
+    debug!("Blorb with {} chunks and {} bytes", chunks.len(), bytes.len());
+
+    Ok(bytes)
+}
+
+/// Returns the IFID of the story
+///
+/// It is the one of the `StoryData` passage or otherwise built from the
+/// release number, the serial number and the checksum in the header.
+pub fn ifid(zcode: &[u8], story: &StoryInfo) -> String {
+    if let Some(ref ifid) = story.ifid {
+        return ifid.clone();
+    }
+
+    let release = header_u16(zcode, 0x02);
+    let serial: String = (0x12..0x18).map(|i| match zcode.get(i) {
+        Some(&c) if (c as char).is_alphanumeric() => c as char,
+        _ => '-',
+    }).collect();
+    format!("ZCODE-{}-{}-{:04X}", release, serial, header_u16(zcode, 0x1c))
+}
+
+/// Returns the iFiction record of the story
+///
+/// Stories without `StoryTitle` or `StoryAuthor` are called "An Untitled
+/// Story" by "Anonymous", as the Treaty of Babel suggests.
+pub fn ifiction(zcode: &[u8], story: &StoryInfo, cover: bool) -> String {
+    let title = story.title.clone().unwrap_or("An Untitled Story".to_string());
+    let author = story.author.clone().unwrap_or("Anonymous".to_string());
+
+    let mut xml = String::new();
+    xml.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    xml.push_str("<ifindex version=\"1.0\" xmlns=\"http://babel.ifarchive.org/protocol/iFiction/\">\n");
+    xml.push_str("  <story>\n");
+    xml.push_str("    <identification>\n");
+    xml.push_str(&format!("      <ifid>{}</ifid>\n", escape_xml(&ifid(zcode, story))));
+    xml.push_str("      <format>zcode</format>\n");
+    xml.push_str("    </identification>\n");
+    xml.push_str("    <bibliographic>\n");
+    xml.push_str(&format!("      <title>{}</title>\n", escape_xml(&title)));
+    xml.push_str(&format!("      <author>{}</author>\n", escape_xml(&author)));
+    xml.push_str("    </bibliographic>\n");
+    xml.push_str("    <zcode>\n");
+    xml.push_str(&format!("      <version>{}</version>\n", zcode.get(0).cloned().unwrap_or(0)));
+    xml.push_str(&format!("      <release>{}</release>\n", header_u16(zcode, 0x02)));
+    xml.push_str(&format!("      <checksum>{:04x}</checksum>\n", header_u16(zcode, 0x1c)));
+    if cover {
+        xml.push_str("      <coverpicture>1</coverpicture>\n");
+    }
+    xml.push_str("    </zcode>\n");
+    xml.push_str("  </story>\n");
+    xml.push_str("</ifindex>\n");
+
+    xml
+}
+
+/// reads the cover image
+pub fn read_image(name: &str) -> Result<Vec<u8>, String> {
+    let mut file = match File::open(Path::new(name)) {
+        Ok(file) => file,
+        Err(why) => return Err(format!("Could not open the cover image {}: {}", name, Error::description(&why)))
+    };
+
+    let mut image: Vec<u8> = Vec::new();
+    match file.read_to_end(&mut image) {
+        Ok(_) => Ok(image),
+        Err(why) => Err(format!("Could not read the cover image {}: {}", name, Error::description(&why)))
+    }
+}
+
+/// returns the chunk id of an image, Blorb only knows PNG and JPEG
+fn image_chunk_id(image: &[u8]) -> Result<&'static str, String> {
+    if image.starts_with(&[0x89, 0x50, 0x4e, 0x47]) {
+        Ok("PNG ")
+    } else if image.starts_with(&[0xff, 0xd8]) {
+        Ok("JPEG")
+    } else {
+        Err("The cover image has to be a PNG or a JPEG image".to_string())
+    }
+}
+
+/// returns a word of the story header
+fn header_u16(zcode: &[u8], index: usize) -> u16 {
+    match (zcode.get(index), zcode.get(index + 1)) {
+        (Some(&high), Some(&low)) => (high as u16) << 8 | low as u16,
+        _ => 0
+    }
+}
+
+fn append_u32(bytes: &mut Vec<u8>, value: u32) {
+    bytes.push((value >> 24) as u8);
+    bytes.push((value >> 16) as u8);
+    bytes.push((value >> 8) as u8);
+    bytes.push(value as u8);
+}
+
+/// replaces the characters with a special meaning in xml
+fn escape_xml(text: &str) -> String {
+    text.replace("&", "&amp;").replace("<", "&lt;").replace(">", "&gt;").replace("\"", "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use backend::zcode::zfile::StoryInfo;
+
+    fn read_u32(bytes: &[u8], index: usize) -> usize {
+        (bytes[index] as usize) << 24 | (bytes[index + 1] as usize) << 16 |
+            (bytes[index + 2] as usize) << 8 | bytes[index + 3] as usize
+    }
+
+    #[test]
+    fn blorb_test() {
+        let zcode: Vec<u8> = (0..101).map(|i| i as u8).collect();
+        let cover: Vec<u8> = vec![0x89, 0x50, 0x4e, 0x47, 0x0d, 0x0a, 0x1a, 0x0a];
+        let bytes = blorb(&zcode, &StoryInfo::default(), Some(&cover[..])).unwrap();
+
+        assert_eq!(&bytes[0..4], b"FORM");
+        assert_eq!(read_u32(&bytes, 4), bytes.len() - 8);
+        assert_eq!(&bytes[8..16], b"IFRSRIdx");
+        assert_eq!(read_u32(&bytes, 16), 28);
+        assert_eq!(read_u32(&bytes, 20), 2);
+
+        // the index points to the story and the cover
+        assert_eq!(&bytes[24..28], b"Exec");
+        let zcod = read_u32(&bytes, 32);
+        assert_eq!(&bytes[zcod..zcod + 4], b"ZCOD");
+        assert_eq!(read_u32(&bytes, zcod + 4), 101);
+        assert_eq!(&bytes[zcod + 8..zcod + 109], &zcode[..]);
+
+        assert_eq!(&bytes[36..40], b"Pict");
+        assert_eq!(read_u32(&bytes, 40), 1);
+        let pict = read_u32(&bytes, 44);
+        assert_eq!(pict, zcod + 110);
+        assert_eq!(&bytes[pict..pict + 4], b"PNG ");
+        assert_eq!(pict + 8 + cover.len(), bytes.len());
+    }
+
+    #[test]
+    fn blorb_cover_test() {
+        assert!(blorb(&[0; 64], &StoryInfo::default(), Some(&b"GIF89a"[..])).is_err());
+    }
+
+    #[test]
+    fn ifid_test() {
+        let mut zcode = vec![0; 64];
+        zcode[0x03] = 2;
+        for (i, c) in b"000000".iter().enumerate() {
+            zcode[0x12 + i] = *c;
+        }
+        zcode[0x1c] = 0xab;
+        zcode[0x1d] = 0x0c;
+
+        let mut story = StoryInfo::default();
+        assert_eq!(ifid(&zcode, &story), "ZCODE-2-000000-AB0C".to_string());
+
+        story.ifid = Some("D674C58C-DEFA-4F70-B7A2-27742230C0FF".to_string());
+        assert_eq!(ifid(&zcode, &story), "D674C58C-DEFA-4F70-B7A2-27742230C0FF".to_string());
+    }
+
+    #[test]
+    fn ifiction_test() {
+        let mut story = StoryInfo::default();
+        story.title = Some("Tom & Jerry".to_string());
+        let xml = ifiction(&[8; 64], &story, false);
+
+        assert!(xml.contains("<title>Tom &amp; Jerry</title>"));
+        assert!(xml.contains("<author>Anonymous</author>"));
+        assert!(xml.contains("<version>8</version>"));
+        assert!(!xml.contains("coverpicture"));
+    }
+}
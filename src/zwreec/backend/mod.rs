@@ -1 +1,2 @@
 pub mod zcode;
+pub mod blorb;
@@ -5,6 +5,8 @@ pub mod zbytes;
 pub mod zfile;
 pub mod ztext;
 pub mod op;
+#[cfg(test)]
+pub mod zrun;
 
 use self::zfile::{Zfile, Operand, Variable, ZOP};
 
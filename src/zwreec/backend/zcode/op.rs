@@ -15,6 +15,69 @@ pub fn op_erase_window(value: i8) -> Vec<u8> {
 }
 
 
+/// splits the screen, the upper window gets the given number of lines
+pub fn op_split_window(lines: &Operand) -> Vec<u8> {
+    let args: Vec<ArgType> = vec![arg_type(lines), ArgType::Nothing, ArgType::Nothing, ArgType::Nothing];
+    let mut bytes = op_var(0x0a, args);
+    write_argument(lines, &mut bytes);
+    bytes
+}
+
+/// selects the window for the output, 0 is the lower and 1 the upper window
+pub fn op_set_window(window: u8) -> Vec<u8> {
+    let args: Vec<ArgType> = vec![ArgType::SmallConst, ArgType::Nothing, ArgType::Nothing, ArgType::Nothing];
+    let mut bytes = op_var(0x0b, args);
+    bytes.push(window);
+    bytes
+}
+
+/// moves the cursor of the upper window, line and column start at 1
+pub fn op_set_cursor(line: &Operand, column: &Operand) -> Vec<u8> {
+    let args: Vec<ArgType> = vec![arg_type(line), arg_type(column), ArgType::Nothing, ArgType::Nothing];
+    let mut bytes = op_var(0x0f, args);
+    write_argument(line, &mut bytes);
+    write_argument(column, &mut bytes);
+    bytes
+}
+
+
+/// writes the row and column of the cursor to the words 0 and 1 of the array
+pub fn op_get_cursor(array_address: &Operand) -> Vec<u8> {
+    let args: Vec<ArgType> = vec![arg_type(array_address), ArgType::Nothing, ArgType::Nothing, ArgType::Nothing];
+    let mut bytes = op_var(0x10, args);
+    write_argument(array_address, &mut bytes);
+    bytes
+}
+
+/// writes the y and x coordinates, the buttons and the menu of the mouse to
+/// the words 0 to 3 of the array (only version 6)
+pub fn op_read_mouse(array_address: &Operand) -> Vec<u8> {
+    let args: Vec<ArgType> = vec![arg_type(array_address), ArgType::Nothing, ArgType::Nothing, ArgType::Nothing];
+    let mut bytes = op_ext(0x16, args);
+    write_argument(array_address, &mut bytes);
+    bytes
+}
+
+/// restricts the mouse to the window (only version 6)
+pub fn op_mouse_window(window: &Operand) -> Vec<u8> {
+    let args: Vec<ArgType> = vec![arg_type(window), ArgType::Nothing, ArgType::Nothing, ArgType::Nothing];
+    let mut bytes = op_ext(0x17, args);
+    write_argument(window, &mut bytes);
+    bytes
+}
+
+/// selects (positive value) or deselects (negative value) an output stream
+/// stream 1 is the screen, so -1 suppresses all printed text
+pub fn op_output_stream(stream: i8) -> Vec<u8> {
+    let args: Vec<ArgType> = vec![ArgType::LargeConst, ArgType::Nothing, ArgType::Nothing, ArgType::Nothing];
+    let mut bytes = op_var(0x13, args);
+
+    // signed to unsigned value
+    write_u16(stream as i16 as u16, &mut bytes);
+    bytes
+}
+
+
 /// calls a routine (the address is stored in a variable)
 pub fn op_call_1n_var(variable: u8) -> Vec<u8> {
     let mut bytes = op_1(0x0f, ArgType::Variable);
@@ -62,6 +125,22 @@ pub fn op_storeb(array_address: &Operand, index: &Variable, variable: &Variable)
 }
 
 
+/// loads a byte from an array in a variable
+/// loadb is an 2op, BUT with 3 ops (like loadw)
+pub fn op_loadb(array_address: &Operand, index: &Variable, variable: &Variable) -> Vec<u8> {
+    let mut bytes = op_2(0x10, vec![arg_type(&array_address), ArgType::Variable]);
+
+    // array address
+    write_argument(array_address, &mut bytes);
+    // array index
+    bytes.push(index.id);
+
+    // variable
+    bytes.push(variable.id);
+    bytes
+}
+
+
 /// loads a word from an array in a variable
 /// loadw is an 2op, BUT with 3 ops -.-
 pub fn op_loadw(array_address: &Operand, index: &Variable, variable: &Variable) -> Vec<u8> {
@@ -136,6 +215,44 @@ pub fn op_pull(variable: u8) -> Vec<u8> {
 }
 
 
+/// throws away the value on top of the stack (only up to version 4)
+pub fn op_pop() -> Vec<u8> {
+    op_0(0x09)
+}
+
+
+/// prints the zscii character
+pub fn op_print_char(character: &Operand) -> Vec<u8> {
+    let args: Vec<ArgType> = vec![arg_type(character), ArgType::Nothing, ArgType::Nothing, ArgType::Nothing];
+    let mut bytes = op_var(0x05, args);
+    write_argument(character, &mut bytes);
+    bytes
+}
+
+
+/// reads a line of text to the text buffer and splits it into words in the
+/// parse buffer (sread of version 3 and 4)
+pub fn op_sread(text_buffer: &Operand, parse_buffer: &Operand) -> Vec<u8> {
+    let args: Vec<ArgType> = vec![arg_type(text_buffer), arg_type(parse_buffer), ArgType::Nothing, ArgType::Nothing];
+    let mut bytes = op_var(0x04, args);
+    write_argument(text_buffer, &mut bytes);
+    write_argument(parse_buffer, &mut bytes);
+    bytes
+}
+
+/// reads a line of text to the text buffer, splits it into words in the parse
+/// buffer if it isn't 0 and stores the key that ended the input (aread of
+/// version 5)
+pub fn op_aread(text_buffer: &Operand, parse_buffer: &Operand, variable: &Variable) -> Vec<u8> {
+    let args: Vec<ArgType> = vec![arg_type(text_buffer), arg_type(parse_buffer), ArgType::Nothing, ArgType::Nothing];
+    let mut bytes = op_var(0x04, args);
+    write_argument(text_buffer, &mut bytes);
+    write_argument(parse_buffer, &mut bytes);
+    bytes.push(variable.id);
+    bytes
+}
+
+
 /// calculates a random numer from 1 to range
 pub fn op_random(range: &Operand, variable: &Variable) -> Vec<u8> {
     let args: Vec<ArgType> = vec![arg_type(range), ArgType::Nothing, ArgType::Nothing, ArgType::Nothing];
@@ -299,6 +416,46 @@ pub fn op_inc( variable: u8) -> Vec<u8> {
     bytes
 }
 
+/// saves the game, the result is 0 on failure, 1 on success
+/// and 2 when the game continues after a restore
+pub fn op_save(variable: &Variable) -> Vec<u8> {
+    let args: Vec<ArgType> = vec![ArgType::Nothing, ArgType::Nothing, ArgType::Nothing, ArgType::Nothing];
+    let mut bytes = op_ext(0x00, args);
+    bytes.push(variable.id);
+    bytes
+}
+
+/// restores a saved game, the result is 0 on failure
+/// (on success the execution continues after the save-opcode)
+pub fn op_restore(variable: &Variable) -> Vec<u8> {
+    let args: Vec<ArgType> = vec![ArgType::Nothing, ArgType::Nothing, ArgType::Nothing, ArgType::Nothing];
+    let mut bytes = op_ext(0x01, args);
+    bytes.push(variable.id);
+    bytes
+}
+
+/// saves the game state in memory for undo, the result is 0 on failure
+/// (or if the interpreter can't undo), 1 on success and 2 after a restore_undo
+pub fn op_save_undo(variable: &Variable) -> Vec<u8> {
+    let args: Vec<ArgType> = vec![ArgType::Nothing, ArgType::Nothing, ArgType::Nothing, ArgType::Nothing];
+    let mut bytes = op_ext(0x09, args);
+    bytes.push(variable.id);
+    bytes
+}
+
+/// restores the last state saved with save_undo, the result is 0 on failure
+pub fn op_restore_undo(variable: &Variable) -> Vec<u8> {
+    let args: Vec<ArgType> = vec![ArgType::Nothing, ArgType::Nothing, ArgType::Nothing, ArgType::Nothing];
+    let mut bytes = op_ext(0x0a, args);
+    bytes.push(variable.id);
+    bytes
+}
+
+/// restarts the game
+pub fn op_restart() -> Vec<u8> {
+    op_0(0x07)
+}
+
 pub fn op_newline() -> Vec<u8> {
     op_0(0x0b)
 }
@@ -325,6 +482,17 @@ pub fn op_var(value: u8, arg_types: Vec<ArgType>) -> Vec<u8> {
 }
 
 
+/// extended op-codes
+/// $be  extended opcode given in next byte (operand types in the byte after)
+pub fn op_ext(value: u8, arg_types: Vec<ArgType>) -> Vec<u8> {
+    let mut ret = Vec::new();
+    ret.push(0xbe);
+    ret.push(value);
+    ret.push(encode_variable_arguments(arg_types));
+    ret
+}
+
+
 /// op-codes with 1 operator
 /// $80 -- $8f  short     1OP     large constant
 /// $90 -- $9f  short     1OP     small constant
@@ -3,13 +3,28 @@
 pub use super::zbytes::Bytes;
 pub use super::ztext;
 pub use super::op;
-use config::Config;
+use std::char;
+use time;
+use config::{Config, LinkSelection};
+
+/// fixed-point numbers are stored multiplied by this scale, so they have two
+/// decimal places
+pub const FIXED_SCALE: i16 = 100;
+
+/// the links of a passage are stored in arrays with room for 128 links and
+/// the status line of version 3 has an object for every number of links
+pub const MAX_LINKS: u16 = 127;
 
 #[derive(Clone, PartialEq, Debug)]
 pub enum Type {
     Bool,
     Integer,
     String,
+    /// a fixed-point number (see `FIXED_SCALE`)
+    Fixed,
+    /// a word table on the heap, the type of the elements is unknown for an
+    /// empty array until something is pushed to it
+    Array(Option<Box<Type>>),
 }
 
 #[derive(Debug,Clone)]
@@ -74,12 +89,19 @@ impl Variable {
     pub fn new_bool(id: u8) -> Variable {
         Variable { id: id, vartype: Type::Bool }
     }
+    pub fn new_fixed(id: u8) -> Variable {
+        Variable { id: id, vartype: Type::Fixed }
+    }
+    pub fn new_array(id: u8, element_type: Option<Type>) -> Variable {
+        Variable { id: id, vartype: Type::Array(element_type.map(|t| Box::new(t))) }
+    }
 }
 
 #[derive(Debug)]
 pub enum ZOP {
   PrintUnicode{c: u16},
   PrintUnicodeVar{var: Variable},
+  PrintChar{var: Variable},
   PrintUnicodeStr{address: Operand},
   Print{text: String},
   PrintNumVar{variable: Variable},
@@ -91,6 +113,7 @@ pub enum ZOP {
   Call2NWithArg{jump_to_label: String, arg: Operand},
   Call1NVar{variable: u8},
   Call2S{jump_to_label: String, arg: Operand, result: Variable},
+  StoreRoutineAddress{variable: Variable, routine: String},
   CallVNA2{jump_to_label: String, arg1: Operand, arg2: Operand},
   CallVNA3{jump_to_label: String, arg1: Operand, arg2: Operand, arg3: Operand},
   CallVSA2{jump_to_label: String, arg1: Operand, arg2: Operand, result: Variable},
@@ -115,6 +138,8 @@ pub enum ZOP {
   Random{range: Operand, variable: Variable},
   ReadChar{local_var_id: u8},
   ReadCharTimer{local_var_id: u8, timer: u8, routine: String},
+  Sread{text_buffer: Operand, parse_buffer: Operand},
+  Aread{text_buffer: Operand, parse_buffer: Operand, result: Variable},
   Add{operand1: Operand, operand2: Operand, save_variable: Variable},
   Sub{operand1: Operand, operand2: Operand, save_variable: Variable},
   Mul{operand1: Operand, operand2: Operand, save_variable: Variable},
@@ -125,7 +150,20 @@ pub enum ZOP {
   Jump{jump_to_label: String},
   Dec{variable: u8},
   LoadW{array_address: Operand, index: Variable, variable: Variable},
+  LoadB{array_address: Operand, index: Variable, variable: Variable},
   EraseWindow{value: i8},
+  OutputStream{stream: i8},
+  Save{result: Variable},
+  Restore{result: Variable},
+  Restart,
+  SaveUndo{result: Variable},
+  SplitWindow{lines: Operand},
+  SetWindow{window: u8},
+  SetCursor{line: Operand, column: Operand},
+  GetCursor{array_address: Operand},
+  ReadMouse{array_address: Operand},
+  MouseWindow{window: Operand},
+  RestoreUndo{result: Variable},
   Quit,
 }
 
@@ -145,6 +183,12 @@ pub enum ArgType {
     Nothing
 }
 
+/// the routine or an argument of a call written with call_vs
+enum CallOperand {
+    Label(String),
+    Operand(Operand),
+}
+
 pub struct Zfile {
     pub data: Bytes,
     unicode_table: Vec<u16>,
@@ -154,12 +198,57 @@ pub struct Zfile {
     program_addr: u16,
     unicode_table_addr: u16,
     global_addr: u16,
+    extension_addr: u16,
     static_addr: u16,
     pub object_addr: u16,
     last_static_written: u16,
     pub heap_start: u16,
     pub force_unicode: bool,
     pub easter_egg: bool,
+    pub undo: bool,
+    pub link_selection: LinkSelection,
+    pub mouse: bool,
+    /// newlines call system_newline, which moves the link positions up when
+    /// the screen scrolls
+    track_scrolling: bool,
+    pub story: StoryInfo,
+    /// the version of the z-machine (3, 4, 5 or 8)
+    pub version: u8,
+    /// keep the emitted ops in `ops`, so the tests can run them (see `zrun`)
+    record_ops: bool,
+    ops: Vec<ZOP>,
+    /// the features that were left out because this version lacks them
+    pub warnings: Vec<String>,
+}
+
+/// The special passages of a story
+#[derive(Debug, Clone, PartialEq)]
+pub struct StoryInfo {
+    /// the passage the story starts with
+    pub start: String,
+    /// the ifid from the StoryData passage, which identifies the story
+    pub ifid: Option<String>,
+    /// the text of StoryTitle
+    pub title: Option<String>,
+    /// the text of StoryAuthor
+    pub author: Option<String>,
+    /// StoryInit exists and is run once before Start
+    pub init: bool,
+    /// StoryMenu exists and its links are shown at every prompt
+    pub menu: bool,
+}
+
+impl Default for StoryInfo {
+    fn default() -> StoryInfo {
+        StoryInfo {
+            start: "Start".to_string(),
+            ifid: None,
+            title: None,
+            author: None,
+            init: false,
+            menu: false,
+        }
+    }
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -197,30 +286,61 @@ impl Zfile {
 
     /// creates a new zfile
     pub fn new() -> Zfile {
-        Zfile::new_with_options(false, false)
+        Zfile::new_with_options(false, false, false, LinkSelection::Digits, false, 8)
     }
 
-    pub fn new_with_options(force_unicode: bool, easter_egg: bool) -> Zfile {
+    /// undo, mouse clicks and the colours of the easter egg need version 5,
+    /// so they are disabled for older versions
+    pub fn new_with_options(force_unicode: bool, easter_egg: bool, undo: bool, link_selection: LinkSelection, mouse: bool, version: u8) -> Zfile {
+        assert!(version == 3 || version == 4 || version == 5 || version == 8, "only the versions 3, 4, 5 and 8 are supported");
+
+        // without a unicode translation table the default characters are used
+        let unicode_table: Vec<u16> = if version >= 5 {
+            Vec::new()
+        } else {
+            ztext::DEFAULT_UNICODE_TABLE.to_vec()
+        };
+
         Zfile {
             data: Bytes{bytes: Vec::new()},
-            unicode_table: Vec::new(),
+            unicode_table: unicode_table,
             jumps: Vec::new(),
             labels: Vec::new(),
             strings: Vec::new(),
             program_addr: 0xfff8,
             unicode_table_addr: 0,
             global_addr: 0,
+            extension_addr: 0,
             object_addr: 0,
             static_addr: 0,
             last_static_written: 0x8000,
             heap_start: 0x800,
             force_unicode: force_unicode,
-            easter_egg: easter_egg,
+            easter_egg: easter_egg && version >= 5,
+            undo: undo && version >= 5,
+            link_selection: link_selection,
+            mouse: mouse && version >= 5,
+            track_scrolling: mouse && version >= 5 && link_selection != LinkSelection::Cursor,
+            story: StoryInfo::default(),
+            version: version,
+            record_ops: false,
+            ops: Vec::new(),
+            warnings: Vec::new(),
         }
     }
 
+    /// the features that older versions lack are reported by the codegen
     pub fn new_with_cfg(cfg: &Config) -> Zfile {
-        Zfile::new_with_options(cfg.force_unicode, cfg.easter_egg)
+        Zfile::new_with_options(cfg.force_unicode, cfg.easter_egg, cfg.undo, cfg.link_selection, cfg.mouse, cfg.zversion)
+    }
+
+    /// the maximum length of a story file of this version
+    pub fn max_file_length(&self) -> usize {
+        match self.version {
+            3 => 128 * 1024,
+            4 | 5 => 256 * 1024,
+            _ => 512 * 1024,
+        }
     }
 
     /// creates the header of a zfile
@@ -230,6 +350,7 @@ impl Zfile {
 
         let alpha_addr: u16 = 0x40;
         let extension_addr: u16 = alpha_addr + 78;
+        self.extension_addr = extension_addr;
         self.unicode_table_addr = extension_addr as u16 + 8;
 
         // 1 byte for the unicode count, 97 possible chars with 2 bytes
@@ -242,7 +363,7 @@ impl Zfile {
         let dictionary_addr: u16 = self.last_static_written;
 
         // version
-        self.data.write_byte(8, 0x00);
+        self.data.write_byte(self.version, 0x00);
 
         // flag1 (from right to left)
         // 0: colours availabe
@@ -250,10 +371,18 @@ impl Zfile {
         // 2: bold
         // 3: italic
         // 4: fixed
-        self.data.write_byte(0x1d, 0x01);
+        // (version 3 has no text styles, its flags are set by the interpreter)
+        if self.version >= 4 {
+            self.data.write_byte(0x1d, 0x01);
+        }
 
         // release version (0x02 und 0x03)
-        self.data.write_u16(0, 0x02);
+        self.data.write_u16(1, 0x02);
+
+        // serial number (0x12 to 0x17), the date of the compilation as
+        // YYMMDD like Inform does, it is part of the ifid of stories
+        // without StoryData
+        self.data.write_bytes(serial_number(&time::now()).as_bytes(), 0x12);
 
         // base of high memory (byte address) (0x04 and 0x05)
         self.data.write_u16(high_memory_addr, 0x04);
@@ -265,9 +394,12 @@ impl Zfile {
         self.data.write_u16(dictionary_addr, 0x08);
 
         // flag2 (from right to left)
+        // 5: game want to use the mouse
         // 6: game want to use colours
-        // 0000000001000000
-        self.data.write_u16(0x40, 0x10);
+        // 0000000001100000
+        // (both only exist since version 5)
+        let flag2: u16 = if self.version < 5 { 0 } else if self.mouse { 0x60 } else { 0x40 };
+        self.data.write_u16(flag2, 0x10);
 
         // location of object table (byte address) (0x0a and 0x0b)
         self.data.write_u16(self.object_addr, 0x0a);
@@ -278,6 +410,12 @@ impl Zfile {
         // base of static memory (byte address) (0x0e and 0x0f)
         self.data.write_u16(self.static_addr, 0x0e);
 
+        // the alphabet table and the header extension table exist since
+        // version 5, older versions use the default alphabet
+        if self.version < 5 {
+            return;
+        }
+
         // alphabet address (bytes) - its 0x34 and 0x35, why not only 0x34?
         self.data.write_u16(alpha_addr, 0x34);
 
@@ -321,6 +459,7 @@ impl Zfile {
     /// goes through all jumps and labels, if they have the same name:
     ///  write the "where to jump"-adress of the label to the position of the jump
     fn write_jumps(&mut self) {
+        let packing = packing_factor(self.version);
         for jump in self.jumps.iter_mut() {
             let mut label_found = false;
 
@@ -329,7 +468,7 @@ impl Zfile {
                     label_found = true;
                     match jump.jump_type {
                         JumpType::Routine => {
-                            let new_addr: u16 = (label.to_addr / 8) as u16;
+                            let new_addr: u16 = (label.to_addr / packing) as u16;
                             self.data.write_u16(new_addr, jump.from_addr as usize);
                         },
                         JumpType::Branch => {
@@ -379,9 +518,119 @@ impl Zfile {
         str_addr
     }
 
+    /// writes the ifid as `UUID://...//` to static memory, where tools that
+    /// follow the Treaty of Babel look for it
+    fn write_ifid(&mut self) {
+        let ifid = match self.story.ifid {
+            Some(ref ifid) => format!("UUID://{}//", ifid),
+            None => return
+        };
+        self.write_static_text(&ifid);
+    }
+
+    /// writes the title and the author as `StoryTitle://...//` and
+    /// `StoryAuthor://...//` behind the ifid, the header of the Z-Machine
+    /// has no fields for them
+    fn write_title_and_author(&mut self) {
+        let mut text = String::new();
+        if let Some(ref title) = self.story.title {
+            text.push_str(&format!("StoryTitle://{}//", title));
+        }
+        if let Some(ref author) = self.story.author {
+            text.push_str(&format!("StoryAuthor://{}//", author));
+        }
+        if !text.is_empty() {
+            self.write_static_text(&text);
+        }
+    }
+
+    /// writes the utf-8 bytes of the text to static memory
+    fn write_static_text(&mut self, text: &str) {
+        let addr: u16 = self.last_static_written;
+        assert!(addr >= self.object_addr && addr + (text.len() as u16) < self.program_addr, "invalid addr to store the text");
+        self.data.write_bytes(text.as_bytes(), addr as usize);
+        self.last_static_written = addr + text.len() as u16;
+    }
+
+    /// writes an empty dictionary to the start of static memory, where the
+    /// header points to, the line input of version 3 looks up the words in it
+    fn write_dictionary(&mut self) {
+        let addr: u16 = self.last_static_written;
+
+        // no word separators, entries of 7 bytes and no entries
+        self.data.write_bytes(&[0, 7, 0, 0], addr as usize);
+        self.last_static_written = addr + 4;
+    }
+
+    /// writes an object table for the status line of version 3
+    ///
+    /// The interpreter prints the name of the object in global 16 before
+    /// every line input, but global 16 is the number of links. So the table
+    /// has an object without a name for every possible number of links.
+    fn write_status_objects(&mut self) {
+        let table_addr: u16 = self.last_static_written;
+        let objects: u16 = MAX_LINKS + 1;
+
+        // 31 words of default properties, then the objects with 9 bytes each,
+        // they all use the empty property table behind them
+        let properties_addr: u16 = table_addr + 62 + 9 * objects;
+        for i in 0..objects {
+            self.data.write_u16(properties_addr, (table_addr + 62 + 9 * i + 7) as usize);
+        }
+        self.data.write_bytes(&[0, 0], properties_addr as usize);
+        assert!(properties_addr + 2 < self.program_addr, "invalid addr to store the objects");
+
+        self.data.write_u16(table_addr, 0x0a);
+        self.last_static_written = properties_addr + 2;
+    }
+
+    /// sets the maximum length of the buffers of the line input in version 3
+    fn write_read_buffers(&mut self) {
+        let text_addr = self.read_buffer_addr();
+
+        // the maximum number of letters minus 1, followed by the letters
+        // and a terminating zero
+        self.data.write_byte(19, text_addr as usize);
+        self.data.write_byte(1, text_addr as usize + 22);
+    }
+
+    /// sets the maximum length of the buffers of <<textinput>> and prompt()
+    fn write_text_input_buffers(&mut self) {
+        let text_addr = self.text_input_addr();
+
+        // version 3 needs space for a terminating zero, the later versions
+        // read the letters themselves into the bytes after the second
+        let max_letters: u8 = if self.version >= 4 { 80 } else { 79 };
+        self.data.write_byte(max_letters, text_addr as usize);
+        self.data.write_byte(1, text_addr as usize + 84);
+    }
+
+    /// writes the tags of a passage as a table of words to static memory and
+    /// returns its address
+    /// the first word is the address of all tags joined by spaces, the second
+    /// the number of tags and then the addresses of the tags follow
+    pub fn write_tags_table(&mut self, tags: &Vec<String>) -> u16 {
+        let joined_addr = self.write_string(&tags.connect(" "));
+        let mut tag_addrs: Vec<u16> = vec![];
+        for tag in tags {
+            tag_addrs.push(self.write_string(tag));
+        }
+
+        let table_addr: u16 = self.last_static_written;
+        assert!(table_addr >= self.object_addr && table_addr + 4 + 2 * tags.len() as u16 < self.program_addr, "invalid addr to store a tags table");
+        self.data.write_u16(joined_addr, table_addr as usize);
+        self.data.write_u16(tags.len() as u16, table_addr as usize + 2);
+        for (i, addr) in tag_addrs.iter().enumerate() {
+            self.data.write_u16(*addr, table_addr as usize + 4 + 2 * i);
+        }
+        self.last_static_written = table_addr + 4 + 2 * tags.len() as u16;
+        table_addr
+    }
+
     /// saves the zstrings to high mem and writes the resulting address to the
     /// print_paddr arguments which referencing the string
     fn write_strings(&mut self) {
+        let packing = packing_factor(self.version);
         let mut prev_strings: Vec<(Zstring, u32)> = vec![];
         for string in self.strings.iter_mut() {
             // optimize to reuse strings if they are the same
@@ -392,7 +641,7 @@ impl Zfile {
                     if string.unicode {
                         self.data.write_u16(addr as u16, string.from_addr as usize);  // normal addr
                     } else {
-                        self.data.write_u16((addr/8) as u16, string.from_addr as usize);  // packed addr
+                        self.data.write_u16((addr/packing) as u16, string.from_addr as usize);  // packed addr
                     }
                     break;
                 }
@@ -409,13 +658,13 @@ impl Zfile {
                     self.last_static_written = self.last_static_written + string.chars.len() as u16;
                     str_addr as u32
                 } else if string.unicode == false && string.written_addr == 0 {
-                    let str_addr: u32 = align_address(self.data.len() as u32, 8);
+                    let str_addr: u32 = align_address(self.data.len() as u32, packing);
                     self.data.write_zero_until(str_addr as usize);
                     debug!("{:#x}: zstring \"{}\"", str_addr, string.orig);
                     let hexstrs: Vec<String> = string.chars.iter().map(|b| format!("{:02X}", b)).collect();
                     trace!("{:#x}: {}", str_addr, hexstrs.connect(" "));
                     self.data.append_bytes(&string.chars);
-                    self.data.write_u16((str_addr/8) as u16, string.from_addr as usize);  // packed addr
+                    self.data.write_u16((str_addr/packing) as u16, string.from_addr as usize);  // packed addr
                     str_addr
                 } else {
                     string.written_addr
@@ -447,8 +696,32 @@ impl Zfile {
         self.labels.push(label);
     }
 
+    /// keeps the ops emitted from now on, so the tests can run them
+    #[cfg(test)]
+    pub fn record_ops(&mut self) {
+        self.record_ops = true;
+    }
+
+    /// the ops emitted since `record_ops`
+    #[cfg(test)]
+    pub fn ops(&self) -> &Vec<ZOP> {
+        &self.ops
+    }
+
     /// write out respective byte stream of opcodes to file
     pub fn emit(&mut self, code: Vec<ZOP>) {
+        let code: Vec<ZOP> = if self.track_scrolling {
+            code.into_iter().map(|instr| match instr {
+                ZOP::Newline => ZOP::Call1N{jump_to_label: "system_newline".to_string()},
+                instr => instr
+            }).collect()
+        } else {
+            code
+        };
+
+        // the ops written for an op like PrintOps are not recorded again
+        let record_ops = self.record_ops;
+        self.record_ops = false;
         for instr in &code {
             let addr = self.data.bytes.len();
             debug!("{:#x}: {:?}", addr, instr);
@@ -456,6 +729,10 @@ impl Zfile {
             let hexstrs: Vec<String> = bytes.iter().map(|b| format!("{:02X}", b)).collect();
             trace!("{:#x}: {}", addr, hexstrs.connect(" "));
         }
+        self.record_ops = record_ops;
+        if record_ops {
+            self.ops.extend(code.into_iter());
+        }
     }
 
     /// write opcodes to file but also return written bytes for testing purposes
@@ -466,8 +743,14 @@ impl Zfile {
         let old_labels: Vec<Zlabel> = self.labels.clone();
 
 
+        // the ops of version 5 that older versions don't have and the mouse
+        // ops of version 6
+        let lowered = (self.version < 5 && self.lower_zop(instr)) ||
+            (self.version != 6 && self.emulate_mouse_zop(instr));
+
         //self.data.write_bytes()
         let bytes: Vec<u8> = match instr {
+            _ if lowered => Vec::new(),
             &ZOP::Quit => op::quit(),
             &ZOP::Newline => op::op_newline(),
             &ZOP::Dec{variable} => op::op_dec(variable),
@@ -487,18 +770,35 @@ impl Zfile {
             &ZOP::SetColorVar{foreground, background} => op::op_set_color_var(foreground, background),
             &ZOP::Random{ref range, ref variable} => op::op_random(range, variable),
             &ZOP::PrintNumVar{ref variable} => op::op_print_num_var(variable),
+            &ZOP::PrintChar{ref var} => op::op_print_char(&Operand::Var(var.clone())),
             &ZOP::SetTextStyle{bold, reverse, monospace, italic} => op::op_set_text_style(bold, reverse, monospace, italic),
             &ZOP::ReadChar{local_var_id} => op::op_read_char(local_var_id),
+            &ZOP::Sread{ref text_buffer, ref parse_buffer} => op::op_sread(text_buffer, parse_buffer),
+            &ZOP::Aread{ref text_buffer, ref parse_buffer, ref result} => op::op_aread(text_buffer, parse_buffer, result),
             &ZOP::LoadW{ref array_address, ref index, ref variable} => op::op_loadw(array_address, index, variable),
+            &ZOP::LoadB{ref array_address, ref index, ref variable} => op::op_loadb(array_address, index, variable),
             &ZOP::StoreW{ref array_address, ref index, ref variable} => op::op_storew(array_address, index, variable),
             &ZOP::StoreB{ref array_address, ref index, ref variable} => op::op_storeb(array_address, index, variable),
             &ZOP::Call1NVar{variable} => op::op_call_1n_var(variable),
             &ZOP::EraseWindow{value} => op::op_erase_window(value),
+            &ZOP::OutputStream{stream} => op::op_output_stream(stream),
+            &ZOP::Save{ref result} => op::op_save(result),
+            &ZOP::Restore{ref result} => op::op_restore(result),
+            &ZOP::Restart => op::op_restart(),
+            &ZOP::SaveUndo{ref result} => op::op_save_undo(result),
+            &ZOP::SplitWindow{ref lines} => op::op_split_window(lines),
+            &ZOP::SetWindow{window} => op::op_set_window(window),
+            &ZOP::SetCursor{ref line, ref column} => op::op_set_cursor(line, column),
+            &ZOP::GetCursor{ref array_address} => op::op_get_cursor(array_address),
+            &ZOP::ReadMouse{ref array_address} => op::op_read_mouse(array_address),
+            &ZOP::MouseWindow{ref window} => op::op_mouse_window(window),
+            &ZOP::RestoreUndo{ref result} => op::op_restore_undo(result),
 
             _ => Vec::new()
         };
         self.data.append_bytes(&bytes);
         match instr {
+            _ if lowered => (),
             &ZOP::PrintUnicode{c} => self.op_print_unicode_char(c),
             &ZOP::PrintUnicodeVar{ref var} => self.op_print_unicode_var(var),
             &ZOP::PrintUnicodeStr{ref address} => self.op_print_unicode_str(address),
@@ -518,6 +818,7 @@ impl Zfile {
             &ZOP::Call2NWithArg{ref jump_to_label, ref arg} => self.op_call_2n_with_arg(jump_to_label, arg),
             &ZOP::Call1N{ref jump_to_label} => self.op_call_1n(jump_to_label),
             &ZOP::Call2S{ref jump_to_label, ref arg, ref result} => self.op_call_2s(jump_to_label, arg, result),
+            &ZOP::StoreRoutineAddress{ref variable, ref routine} => self.op_store_routine_address(variable, routine),
             &ZOP::CallVNA2{ref jump_to_label, ref arg1, ref arg2} => self.op_call_vn_a2(jump_to_label, arg1, arg2),
             &ZOP::CallVNA3{ref jump_to_label, ref arg1, ref arg2, ref arg3} => self.op_call_vn_a3(jump_to_label, arg1, arg2, arg3),
             &ZOP::CallVSA2{ref jump_to_label, ref arg1, ref arg2, ref result} => self.op_call_vs_a2(jump_to_label, arg1, arg2, result),
@@ -539,6 +840,199 @@ impl Zfile {
         (new_labels, new_jumps, self.data.bytes[beginning..self.data.bytes.len()].to_vec())
     }
 
+    /// writes read_mouse and mouse_window, which only exist in version 6,
+    /// returns false for all other ops
+    ///
+    /// The other versions write the coordinates of a click to the header
+    /// extension table, read_mouse copies them to the words 0 (y) and 1 (x)
+    /// of the array, the buttons and the menu are left alone. There is no
+    /// mouse window, so mouse_window is left out.
+    fn emulate_mouse_zop(&mut self, instr: &ZOP) -> bool {
+        match instr {
+            &ZOP::ReadMouse{ref array_address} => {
+                let extension_addr = Operand::new_large_const(self.extension_addr as i16);
+                let stack = Variable::new(0);
+                for &(from, to) in [(2, 0), (1, 1)].iter() {
+                    // the index of storew is popped before the value
+                    self.data.append_bytes(&op::op_add(&Operand::new_const(from), &Operand::new_const(0), &stack));
+                    self.data.append_bytes(&op::op_loadw(&extension_addr, &stack, &stack));
+                    self.data.append_bytes(&op::op_add(&Operand::new_const(to), &Operand::new_const(0), &stack));
+                    self.data.append_bytes(&op::op_storew(array_address, &stack, &stack));
+                }
+            },
+            &ZOP::MouseWindow{..} => (),
+            _ => return false
+        }
+
+        true
+    }
+
+    /// writes the ops that don't exist before version 5 with the opcodes of
+    /// versions 3 and 4, returns false if the op exists in this version
+    ///
+    /// Colours, text styles and erasing windows only change the look of the
+    /// story, so they are left out with a warning. Undo, the mouse and the
+    /// cursor menu are not available, the frontend reports their use as error.
+    fn lower_zop(&mut self, instr: &ZOP) -> bool {
+        let version = self.version;
+        match instr {
+            // call_vs is the only call opcode of version 3
+            &ZOP::Call1N{ref jump_to_label} =>
+                self.op_call_vs(CallOperand::Label(jump_to_label.clone()), vec![], None),
+            &ZOP::Call1NVar{variable} =>
+                self.op_call_vs(CallOperand::Operand(Operand::new_var(variable)), vec![], None),
+            &ZOP::Call2NWithAddress{ref jump_to_label, ref address} =>
+                self.op_call_vs(CallOperand::Label(jump_to_label.clone()), vec![CallOperand::Label(address.clone())], None),
+            &ZOP::Call2NWithArg{ref jump_to_label, ref arg} =>
+                self.op_call_vs(CallOperand::Label(jump_to_label.clone()), vec![CallOperand::Operand(arg.clone())], None),
+            &ZOP::CallVNA2{ref jump_to_label, ref arg1, ref arg2} =>
+                self.op_call_vs(CallOperand::Label(jump_to_label.clone()),
+                    vec![CallOperand::Operand(arg1.clone()), CallOperand::Operand(arg2.clone())], None),
+            &ZOP::CallVNA3{ref jump_to_label, ref arg1, ref arg2, ref arg3} =>
+                self.op_call_vs(CallOperand::Label(jump_to_label.clone()),
+                    vec![CallOperand::Operand(arg1.clone()), CallOperand::Operand(arg2.clone()), CallOperand::Operand(arg3.clone())], None),
+            &ZOP::Call2S{ref jump_to_label, ref arg, ref result} if version == 3 =>
+                self.op_call_vs(CallOperand::Label(jump_to_label.clone()), vec![CallOperand::Operand(arg.clone())], Some(result)),
+
+            // read_char is emulated with a line input in version 3
+            &ZOP::ReadChar{local_var_id} if version == 3 =>
+                self.op_call_vs(CallOperand::Label("system_read_char".to_string()), vec![], Some(&Variable::new(local_var_id))),
+
+            // sread has no result, the input always ends with enter
+            &ZOP::Aread{ref text_buffer, ref parse_buffer, ref result} => {
+                self.data.append_bytes(&op::op_sread(text_buffer, parse_buffer));
+                self.data.append_bytes(&op::op_store_var(result, &Operand::new_const(13)));
+            },
+
+            // print_char prints zscii, so unicode is limited to the default characters
+            &ZOP::PrintUnicode{c} => {
+                let zscii: u8 = if c <= 126 {
+                    c as u8
+                } else {
+                    match ztext::pos_in_unicode(c, &self.unicode_table) {
+                        -1 => {
+                            let character = char::from_u32(c as u32).unwrap_or('?');
+                            self.warn(format!("The character '{}' is not in the unicode table of version {}, it is printed as ?", character, version));
+                            '?' as u8
+                        },
+                        index => 155 + index as u8,
+                    }
+                };
+                self.data.append_bytes(&op::op_print_char(&Operand::new_const(zscii)));
+            },
+            &ZOP::PrintUnicodeVar{ref var} =>
+                self.data.append_bytes(&op::op_print_char(&Operand::Var(var.clone()))),
+
+            &ZOP::Save{ref result} => self.op_save_restore(0x05, result),
+            &ZOP::Restore{ref result} => self.op_save_restore(0x06, result),
+
+            // without undo there is nothing to restore
+            &ZOP::SaveUndo{ref result} | &ZOP::RestoreUndo{ref result} =>
+                self.data.append_bytes(&op::op_or(&Operand::new_const(0), &Operand::new_const(0), result)),
+
+            &ZOP::SetColor{..} | &ZOP::SetColorVar{..} =>
+                self.warn(format!("Version {} of the Z-Machine has no colours, they are left out", version)),
+            &ZOP::SetTextStyle{..} if version == 3 =>
+                self.warn("Version 3 of the Z-Machine has no text styles, they are left out".to_string()),
+            &ZOP::EraseWindow{..} if version == 3 =>
+                self.warn("Version 3 of the Z-Machine can't erase the screen, the passages follow each other".to_string()),
+            &ZOP::SetCursor{..} | &ZOP::GetCursor{..} if version == 3 =>
+                self.warn("Version 3 of the Z-Machine can't move the cursor, it is left out".to_string()),
+            &ZOP::ReadCharTimer{..} if version == 3 =>
+                self.warn("Version 3 of the Z-Machine has no timed input, the timer is left out".to_string()),
+            _ => return false
+        }
+
+        true
+    }
+
+    /// keeps a warning about a left out feature, every warning only once
+    fn warn(&mut self, warning: String) {
+        if !self.warnings.contains(&warning) {
+            self.warnings.push(warning);
+        }
+    }
+
+    /// calls a routine with call_vs, without a result the return value is
+    /// pushed to the stack and thrown away
+    fn op_call_vs(&mut self, routine: CallOperand, args: Vec<CallOperand>, result: Option<&Variable>) {
+        let mut operands: Vec<CallOperand> = vec![routine];
+        operands.extend(args.into_iter());
+
+        let mut arg_types: Vec<ArgType> = vec![];
+        for operand in operands.iter() {
+            arg_types.push(match operand {
+                &CallOperand::Label(_) => ArgType::LargeConst,
+                &CallOperand::Operand(ref operand) => op::arg_type(operand),
+            });
+        }
+        while arg_types.len() < 4 {
+            arg_types.push(ArgType::Nothing);
+        }
+        self.op_var(0x00, arg_types);
+
+        for operand in operands.iter() {
+            match operand {
+                &CallOperand::Label(ref label) => self.add_jump(label.clone(), JumpType::Routine),
+                &CallOperand::Operand(ref operand) => op::write_argument(operand, &mut self.data.bytes),
+            }
+        }
+
+        match result {
+            Some(variable) => self.data.append_byte(variable.id),
+            None => {
+                self.data.append_byte(0x00);
+                self.data.append_bytes(&op::op_pop());
+            }
+        }
+    }
+
+    /// save (0x05) and restore (0x06) of version 3 and 4
+    ///
+    /// Version 4 stores the result, version 3 branches on success. As in
+    /// version 5 the result is 0 on failure, 1 after saving and 2 after a
+    /// restore. The save of version 3 succeeds after a restore as well, so
+    /// restore sets the fixed-pitch bit of flags 2 before: the interpreter
+    /// keeps this bit of the header when it restores the memory.
+    fn op_save_restore(&mut self, opcode: u8, result: &Variable) {
+        if self.version == 4 {
+            self.op_0(opcode);
+            self.data.append_byte(result.id);
+            return;
+        }
+
+        let failed = op::op_or(&Operand::new_const(0), &Operand::new_const(0), result);
+
+        if opcode == 0x06 {
+            self.data.append_bytes(&fixed_pitch_bit(true));
+            self.op_0(opcode);
+
+            // a successful restore doesn't return, so the branch (with offset 2)
+            // continues in any case with the failure
+            self.data.append_byte(0x80 | 0x40 | 2);
+            self.data.append_bytes(&fixed_pitch_bit(false));
+            self.data.append_bytes(&failed);
+        } else {
+            // the result is 1 plus the bit (2 or 0) divided by 2, then the
+            // bit is cleared
+            let stack = Variable::new(0);
+            let mut succeeded = op::op_add(&Operand::new_const(0x11), &Operand::new_const(0), &stack);
+            succeeded.extend(op::op_loadb(&Operand::new_const(0), &stack, &stack).into_iter());
+            succeeded.extend(op::op_and(&Operand::new_var(0), &Operand::new_const(2), &stack).into_iter());
+            succeeded.extend(op::op_div(&Operand::new_var(0), &Operand::new_const(2), &stack).into_iter());
+            succeeded.extend(op::op_add(&Operand::new_var(0), &Operand::new_const(1), result).into_iter());
+            succeeded.extend(fixed_pitch_bit(false).into_iter());
+
+            // on success the branch skips the failure and the jump over the success
+            self.op_0(opcode);
+            self.data.append_byte(0x80 | 0x40 | (failed.len() as u8 + 3 + 2));
+            self.data.append_bytes(&failed);
+            self.data.append_bytes(&op::op_1(0x0c, ArgType::LargeConst));
+            self.data.append_u16(succeeded.len() as u16 + 2);
+            self.data.append_bytes(&succeeded);
+        }
+    }
+
     /// generates normal print opcodes for ASCII characters and unicode print
     /// opcodes for unicode characters
     pub fn gen_print_ops(&mut self, text: &str) {
@@ -562,7 +1056,7 @@ impl Zfile {
                     current_utf16.clear();
                     // unicode exist in table
                     current_text.push(character);
-                } else if self.force_unicode == false && self.unicode_table.len() < 96 {
+                } else if self.force_unicode == false && self.version >= 5 && self.unicode_table.len() < 96 {
                     self.gen_write_out_unicode(current_utf16.to_string());  // write out utf16 string
                     current_utf16.clear();
                     // there is space in the unicode table
@@ -634,14 +1128,71 @@ impl Zfile {
     pub fn start(&mut self) {
         self.create_header();
         self.data.write_zero_until(self.program_addr as usize);
+        self.write_dictionary();
+        if self.version == 3 {
+            self.write_status_objects();
+            self.write_read_buffers();
+        }
+        self.write_text_input_buffers();
+        self.write_ifid();
+        self.write_title_and_author();
 
         // default theme and erase_window to fore the color
         self.emit(vec![
             ZOP::SetColor{foreground: 9, background: 2},
             ZOP::EraseWindow{value: -1},
             ZOP::Call1N{jump_to_label: "malloc_init".to_string()},
-            ZOP::Call1N{jump_to_label: "Start".to_string()},
+            ZOP::Call1N{jump_to_label: "system_visits_init".to_string()},
+        ]);
+
+        if self.mouse {
+            // the links are clicked in the lower window, the cursor menu is
+            // in the upper one
+            let window: u8 = if self.link_selection == LinkSelection::Cursor { 1 } else { 0 };
+            self.emit(vec![
+                ZOP::MouseWindow{window: Operand::new_const(window)},
+            ]);
+        }
+
+        // banner with the title and the author
+        let mut banner: Vec<ZOP> = vec![];
+        if let Some(title) = self.story.title.clone() {
+            banner.push(ZOP::SetTextStyle{bold: true, reverse: false, monospace: false, italic: false});
+            banner.push(ZOP::Print{text: title});
+            banner.push(ZOP::SetTextStyle{bold: false, reverse: false, monospace: false, italic: false});
+            banner.push(ZOP::Newline);
+        }
+        if let Some(author) = self.story.author.clone() {
+            banner.push(ZOP::Print{text: format!("by {}", author)});
+            banner.push(ZOP::Newline);
+        }
+        if !banner.is_empty() {
+            banner.push(ZOP::Newline);
+            self.emit(banner);
+        }
+
+        if self.story.init {
+            // the output of StoryInit is discarded like in <<silently>>
+            self.emit(vec![
+                ZOP::Inc{variable: 18},
+                ZOP::OutputStream{stream: -1},
+                ZOP::Call1N{jump_to_label: "StoryInit".to_string()},
+                ZOP::Dec{variable: 18},
+                ZOP::OutputStream{stream: 1},
+
+                // the links of StoryInit can't be chosen
+                ZOP::StoreVariable{variable: Variable::new(16), value: Operand::new_const(0)},
+            ]);
+        }
+
+        // global 24 counts the turns, the start passage is the first one and
+        // every passage chosen in the mainloop the next one
+        let start = self.story.start.clone();
+        self.emit(vec![
+            ZOP::Inc{variable: 24},
+            ZOP::Call1N{jump_to_label: start},
             ZOP::Label{name: "mainloop".to_string()},
+            ZOP::Inc{variable: 24},
             ZOP::Call1N{jump_to_label: "system_check_links".to_string()},
             ZOP::Jump{jump_to_label: "mainloop".to_string()},
         ]);
@@ -650,30 +1201,66 @@ impl Zfile {
     /// writes all stuff that couldn't written directly
     /// should be called as the last commend
     pub fn end(&mut self) {
+        // older versions use the default table, it is only written for
+        // system_read_line
         self.write_unicode_table();
         self.routine_check_links();
         self.routine_add_link();
+        self.routine_print_line();
+        self.routine_indent();
         self.routine_check_more();
         self.routine_print_unicode();
         self.routine_mem_free();
         self.routine_malloc_init();
         self.routine_strcpy();
+        self.routine_strcmp();
+        self.routine_has_tag();
         self.routine_malloc();
         self.routine_strcat();
         self.routine_itoa();
+        self.routine_array();
+        self.routine_fixed();
+        self.routine_read_line();
+        if self.version == 3 {
+            self.routine_read_char();
+        }
         self.write_jumps();
         self.write_strings();
+        self.write_file_length();
+    }
+
+    /// pads the file to a multiple of the packing factor and writes its
+    /// length and checksum to the header
+    fn write_file_length(&mut self) {
+        let packing = packing_factor(self.version);
+        let length = align_address(self.data.len() as u32, packing);
+        self.data.write_zero_until(length as usize);
+        self.data.write_u16((length / packing) as u16, 0x1a);
+
+        // the checksum is the sum of all bytes after the header
+        let mut checksum: u32 = 0;
+        for byte in self.data.bytes[0x40..].iter() {
+            checksum += *byte as u32;
+        }
+        self.data.write_u16((checksum & 0xffff) as u16, 0x1c);
     }
 
     /// command to create a routine
     pub fn routine(&mut self, name: &str, count_variables: u8) {    
-        let index: u32 = routine_address(self.data.bytes.len() as u32);
+        let index: u32 = routine_address(self.data.bytes.len() as u32, self.version);
         
         assert!(count_variables <= 15, "only 15 local variables are allowed");
-        assert!(index % 8 == 0, "adress of a routine must start at address % 8 == 0");
+        assert!(index % packing_factor(self.version) == 0, "adress of a routine must be a multiple of the packing factor");
 
         self.add_label(name.to_string(), index);
         self.data.write_byte(count_variables, index as usize);
+
+        // up to version 4 the initial values of the locals follow
+        if self.version < 5 {
+            for _ in 0..count_variables {
+                self.data.append_u16(0);
+            }
+        }
     }
 
     /// command to create a label
@@ -689,10 +1276,44 @@ impl Zfile {
     pub fn routine_add_link(&mut self) {
         let save_at_addr: u16 = 1 + self.object_addr;
         self.emit(vec![
-            ZOP::Routine{name: "system_add_link".to_string(), count_variables: 1},
+            ZOP::Routine{name: "system_add_link".to_string(), count_variables: 3},
+            // further links are left out, the arrays are full
+            ZOP::JL{operand1: Operand::new_var(16), operand2: Operand::new_const(MAX_LINKS as u8), jump_to_label: "system_add_link_store".to_string()},
+        ]);
+
+        if self.track_scrolling {
+            // the position of a link that was left out is not stored
+            let added_addr = self.link_added_addr();
+            self.emit(vec![
+                ZOP::StoreVariable{variable: Variable::new(1), value: Operand::new_const(0)},
+                ZOP::StoreW{array_address: Operand::new_large_const(added_addr as i16), index: Variable::new(1), variable: Variable::new(1)},
+                ZOP::Ret{value: Operand::new_const(0)},
+                ZOP::Label{name: "system_add_link_store".to_string()},
+                ZOP::StoreVariable{variable: Variable::new(2), value: Operand::new_const(0)},
+                ZOP::StoreVariable{variable: Variable::new(3), value: Operand::new_const(1)},
+                ZOP::StoreW{array_address: Operand::new_large_const(added_addr as i16), index: Variable::new(2), variable: Variable::new(3)},
+            ]);
+        } else {
+            self.emit(vec![
+                ZOP::Ret{value: Operand::new_const(0)},
+                ZOP::Label{name: "system_add_link_store".to_string()},
+            ]);
+        }
+
+        self.emit(vec![
             // saves routine-argument to array
             ZOP::StoreW{array_address: Operand::new_large_const(save_at_addr as i16), index: Variable::new(16), variable: Variable::new(1)},
+        ]);
 
+        if self.link_selection == LinkSelection::Cursor {
+            // the menu needs the text of the link, which is stored in global 19
+            let text_addr = self.link_text_addr();
+            self.emit(vec![
+                ZOP::StoreW{array_address: Operand::new_large_const(text_addr as i16), index: Variable::new(16), variable: Variable::new(19)},
+            ]);
+        }
+
+        self.emit(vec![
             // inc the count links
             ZOP::Inc{variable: 16},
 
@@ -700,6 +1321,274 @@ impl Zfile {
         ]);
     }
 
+    /// address of the array with the texts of the links for the cursor menu,
+    /// behind the array of the link addresses (with room for 128 links)
+    fn link_text_addr(&self) -> u16 {
+        1 + self.object_addr + 256
+    }
+
+    /// address of the array with the screen positions of the links, two words
+    /// (start and end as row * 256 + column) for each link
+    fn link_position_addr(&self) -> u16 {
+        self.link_text_addr() + 256
+    }
+
+    /// address of the two words written by get_cursor, which are also the
+    /// first two of the four words written by read_mouse
+    fn cursor_buffer_addr(&self) -> u16 {
+        self.link_position_addr() + 512
+    }
+
+    /// address of the word that is 1 if the last link was added and 0 if the
+    /// array of the links was full, so its position is not stored
+    fn link_added_addr(&self) -> u16 {
+        self.cursor_buffer_addr() + 8
+    }
+
+    /// address of the word with the column of the current line in an indent
+    /// block, counted by system_indent_start and system_indent_word
+    fn indent_column_addr(&self) -> u16 {
+        self.link_added_addr() + 2
+    }
+
+    /// address of the text buffer (22 bytes) of the line input in version 3,
+    /// followed by the parse buffer (6 bytes) and the read position (1 word)
+    fn read_buffer_addr(&self) -> u16 {
+        self.indent_column_addr() + 2
+    }
+
+    /// address of the text buffer (84 bytes) of <<textinput>> and prompt(),
+    /// followed by the parse buffer (6 bytes) that versions 3 and 4 need
+    fn text_input_addr(&self) -> u16 {
+        self.read_buffer_addr() + 30
+    }
+
+    /// stores the cursor position as the start (argument 0) or the end
+    /// (argument 1) of the last added link, nothing is stored for a link that
+    /// was left out because there were more than MAX_LINKS
+    pub fn routine_link_position(&mut self) {
+        let buffer_addr = self.cursor_buffer_addr();
+        let added_addr = self.link_added_addr();
+        let position_addr = self.link_position_addr();
+        self.emit(vec![
+            ZOP::Routine{name: "system_link_position".to_string(), count_variables: 4},
+            ZOP::LoadW{array_address: Operand::new_large_const(added_addr as i16), index: Variable::new(4), variable: Variable::new(2)},
+            ZOP::JNE{operand1: Operand::new_var(2), operand2: Operand::new_const(0), jump_to_label: "system_link_position_added".to_string()},
+            ZOP::Ret{value: Operand::new_const(0)},
+            ZOP::Label{name: "system_link_position_added".to_string()},
+            ZOP::GetCursor{array_address: Operand::new_large_const(buffer_addr as i16)},
+
+            // local 2 is the row and local 3 the column
+            ZOP::LoadW{array_address: Operand::new_large_const(buffer_addr as i16), index: Variable::new(4), variable: Variable::new(2)},
+            ZOP::Inc{variable: 4},
+            ZOP::LoadW{array_address: Operand::new_large_const(buffer_addr as i16), index: Variable::new(4), variable: Variable::new(3)},
+            ZOP::Mul{operand1: Operand::new_var(2), operand2: Operand::new_large_const(256), save_variable: Variable::new(2)},
+            ZOP::Add{operand1: Operand::new_var(2), operand2: Operand::new_var(3), save_variable: Variable::new(2)},
+
+            // the link was already counted, so its index is global 16 - 1
+            ZOP::Sub{operand1: Operand::new_var(16), operand2: Operand::new_const(1), save_variable: Variable::new(3)},
+            ZOP::Mul{operand1: Operand::new_var(3), operand2: Operand::new_const(2), save_variable: Variable::new(3)},
+            ZOP::Add{operand1: Operand::new_var(3), operand2: Operand::new_var(1), save_variable: Variable::new(3)},
+            ZOP::StoreW{array_address: Operand::new_large_const(position_addr as i16), index: Variable::new(3), variable: Variable::new(2)},
+            ZOP::Ret{value: Operand::new_const(0)}
+        ]);
+    }
+
+    /// returns the number of the link under the last mouse click or 0
+    ///
+    /// The click is read with read_char (key 254 or 253) and its coordinates
+    /// with read_mouse, which the versions before 6 emulate with the header
+    /// extension table. They are converted from screen units to characters
+    /// with the font size in the header.
+    pub fn routine_link_at_mouse(&mut self) {
+        let buffer_addr = self.cursor_buffer_addr();
+        let position_addr = self.link_position_addr();
+        self.emit(vec![
+            ZOP::Routine{name: "system_link_at_mouse".to_string(), count_variables: 6},
+            ZOP::ReadMouse{array_address: Operand::new_large_const(buffer_addr as i16)},
+
+            // local 1 is the column: (x - 1) / font width + 1
+            ZOP::StoreVariable{variable: Variable::new(2), value: Operand::new_const(0x26)},
+            ZOP::LoadB{array_address: Operand::new_const(0), index: Variable::new(2), variable: Variable::new(2)},
+            ZOP::JNE{operand1: Operand::new_var(2), operand2: Operand::new_const(0), jump_to_label: "system_link_at_mouse_width".to_string()},
+            ZOP::StoreVariable{variable: Variable::new(2), value: Operand::new_const(1)},
+            ZOP::Label{name: "system_link_at_mouse_width".to_string()},
+            ZOP::StoreVariable{variable: Variable::new(3), value: Operand::new_const(1)},
+            ZOP::LoadW{array_address: Operand::new_large_const(buffer_addr as i16), index: Variable::new(3), variable: Variable::new(1)},
+            ZOP::Sub{operand1: Operand::new_var(1), operand2: Operand::new_const(1), save_variable: Variable::new(1)},
+            ZOP::Div{operand1: Operand::new_var(1), operand2: Operand::new_var(2), save_variable: Variable::new(1)},
+            ZOP::Inc{variable: 1},
+
+            // local 4 is the row: (y - 1) / font height + 1
+            ZOP::StoreVariable{variable: Variable::new(2), value: Operand::new_const(0x27)},
+            ZOP::LoadB{array_address: Operand::new_const(0), index: Variable::new(2), variable: Variable::new(2)},
+            ZOP::JNE{operand1: Operand::new_var(2), operand2: Operand::new_const(0), jump_to_label: "system_link_at_mouse_height".to_string()},
+            ZOP::StoreVariable{variable: Variable::new(2), value: Operand::new_const(1)},
+            ZOP::Label{name: "system_link_at_mouse_height".to_string()},
+            ZOP::Dec{variable: 3},
+            ZOP::LoadW{array_address: Operand::new_large_const(buffer_addr as i16), index: Variable::new(3), variable: Variable::new(4)},
+            ZOP::Sub{operand1: Operand::new_var(4), operand2: Operand::new_const(1), save_variable: Variable::new(4)},
+            ZOP::Div{operand1: Operand::new_var(4), operand2: Operand::new_var(2), save_variable: Variable::new(4)},
+            ZOP::Inc{variable: 4},
+        ]);
+
+        if self.link_selection == LinkSelection::Cursor {
+            // the links are the lines of the menu in the upper window
+            self.emit(vec![
+                ZOP::JG{operand1: Operand::new_var(4), operand2: Operand::new_var(16), jump_to_label: "system_link_at_mouse_none".to_string()},
+                ZOP::Ret{value: Operand::new_var(4)},
+            ]);
+        }
+
+        self.emit(vec![
+            // compares the click (row * 256 + column) with the positions of the links
+            ZOP::Mul{operand1: Operand::new_var(4), operand2: Operand::new_large_const(256), save_variable: Variable::new(4)},
+            ZOP::Add{operand1: Operand::new_var(4), operand2: Operand::new_var(1), save_variable: Variable::new(1)},
+            ZOP::StoreVariable{variable: Variable::new(3), value: Operand::new_const(0)},
+
+            ZOP::Label{name: "system_link_at_mouse_loop".to_string()},
+            ZOP::JE{operand1: Operand::new_var(3), operand2: Operand::new_var(16), jump_to_label: "system_link_at_mouse_none".to_string()},
+            ZOP::Mul{operand1: Operand::new_var(3), operand2: Operand::new_const(2), save_variable: Variable::new(6)},
+            ZOP::LoadW{array_address: Operand::new_large_const(position_addr as i16), index: Variable::new(6), variable: Variable::new(4)},
+            ZOP::Inc{variable: 6},
+            ZOP::LoadW{array_address: Operand::new_large_const(position_addr as i16), index: Variable::new(6), variable: Variable::new(5)},
+            ZOP::Inc{variable: 3},
+            ZOP::JL{operand1: Operand::new_var(1), operand2: Operand::new_var(4), jump_to_label: "system_link_at_mouse_loop".to_string()},
+            ZOP::JL{operand1: Operand::new_var(1), operand2: Operand::new_var(5), jump_to_label: "system_link_at_mouse_found".to_string()},
+            ZOP::Jump{jump_to_label: "system_link_at_mouse_loop".to_string()},
+
+            ZOP::Label{name: "system_link_at_mouse_found".to_string()},
+            ZOP::Ret{value: Operand::new_var(3)},
+
+            ZOP::Label{name: "system_link_at_mouse_none".to_string()},
+            ZOP::Ret{value: Operand::new_const(0)}
+        ]);
+    }
+
+    /// prints a newline, the links move one row up if the screen scrolls
+    ///
+    /// The screen scrolls when the cursor is in the last row, the height is
+    /// read from header byte 0x20 (screen height in lines, 255 is infinite).
+    /// The interpreter scrolls on its own when it wraps a line in the last
+    /// row, these rows are not noticed.
+    pub fn routine_newline(&mut self) {
+        let buffer_addr = self.cursor_buffer_addr();
+        let position_addr = self.link_position_addr();
+
+        // the newline of this routine is printed as it is
+        self.track_scrolling = false;
+        self.emit(vec![
+            ZOP::Routine{name: "system_newline".to_string(), count_variables: 3},
+
+            // nothing is shown inside <<silently>>
+            ZOP::JNE{operand1: Operand::new_var(18), operand2: Operand::new_const(0), jump_to_label: "system_newline_print".to_string()},
+
+            // local 1 is the row of the cursor and local 2 the height
+            ZOP::GetCursor{array_address: Operand::new_large_const(buffer_addr as i16)},
+            ZOP::LoadW{array_address: Operand::new_large_const(buffer_addr as i16), index: Variable::new(1), variable: Variable::new(1)},
+            ZOP::StoreVariable{variable: Variable::new(2), value: Operand::new_const(0x20)},
+            ZOP::LoadB{array_address: Operand::new_const(0), index: Variable::new(2), variable: Variable::new(2)},
+            ZOP::JE{operand1: Operand::new_var(2), operand2: Operand::new_const(0), jump_to_label: "system_newline_print".to_string()},
+            ZOP::JE{operand1: Operand::new_var(2), operand2: Operand::new_const(255), jump_to_label: "system_newline_print".to_string()},
+            ZOP::JL{operand1: Operand::new_var(1), operand2: Operand::new_var(2), jump_to_label: "system_newline_print".to_string()},
+
+            // the screen scrolls, every position (row * 256 + column) of the
+            // links moves one row up, the ones that left the screen are negative
+            ZOP::StoreVariable{variable: Variable::new(1), value: Operand::new_const(0)},
+            ZOP::Mul{operand1: Operand::new_var(16), operand2: Operand::new_const(2), save_variable: Variable::new(2)},
+            ZOP::Label{name: "system_newline_loop".to_string()},
+            ZOP::JE{operand1: Operand::new_var(1), operand2: Operand::new_var(2), jump_to_label: "system_newline_print".to_string()},
+            ZOP::LoadW{array_address: Operand::new_large_const(position_addr as i16), index: Variable::new(1), variable: Variable::new(3)},
+            ZOP::JL{operand1: Operand::new_var(3), operand2: Operand::new_const(0), jump_to_label: "system_newline_next".to_string()},
+            ZOP::Sub{operand1: Operand::new_var(3), operand2: Operand::new_large_const(256), save_variable: Variable::new(3)},
+            ZOP::StoreW{array_address: Operand::new_large_const(position_addr as i16), index: Variable::new(1), variable: Variable::new(3)},
+            ZOP::Label{name: "system_newline_next".to_string()},
+            ZOP::Inc{variable: 1},
+            ZOP::Jump{jump_to_label: "system_newline_loop".to_string()},
+
+            ZOP::Label{name: "system_newline_print".to_string()},
+            ZOP::Newline,
+            ZOP::Ret{value: Operand::new_const(0)}
+        ]);
+        self.track_scrolling = true;
+    }
+
+    /// prints a horizontal line over the whole width of the screen
+    /// the width is read from header byte 0x21 (screen width in characters)
+    pub fn routine_print_line(&mut self) {
+        self.emit(vec![
+            ZOP::Routine{name: "system_print_line".to_string(), count_variables: 2},
+            ZOP::StoreVariable{variable: Variable::new(1), value: Operand::new_const(0x21)},
+            ZOP::LoadB{array_address: Operand::new_const(0), index: Variable::new(1), variable: Variable::new(2)},
+            ZOP::StoreVariable{variable: Variable::new(1), value: Operand::new_const(0)},
+
+            // some interpreters don't set the screen width
+            ZOP::JNE{operand1: Operand::new_var(2), operand2: Operand::new_const(0), jump_to_label: "system_print_line_width".to_string()},
+            ZOP::StoreVariable{variable: Variable::new(2), value: Operand::new_const(21)},
+
+            // a character in the last column wraps the line, the newline
+            // would then leave an empty line
+            ZOP::Label{name: "system_print_line_width".to_string()},
+            ZOP::Dec{variable: 2},
+
+            ZOP::Label{name: "system_print_line_loop".to_string()},
+            ZOP::Print{text: "-".to_string()},
+            ZOP::Inc{variable: 1},
+            ZOP::JL{operand1: Operand::new_var(1), operand2: Operand::new_var(2), jump_to_label: "system_print_line_loop".to_string()},
+            ZOP::Newline,
+            ZOP::Ret{value: Operand::new_const(0)}
+        ]);
+    }
+
+    /// indents the lines of indent blocks (`<<<`)
+    ///
+    /// The Z-Machine has no margins, so the words of an indent block are
+    /// wrapped here instead of by the interpreter: system_indent_start
+    /// indents a new line, system_indent_word(spaces, length) prints the
+    /// spaces before a word of the length or starts a new indented line
+    /// if the word doesn't fit in it anymore. Like the horizontal line the
+    /// words stay out of the last column, which would wrap the line.
+    pub fn routine_indent(&mut self) {
+        let column_addr = self.indent_column_addr();
+        self.emit(vec![
+            ZOP::Routine{name: "system_indent_start".to_string(), count_variables: 2},
+            ZOP::Print{text: "    ".to_string()},
+            ZOP::StoreVariable{variable: Variable::new(2), value: Operand::new_const(4)},
+            ZOP::StoreW{array_address: Operand::new_large_const(column_addr as i16), index: Variable::new(1), variable: Variable::new(2)},
+            ZOP::Ret{value: Operand::new_const(0)},
+
+            ZOP::Routine{name: "system_indent_word".to_string(), count_variables: 6},
+            ZOP::LoadW{array_address: Operand::new_large_const(column_addr as i16), index: Variable::new(5), variable: Variable::new(3)},
+            ZOP::StoreVariable{variable: Variable::new(4), value: Operand::new_const(0x21)},
+            ZOP::LoadB{array_address: Operand::new_const(0), index: Variable::new(4), variable: Variable::new(4)},
+            ZOP::Add{operand1: Operand::new_var(3), operand2: Operand::new_var(1), save_variable: Variable::new(6)},
+            ZOP::Add{operand1: Operand::new_var(6), operand2: Operand::new_var(2), save_variable: Variable::new(6)},
+
+            // words are not wrapped if the interpreter doesn't set the screen
+            // width or if only the indentation is in front of them
+            ZOP::JE{operand1: Operand::new_var(4), operand2: Operand::new_const(0), jump_to_label: "system_indent_word_fits".to_string()},
+            ZOP::JL{operand1: Operand::new_var(6), operand2: Operand::new_var(4), jump_to_label: "system_indent_word_fits".to_string()},
+            ZOP::JLE{operand1: Operand::new_var(3), operand2: Operand::new_const(4), jump_to_label: "system_indent_word_fits".to_string()},
+
+            // the word starts the next line, the spaces are left out
+            ZOP::Newline,
+            ZOP::Print{text: "    ".to_string()},
+            ZOP::Add{operand1: Operand::new_var(2), operand2: Operand::new_const(4), save_variable: Variable::new(6)},
+            ZOP::StoreW{array_address: Operand::new_large_const(column_addr as i16), index: Variable::new(5), variable: Variable::new(6)},
+            ZOP::Ret{value: Operand::new_const(0)},
+
+            ZOP::Label{name: "system_indent_word_fits".to_string()},
+            ZOP::StoreW{array_address: Operand::new_large_const(column_addr as i16), index: Variable::new(5), variable: Variable::new(6)},
+            ZOP::Label{name: "system_indent_word_spaces".to_string()},
+            ZOP::JE{operand1: Operand::new_var(1), operand2: Operand::new_const(0), jump_to_label: "system_indent_word_end".to_string()},
+            ZOP::Print{text: " ".to_string()},
+            ZOP::Dec{variable: 1},
+            ZOP::Jump{jump_to_label: "system_indent_word_spaces".to_string()},
+            ZOP::Label{name: "system_indent_word_end".to_string()},
+            ZOP::Ret{value: Operand::new_const(0)}
+        ]);
+    }
+
     /// exits the program
     /// quit is 0OP
     pub fn op_quit(&mut self) {
@@ -711,42 +1600,184 @@ impl Zfile {
     /// with the keyboard
     pub fn routine_check_links(&mut self) {
         let save_at_addr: u16 = 1 + self.object_addr;
+
+        // with letters as link keys the reserved keys are upper case
+        let letters = self.link_selection == LinkSelection::Letters;
+        let (save_key, restore_key, restart_key, undo_key) = if letters { (83, 82, 78, 85) } else { (115, 114, 110, 117) };
+        let mut reserved_keys = if letters {
+            "(S: save, R: restore, N: restart".to_string()
+        } else {
+            "(s: save, r: restore, n: restart".to_string()
+        };
+        if self.undo {
+            reserved_keys.push_str(if letters { ", U: undo" } else { ", u: undo" });
+        }
+        reserved_keys.push_str(")");
+
         self.emit(vec![
-            ZOP::Routine{name: "system_check_links".to_string(), count_variables: 2},
+            // local 1 is the key, local 2 the address of the passage and
+            // local 3 the typed number or the selected entry of the menu
+            ZOP::Routine{name: "system_check_links".to_string(), count_variables: 3},
 
             // jumps to the end, if this passage was called as <<display>>
             ZOP::JE{operand1: Operand::new_var(17), operand2: Operand::new_const(0x01), jump_to_label: "system_check_links_end_ret".to_string()},
+        ]);
+
+        if self.story.menu {
+            // the links of StoryMenu are added behind the links of the passage
+            self.emit(vec![
+                ZOP::Print{text: "--------------------".to_string()},
+                ZOP::Newline,
+                ZOP::Call1N{jump_to_label: "StoryMenu".to_string()},
+                ZOP::Newline,
+            ]);
+        }
 
+        self.emit(vec![
             // jumps to the end, if there a no links
             ZOP::JE{operand1: Operand::new_var(16), operand2: Operand::new_const(0x00), jump_to_label: "system_check_links_end_quit".to_string()},
-            ZOP::Print{text: "--------------------".to_string()},
-            ZOP::Newline,
+        ]);
+
+        if !self.story.menu {
+            self.emit(vec![
+                ZOP::Print{text: "--------------------".to_string()},
+                ZOP::Newline,
+            ]);
+        }
+
+        self.emit(vec![
+            ZOP::Label{name: "system_check_links_prompt".to_string()},
             ZOP::Print{text: "press a key... ".to_string()},
             ZOP::Newline,
+            ZOP::Print{text: reserved_keys},
+            ZOP::Newline,
+        ]);
+
+        if self.link_selection == LinkSelection::Cursor {
+            // the menu starts with the first link
+            self.emit(vec![
+                ZOP::StoreVariable{variable: Variable::new(0x03), value: Operand::new_const(1)},
+                ZOP::Label{name: "system_check_links_redraw".to_string()},
+                ZOP::Call2NWithArg{jump_to_label: "system_link_menu".to_string(), arg: Operand::new_var(0x03)},
+            ]);
+        } else {
+            self.emit(vec![
+                ZOP::Label{name: "system_check_links_redraw".to_string()},
+            ]);
+        }
 
+        self.emit(vec![
             ZOP::Label{name: "system_check_links_loop".to_string()},
             ZOP::ReadChar{local_var_id: 0x01},
-            ZOP::JE{operand1: Operand::new_var(0x01), operand2: Operand::new_const(129), jump_to_label: "system_check_links_jmp".to_string()},
-            ZOP::Jump{jump_to_label: "system_check_links_after".to_string()},
-            ZOP::Label{name: "system_check_links_jmp".to_string()},
-            ZOP::Call1N{jump_to_label: "system_check_more".to_string()},
 
-            ZOP::Label{name: "system_check_links_after".to_string()},
+            // reserved keys to save, restore and restart the game
+            ZOP::JE{operand1: Operand::new_var(0x01), operand2: Operand::new_const(save_key), jump_to_label: "system_check_links_save".to_string()},
+            ZOP::JE{operand1: Operand::new_var(0x01), operand2: Operand::new_const(restore_key), jump_to_label: "system_check_links_restore".to_string()},
+            ZOP::JE{operand1: Operand::new_var(0x01), operand2: Operand::new_const(restart_key), jump_to_label: "system_check_links_restart".to_string()},
+        ]);
 
-            ZOP::Sub{operand1: Operand::new_var(0x01), operand2: Operand::new_const(48), save_variable: Variable::new(0x01)},
+        if self.undo {
+            // undoes the last choice
+            self.emit(vec![
+                ZOP::JE{operand1: Operand::new_var(0x01), operand2: Operand::new_const(undo_key), jump_to_label: "system_check_links_undo".to_string()},
+            ]);
+        }
+
+        if self.mouse {
+            // a single (254) or double (253) click
+            self.emit(vec![
+                ZOP::JE{operand1: Operand::new_var(0x01), operand2: Operand::new_const(254), jump_to_label: "system_check_links_click".to_string()},
+                ZOP::JE{operand1: Operand::new_var(0x01), operand2: Operand::new_const(253), jump_to_label: "system_check_links_click".to_string()},
+            ]);
+        }
+
+        if self.link_selection != LinkSelection::Cursor {
+            // the arrow keys of the cursor menu are used by the easter egg
+            self.emit(vec![
+                ZOP::JE{operand1: Operand::new_var(0x01), operand2: Operand::new_const(129), jump_to_label: "system_check_links_jmp".to_string()},
+                ZOP::Jump{jump_to_label: "system_check_links_after".to_string()},
+                ZOP::Label{name: "system_check_links_jmp".to_string()},
+                ZOP::Call1N{jump_to_label: "system_check_more".to_string()},
+
+                ZOP::Label{name: "system_check_links_after".to_string()},
+            ]);
+        }
+
+        // the number of the selected link is stored in local 1
+        match self.link_selection {
+            LinkSelection::Digits => self.emit(vec![
+                // up to nine links are selected with a single key
+                ZOP::JL{operand1: Operand::new_var(16), operand2: Operand::new_const(10), jump_to_label: "system_check_links_single".to_string()},
+
+                // otherwise the digits are collected in local 3 until enter is pressed
+                ZOP::JE{operand1: Operand::new_var(0x01), operand2: Operand::new_const(13), jump_to_label: "system_check_links_enter".to_string()},
+                ZOP::Sub{operand1: Operand::new_var(0x01), operand2: Operand::new_const(48), save_variable: Variable::new(0x01)},
+                ZOP::JL{operand1: Operand::new_var(0x01), operand2: Operand::new_const(0), jump_to_label: "system_check_links_loop".to_string()},
+                ZOP::JG{operand1: Operand::new_var(0x01), operand2: Operand::new_const(9), jump_to_label: "system_check_links_loop".to_string()},
+                ZOP::PrintNumVar{variable: Variable::new(0x01)},
+                ZOP::Mul{operand1: Operand::new_var(0x03), operand2: Operand::new_const(10), save_variable: Variable::new(0x03)},
+                ZOP::Add{operand1: Operand::new_var(0x03), operand2: Operand::new_var(0x01), save_variable: Variable::new(0x03)},
+                ZOP::Jump{jump_to_label: "system_check_links_loop".to_string()},
+
+                ZOP::Label{name: "system_check_links_enter".to_string()},
+                ZOP::StoreVariable{variable: Variable::new(0x01), value: Operand::new_var(0x03)},
+                ZOP::StoreVariable{variable: Variable::new(0x03), value: Operand::new_const(0)},
+                ZOP::Newline,
+                ZOP::Jump{jump_to_label: "system_check_links_select".to_string()},
+
+                ZOP::Label{name: "system_check_links_single".to_string()},
+                ZOP::Sub{operand1: Operand::new_var(0x01), operand2: Operand::new_const(48), save_variable: Variable::new(0x01)},
+            ]),
+            LinkSelection::Letters => self.emit(vec![
+                // 'a' is the first link
+                ZOP::Sub{operand1: Operand::new_var(0x01), operand2: Operand::new_const(96), save_variable: Variable::new(0x01)},
+            ]),
+            LinkSelection::Cursor => self.emit(vec![
+                ZOP::JE{operand1: Operand::new_var(0x01), operand2: Operand::new_const(129), jump_to_label: "system_check_links_up".to_string()},
+                ZOP::JE{operand1: Operand::new_var(0x01), operand2: Operand::new_const(130), jump_to_label: "system_check_links_down".to_string()},
+                ZOP::JE{operand1: Operand::new_var(0x01), operand2: Operand::new_const(13), jump_to_label: "system_check_links_choose".to_string()},
+                ZOP::Jump{jump_to_label: "system_check_links_loop".to_string()},
+
+                ZOP::Label{name: "system_check_links_up".to_string()},
+                ZOP::JE{operand1: Operand::new_var(0x03), operand2: Operand::new_const(1), jump_to_label: "system_check_links_loop".to_string()},
+                ZOP::Dec{variable: 0x03},
+                ZOP::Jump{jump_to_label: "system_check_links_redraw".to_string()},
+
+                ZOP::Label{name: "system_check_links_down".to_string()},
+                ZOP::JE{operand1: Operand::new_var(0x03), operand2: Operand::new_var(16), jump_to_label: "system_check_links_loop".to_string()},
+                ZOP::Inc{variable: 0x03},
+                ZOP::Jump{jump_to_label: "system_check_links_redraw".to_string()},
+
+                ZOP::Label{name: "system_check_links_choose".to_string()},
+                ZOP::StoreVariable{variable: Variable::new(0x01), value: Operand::new_var(0x03)},
+            ]),
+        }
+
+        self.emit(vec![
+            ZOP::Label{name: "system_check_links_select".to_string()},
 
             // check if the link in 0x01 exist, if not
             // => "wrong key => jump before key-detection
             ZOP::JL{operand1: Operand::new_var(16), operand2: Operand::new_var(0x01), jump_to_label: "system_check_links_loop".to_string()},
 
-            // check if the key-48 is < 0, if it is => jump before key-detection
+            // check if the link number is < 1, if it is => jump before key-detection
             ZOP::StoreVariable{variable: Variable::new(0x02), value: Operand::new_const(1)},
             ZOP::JL{operand1: Operand::new_var(0x01), operand2: Operand::new_var(0x02), jump_to_label: "system_check_links_loop".to_string()},
             ZOP::Dec{variable: 0x01},
 
             // loads the address of the link from the array
             ZOP::LoadW{array_address: Operand::new_large_const(save_at_addr as i16), index: Variable::new(1), variable: Variable::new(2)},
+        ]);
 
+        if self.undo {
+            // snapshot before the jump, restore_undo continues here with the result 2
+            self.emit(vec![
+                ZOP::SaveUndo{result: Variable::new(0x01)},
+                ZOP::JE{operand1: Operand::new_var(0x01), operand2: Operand::new_const(2), jump_to_label: "system_check_links_undone".to_string()},
+            ]);
+        }
+
+        self.emit(vec![
             // no more links exist
             ZOP::StoreVariable{variable: Variable::new(16), value: Operand::new_const(0)},
             ZOP::Newline,
@@ -760,7 +1791,119 @@ impl Zfile {
             ZOP::Ret{value: Operand::new_const(0)},
 
             ZOP::Label{name: "system_check_links_end_quit".to_string()},
-            ZOP::Quit
+            ZOP::Quit,
+
+            // save: 0 = failed, 1 = saved, 2 = the game was restored to this point
+            ZOP::Label{name: "system_check_links_save".to_string()},
+            ZOP::Save{result: Variable::new(0x02)},
+            ZOP::JE{operand1: Operand::new_var(0x02), operand2: Operand::new_const(0), jump_to_label: "system_check_links_save_failed".to_string()},
+            ZOP::JE{operand1: Operand::new_var(0x02), operand2: Operand::new_const(2), jump_to_label: "system_check_links_restored".to_string()},
+            ZOP::Print{text: "Game saved.".to_string()},
+            ZOP::Newline,
+            ZOP::Jump{jump_to_label: "system_check_links_loop".to_string()},
+            ZOP::Label{name: "system_check_links_save_failed".to_string()},
+            ZOP::Print{text: "Save failed.".to_string()},
+            ZOP::Newline,
+            ZOP::Jump{jump_to_label: "system_check_links_loop".to_string()},
+            ZOP::Label{name: "system_check_links_restored".to_string()},
+            ZOP::Print{text: "Game restored.".to_string()},
+            ZOP::Newline,
+            ZOP::Jump{jump_to_label: "system_check_links_redraw".to_string()},
+
+            // restore only returns if it failed
+            ZOP::Label{name: "system_check_links_restore".to_string()},
+            ZOP::Restore{result: Variable::new(0x02)},
+            ZOP::Print{text: "Restore failed.".to_string()},
+            ZOP::Newline,
+            ZOP::Jump{jump_to_label: "system_check_links_loop".to_string()},
+
+            ZOP::Label{name: "system_check_links_restart".to_string()},
+            ZOP::Restart
+        ]);
+
+        if self.undo {
+            self.emit(vec![
+                // restore_undo only returns if it failed
+                ZOP::Label{name: "system_check_links_undo".to_string()},
+                ZOP::RestoreUndo{result: Variable::new(0x01)},
+                ZOP::Print{text: "Nothing to undo.".to_string()},
+                ZOP::Newline,
+                ZOP::Jump{jump_to_label: "system_check_links_loop".to_string()},
+
+                // the screen is not restored, so the passage (global 23 is
+                // its name) is shown again and the changes it makes are
+                // undone with a second snapshot
+                ZOP::Label{name: "system_check_links_undone".to_string()},
+                ZOP::SaveUndo{result: Variable::new(0x01)},
+                ZOP::JNE{operand1: Operand::new_var(0x01), operand2: Operand::new_const(1), jump_to_label: "system_check_links_redrawn".to_string()},
+                ZOP::EraseWindow{value: -1},
+                ZOP::StoreVariable{variable: Variable::new(16), value: Operand::new_const(0)},
+                ZOP::Call2S{jump_to_label: "system_passage_address".to_string(), arg: Operand::new_var(23), result: Variable::new(0x02)},
+                ZOP::Call1NVar{variable: 0x02},
+                ZOP::Print{text: "--------------------".to_string()},
+                ZOP::Newline,
+            ]);
+            if self.story.menu {
+                self.emit(vec![
+                    ZOP::Call1N{jump_to_label: "StoryMenu".to_string()},
+                    ZOP::Newline,
+                ]);
+            }
+            self.emit(vec![
+                ZOP::RestoreUndo{result: Variable::new(0x01)},
+
+                ZOP::Label{name: "system_check_links_redrawn".to_string()},
+                ZOP::Print{text: "Previous choice undone.".to_string()},
+                ZOP::Newline,
+                ZOP::Jump{jump_to_label: "system_check_links_prompt".to_string()},
+            ]);
+        }
+
+        if self.mouse {
+            self.emit(vec![
+                ZOP::Label{name: "system_check_links_click".to_string()},
+                ZOP::Call2S{jump_to_label: "system_link_at_mouse".to_string(), arg: Operand::new_const(0), result: Variable::new(0x01)},
+                ZOP::JE{operand1: Operand::new_var(0x01), operand2: Operand::new_const(0), jump_to_label: "system_check_links_loop".to_string()},
+                ZOP::Jump{jump_to_label: "system_check_links_select".to_string()},
+            ]);
+            self.routine_link_at_mouse();
+            if self.track_scrolling {
+                self.routine_link_position();
+                self.routine_newline();
+            }
+        }
+
+        if self.link_selection == LinkSelection::Cursor {
+            self.routine_link_menu();
+        }
+    }
+
+    /// draws the links in the upper window, the selected link (argument 1)
+    /// is shown in reverse video
+    fn routine_link_menu(&mut self) {
+        let text_addr = self.link_text_addr();
+        self.emit(vec![
+            ZOP::Routine{name: "system_link_menu".to_string(), count_variables: 3},
+            ZOP::SplitWindow{lines: Operand::new_var(16)},
+            ZOP::SetWindow{window: 1},
+            ZOP::EraseWindow{value: 1},
+
+            // local 2 counts the drawn links
+            ZOP::Label{name: "system_link_menu_loop".to_string()},
+            ZOP::JE{operand1: Operand::new_var(0x02), operand2: Operand::new_var(16), jump_to_label: "system_link_menu_end".to_string()},
+            ZOP::LoadW{array_address: Operand::new_large_const(text_addr as i16), index: Variable::new(2), variable: Variable::new(3)},
+            ZOP::Inc{variable: 0x02},
+            ZOP::SetCursor{line: Operand::new_var(0x02), column: Operand::new_const(1)},
+            ZOP::JNE{operand1: Operand::new_var(0x02), operand2: Operand::new_var(0x01), jump_to_label: "system_link_menu_text".to_string()},
+            ZOP::SetTextStyle{bold: false, reverse: true, monospace: false, italic: false},
+            ZOP::Label{name: "system_link_menu_text".to_string()},
+            ZOP::PrintUnicodeStr{address: Operand::new_var(0x03)},
+            ZOP::SetTextStyle{bold: false, reverse: false, monospace: false, italic: false},
+            ZOP::Jump{jump_to_label: "system_link_menu_loop".to_string()},
+
+            ZOP::Label{name: "system_link_menu_end".to_string()},
+            ZOP::SetWindow{window: 0},
+            ZOP::Ret{value: Operand::new_const(0)}
         ]);
     }
 
@@ -874,6 +2017,18 @@ impl Zfile {
             // load u16 char to 0x3
             ZOP::LoadW{array_address: Operand::new_var(1), index: Variable::new(4), variable: Variable::new(3)},  // index at var:4 is 0
             // DEBUG    ZOP::Print{text: "code:".to_string()}, ZOP::PrintNumVar{variable: 0x03},
+        ]);
+
+        if self.version < 5 {
+            // without print_unicode only ascii can be printed
+            self.emit(vec![
+                ZOP::JL{operand1: Operand::new_var(3), operand2: Operand::new_const(127), jump_to_label: "inter_char_ascii".to_string()},
+                ZOP::StoreVariable{variable: Variable::new(3), value: Operand::new_const(63)},
+                ZOP::Label{name: "inter_char_ascii".to_string()},
+            ]);
+        }
+
+        self.emit(vec![
             ZOP::PrintUnicodeVar{var: Variable::new(3)},
             ZOP::Add{operand1: Operand::new_var(1), operand2: Operand::new_large_const(2i16), save_variable: Variable::new(1)}, // point to next char
             ZOP::JL{operand1: Operand::new_var(1), operand2: Operand::new_var(2), jump_to_label: "inter_char".to_string()},
@@ -882,6 +2037,125 @@ impl Zfile {
         ]);
     }
 
+    /// read_char only exists since version 4, so version 3 reads a line and
+    /// returns its characters one by one, followed by 13 for the end of the line
+    pub fn routine_read_char(&mut self) {
+        let text_addr = self.read_buffer_addr();
+        let position_addr = text_addr + 28;
+        self.emit(vec![
+            // local 1 is the position in the line, local 2 the character
+            // and local 3 stays 0 as index
+            ZOP::Routine{name: "system_read_char".to_string(), count_variables: 3},
+            ZOP::LoadW{array_address: Operand::new_large_const(position_addr as i16), index: Variable::new(3), variable: Variable::new(1)},
+            ZOP::JNE{operand1: Operand::new_var(1), operand2: Operand::new_const(0), jump_to_label: "system_read_char_next".to_string()},
+            ZOP::Sread{text_buffer: Operand::new_large_const(text_addr as i16), parse_buffer: Operand::new_large_const(text_addr as i16 + 22)},
+            ZOP::StoreVariable{variable: Variable::new(1), value: Operand::new_const(1)},
+
+            ZOP::Label{name: "system_read_char_next".to_string()},
+            ZOP::LoadB{array_address: Operand::new_large_const(text_addr as i16), index: Variable::new(1), variable: Variable::new(2)},
+            ZOP::JE{operand1: Operand::new_var(2), operand2: Operand::new_const(0), jump_to_label: "system_read_char_end".to_string()},
+            ZOP::Inc{variable: 1},
+            ZOP::StoreW{array_address: Operand::new_large_const(position_addr as i16), index: Variable::new(3), variable: Variable::new(1)},
+            ZOP::Ret{value: Operand::new_var(2)},
+
+            // the next call reads a new line
+            ZOP::Label{name: "system_read_char_end".to_string()},
+            ZOP::StoreW{array_address: Operand::new_large_const(position_addr as i16), index: Variable::new(3), variable: Variable::new(3)},
+            ZOP::Ret{value: Operand::new_const(13)}
+        ]);
+    }
+
+    /// reads a line of text and returns it as a new string
+    ///
+    /// The letters are zscii, those above 154 are translated to unicode by
+    /// the unicode table. Since version 4 the letters are read one by one
+    /// with read_char, so they keep their case. The line input of version 3
+    /// is converted to lower case by the interpreter.
+    pub fn routine_read_line(&mut self) {
+        let text_addr = self.text_input_addr();
+        let unicode_table_addr = self.unicode_table_addr;
+        let length = Variable::new(1);
+        let i = Variable::new(2);
+        let c = Variable::new(3);
+        let stra = Variable::new(4);
+        let first = Variable::new(5);
+        let zero = Variable::new(6);
+
+        let mut code: Vec<ZOP> = vec![
+            ZOP::Routine{name: "system_read_line".to_string(), count_variables: 6},
+        ];
+        if self.version >= 4 {
+            // read_char doesn't print the keys, so the letters are printed
+            // here, delete prints the remaining letters on a new line
+            code.extend(vec![
+                ZOP::StoreVariable{variable: first.clone(), value: Operand::new_large_const(text_addr as i16 + 2)},
+                ZOP::Label{name: "system_read_line_key".to_string()},
+                ZOP::ReadChar{local_var_id: c.id},
+                ZOP::JE{operand1: Operand::new_var(c.id), operand2: Operand::new_const(13), jump_to_label: "system_read_line_enter".to_string()},
+                ZOP::JE{operand1: Operand::new_var(c.id), operand2: Operand::new_const(8), jump_to_label: "system_read_line_delete".to_string()},
+
+                // only the printable letters, not the cursor, function or mouse keys
+                ZOP::JL{operand1: Operand::new_var(c.id), operand2: Operand::new_const(32), jump_to_label: "system_read_line_key".to_string()},
+                ZOP::JG{operand1: Operand::new_var(c.id), operand2: Operand::new_large_const(251), jump_to_label: "system_read_line_key".to_string()},
+                ZOP::JL{operand1: Operand::new_var(c.id), operand2: Operand::new_const(127), jump_to_label: "system_read_line_letter".to_string()},
+                ZOP::JL{operand1: Operand::new_var(c.id), operand2: Operand::new_large_const(155), jump_to_label: "system_read_line_key".to_string()},
+                ZOP::Label{name: "system_read_line_letter".to_string()},
+                ZOP::JE{operand1: Operand::new_var(length.id), operand2: Operand::new_const(80), jump_to_label: "system_read_line_key".to_string()},
+                ZOP::StoreB{array_address: Operand::new_var(first.id), index: length.clone(), variable: c.clone()},
+                ZOP::Inc{variable: length.id},
+                ZOP::PrintChar{var: c.clone()},
+                ZOP::Jump{jump_to_label: "system_read_line_key".to_string()},
+
+                ZOP::Label{name: "system_read_line_delete".to_string()},
+                ZOP::JE{operand1: Operand::new_var(length.id), operand2: Operand::new_const(0), jump_to_label: "system_read_line_key".to_string()},
+                ZOP::Dec{variable: length.id},
+                ZOP::Newline,
+                ZOP::StoreVariable{variable: i.clone(), value: Operand::new_const(0)},
+                ZOP::Label{name: "system_read_line_reprint".to_string()},
+                ZOP::JE{operand1: Operand::new_var(i.id), operand2: Operand::new_var(length.id), jump_to_label: "system_read_line_key".to_string()},
+                ZOP::LoadB{array_address: Operand::new_var(first.id), index: i.clone(), variable: c.clone()},
+                ZOP::PrintChar{var: c.clone()},
+                ZOP::Inc{variable: i.id},
+                ZOP::Jump{jump_to_label: "system_read_line_reprint".to_string()},
+
+                ZOP::Label{name: "system_read_line_enter".to_string()},
+                ZOP::Newline,
+            ]);
+        } else {
+            // the letters are terminated by a zero
+            code.push(ZOP::Aread{text_buffer: Operand::new_large_const(text_addr as i16), parse_buffer: Operand::new_large_const(text_addr as i16 + 84), result: c.clone()});
+            code.push(ZOP::StoreVariable{variable: first.clone(), value: Operand::new_large_const(text_addr as i16 + 1)});
+            code.push(ZOP::Label{name: "system_read_line_length".to_string()});
+            code.push(ZOP::LoadB{array_address: Operand::new_var(first.id), index: length.clone(), variable: c.clone()});
+            code.push(ZOP::JE{operand1: Operand::new_var(c.id), operand2: Operand::new_const(0), jump_to_label: "system_read_line_copy".to_string()});
+            code.push(ZOP::Inc{variable: length.id});
+            code.push(ZOP::Jump{jump_to_label: "system_read_line_length".to_string()});
+            code.push(ZOP::Label{name: "system_read_line_copy".to_string()});
+        }
+        code.extend(vec![
+            // the string needs one more word for its length
+            ZOP::Add{operand1: Operand::new_var(length.id), operand2: Operand::new_const(1), save_variable: i.clone()},
+            ZOP::Call2S{jump_to_label: "malloc".to_string(), arg: Operand::new_var(i.id), result: stra.clone()},
+            ZOP::StoreW{array_address: Operand::new_var(stra.id), index: zero.clone(), variable: length.clone()},
+            ZOP::StoreVariable{variable: i.clone(), value: Operand::new_const(0)},
+            ZOP::Label{name: "system_read_line_loop".to_string()},
+            ZOP::JE{operand1: Operand::new_var(i.id), operand2: Operand::new_var(length.id), jump_to_label: "system_read_line_end".to_string()},
+            ZOP::LoadB{array_address: Operand::new_var(first.id), index: i.clone(), variable: c.clone()},
+            ZOP::JL{operand1: Operand::new_var(c.id), operand2: Operand::new_large_const(155), jump_to_label: "system_read_line_store".to_string()},
+            ZOP::JG{operand1: Operand::new_var(c.id), operand2: Operand::new_large_const(251), jump_to_label: "system_read_line_store".to_string()},
+            ZOP::Sub{operand1: Operand::new_var(c.id), operand2: Operand::new_large_const(155), save_variable: c.clone()},
+            ZOP::LoadW{array_address: Operand::new_large_const(unicode_table_addr as i16 + 1), index: c.clone(), variable: c.clone()},
+            ZOP::Label{name: "system_read_line_store".to_string()},
+            // the letters start after the length
+            ZOP::Inc{variable: i.id},
+            ZOP::StoreW{array_address: Operand::new_var(stra.id), index: i.clone(), variable: c.clone()},
+            ZOP::Jump{jump_to_label: "system_read_line_loop".to_string()},
+            ZOP::Label{name: "system_read_line_end".to_string()},
+            ZOP::Ret{value: Operand::new_var(stra.id)}
+        ]);
+        self.emit(code);
+    }
+
     /// malloc
     pub fn routine_malloc(&mut self) {
         let heap_start = self.heap_start;
@@ -957,6 +2231,176 @@ impl Zfile {
         ]);
     }
 
+    /// strcmp
+    /// returns 1 if the two strings are equal, else 0
+    pub fn routine_strcmp(&mut self) {
+        self.emit(vec![
+            ZOP::Routine{name: "strcmp".to_string(), count_variables: 7},
+            // var1 and var2 have the str-addrs where the first u16 is the length
+            // var3 and var4 have the lengths
+            // var5 is the index
+            // var6 and var7 have the characters to compare
+            ZOP::LoadW{array_address: Operand::new_var(1), index: Variable::new(5), variable: Variable::new(3)},
+            ZOP::LoadW{array_address: Operand::new_var(2), index: Variable::new(5), variable: Variable::new(4)},
+            ZOP::JNE{operand1: Operand::new_var(3), operand2: Operand::new_var(4), jump_to_label: "strcmp_false".to_string()},
+            ZOP::Inc{variable: 1}, ZOP::Inc{variable: 1},  // point to first characters
+            ZOP::Inc{variable: 2}, ZOP::Inc{variable: 2},
+            ZOP::Label{name: "strcmp_loop".to_string()},
+            ZOP::JE{operand1: Operand::new_var(5), operand2: Operand::new_var(3), jump_to_label: "strcmp_true".to_string()},
+            ZOP::LoadW{array_address: Operand::new_var(1), index: Variable::new(5), variable: Variable::new(6)},
+            ZOP::LoadW{array_address: Operand::new_var(2), index: Variable::new(5), variable: Variable::new(7)},
+            ZOP::JNE{operand1: Operand::new_var(6), operand2: Operand::new_var(7), jump_to_label: "strcmp_false".to_string()},
+            ZOP::Inc{variable: 5},
+            ZOP::Jump{jump_to_label: "strcmp_loop".to_string()},
+            ZOP::Label{name: "strcmp_true".to_string()},
+            ZOP::Ret{value: Operand::new_const(1)},
+            ZOP::Label{name: "strcmp_false".to_string()},
+            ZOP::Ret{value: Operand::new_const(0)}
+        ]);
+    }
+
+    /// returns 1 if the current passage has the tag in the argument, else 0
+    /// the tags table of the current passage is in global var 20
+    pub fn routine_has_tag(&mut self) {
+        self.emit(vec![
+            ZOP::Routine{name: "system_has_tag".to_string(), count_variables: 5},
+            // var1 has the str-addr of the tag
+            // var2 is the index of the last tag in the table
+            // var3 is the index
+            // var4 has the str-addr of the tag in the table
+            // var5 is the result of strcmp
+            ZOP::StoreVariable{variable: Variable::new(3), value: Operand::new_const(1)},
+            ZOP::LoadW{array_address: Operand::new_var(20), index: Variable::new(3), variable: Variable::new(2)},
+            ZOP::Add{operand1: Operand::new_var(2), operand2: Operand::new_const(1), save_variable: Variable::new(2)},
+            ZOP::Label{name: "system_has_tag_loop".to_string()},
+            ZOP::JE{operand1: Operand::new_var(3), operand2: Operand::new_var(2), jump_to_label: "system_has_tag_false".to_string()},
+            ZOP::Inc{variable: 3},
+            ZOP::LoadW{array_address: Operand::new_var(20), index: Variable::new(3), variable: Variable::new(4)},
+            ZOP::CallVSA2{jump_to_label: "strcmp".to_string(), arg1: Operand::new_var(1), arg2: Operand::new_var(4), result: Variable::new(5)},
+            ZOP::JE{operand1: Operand::new_var(5), operand2: Operand::new_const(1), jump_to_label: "system_has_tag_true".to_string()},
+            ZOP::Jump{jump_to_label: "system_has_tag_loop".to_string()},
+            ZOP::Label{name: "system_has_tag_true".to_string()},
+            ZOP::Ret{value: Operand::new_const(1)},
+            ZOP::Label{name: "system_has_tag_false".to_string()},
+            ZOP::Ret{value: Operand::new_const(0)}
+        ]);
+    }
+
+    /// the lookup table from passage names to passage routines
+    /// system_passage_address returns the routine address of the passage whose
+    /// name is the string at the address in the argument, or 0 if there is none
+    /// system_add_link_by_name and system_display_by_name use it for links and
+    /// <<display>> with a passage name computed at runtime
+    pub fn routine_passage_address(&mut self, passages: &Vec<String>) {
+        let mut code: Vec<ZOP> = vec![
+            ZOP::Routine{name: "system_passage_address".to_string(), count_variables: 2},
+        ];
+        for (i, passage) in passages.iter().enumerate() {
+            let name_addr = self.write_string(passage);
+            code.push(ZOP::CallVSA2{jump_to_label: "strcmp".to_string(), arg1: Operand::new_var(1),
+                arg2: Operand::new_large_const(name_addr as i16), result: Variable::new(2)});
+            code.push(ZOP::JE{operand1: Operand::new_var(2), operand2: Operand::new_const(1),
+                jump_to_label: format!("system_passage_address_{}", i)});
+        }
+        code.push(ZOP::Ret{value: Operand::new_const(0)});
+        for (i, passage) in passages.iter().enumerate() {
+            code.push(ZOP::Label{name: format!("system_passage_address_{}", i)});
+            code.push(ZOP::StoreRoutineAddress{variable: Variable::new(2), routine: passage.to_string()});
+            code.push(ZOP::Ret{value: Operand::new_var(2)});
+        }
+
+        // adds the link to the passage with the name in the argument,
+        // returns 0 and prints a message if the passage doesn't exist
+        code.extend(vec![
+            ZOP::Routine{name: "system_add_link_by_name".to_string(), count_variables: 2},
+            ZOP::Call2S{jump_to_label: "system_passage_address".to_string(), arg: Operand::new_var(1), result: Variable::new(2)},
+            ZOP::JE{operand1: Operand::new_var(2), operand2: Operand::new_const(0), jump_to_label: "system_add_link_by_name_missing".to_string()},
+            ZOP::Call2NWithArg{jump_to_label: "system_add_link".to_string(), arg: Operand::new_var(2)},
+            ZOP::Ret{value: Operand::new_const(1)},
+            ZOP::Label{name: "system_add_link_by_name_missing".to_string()},
+            ZOP::Print{text: "[no passage named \"".to_string()},
+            ZOP::PrintUnicodeStr{address: Operand::new_var(1)},
+            ZOP::Print{text: "\"]".to_string()},
+            ZOP::Ret{value: Operand::new_const(0)},
+
+            // displays the passage with the name in the argument,
+            // prints a message if the passage doesn't exist
+            ZOP::Routine{name: "system_display_by_name".to_string(), count_variables: 2},
+            ZOP::Call2S{jump_to_label: "system_passage_address".to_string(), arg: Operand::new_var(1), result: Variable::new(2)},
+            ZOP::JE{operand1: Operand::new_var(2), operand2: Operand::new_const(0), jump_to_label: "system_display_by_name_missing".to_string()},
+            // activates the display-modus
+            ZOP::StoreVariable{variable: Variable::new(17), value: Operand::new_const(1)},
+            ZOP::Call1NVar{variable: 2},
+            // deactivates the display-modus
+            ZOP::StoreVariable{variable: Variable::new(17), value: Operand::new_const(0)},
+            ZOP::Ret{value: Operand::new_const(1)},
+            ZOP::Label{name: "system_display_by_name_missing".to_string()},
+            ZOP::Print{text: "[no passage named \"".to_string()},
+            ZOP::PrintUnicodeStr{address: Operand::new_var(1)},
+            ZOP::Print{text: "\"]".to_string()},
+            ZOP::Ret{value: Operand::new_const(0)}
+        ].into_iter());
+        self.emit(code);
+    }
+
+    /// the visit counters and the history of the passages
+    /// global 21 is the table on the heap with the number of visits of each
+    /// passage (the index in passages plus 1), global 22 the name of the
+    /// previous and global 23 the name of the current passage
+    /// system_visit is called by the passage routines, unless they are
+    /// included with <<display>>
+    pub fn routine_visits(&mut self, passages: &Vec<String>) {
+        let count = passages.len() as i16;
+        let empty_addr = self.write_string("");
+        let mut code: Vec<ZOP> = vec![
+            ZOP::Routine{name: "system_visits_init".to_string(), count_variables: 2},
+            // var1 is the index and var2 stays 0
+            ZOP::Call2S{jump_to_label: "malloc".to_string(), arg: Operand::new_large_const(count + 1), result: Variable::new(21)},
+            ZOP::StoreVariable{variable: Variable::new(2), value: Operand::new_large_const(count)},
+            ZOP::StoreW{array_address: Operand::new_var(21), index: Variable::new(1), variable: Variable::new(2)},
+            ZOP::StoreVariable{variable: Variable::new(2), value: Operand::new_const(0)},
+            ZOP::Label{name: "system_visits_init_loop".to_string()},
+            ZOP::JE{operand1: Operand::new_var(1), operand2: Operand::new_large_const(count), jump_to_label: "system_visits_init_return".to_string()},
+            ZOP::Inc{variable: 1},
+            ZOP::StoreW{array_address: Operand::new_var(21), index: Variable::new(1), variable: Variable::new(2)},
+            ZOP::Jump{jump_to_label: "system_visits_init_loop".to_string()},
+            ZOP::Label{name: "system_visits_init_return".to_string()},
+            ZOP::StoreVariable{variable: Variable::new(22), value: Operand::new_large_const(empty_addr as i16)},
+            ZOP::StoreVariable{variable: Variable::new(23), value: Operand::new_large_const(empty_addr as i16)},
+            ZOP::Ret{value: Operand::new_const(0)},
+
+            // counts the visit of the passage with the index in var1 and the
+            // name in var2
+            ZOP::Routine{name: "system_visit".to_string(), count_variables: 3},
+            ZOP::LoadW{array_address: Operand::new_var(21), index: Variable::new(1), variable: Variable::new(3)},
+            ZOP::Inc{variable: 3},
+            ZOP::StoreW{array_address: Operand::new_var(21), index: Variable::new(1), variable: Variable::new(3)},
+            ZOP::StoreVariable{variable: Variable::new(22), value: Operand::new_var(23)},
+            ZOP::StoreVariable{variable: Variable::new(23), value: Operand::new_var(2)},
+            ZOP::Ret{value: Operand::new_const(0)},
+
+            // returns the number of visits of the passage with the name at
+            // the address in var1, or 0 if there is none
+            ZOP::Routine{name: "system_visited".to_string(), count_variables: 3},
+            // var2 is the result of strcmp and var3 the index
+        ];
+        for (i, passage) in passages.iter().enumerate() {
+            let name_addr = self.write_string(passage);
+            code.push(ZOP::StoreVariable{variable: Variable::new(3), value: Operand::new_large_const(i as i16 + 1)});
+            code.push(ZOP::CallVSA2{jump_to_label: "strcmp".to_string(), arg1: Operand::new_var(1),
+                arg2: Operand::new_large_const(name_addr as i16), result: Variable::new(2)});
+            code.push(ZOP::JE{operand1: Operand::new_var(2), operand2: Operand::new_const(1),
+                jump_to_label: "system_visited_found".to_string()});
+        }
+        code.extend(vec![
+            ZOP::Ret{value: Operand::new_const(0)},
+            ZOP::Label{name: "system_visited_found".to_string()},
+            ZOP::LoadW{array_address: Operand::new_var(21), index: Variable::new(3), variable: Variable::new(2)},
+            ZOP::Ret{value: Operand::new_var(2)}
+        ].into_iter());
+        self.emit(code);
+    }
+
     /// strcat
     /// returns a reference to a string concatenation of the first and second string parameters
     pub fn routine_strcat(&mut self) {
@@ -1065,6 +2509,9 @@ impl Zfile {
             ZOP::JE{operand1: Operand::new_var(pos.id), operand2: Operand::new_var(varcontent.id), jump_to_label: "mem_free_continue".to_string()},
             ZOP::JL{operand1: Operand::new_var(varid.id), operand2: Operand::new_large_const(255i16), jump_to_label: "mem_free_check".to_string()},
             // finished loop for checking
+            // the entry could still be an element of an array
+            ZOP::Call2S{jump_to_label: "system_in_array".to_string(), arg: Operand::new_var(pos.id), result: t.clone()},
+            ZOP::JE{operand1: Operand::new_var(t.id), operand2: Operand::new_const(1), jump_to_label: "mem_free_continue".to_string()},
             // set t to position after the whole entry so now we skip length*2 (content)
             ZOP::Add{operand1: Operand::new_var(pos.id), operand2: Operand::new_var(c.id), save_variable: t.clone()},
             ZOP::Add{operand1: Operand::new_var(t.id), operand2: Operand::new_var(c.id), save_variable: t.clone()},
@@ -1140,6 +2587,478 @@ impl Zfile {
         ]);
     }
 
+    /// the routines for arrays
+    /// an array is a block on the heap like a string: the first u16 is the
+    /// size of the block, the second the length and the elements follow
+    /// them, element i is at index i+2
+    /// the block has room for more elements than the length, so push only
+    /// needs a new block when the array is full
+    pub fn routine_array(&mut self) {
+        let heap_start = self.heap_start;
+        let static_addr = self.static_addr;
+        let global_addr = self.global_addr;
+        self.emit(vec![
+            // returns a new array with the length in var1, all elements are 0
+            ZOP::Routine{name: "system_array_new".to_string(), count_variables: 5},
+            // var2 is the array, var3 the index, var4 stays 0 and var5 is
+            // the size, there is room for four more elements
+            ZOP::Add{operand1: Operand::new_var(1), operand2: Operand::new_const(5), save_variable: Variable::new(5)},
+            ZOP::Add{operand1: Operand::new_var(5), operand2: Operand::new_const(1), save_variable: Variable::new(2)},
+            ZOP::Call2S{jump_to_label: "malloc".to_string(), arg: Operand::new_var(2), result: Variable::new(2)},
+            ZOP::StoreW{array_address: Operand::new_var(2), index: Variable::new(3), variable: Variable::new(5)},
+            ZOP::Inc{variable: 3},
+            ZOP::StoreW{array_address: Operand::new_var(2), index: Variable::new(3), variable: Variable::new(1)},
+            ZOP::Label{name: "system_array_new_loop".to_string()},
+            ZOP::JE{operand1: Operand::new_var(3), operand2: Operand::new_var(5), jump_to_label: "system_array_new_return".to_string()},
+            ZOP::Inc{variable: 3},
+            ZOP::StoreW{array_address: Operand::new_var(2), index: Variable::new(3), variable: Variable::new(4)},
+            ZOP::Jump{jump_to_label: "system_array_new_loop".to_string()},
+            ZOP::Label{name: "system_array_new_return".to_string()},
+            ZOP::Ret{value: Operand::new_var(2)},
+
+            // returns the element of the array in var1 at the index in var2,
+            // or the default in var3 if the index is out of range
+            ZOP::Routine{name: "system_array_get".to_string(), count_variables: 5},
+            // var4 is the length and var5 the index of the length
+            ZOP::JL{operand1: Operand::new_var(2), operand2: Operand::new_const(0), jump_to_label: "system_array_get_default".to_string()},
+            ZOP::StoreVariable{variable: Variable::new(5), value: Operand::new_const(1)},
+            ZOP::LoadW{array_address: Operand::new_var(1), index: Variable::new(5), variable: Variable::new(4)},
+            ZOP::JGE{operand1: Operand::new_var(2), operand2: Operand::new_var(4), jump_to_label: "system_array_get_default".to_string()},
+            ZOP::Add{operand1: Operand::new_var(2), operand2: Operand::new_const(2), save_variable: Variable::new(2)},
+            ZOP::LoadW{array_address: Operand::new_var(1), index: Variable::new(2), variable: Variable::new(4)},
+            ZOP::Ret{value: Operand::new_var(4)},
+            ZOP::Label{name: "system_array_get_default".to_string()},
+            ZOP::Ret{value: Operand::new_var(3)},
+
+            // sets the element of the array in var1 at the index in var2 to
+            // the value in var3, indexes out of range are ignored
+            ZOP::Routine{name: "system_array_set".to_string(), count_variables: 5},
+            // var4 is the length and var5 the index of the length
+            ZOP::JL{operand1: Operand::new_var(2), operand2: Operand::new_const(0), jump_to_label: "system_array_set_return".to_string()},
+            ZOP::StoreVariable{variable: Variable::new(5), value: Operand::new_const(1)},
+            ZOP::LoadW{array_address: Operand::new_var(1), index: Variable::new(5), variable: Variable::new(4)},
+            ZOP::JGE{operand1: Operand::new_var(2), operand2: Operand::new_var(4), jump_to_label: "system_array_set_return".to_string()},
+            ZOP::Add{operand1: Operand::new_var(2), operand2: Operand::new_const(2), save_variable: Variable::new(2)},
+            ZOP::StoreW{array_address: Operand::new_var(1), index: Variable::new(2), variable: Variable::new(3)},
+            ZOP::Label{name: "system_array_set_return".to_string()},
+            ZOP::Ret{value: Operand::new_const(0)},
+
+            // appends the value in var2 to the array in var1 and returns the
+            // array, a full array is copied to a block of twice the size
+            // and the old one is freed by mem_free
+            ZOP::Routine{name: "system_array_push".to_string(), count_variables: 8},
+            // var3 is the length, var4 the size, var5 the index, var6 the
+            // new array, var7 the element and var8 the new size
+            ZOP::LoadW{array_address: Operand::new_var(1), index: Variable::new(5), variable: Variable::new(4)},
+            ZOP::Inc{variable: 5},
+            ZOP::LoadW{array_address: Operand::new_var(1), index: Variable::new(5), variable: Variable::new(3)},
+            // the elements end at index length+1, the block at index size
+            ZOP::Add{operand1: Operand::new_var(3), operand2: Operand::new_const(1), save_variable: Variable::new(5)},
+            ZOP::JL{operand1: Operand::new_var(5), operand2: Operand::new_var(4), jump_to_label: "system_array_push_append".to_string()},
+            ZOP::Add{operand1: Operand::new_var(4), operand2: Operand::new_var(4), save_variable: Variable::new(8)},
+            ZOP::Add{operand1: Operand::new_var(8), operand2: Operand::new_const(1), save_variable: Variable::new(6)},
+            ZOP::Call2S{jump_to_label: "malloc".to_string(), arg: Operand::new_var(6), result: Variable::new(6)},
+            ZOP::StoreVariable{variable: Variable::new(5), value: Operand::new_const(0)},
+            ZOP::StoreW{array_address: Operand::new_var(6), index: Variable::new(5), variable: Variable::new(8)},
+            ZOP::Label{name: "system_array_push_copy".to_string()},
+            ZOP::JE{operand1: Operand::new_var(5), operand2: Operand::new_var(4), jump_to_label: "system_array_push_clear".to_string()},
+            ZOP::Inc{variable: 5},
+            ZOP::LoadW{array_address: Operand::new_var(1), index: Variable::new(5), variable: Variable::new(7)},
+            ZOP::StoreW{array_address: Operand::new_var(6), index: Variable::new(5), variable: Variable::new(7)},
+            ZOP::Jump{jump_to_label: "system_array_push_copy".to_string()},
+            ZOP::Label{name: "system_array_push_clear".to_string()},
+            ZOP::StoreVariable{variable: Variable::new(7), value: Operand::new_const(0)},
+            ZOP::Label{name: "system_array_push_clear_loop".to_string()},
+            ZOP::JE{operand1: Operand::new_var(5), operand2: Operand::new_var(8), jump_to_label: "system_array_push_moved".to_string()},
+            ZOP::Inc{variable: 5},
+            ZOP::StoreW{array_address: Operand::new_var(6), index: Variable::new(5), variable: Variable::new(7)},
+            ZOP::Jump{jump_to_label: "system_array_push_clear_loop".to_string()},
+            ZOP::Label{name: "system_array_push_moved".to_string()},
+            ZOP::StoreVariable{variable: Variable::new(1), value: Operand::new_var(6)},
+            ZOP::Label{name: "system_array_push_append".to_string()},
+            ZOP::Inc{variable: 3},
+            ZOP::StoreVariable{variable: Variable::new(5), value: Operand::new_const(1)},
+            ZOP::StoreW{array_address: Operand::new_var(1), index: Variable::new(5), variable: Variable::new(3)},
+            ZOP::Add{operand1: Operand::new_var(3), operand2: Operand::new_const(1), save_variable: Variable::new(5)},
+            ZOP::StoreW{array_address: Operand::new_var(1), index: Variable::new(5), variable: Variable::new(2)},
+            ZOP::Ret{value: Operand::new_var(1)},
+
+            // returns 1 if the array in var1 contains the value in var2, else 0
+            ZOP::Routine{name: "system_array_contains".to_string(), count_variables: 5},
+            // var3 is the index of the last element, var4 the index and var5
+            // the element
+            ZOP::StoreVariable{variable: Variable::new(4), value: Operand::new_const(1)},
+            ZOP::LoadW{array_address: Operand::new_var(1), index: Variable::new(4), variable: Variable::new(3)},
+            ZOP::Inc{variable: 3},
+            ZOP::Label{name: "system_array_contains_loop".to_string()},
+            ZOP::JE{operand1: Operand::new_var(4), operand2: Operand::new_var(3), jump_to_label: "system_array_contains_false".to_string()},
+            ZOP::Inc{variable: 4},
+            ZOP::LoadW{array_address: Operand::new_var(1), index: Variable::new(4), variable: Variable::new(5)},
+            ZOP::JE{operand1: Operand::new_var(5), operand2: Operand::new_var(2), jump_to_label: "system_array_contains_true".to_string()},
+            ZOP::Jump{jump_to_label: "system_array_contains_loop".to_string()},
+            ZOP::Label{name: "system_array_contains_true".to_string()},
+            ZOP::Ret{value: Operand::new_const(1)},
+            ZOP::Label{name: "system_array_contains_false".to_string()},
+            ZOP::Ret{value: Operand::new_const(0)},
+
+            // like system_array_contains, but compares the strings with strcmp
+            ZOP::Routine{name: "system_array_contains_string".to_string(), count_variables: 5},
+            ZOP::StoreVariable{variable: Variable::new(4), value: Operand::new_const(1)},
+            ZOP::LoadW{array_address: Operand::new_var(1), index: Variable::new(4), variable: Variable::new(3)},
+            ZOP::Inc{variable: 3},
+            ZOP::Label{name: "system_array_contains_string_loop".to_string()},
+            ZOP::JE{operand1: Operand::new_var(4), operand2: Operand::new_var(3), jump_to_label: "system_array_contains_string_false".to_string()},
+            ZOP::Inc{variable: 4},
+            ZOP::LoadW{array_address: Operand::new_var(1), index: Variable::new(4), variable: Variable::new(5)},
+            ZOP::CallVSA2{jump_to_label: "strcmp".to_string(), arg1: Operand::new_var(5), arg2: Operand::new_var(2), result: Variable::new(5)},
+            ZOP::JE{operand1: Operand::new_var(5), operand2: Operand::new_const(1), jump_to_label: "system_array_contains_string_true".to_string()},
+            ZOP::Jump{jump_to_label: "system_array_contains_string_loop".to_string()},
+            ZOP::Label{name: "system_array_contains_string_true".to_string()},
+            ZOP::Ret{value: Operand::new_const(1)},
+            ZOP::Label{name: "system_array_contains_string_false".to_string()},
+            ZOP::Ret{value: Operand::new_const(0)},
+
+            // prints the elements of the array in var1 separated by commas,
+            // var2 is 1 if the elements are strings and 2 if they are
+            // fixed-point numbers
+            ZOP::Routine{name: "system_print_array".to_string(), count_variables: 5},
+            // var3 is the index of the last element, var4 the index and var5
+            // the element
+            ZOP::StoreVariable{variable: Variable::new(4), value: Operand::new_const(1)},
+            ZOP::LoadW{array_address: Operand::new_var(1), index: Variable::new(4), variable: Variable::new(3)},
+            ZOP::Inc{variable: 3},
+            ZOP::Label{name: "system_print_array_loop".to_string()},
+            ZOP::JE{operand1: Operand::new_var(4), operand2: Operand::new_var(3), jump_to_label: "system_print_array_return".to_string()},
+            ZOP::JE{operand1: Operand::new_var(4), operand2: Operand::new_const(1), jump_to_label: "system_print_array_element".to_string()},
+            ZOP::Print{text: ", ".to_string()},
+            ZOP::Label{name: "system_print_array_element".to_string()},
+            ZOP::Inc{variable: 4},
+            ZOP::LoadW{array_address: Operand::new_var(1), index: Variable::new(4), variable: Variable::new(5)},
+            ZOP::JE{operand1: Operand::new_var(2), operand2: Operand::new_const(0), jump_to_label: "system_print_array_number".to_string()},
+            ZOP::JE{operand1: Operand::new_var(2), operand2: Operand::new_const(2), jump_to_label: "system_print_array_fixed".to_string()},
+            ZOP::PrintUnicodeStr{address: Operand::new_var(5)},
+            ZOP::Jump{jump_to_label: "system_print_array_loop".to_string()},
+            ZOP::Label{name: "system_print_array_fixed".to_string()},
+            ZOP::Call2NWithArg{jump_to_label: "system_print_fixed".to_string(), arg: Operand::new_var(5)},
+            ZOP::Jump{jump_to_label: "system_print_array_loop".to_string()},
+            ZOP::Label{name: "system_print_array_number".to_string()},
+            ZOP::PrintNumVar{variable: Variable::new(5)},
+            ZOP::Jump{jump_to_label: "system_print_array_loop".to_string()},
+            ZOP::Label{name: "system_print_array_return".to_string()},
+            ZOP::Ret{value: Operand::new_const(0)},
+
+            // returns 1 if the address in var1 is an element of an array in
+            // a global variable, mem_free keeps these entries
+            // every used entry on the heap is checked, so strings and
+            // numbers that look like an address keep the entry as well
+            ZOP::Routine{name: "system_in_array".to_string(), count_variables: 7},
+            // var2 is the varid, var3 the content of the global variable,
+            // var4 its size, var5 the maximum size, var6 the index
+            // and var7 the element
+            ZOP::StoreVariable{variable: Variable::new(2), value: Operand::new_large_const(15i16)},
+            ZOP::Label{name: "system_in_array_loop".to_string()},
+            ZOP::JE{operand1: Operand::new_var(2), operand2: Operand::new_large_const(255i16), jump_to_label: "system_in_array_false".to_string()},
+            ZOP::Inc{variable: 2},
+            ZOP::LoadW{array_address: Operand::new_large_const(global_addr as i16 - 32i16), index: Variable::new(2), variable: Variable::new(3)},
+            // skip everything that is not on the heap
+            ZOP::JL{operand1: Operand::new_var(3), operand2: Operand::new_large_const(heap_start as i16), jump_to_label: "system_in_array_loop".to_string()},
+            ZOP::StoreVariable{variable: Variable::new(6), value: Operand::new_large_const(0)},
+            ZOP::LoadW{array_address: Operand::new_var(3), index: Variable::new(6), variable: Variable::new(4)},
+            ZOP::JL{operand1: Operand::new_var(4), operand2: Operand::new_const(0), jump_to_label: "system_in_array_loop".to_string()},
+            // the entry has to end before the static memory
+            ZOP::Sub{operand1: Operand::new_large_const(static_addr as i16), operand2: Operand::new_var(3), save_variable: Variable::new(5)},
+            ZOP::Div{operand1: Operand::new_var(5), operand2: Operand::new_const(2), save_variable: Variable::new(5)},
+            ZOP::JGE{operand1: Operand::new_var(4), operand2: Operand::new_var(5), jump_to_label: "system_in_array_loop".to_string()},
+            ZOP::Label{name: "system_in_array_element".to_string()},
+            ZOP::JE{operand1: Operand::new_var(6), operand2: Operand::new_var(4), jump_to_label: "system_in_array_loop".to_string()},
+            ZOP::Inc{variable: 6},
+            ZOP::LoadW{array_address: Operand::new_var(3), index: Variable::new(6), variable: Variable::new(7)},
+            ZOP::JE{operand1: Operand::new_var(7), operand2: Operand::new_var(1), jump_to_label: "system_in_array_true".to_string()},
+            ZOP::Jump{jump_to_label: "system_in_array_element".to_string()},
+            ZOP::Label{name: "system_in_array_true".to_string()},
+            ZOP::Ret{value: Operand::new_const(1)},
+            ZOP::Label{name: "system_in_array_false".to_string()},
+            ZOP::Ret{value: Operand::new_const(0)}
+        ]);
+    }
+
+    /// the routines for fixed-point numbers (see `FIXED_SCALE`)
+    /// comparisons work on the scaled values
+    /// results outside of -327.67 to 327.67 print a message and return the
+    /// nearest number in range, division by zero prints a message and
+    /// returns 0
+    pub fn routine_fixed(&mut self) {
+        let scale = Operand::new_large_const(FIXED_SCALE);
+        let max_integer = ::std::i16::MAX / FIXED_SCALE;
+        let min = Operand::new_large_const(::std::i16::MIN);
+        let min_string_addr = self.write_string("-327.68");
+        let minus_addr = self.write_string("-");
+        let point_addr = self.write_string(".");
+        self.emit(vec![
+            // returns the integer in var1 as a fixed-point number, integers
+            // that don't fit print a message and return the largest number
+            ZOP::Routine{name: "system_to_fixed".to_string(), count_variables: 1},
+            ZOP::JG{operand1: Operand::new_var(1), operand2: Operand::new_large_const(max_integer), jump_to_label: "system_to_fixed_too_large".to_string()},
+            ZOP::JL{operand1: Operand::new_var(1), operand2: Operand::new_large_const(-max_integer), jump_to_label: "system_to_fixed_too_small".to_string()},
+            ZOP::Mul{operand1: Operand::new_var(1), operand2: scale.clone(), save_variable: Variable::new(1)},
+            ZOP::Ret{value: Operand::new_var(1)},
+            ZOP::Label{name: "system_to_fixed_too_large".to_string()},
+            ZOP::Print{text: "[number out of range]".to_string()},
+            ZOP::Ret{value: Operand::new_large_const(::std::i16::MAX)},
+            ZOP::Label{name: "system_to_fixed_too_small".to_string()},
+            ZOP::Print{text: "[number out of range]".to_string()},
+            ZOP::Ret{value: Operand::new_large_const(-::std::i16::MAX)},
+
+            // returns the sum of var1 and var2 in var3
+            ZOP::Routine{name: "system_fixed_add".to_string(), count_variables: 3},
+            ZOP::Add{operand1: Operand::new_var(1), operand2: Operand::new_var(2), save_variable: Variable::new(3)},
+            // only numbers with the same sign can overflow
+            ZOP::JL{operand1: Operand::new_var(1), operand2: Operand::new_const(0), jump_to_label: "system_fixed_add_negative".to_string()},
+            ZOP::JL{operand1: Operand::new_var(2), operand2: Operand::new_const(0), jump_to_label: "system_fixed_add_return".to_string()},
+            ZOP::JL{operand1: Operand::new_var(3), operand2: Operand::new_const(0), jump_to_label: "system_fixed_add_too_large".to_string()},
+            ZOP::Jump{jump_to_label: "system_fixed_add_return".to_string()},
+            ZOP::Label{name: "system_fixed_add_negative".to_string()},
+            ZOP::JGE{operand1: Operand::new_var(2), operand2: Operand::new_const(0), jump_to_label: "system_fixed_add_return".to_string()},
+            ZOP::JGE{operand1: Operand::new_var(3), operand2: Operand::new_const(0), jump_to_label: "system_fixed_add_too_small".to_string()},
+            ZOP::JE{operand1: Operand::new_var(3), operand2: min.clone(), jump_to_label: "system_fixed_add_too_small".to_string()},
+            ZOP::Label{name: "system_fixed_add_return".to_string()},
+            ZOP::Ret{value: Operand::new_var(3)},
+            ZOP::Label{name: "system_fixed_add_too_large".to_string()},
+            ZOP::Print{text: "[number out of range]".to_string()},
+            ZOP::Ret{value: Operand::new_large_const(::std::i16::MAX)},
+            ZOP::Label{name: "system_fixed_add_too_small".to_string()},
+            ZOP::Print{text: "[number out of range]".to_string()},
+            ZOP::Ret{value: Operand::new_large_const(-::std::i16::MAX)},
+
+            // returns the difference of var1 and var2 in var3
+            ZOP::Routine{name: "system_fixed_sub".to_string(), count_variables: 3},
+            ZOP::Sub{operand1: Operand::new_var(1), operand2: Operand::new_var(2), save_variable: Variable::new(3)},
+            // only numbers with different signs can overflow
+            ZOP::JL{operand1: Operand::new_var(1), operand2: Operand::new_const(0), jump_to_label: "system_fixed_sub_negative".to_string()},
+            ZOP::JGE{operand1: Operand::new_var(2), operand2: Operand::new_const(0), jump_to_label: "system_fixed_sub_return".to_string()},
+            ZOP::JL{operand1: Operand::new_var(3), operand2: Operand::new_const(0), jump_to_label: "system_fixed_sub_too_large".to_string()},
+            ZOP::Jump{jump_to_label: "system_fixed_sub_return".to_string()},
+            ZOP::Label{name: "system_fixed_sub_negative".to_string()},
+            ZOP::JL{operand1: Operand::new_var(2), operand2: Operand::new_const(0), jump_to_label: "system_fixed_sub_return".to_string()},
+            ZOP::JGE{operand1: Operand::new_var(3), operand2: Operand::new_const(0), jump_to_label: "system_fixed_sub_too_small".to_string()},
+            ZOP::JE{operand1: Operand::new_var(3), operand2: min.clone(), jump_to_label: "system_fixed_sub_too_small".to_string()},
+            ZOP::Label{name: "system_fixed_sub_return".to_string()},
+            ZOP::Ret{value: Operand::new_var(3)},
+            ZOP::Label{name: "system_fixed_sub_too_large".to_string()},
+            ZOP::Print{text: "[number out of range]".to_string()},
+            ZOP::Ret{value: Operand::new_large_const(::std::i16::MAX)},
+            ZOP::Label{name: "system_fixed_sub_too_small".to_string()},
+            ZOP::Print{text: "[number out of range]".to_string()},
+            ZOP::Ret{value: Operand::new_large_const(-::std::i16::MAX)},
+
+            // returns the product of var1 and var2
+            // the absolute values are split into the integer and the
+            // fractional part, so every intermediate product fits and the
+            // sum can be checked for an overflow
+            ZOP::Routine{name: "system_fixed_mul".to_string(), count_variables: 9},
+            // var3 is 1 if the product is negative, var4 and var5 are the
+            // parts of var1, var6 and var7 the parts of var2, var8 is the
+            // result and var9 a product
+            ZOP::JGE{operand1: Operand::new_var(1), operand2: Operand::new_const(0), jump_to_label: "system_fixed_mul_positive1".to_string()},
+            ZOP::Sub{operand1: Operand::new_const(0), operand2: Operand::new_var(1), save_variable: Variable::new(1)},
+            ZOP::StoreVariable{variable: Variable::new(3), value: Operand::new_const(1)},
+            ZOP::Label{name: "system_fixed_mul_positive1".to_string()},
+            ZOP::JGE{operand1: Operand::new_var(2), operand2: Operand::new_const(0), jump_to_label: "system_fixed_mul_positive2".to_string()},
+            ZOP::Sub{operand1: Operand::new_const(0), operand2: Operand::new_var(2), save_variable: Variable::new(2)},
+            ZOP::Sub{operand1: Operand::new_const(1), operand2: Operand::new_var(3), save_variable: Variable::new(3)},
+            ZOP::Label{name: "system_fixed_mul_positive2".to_string()},
+            // -327.68 stays negative
+            ZOP::JL{operand1: Operand::new_var(1), operand2: Operand::new_const(0), jump_to_label: "system_fixed_mul_range".to_string()},
+            ZOP::JL{operand1: Operand::new_var(2), operand2: Operand::new_const(0), jump_to_label: "system_fixed_mul_range".to_string()},
+            ZOP::Div{operand1: Operand::new_var(1), operand2: scale.clone(), save_variable: Variable::new(4)},
+            ZOP::Mod{operand1: Operand::new_var(1), operand2: scale.clone(), save_variable: Variable::new(5)},
+            ZOP::Div{operand1: Operand::new_var(2), operand2: scale.clone(), save_variable: Variable::new(6)},
+            ZOP::Mod{operand1: Operand::new_var(2), operand2: scale.clone(), save_variable: Variable::new(7)},
+            // the product of the integer parts has to be at most 327
+            ZOP::JE{operand1: Operand::new_var(4), operand2: Operand::new_const(0), jump_to_label: "system_fixed_mul_parts".to_string()},
+            ZOP::Div{operand1: Operand::new_large_const(max_integer), operand2: Operand::new_var(4), save_variable: Variable::new(9)},
+            ZOP::JG{operand1: Operand::new_var(6), operand2: Operand::new_var(9), jump_to_label: "system_fixed_mul_range".to_string()},
+            ZOP::Label{name: "system_fixed_mul_parts".to_string()},
+            ZOP::Mul{operand1: Operand::new_var(4), operand2: Operand::new_var(6), save_variable: Variable::new(8)},
+            ZOP::Mul{operand1: Operand::new_var(8), operand2: scale.clone(), save_variable: Variable::new(8)},
+            // every product is smaller than 32768, so a sum above 32767
+            // becomes negative
+            ZOP::Mul{operand1: Operand::new_var(4), operand2: Operand::new_var(7), save_variable: Variable::new(9)},
+            ZOP::Add{operand1: Operand::new_var(8), operand2: Operand::new_var(9), save_variable: Variable::new(8)},
+            ZOP::JL{operand1: Operand::new_var(8), operand2: Operand::new_const(0), jump_to_label: "system_fixed_mul_range".to_string()},
+            ZOP::Mul{operand1: Operand::new_var(5), operand2: Operand::new_var(6), save_variable: Variable::new(9)},
+            ZOP::Add{operand1: Operand::new_var(8), operand2: Operand::new_var(9), save_variable: Variable::new(8)},
+            ZOP::JL{operand1: Operand::new_var(8), operand2: Operand::new_const(0), jump_to_label: "system_fixed_mul_range".to_string()},
+            ZOP::Mul{operand1: Operand::new_var(5), operand2: Operand::new_var(7), save_variable: Variable::new(9)},
+            ZOP::Div{operand1: Operand::new_var(9), operand2: scale.clone(), save_variable: Variable::new(9)},
+            ZOP::Add{operand1: Operand::new_var(8), operand2: Operand::new_var(9), save_variable: Variable::new(8)},
+            ZOP::JL{operand1: Operand::new_var(8), operand2: Operand::new_const(0), jump_to_label: "system_fixed_mul_range".to_string()},
+            ZOP::JE{operand1: Operand::new_var(3), operand2: Operand::new_const(0), jump_to_label: "system_fixed_mul_return".to_string()},
+            ZOP::Sub{operand1: Operand::new_const(0), operand2: Operand::new_var(8), save_variable: Variable::new(8)},
+            ZOP::Label{name: "system_fixed_mul_return".to_string()},
+            ZOP::Ret{value: Operand::new_var(8)},
+            ZOP::Label{name: "system_fixed_mul_range".to_string()},
+            ZOP::Print{text: "[number out of range]".to_string()},
+            ZOP::JE{operand1: Operand::new_var(3), operand2: Operand::new_const(0), jump_to_label: "system_fixed_mul_too_large".to_string()},
+            ZOP::Ret{value: Operand::new_large_const(-::std::i16::MAX)},
+            ZOP::Label{name: "system_fixed_mul_too_large".to_string()},
+            ZOP::Ret{value: Operand::new_large_const(::std::i16::MAX)},
+
+            // returns the quotient of var1 and var2
+            // the decimal places are computed one after another like in a
+            // long division of the absolute values
+            ZOP::Routine{name: "system_fixed_div".to_string(), count_variables: 10},
+            // var3 is 1 if the quotient is negative, var4 the result, var5
+            // the remainder, var6 a digit, var7 counts the additions, var8
+            // is the place of the digit, var9 the next remainder and var10
+            // the divisor minus the remainder
+            ZOP::JE{operand1: Operand::new_var(2), operand2: Operand::new_const(0), jump_to_label: "system_fixed_div_zero".to_string()},
+            ZOP::JGE{operand1: Operand::new_var(1), operand2: Operand::new_const(0), jump_to_label: "system_fixed_div_positive1".to_string()},
+            ZOP::Sub{operand1: Operand::new_const(0), operand2: Operand::new_var(1), save_variable: Variable::new(1)},
+            ZOP::StoreVariable{variable: Variable::new(3), value: Operand::new_const(1)},
+            ZOP::Label{name: "system_fixed_div_positive1".to_string()},
+            ZOP::JGE{operand1: Operand::new_var(2), operand2: Operand::new_const(0), jump_to_label: "system_fixed_div_positive2".to_string()},
+            ZOP::Sub{operand1: Operand::new_const(0), operand2: Operand::new_var(2), save_variable: Variable::new(2)},
+            ZOP::Sub{operand1: Operand::new_const(1), operand2: Operand::new_var(3), save_variable: Variable::new(3)},
+            ZOP::Label{name: "system_fixed_div_positive2".to_string()},
+            // -327.68 stays negative
+            ZOP::JL{operand1: Operand::new_var(1), operand2: Operand::new_const(0), jump_to_label: "system_fixed_div_range".to_string()},
+            ZOP::JL{operand1: Operand::new_var(2), operand2: Operand::new_const(0), jump_to_label: "system_fixed_div_range".to_string()},
+            ZOP::Div{operand1: Operand::new_var(1), operand2: Operand::new_var(2), save_variable: Variable::new(4)},
+            ZOP::Mod{operand1: Operand::new_var(1), operand2: Operand::new_var(2), save_variable: Variable::new(5)},
+            ZOP::JG{operand1: Operand::new_var(4), operand2: Operand::new_large_const(max_integer), jump_to_label: "system_fixed_div_range".to_string()},
+            ZOP::Mul{operand1: Operand::new_var(4), operand2: scale.clone(), save_variable: Variable::new(4)},
+            ZOP::StoreVariable{variable: Variable::new(8), value: Operand::new_large_const(FIXED_SCALE / 10)},
+            ZOP::Label{name: "system_fixed_div_loop".to_string()},
+            ZOP::JE{operand1: Operand::new_var(8), operand2: Operand::new_const(0), jump_to_label: "system_fixed_div_sign".to_string()},
+            // the digit is the remainder times 10 divided by the divisor,
+            // the remainder is added ten times and the divisor is taken
+            // away whenever the sum reaches it, so nothing overflows
+            ZOP::StoreVariable{variable: Variable::new(6), value: Operand::new_const(0)},
+            ZOP::StoreVariable{variable: Variable::new(7), value: Operand::new_const(10)},
+            ZOP::StoreVariable{variable: Variable::new(9), value: Operand::new_const(0)},
+            ZOP::Sub{operand1: Operand::new_var(2), operand2: Operand::new_var(5), save_variable: Variable::new(10)},
+            ZOP::Label{name: "system_fixed_div_add".to_string()},
+            ZOP::JE{operand1: Operand::new_var(7), operand2: Operand::new_const(0), jump_to_label: "system_fixed_div_digit".to_string()},
+            ZOP::Dec{variable: 7},
+            ZOP::JGE{operand1: Operand::new_var(9), operand2: Operand::new_var(10), jump_to_label: "system_fixed_div_carry".to_string()},
+            ZOP::Add{operand1: Operand::new_var(9), operand2: Operand::new_var(5), save_variable: Variable::new(9)},
+            ZOP::Jump{jump_to_label: "system_fixed_div_add".to_string()},
+            ZOP::Label{name: "system_fixed_div_carry".to_string()},
+            ZOP::Sub{operand1: Operand::new_var(9), operand2: Operand::new_var(10), save_variable: Variable::new(9)},
+            ZOP::Inc{variable: 6},
+            ZOP::Jump{jump_to_label: "system_fixed_div_add".to_string()},
+            ZOP::Label{name: "system_fixed_div_digit".to_string()},
+            ZOP::StoreVariable{variable: Variable::new(5), value: Operand::new_var(9)},
+            ZOP::Mul{operand1: Operand::new_var(6), operand2: Operand::new_var(8), save_variable: Variable::new(6)},
+            ZOP::Add{operand1: Operand::new_var(4), operand2: Operand::new_var(6), save_variable: Variable::new(4)},
+            ZOP::JL{operand1: Operand::new_var(4), operand2: Operand::new_const(0), jump_to_label: "system_fixed_div_range".to_string()},
+            ZOP::Div{operand1: Operand::new_var(8), operand2: Operand::new_const(10), save_variable: Variable::new(8)},
+            ZOP::Jump{jump_to_label: "system_fixed_div_loop".to_string()},
+            ZOP::Label{name: "system_fixed_div_sign".to_string()},
+            ZOP::JE{operand1: Operand::new_var(3), operand2: Operand::new_const(0), jump_to_label: "system_fixed_div_return".to_string()},
+            ZOP::Sub{operand1: Operand::new_const(0), operand2: Operand::new_var(4), save_variable: Variable::new(4)},
+            ZOP::Label{name: "system_fixed_div_return".to_string()},
+            ZOP::Ret{value: Operand::new_var(4)},
+            ZOP::Label{name: "system_fixed_div_range".to_string()},
+            ZOP::Print{text: "[number out of range]".to_string()},
+            ZOP::JE{operand1: Operand::new_var(3), operand2: Operand::new_const(0), jump_to_label: "system_fixed_div_too_large".to_string()},
+            ZOP::Ret{value: Operand::new_large_const(-::std::i16::MAX)},
+            ZOP::Label{name: "system_fixed_div_too_large".to_string()},
+            ZOP::Ret{value: Operand::new_large_const(::std::i16::MAX)},
+            ZOP::Label{name: "system_fixed_div_zero".to_string()},
+            ZOP::Print{text: "[division by zero]".to_string()},
+            ZOP::Ret{value: Operand::new_const(0)},
+
+            // returns the remainder of var1 divided by var2
+            ZOP::Routine{name: "system_fixed_mod".to_string(), count_variables: 3},
+            ZOP::JE{operand1: Operand::new_var(2), operand2: Operand::new_const(0), jump_to_label: "system_fixed_mod_zero".to_string()},
+            ZOP::Mod{operand1: Operand::new_var(1), operand2: Operand::new_var(2), save_variable: Variable::new(3)},
+            ZOP::Ret{value: Operand::new_var(3)},
+            ZOP::Label{name: "system_fixed_mod_zero".to_string()},
+            ZOP::Print{text: "[division by zero]".to_string()},
+            ZOP::Ret{value: Operand::new_const(0)},
+
+            // returns the largest integer less than or equal to var1
+            ZOP::Routine{name: "system_fixed_floor".to_string(), count_variables: 3},
+            // var2 is the result and var3 the fractional part
+            ZOP::Div{operand1: Operand::new_var(1), operand2: scale.clone(), save_variable: Variable::new(2)},
+            ZOP::JGE{operand1: Operand::new_var(1), operand2: Operand::new_const(0), jump_to_label: "system_fixed_floor_return".to_string()},
+            ZOP::Mod{operand1: Operand::new_var(1), operand2: scale.clone(), save_variable: Variable::new(3)},
+            ZOP::JE{operand1: Operand::new_var(3), operand2: Operand::new_const(0), jump_to_label: "system_fixed_floor_return".to_string()},
+            ZOP::Sub{operand1: Operand::new_var(2), operand2: Operand::new_const(1), save_variable: Variable::new(2)},
+            ZOP::Label{name: "system_fixed_floor_return".to_string()},
+            ZOP::Ret{value: Operand::new_var(2)},
+
+            // returns the integer nearest to var1, halves are rounded up
+            // like in JavaScript
+            ZOP::Routine{name: "system_fixed_round".to_string(), count_variables: 3},
+            // var2 is the result and var3 the fractional part
+            ZOP::Div{operand1: Operand::new_var(1), operand2: scale.clone(), save_variable: Variable::new(2)},
+            ZOP::Mod{operand1: Operand::new_var(1), operand2: scale.clone(), save_variable: Variable::new(3)},
+            ZOP::JL{operand1: Operand::new_var(1), operand2: Operand::new_const(0), jump_to_label: "system_fixed_round_negative".to_string()},
+            ZOP::JL{operand1: Operand::new_var(3), operand2: Operand::new_large_const(FIXED_SCALE / 2), jump_to_label: "system_fixed_round_return".to_string()},
+            ZOP::Inc{variable: 2},
+            ZOP::Jump{jump_to_label: "system_fixed_round_return".to_string()},
+            ZOP::Label{name: "system_fixed_round_negative".to_string()},
+            ZOP::JGE{operand1: Operand::new_var(3), operand2: Operand::new_large_const(-FIXED_SCALE / 2), jump_to_label: "system_fixed_round_return".to_string()},
+            ZOP::Dec{variable: 2},
+            ZOP::Label{name: "system_fixed_round_return".to_string()},
+            ZOP::Ret{value: Operand::new_var(2)},
+
+            // prints var1 with two decimal places
+            ZOP::Routine{name: "system_print_fixed".to_string(), count_variables: 2},
+            ZOP::JGE{operand1: Operand::new_var(1), operand2: Operand::new_const(0), jump_to_label: "system_print_fixed_positive".to_string()},
+            ZOP::Print{text: "-".to_string()},
+            ZOP::Sub{operand1: Operand::new_const(0), operand2: Operand::new_var(1), save_variable: Variable::new(1)},
+            // -327.68 has no positive counterpart
+            ZOP::JGE{operand1: Operand::new_var(1), operand2: Operand::new_const(0), jump_to_label: "system_print_fixed_positive".to_string()},
+            ZOP::Print{text: "327.68".to_string()},
+            ZOP::Ret{value: Operand::new_const(0)},
+            ZOP::Label{name: "system_print_fixed_positive".to_string()},
+            ZOP::Div{operand1: Operand::new_var(1), operand2: scale.clone(), save_variable: Variable::new(2)},
+            ZOP::PrintNumVar{variable: Variable::new(2)},
+            ZOP::Print{text: ".".to_string()},
+            ZOP::Mod{operand1: Operand::new_var(1), operand2: scale.clone(), save_variable: Variable::new(2)},
+            ZOP::JGE{operand1: Operand::new_var(2), operand2: Operand::new_const(10), jump_to_label: "system_print_fixed_fraction".to_string()},
+            ZOP::Print{text: "0".to_string()},
+            ZOP::Label{name: "system_print_fixed_fraction".to_string()},
+            ZOP::PrintNumVar{variable: Variable::new(2)},
+            ZOP::Ret{value: Operand::new_const(0)},
+
+            // converts var1 to a string with two decimal places like itoa
+            ZOP::Routine{name: "system_ftoa".to_string(), count_variables: 6},
+            // var2 is the result, var3 is 1 for negative numbers, var4 the
+            // string of the decimal places, var5 the index and var6 a character
+            ZOP::JGE{operand1: Operand::new_var(1), operand2: Operand::new_const(0), jump_to_label: "system_ftoa_positive".to_string()},
+            ZOP::StoreVariable{variable: Variable::new(3), value: Operand::new_const(1)},
+            ZOP::Sub{operand1: Operand::new_const(0), operand2: Operand::new_var(1), save_variable: Variable::new(1)},
+            ZOP::JGE{operand1: Operand::new_var(1), operand2: Operand::new_const(0), jump_to_label: "system_ftoa_positive".to_string()},
+            ZOP::Ret{value: Operand::new_large_const(min_string_addr as i16)},
+            ZOP::Label{name: "system_ftoa_positive".to_string()},
+            ZOP::Div{operand1: Operand::new_var(1), operand2: scale.clone(), save_variable: Variable::new(2)},
+            ZOP::Call2S{jump_to_label: "itoa".to_string(), arg: Operand::new_var(2), result: Variable::new(2)},
+            ZOP::JE{operand1: Operand::new_var(3), operand2: Operand::new_const(0), jump_to_label: "system_ftoa_fraction".to_string()},
+            ZOP::CallVSA2{jump_to_label: "strcat".to_string(), arg1: Operand::new_large_const(minus_addr as i16), arg2: Operand::new_var(2), result: Variable::new(2)},
+            ZOP::Label{name: "system_ftoa_fraction".to_string()},
+            ZOP::Call2S{jump_to_label: "malloc".to_string(), arg: Operand::new_const(3), result: Variable::new(4)},
+            ZOP::StoreVariable{variable: Variable::new(6), value: Operand::new_const(2)},
+            ZOP::StoreW{array_address: Operand::new_var(4), index: Variable::new(5), variable: Variable::new(6)},
+            ZOP::Mod{operand1: Operand::new_var(1), operand2: scale.clone(), save_variable: Variable::new(1)},
+            ZOP::Inc{variable: 5},
+            ZOP::Div{operand1: Operand::new_var(1), operand2: Operand::new_const(10), save_variable: Variable::new(6)},
+            ZOP::Add{operand1: Operand::new_var(6), operand2: Operand::new_large_const('0' as i16), save_variable: Variable::new(6)},
+            ZOP::StoreW{array_address: Operand::new_var(4), index: Variable::new(5), variable: Variable::new(6)},
+            ZOP::Inc{variable: 5},
+            ZOP::Mod{operand1: Operand::new_var(1), operand2: Operand::new_const(10), save_variable: Variable::new(6)},
+            ZOP::Add{operand1: Operand::new_var(6), operand2: Operand::new_large_const('0' as i16), save_variable: Variable::new(6)},
+            ZOP::StoreW{array_address: Operand::new_var(4), index: Variable::new(5), variable: Variable::new(6)},
+            ZOP::CallVSA2{jump_to_label: "strcat".to_string(), arg1: Operand::new_var(2), arg2: Operand::new_large_const(point_addr as i16), result: Variable::new(2)},
+            ZOP::CallVSA2{jump_to_label: "strcat".to_string(), arg1: Operand::new_var(2), arg2: Operand::new_var(4), result: Variable::new(2)},
+            ZOP::Ret{value: Operand::new_var(2)}
+        ]);
+    }
+
     // ================================
     // specific ops
 
@@ -1185,6 +3104,17 @@ impl Zfile {
         self.add_jump(address.to_string(), JumpType::Routine);
     }
 
+    /// stores the packed address of a routine in a variable
+    /// store is 2OP
+    pub fn op_store_routine_address(&mut self, variable: &Variable, routine: &str) {
+        let args: Vec<ArgType> = vec![ArgType::Reference, ArgType::LargeConst];
+        self.op_2(0x0d, args);
+        self.data.append_byte(variable.id);
+
+        // the address of the routine
+        self.add_jump(routine.to_string(), JumpType::Routine);
+    }
+
     /// calls a routine with one argument an throws result away
     /// call_2n is 2OP
     pub fn op_call_2n_with_arg(&mut self, jump_to_label: &str, arg: &Operand) {
@@ -1402,9 +3332,41 @@ fn align_address(address: u32, align: u32) -> u32 {
     address + (align - (address % align)) % align
 }
 
-/// returns the routine address, should be adress % 8 == 0 (becouse its an packed address)
-fn routine_address(address: u32) -> u32 {
-    return align_address(address, 8);
+/// returns the factor of packed addresses (and of the file length in the header)
+fn packing_factor(version: u8) -> u32 {
+    match version {
+        3 => 2,
+        4 | 5 => 4,
+        _ => 8,
+    }
+}
+
+/// returns the routine address, a multiple of the packing factor (becouse its an packed address)
+fn routine_address(address: u32, version: u8) -> u32 {
+    return align_address(address, packing_factor(version));
+}
+
+/// sets or clears the fixed-pitch bit of flags 2 in the header (bit 1 of
+/// the byte 0x11), the stack is the same afterwards
+fn fixed_pitch_bit(set: bool) -> Vec<u8> {
+    let stack = Variable::new(0);
+    let mut bytes = op::op_add(&Operand::new_const(0x11), &Operand::new_const(0), &stack);
+    bytes.extend(op::op_loadb(&Operand::new_const(0), &stack, &stack).into_iter());
+    if set {
+        bytes.extend(op::op_or(&Operand::new_var(0), &Operand::new_const(2), &stack).into_iter());
+    } else {
+        bytes.extend(op::op_and(&Operand::new_var(0), &Operand::new_const(0xfd), &stack).into_iter());
+    }
+
+    // the index of storeb is popped before the value
+    bytes.extend(op::op_add(&Operand::new_const(0x11), &Operand::new_const(0), &stack).into_iter());
+    bytes.extend(op::op_storeb(&Operand::new_const(0), &stack, &stack).into_iter());
+    bytes
+}
+
+/// returns the serial number of the header for the date, as YYMMDD
+fn serial_number(date: &time::Tm) -> String {
+    format!("{:02}{:02}{:02}", date.tm_year % 100, date.tm_mon + 1, date.tm_mday)
 }
 
 // ================================
@@ -1424,11 +3386,25 @@ fn test_align_address() {
 
 #[test]
 fn test_routine_address() {
-    assert_eq!(routine_address(8), 8);
-    assert_eq!(routine_address(9), 16);
-    assert_eq!(routine_address(10), 16);
-    assert_eq!(routine_address(15), 16);
-    assert_eq!(routine_address(17), 24);
+    assert_eq!(routine_address(8, 8), 8);
+    assert_eq!(routine_address(9, 8), 16);
+    assert_eq!(routine_address(10, 8), 16);
+    assert_eq!(routine_address(15, 8), 16);
+    assert_eq!(routine_address(17, 8), 24);
+
+    assert_eq!(routine_address(9, 5), 12);
+    assert_eq!(routine_address(9, 4), 12);
+    assert_eq!(routine_address(9, 3), 10);
+    assert_eq!(routine_address(10, 3), 10);
+}
+
+#[test]
+fn test_serial_number() {
+    let mut date = time::empty_tm();
+    date.tm_year = 126;
+    date.tm_mon = 9;
+    date.tm_mday = 3;
+    assert_eq!(serial_number(&date), "261003".to_string());
 }
 
 #[test]
@@ -1482,6 +3458,98 @@ fn test_zfile_label_and_jump_loop() {
     assert_eq!(-1 as i16, rel_addr);  // this is the expected result, jump one address back
 }
 
+#[test]
+fn test_zfile_versions() {
+    for &(version, packing) in [(3u8, 2usize), (4, 4), (5, 4), (8, 8)].iter() {
+        let mut zfile: Zfile = Zfile::new_with_options(false, false, true, LinkSelection::Digits, true, version);
+        zfile.start();
+        zfile.emit(vec![
+            ZOP::Routine{name: "Start".to_string(), count_variables: 1},
+            ZOP::Ret{value: Operand::new_const(0)}
+        ]);
+        zfile.end();
+
+        assert_eq!(zfile.data.bytes[0], version);
+        assert_eq!(zfile.data.len() % packing, 0);
+        assert_eq!((zfile.data.bytes[0x1a] as usize * 256 + zfile.data.bytes[0x1b] as usize) * packing, zfile.data.len());
+
+        // undo and the mouse need version 5
+        assert_eq!(zfile.undo, version >= 5);
+        assert_eq!(zfile.mouse, version >= 5);
+    }
+}
+
+#[test]
+fn test_zfile_version_3_ops() {
+    let mut zfile: Zfile = Zfile::new_with_options(false, false, false, LinkSelection::Digits, false, 3);
+
+    // the locals have initial values
+    let (labels, _, bytes) = zfile.write_zop(&ZOP::Routine{name: "routine".to_string(), count_variables: 2});
+    assert_eq!(labels.len(), 1);
+    assert_eq!(bytes, vec![0x02, 0x00, 0x00, 0x00, 0x00]);
+
+    // call_1n is written as call_vs, the result is popped
+    let (_, jumps, bytes) = zfile.write_zop(&ZOP::Call1N{jump_to_label: "routine".to_string()});
+    assert_eq!(jumps.len(), 1);
+    assert_eq!(bytes, vec![0xE0, 0x3F, 0x00, 0x00, 0x00, 0xB9]);
+
+    // unicode is printed as zscii of the default unicode table
+    let (_, _, bytes) = zfile.write_zop(&ZOP::PrintUnicode{c: 'ä' as u16});
+    assert_eq!(bytes, vec![0xE5, 0x7F, 155]);
+    let (_, _, bytes) = zfile.write_zop(&ZOP::PrintUnicode{c: '€' as u16});
+    assert_eq!(bytes, vec![0xE5, 0x7F, 0x3F]);
+
+    // colours, text styles, erasing the screen and the cursor are left out
+    // with a warning
+    for op in vec![ZOP::SetColor{foreground: 9, background: 2}, ZOP::SetColor{foreground: 8, background: 2},
+            ZOP::SetTextStyle{bold: true, reverse: false, monospace: false, italic: false},
+            ZOP::EraseWindow{value: -1}, ZOP::SetCursor{line: Operand::new_const(1), column: Operand::new_const(1)}] {
+        let (_, _, bytes) = zfile.write_zop(&op);
+        assert!(bytes.is_empty(), "{:?}", op);
+    }
+    assert_eq!(zfile.warnings.len(), 5);
+    assert_eq!(zfile.warnings[0], "The character '€' is not in the unicode table of version 3, it is printed as ?".to_string());
+    assert_eq!(zfile.warnings[1], "Version 3 of the Z-Machine has no colours, they are left out".to_string());
+
+    // read_char reads a line, undo always fails
+    let (_, jumps, bytes) = zfile.write_zop(&ZOP::ReadChar{local_var_id: 1});
+    assert_eq!(jumps.len(), 1);
+    assert_eq!(bytes, vec![0xE0, 0x3F, 0x00, 0x00, 0x01]);
+    let (_, _, bytes) = zfile.write_zop(&ZOP::SaveUndo{result: Variable::new(1)});
+    assert_eq!(bytes, vec![0x08, 0x00, 0x00, 0x01]);
+
+    // restore sets the fixed-pitch bit, which tells the save that it was
+    // restored, and clears it again if it failed
+    let (_, _, bytes) = zfile.write_zop(&ZOP::Restore{result: Variable::new(1)});
+    let set = fixed_pitch_bit(true);
+    assert_eq!(&bytes[..set.len()], &set[..]);
+    assert_eq!(&bytes[set.len()..set.len() + 2], &[0xB6, 0xC2]);
+    assert!(bytes.ends_with(&[0x08, 0x00, 0x00, 0x01]));
+    let (_, _, bytes) = zfile.write_zop(&ZOP::Save{result: Variable::new(1)});
+    assert_eq!(&bytes[..2], &[0xB5, 0xC9]);
+    assert!(bytes.ends_with(&fixed_pitch_bit(false)));
+}
+
+#[test]
+fn test_zfile_version_4_ops() {
+    let mut zfile: Zfile = Zfile::new_with_options(false, false, false, LinkSelection::Digits, false, 4);
+
+    // text styles and erase_window exist, colours not
+    let (_, _, bytes) = zfile.write_zop(&ZOP::SetTextStyle{bold: true, reverse: false, monospace: false, italic: false});
+    assert!(!bytes.is_empty());
+    let (_, _, bytes) = zfile.write_zop(&ZOP::EraseWindow{value: -1});
+    assert!(!bytes.is_empty());
+    let (_, _, bytes) = zfile.write_zop(&ZOP::SetColor{foreground: 9, background: 2});
+    assert!(bytes.is_empty());
+    assert_eq!(zfile.warnings, vec!["Version 4 of the Z-Machine has no colours, they are left out".to_string()]);
+
+    // call_2s exists, call_1n not
+    let (_, _, bytes) = zfile.write_zop(&ZOP::Call2S{jump_to_label: "routine".to_string(), arg: Operand::new_const(1), result: Variable::new(2)});
+    assert_eq!(bytes[0], 0xD9);
+    let (_, _, bytes) = zfile.write_zop(&ZOP::Call1N{jump_to_label: "routine".to_string()});
+    assert_eq!(bytes[0], 0xE0);
+}
+
 #[test]
 fn test_op_inc() {
     assert_eq!(op::op_inc(1),vec![0x95,0x01]);
@@ -1526,6 +3594,85 @@ fn test_op_mod() {
     assert_eq!(op::op_mod(&Operand::new_var(1),&Operand::new_var(2),&Variable::new(3)),vec![0x78,0x01,0x02,0x03]);
 }
 
+#[test]
+fn test_op_output_stream() {
+    assert_eq!(op::op_output_stream(-1),vec![0xf3,0x3f,0xff,0xff]);
+    assert_eq!(op::op_output_stream(1),vec![0xf3,0x3f,0x00,0x01]);
+}
+
+#[test]
+fn test_op_save() {
+    assert_eq!(op::op_save(&Variable::new(0)),vec![0xbe,0x00,0xff,0x00]);
+    assert_eq!(op::op_save(&Variable::new(3)),vec![0xbe,0x00,0xff,0x03]);
+}
+
+#[test]
+fn test_op_restore() {
+    assert_eq!(op::op_restore(&Variable::new(0)),vec![0xbe,0x01,0xff,0x00]);
+}
+
+#[test]
+fn test_op_restart() {
+    assert_eq!(op::op_restart(),vec![0xb7]);
+}
+
+#[test]
+fn test_op_save_undo() {
+    assert_eq!(op::op_save_undo(&Variable::new(1)),vec![0xbe,0x09,0xff,0x01]);
+}
+
+#[test]
+fn test_op_restore_undo() {
+    assert_eq!(op::op_restore_undo(&Variable::new(0)),vec![0xbe,0x0a,0xff,0x00]);
+}
+
+#[test]
+fn test_op_split_window() {
+    assert_eq!(op::op_split_window(&Operand::new_var(16)),vec![0xea,0xbf,0x10]);
+    assert_eq!(op::op_split_window(&Operand::new_const(0)),vec![0xea,0x7f,0x00]);
+}
+
+#[test]
+fn test_op_set_window() {
+    assert_eq!(op::op_set_window(1),vec![0xeb,0x7f,0x01]);
+}
+
+#[test]
+fn test_op_set_cursor() {
+    assert_eq!(op::op_set_cursor(&Operand::new_var(2),&Operand::new_const(1)),vec![0xef,0x9f,0x02,0x01]);
+}
+
+#[test]
+fn test_op_get_cursor() {
+    assert_eq!(op::op_get_cursor(&Operand::new_large_const(0x0400)),vec![0xf0,0x3f,0x04,0x00]);
+}
+
+#[test]
+fn test_op_read_mouse() {
+    assert_eq!(op::op_read_mouse(&Operand::new_large_const(0x0400)),vec![0xbe,0x16,0x3f,0x04,0x00]);
+}
+
+#[test]
+fn test_op_mouse_window() {
+    assert_eq!(op::op_mouse_window(&Operand::new_const(1)),vec![0xbe,0x17,0x7f,0x01]);
+}
+
+#[test]
+fn test_zfile_emulated_mouse_ops() {
+    let mut zfile: Zfile = Zfile::new_with_options(false, false, false, LinkSelection::Digits, true, 5);
+    zfile.extension_addr = 0x8e;
+
+    // read_mouse copies y and x from the header extension table to the array
+    let (_, _, bytes) = zfile.write_zop(&ZOP::ReadMouse{array_address: Operand::new_large_const(0x0400)});
+    assert_eq!(bytes, vec![
+        0x14,0x02,0x00,0x00, 0xcf,0x2f,0x00,0x8e,0x00,0x00, 0x14,0x00,0x00,0x00, 0xe1,0x2b,0x04,0x00,0x00,0x00,
+        0x14,0x01,0x00,0x00, 0xcf,0x2f,0x00,0x8e,0x00,0x00, 0x14,0x01,0x00,0x00, 0xe1,0x2b,0x04,0x00,0x00,0x00]);
+
+    // there is no mouse window
+    let (_, _, bytes) = zfile.write_zop(&ZOP::MouseWindow{window: Operand::new_const(0)});
+    assert!(bytes.is_empty());
+}
+
 #[test]
 fn test_op_and() {
     assert_eq!(op::op_and(&Operand::new_var(1),&Operand::new_var(2),&Variable::new(3)),vec![0x69,0x01,0x02,0x03]);
@@ -1581,6 +3728,31 @@ fn test_op_read_char() {
     assert_eq!(op::op_read_char(0x01),vec![0xF6,0x7F,0x00,0x01]);
 }
 
+#[test]
+fn test_op_print_char() {
+    assert_eq!(op::op_print_char(&Operand::new_const(0x61)),vec![0xE5,0x7F,0x61]);
+}
+
+#[test]
+fn test_op_sread() {
+    assert_eq!(op::op_sread(&Operand::new_large_const(0x073e), &Operand::new_large_const(0x0754)),vec![0xE4,0x0F,0x07,0x3E,0x07,0x54]);
+}
+
+#[test]
+fn test_op_aread() {
+    assert_eq!(op::op_aread(&Operand::new_large_const(0x073e), &Operand::new_const(0), &Variable::new(0x10)),vec![0xE4,0x1F,0x07,0x3E,0x00,0x10]);
+}
+
+#[test]
+fn test_op_pop() {
+    assert_eq!(op::op_pop(),vec![0xB9]);
+}
+
+#[test]
+fn test_op_loadb() {
+    assert_eq!(op::op_loadb(&Operand::new_var(1),&Variable::new(2),&Variable::new(3)),vec![0x70,0x01,0x02,0x03]);
+}
+
 #[test]
 fn test_op_loadw() {
     assert_eq!(op::op_loadw(&Operand::new_var(1),&Variable::new(2),&Variable::new(3)),vec![0x6F,0x01,0x02,0x03]);
@@ -0,0 +1,558 @@
+//! The `zrun` module runs the ops recorded by a `Zfile` like an interpreter
+//! would, so the tests can check what the generated code prints.
+//!
+//! Only the ops of version 5 and 8 are run, the lowering for older versions
+//! is tested on the written bytes. Colours, text styles and windows are
+//! ignored, random numbers are always the lowest possible number. The screen
+//! has 80 columns without wrapping and scrolls after its last row.
+
+use std::collections::HashMap;
+
+use super::zfile::{Zfile, ZOP, Operand};
+
+/// the routine addresses start above the heap, so they are never taken for
+/// the address of a string or an array
+const ROUTINE_BASE: u16 = 0xc000;
+
+/// runs that take longer are most likely stuck in a loop
+const MAX_STEPS: usize = 20_000_000;
+
+/// the number of undo snapshots kept, like interpreters the oldest one is
+/// dropped when there are more
+const MAX_UNDO: usize = 10;
+
+#[derive(Clone)]
+struct Frame {
+    locals: Vec<u16>,
+    return_to: usize,
+    result: Option<u8>,
+}
+
+/// the state saved by save or save_undo
+#[derive(Clone)]
+struct Snapshot {
+    memory: Vec<u8>,
+    frames: Vec<Frame>,
+    stack: Vec<u16>,
+    pc: usize,
+    result: u8,
+}
+
+pub struct Machine<'a> {
+    ops: &'a Vec<ZOP>,
+    labels: HashMap<String, usize>,
+    routines: Vec<String>,
+    memory: Vec<u8>,
+    global_addr: usize,
+    frames: Vec<Frame>,
+    stack: Vec<u16>,
+    pc: usize,
+    keys: Vec<u16>,
+    screen: bool,
+    /// the row and column of the cursor, counted from 1
+    cursor: (u16, u16),
+    /// the number of rows, a newline in the last one scrolls the screen
+    height: u16,
+    saved: Option<Snapshot>,
+    undo: Vec<Snapshot>,
+    steps: usize,
+    /// the return value of the routine started with `call`
+    returned: u16,
+    /// the story ended with quit or restart
+    pub finished: bool,
+    /// everything printed to the screen
+    pub output: String,
+}
+
+impl<'a> Machine<'a> {
+    /// loads the memory and the ops the zfile recorded since `record_ops`,
+    /// `end` must have been called
+    pub fn new(zfile: &'a Zfile) -> Machine<'a> {
+        assert!(!zfile.ops().is_empty(), "the ops of the zfile are not recorded");
+
+        let mut labels: HashMap<String, usize> = HashMap::new();
+        let mut routines: Vec<String> = Vec::new();
+        for (i, op) in zfile.ops().iter().enumerate() {
+            match op {
+                &ZOP::Routine{ref name, ..} => {
+                    labels.insert(name.clone(), i);
+                    routines.push(name.clone());
+                },
+                &ZOP::Label{ref name} => { labels.insert(name.clone(), i); },
+                _ => ()
+            }
+        }
+
+        let mut memory = zfile.data.bytes.clone();
+        if memory.len() < 0x10000 {
+            memory.resize(0x10000, 0);
+        }
+        // the interpreter writes the size of the screen to the header
+        memory[0x20] = 25;
+        memory[0x21] = 80;
+        let global_addr = ((memory[0x0c] as usize) << 8) + memory[0x0d] as usize;
+
+        Machine {
+            ops: zfile.ops(),
+            labels: labels,
+            routines: routines,
+            memory: memory,
+            global_addr: global_addr,
+            frames: vec![Frame{locals: vec![0; 15], return_to: 0, result: None}],
+            stack: Vec::new(),
+            pc: 0,
+            keys: Vec::new(),
+            screen: true,
+            cursor: (1, 1),
+            height: 25,
+            saved: None,
+            undo: Vec::new(),
+            steps: 0,
+            returned: 0,
+            finished: false,
+            output: String::new(),
+        }
+    }
+
+    /// adds keys for read_char, a newline is the enter key
+    pub fn type_keys(&mut self, keys: &str) {
+        for c in keys.chars() {
+            self.keys.push(if c == '\n' { 13 } else { c as u16 });
+        }
+    }
+
+    /// adds a key by its zscii code, like 129 for cursor up
+    pub fn type_key(&mut self, key: u16) {
+        self.keys.push(key);
+    }
+
+    /// changes the number of rows of the screen, which is 25 at the start
+    pub fn set_screen_height(&mut self, height: u8) {
+        self.height = height as u16;
+        self.memory[0x20] = height;
+    }
+
+    /// clicks the mouse at the row and column, the font size is 0 in the
+    /// header, so the coordinates are the same as the ones of the cursor
+    pub fn click(&mut self, row: u16, column: u16) {
+        let extension_addr = self.read_word(0x36) as usize;
+        self.write_word(extension_addr + 2, column);
+        self.write_word(extension_addr + 4, row);
+        self.keys.push(254);
+    }
+
+    /// runs the story until it ends or waits for a key that wasn't typed
+    pub fn run(&mut self) {
+        while !self.finished && self.step() {}
+    }
+
+    /// calls the routine with the arguments and returns its result
+    pub fn call(&mut self, routine: &str, args: &[i16]) -> i16 {
+        let depth = self.frames.len();
+        let pc = self.pc;
+        let args: Vec<u16> = args.iter().map(|arg| *arg as u16).collect();
+        self.call_routine(routine, args, None);
+        while self.frames.len() > depth {
+            if !self.step() {
+                panic!("routine {} waits for a key or quit", routine);
+            }
+        }
+        self.pc = pc;
+        self.returned as i16
+    }
+
+    /// returns the value of a global variable
+    pub fn global(&self, id: u8) -> i16 {
+        self.read_word(self.global_addr + 2 * (id as usize - 16)) as i16
+    }
+
+    /// returns the word at the address
+    pub fn word(&self, addr: u16) -> i16 {
+        self.read_word(addr as usize) as i16
+    }
+
+    /// returns the string at the address, the first word is the length and
+    /// then a word for each character follows
+    pub fn string(&self, addr: u16) -> String {
+        let addr = addr as usize;
+        let length = self.read_word(addr) as usize;
+        let mut string = String::new();
+        for i in 0..length {
+            string.push(char_of(self.read_word(addr + 2 + 2 * i)));
+        }
+        string
+    }
+
+    fn read_word(&self, addr: usize) -> u16 {
+        ((self.memory[addr] as u16) << 8) + self.memory[addr + 1] as u16
+    }
+
+    fn write_word(&mut self, addr: usize, value: u16) {
+        self.memory[addr] = (value >> 8) as u8;
+        self.memory[addr + 1] = (value & 0xff) as u8;
+    }
+
+    fn read_var(&mut self, id: u8) -> u16 {
+        match id {
+            0 => self.stack.pop().expect("the stack is empty"),
+            1...15 => self.frames.last().unwrap().locals[id as usize - 1],
+            _ => {
+                let addr = self.global_addr + 2 * (id as usize - 16);
+                self.read_word(addr)
+            }
+        }
+    }
+
+    fn write_var(&mut self, id: u8, value: u16) {
+        match id {
+            0 => self.stack.push(value),
+            1...15 => self.frames.last_mut().unwrap().locals[id as usize - 1] = value,
+            _ => {
+                let addr = self.global_addr + 2 * (id as usize - 16);
+                self.write_word(addr, value);
+            }
+        }
+    }
+
+    fn value(&mut self, operand: &Operand) -> u16 {
+        match operand {
+            &Operand::Var(ref var) => self.read_var(var.id),
+            &Operand::Const(ref constant) => constant.value as u16,
+            &Operand::LargeConst(ref constant) | &Operand::StringRef(ref constant) => constant.value as u16,
+        }
+    }
+
+    fn print(&mut self, text: &str) {
+        if self.screen {
+            self.output.push_str(text);
+            for c in text.chars() {
+                self.cursor = if c != '\n' {
+                    (self.cursor.0, self.cursor.1 + 1)
+                } else if self.cursor.0 < self.height {
+                    (self.cursor.0 + 1, 1)
+                } else {
+                    // the screen scrolls, the cursor stays in the last row
+                    (self.cursor.0, 1)
+                };
+            }
+        }
+    }
+
+    fn label(&self, name: &str) -> usize {
+        match self.labels.get(name) {
+            Some(index) => *index,
+            None => panic!("no label {}", name)
+        }
+    }
+
+    fn routine_address(&self, name: &str) -> u16 {
+        match self.routines.iter().position(|routine| routine == name) {
+            Some(index) => ROUTINE_BASE + index as u16,
+            None => panic!("no routine {}", name)
+        }
+    }
+
+    fn call_routine(&mut self, name: &str, args: Vec<u16>, result: Option<u8>) {
+        let index = self.label(name);
+        let count = match self.ops[index] {
+            ZOP::Routine{count_variables, ..} => count_variables as usize,
+            _ => panic!("{} is a label, not a routine", name)
+        };
+        let mut locals: Vec<u16> = vec![0; 15];
+        for (i, arg) in args.into_iter().enumerate().take(count) {
+            locals[i] = arg;
+        }
+        self.frames.push(Frame{locals: locals, return_to: self.pc, result: result});
+        self.pc = index + 1;
+    }
+
+    /// calls the routine at the address in a variable, address 0 does nothing
+    fn call_address(&mut self, address: u16, args: Vec<u16>, result: Option<u8>) {
+        if address == 0 {
+            if let Some(result) = result {
+                self.write_var(result, 0);
+            }
+            return;
+        }
+        let name = self.routines[(address - ROUTINE_BASE) as usize].clone();
+        self.call_routine(&name, args, result);
+    }
+
+    fn snapshot(&self, result: u8) -> Snapshot {
+        Snapshot{memory: self.memory.clone(), frames: self.frames.clone(), stack: self.stack.clone(),
+            pc: self.pc, result: result}
+    }
+
+    /// continues after the save op of the snapshot, which now stores 2, or
+    /// stores 0 if nothing was saved
+    fn restore(&mut self, snapshot: Option<Snapshot>, result: u8) {
+        match snapshot {
+            Some(snapshot) => {
+                self.memory = snapshot.memory;
+                self.frames = snapshot.frames;
+                self.stack = snapshot.stack;
+                self.pc = snapshot.pc;
+                self.write_var(snapshot.result, 2);
+            },
+            None => self.write_var(result, 0)
+        }
+    }
+
+    fn branch(&mut self, condition: bool, label: &str) {
+        if condition {
+            self.pc = self.label(label);
+        }
+    }
+
+    fn arithmetic(&mut self, operand1: &Operand, operand2: &Operand, save: u8, op: fn(i16, i16) -> i16) {
+        let a = self.value(operand1) as i16;
+        let b = self.value(operand2) as i16;
+        self.write_var(save, op(a, b) as u16);
+    }
+
+    /// runs one op, returns false if the story waits for a key
+    fn step(&mut self) -> bool {
+        self.steps += 1;
+        assert!(self.steps < MAX_STEPS, "the story runs too long");
+
+        let ops = self.ops;
+        let op = match ops.get(self.pc) {
+            Some(op) => op,
+            None => {
+                self.finished = true;
+                return false;
+            }
+        };
+        self.pc += 1;
+
+        match op {
+            &ZOP::Print{ref text} | &ZOP::PrintOps{ref text} => self.print(text),
+            &ZOP::PrintNumVar{ref variable} => {
+                let value = self.read_var(variable.id) as i16;
+                self.print(&format!("{}", value));
+            },
+            &ZOP::PrintUnicode{c} => self.print(&char_of(c).to_string()),
+            &ZOP::PrintUnicodeVar{ref var} => {
+                let c = self.read_var(var.id);
+                self.print(&char_of(c).to_string());
+            },
+            &ZOP::PrintChar{ref var} => {
+                let c = self.read_var(var.id);
+                self.print(&char_of(c).to_string());
+            },
+            &ZOP::PrintUnicodeStr{ref address} => {
+                let address = self.value(address);
+                let text = self.string(address);
+                self.print(&text);
+            },
+            &ZOP::Newline => self.print("\n"),
+
+            &ZOP::Call1N{ref jump_to_label} => self.call_routine(jump_to_label, vec![], None),
+            &ZOP::Call2NWithAddress{ref jump_to_label, ref address} => {
+                let address = self.routine_address(address);
+                self.call_routine(jump_to_label, vec![address], None);
+            },
+            &ZOP::Call2NWithArg{ref jump_to_label, ref arg} => {
+                let arg = self.value(arg);
+                self.call_routine(jump_to_label, vec![arg], None);
+            },
+            &ZOP::Call1NVar{variable} => {
+                let address = self.read_var(variable);
+                self.call_address(address, vec![], None);
+            },
+            &ZOP::Call2S{ref jump_to_label, ref arg, ref result} => {
+                let arg = self.value(arg);
+                self.call_routine(jump_to_label, vec![arg], Some(result.id));
+            },
+            &ZOP::CallVNA2{ref jump_to_label, ref arg1, ref arg2} => {
+                let args = vec![self.value(arg1), self.value(arg2)];
+                self.call_routine(jump_to_label, args, None);
+            },
+            &ZOP::CallVNA3{ref jump_to_label, ref arg1, ref arg2, ref arg3} => {
+                let args = vec![self.value(arg1), self.value(arg2), self.value(arg3)];
+                self.call_routine(jump_to_label, args, None);
+            },
+            &ZOP::CallVSA2{ref jump_to_label, ref arg1, ref arg2, ref result} => {
+                let args = vec![self.value(arg1), self.value(arg2)];
+                self.call_routine(jump_to_label, args, Some(result.id));
+            },
+            &ZOP::CallVSA3{ref jump_to_label, ref arg1, ref arg2, ref arg3, ref result} => {
+                let args = vec![self.value(arg1), self.value(arg2), self.value(arg3)];
+                self.call_routine(jump_to_label, args, Some(result.id));
+            },
+            &ZOP::StoreRoutineAddress{ref variable, ref routine} => {
+                let address = self.routine_address(routine);
+                self.write_var(variable.id, address);
+            },
+            &ZOP::Ret{ref value} => {
+                let value = self.value(value);
+                let frame = self.frames.pop().expect("return without a routine");
+                self.pc = frame.return_to;
+                self.returned = value;
+                if let Some(result) = frame.result {
+                    self.write_var(result, value);
+                }
+            },
+            &ZOP::Routine{ref name, ..} => panic!("the program runs into the routine {}", name),
+            &ZOP::Label{..} => (),
+
+            &ZOP::StoreVariable{ref variable, ref value} => {
+                let value = self.value(value);
+                self.write_var(variable.id, value);
+            },
+            &ZOP::StoreW{ref array_address, ref index, ref variable} => {
+                let addr = self.value(array_address) as usize + 2 * self.read_var(index.id) as usize;
+                let value = self.read_var(variable.id);
+                self.write_word(addr & 0xffff, value);
+            },
+            &ZOP::StoreB{ref array_address, ref index, ref variable} => {
+                let addr = self.value(array_address) as usize + self.read_var(index.id) as usize;
+                let value = self.read_var(variable.id);
+                self.memory[addr & 0xffff] = value as u8;
+            },
+            &ZOP::LoadW{ref array_address, ref index, ref variable} => {
+                let addr = self.value(array_address) as usize + 2 * self.read_var(index.id) as usize;
+                let value = self.read_word(addr & 0xffff);
+                self.write_var(variable.id, value);
+            },
+            &ZOP::LoadB{ref array_address, ref index, ref variable} => {
+                let addr = self.value(array_address) as usize + self.read_var(index.id) as usize;
+                let value = self.memory[addr & 0xffff] as u16;
+                self.write_var(variable.id, value);
+            },
+            &ZOP::Inc{variable} => {
+                let value = self.read_var(variable).wrapping_add(1);
+                self.write_var(variable, value);
+            },
+            &ZOP::Dec{variable} => {
+                let value = self.read_var(variable).wrapping_sub(1);
+                self.write_var(variable, value);
+            },
+
+            &ZOP::Add{ref operand1, ref operand2, ref save_variable} =>
+                self.arithmetic(operand1, operand2, save_variable.id, add),
+            &ZOP::Sub{ref operand1, ref operand2, ref save_variable} =>
+                self.arithmetic(operand1, operand2, save_variable.id, sub),
+            &ZOP::Mul{ref operand1, ref operand2, ref save_variable} =>
+                self.arithmetic(operand1, operand2, save_variable.id, mul),
+            &ZOP::Div{ref operand1, ref operand2, ref save_variable} =>
+                self.arithmetic(operand1, operand2, save_variable.id, div),
+            &ZOP::Mod{ref operand1, ref operand2, ref save_variable} =>
+                self.arithmetic(operand1, operand2, save_variable.id, rem),
+            &ZOP::Or{ref operand1, ref operand2, ref save_variable} =>
+                self.arithmetic(operand1, operand2, save_variable.id, or),
+            &ZOP::And{ref operand1, ref operand2, ref save_variable} =>
+                self.arithmetic(operand1, operand2, save_variable.id, and),
+            &ZOP::Random{ref range, ref variable} => {
+                let range = self.value(range) as i16;
+                self.write_var(variable.id, if range > 0 { 1 } else { 0 });
+            },
+
+            &ZOP::Jump{ref jump_to_label} => self.pc = self.label(jump_to_label),
+            &ZOP::JE{ref operand1, ref operand2, ref jump_to_label} => {
+                let (a, b) = (self.value(operand1), self.value(operand2));
+                self.branch(a == b, jump_to_label);
+            },
+            &ZOP::JNE{ref operand1, ref operand2, ref jump_to_label} => {
+                let (a, b) = (self.value(operand1), self.value(operand2));
+                self.branch(a != b, jump_to_label);
+            },
+            &ZOP::JL{ref operand1, ref operand2, ref jump_to_label} => {
+                let (a, b) = (self.value(operand1) as i16, self.value(operand2) as i16);
+                self.branch(a < b, jump_to_label);
+            },
+            &ZOP::JLE{ref operand1, ref operand2, ref jump_to_label} => {
+                let (a, b) = (self.value(operand1) as i16, self.value(operand2) as i16);
+                self.branch(a <= b, jump_to_label);
+            },
+            &ZOP::JG{ref operand1, ref operand2, ref jump_to_label} => {
+                let (a, b) = (self.value(operand1) as i16, self.value(operand2) as i16);
+                self.branch(a > b, jump_to_label);
+            },
+            &ZOP::JGE{ref operand1, ref operand2, ref jump_to_label} => {
+                let (a, b) = (self.value(operand1) as i16, self.value(operand2) as i16);
+                self.branch(a >= b, jump_to_label);
+            },
+
+            &ZOP::ReadChar{local_var_id} | &ZOP::ReadCharTimer{local_var_id, ..} => {
+                if self.keys.is_empty() {
+                    self.pc -= 1;
+                    return false;
+                }
+                let key = self.keys.remove(0);
+                self.write_var(local_var_id, key);
+            },
+
+            &ZOP::OutputStream{stream} => match stream {
+                1 => self.screen = true,
+                -1 => self.screen = false,
+                _ => ()
+            },
+            &ZOP::GetCursor{ref array_address} => {
+                let addr = self.value(array_address) as usize;
+                let (row, column) = self.cursor;
+                self.write_word(addr, row);
+                self.write_word(addr + 2, column);
+            },
+            // like the emulation of the versions before 6, only y and x are written
+            &ZOP::ReadMouse{ref array_address} => {
+                let addr = self.value(array_address) as usize;
+                let extension_addr = self.read_word(0x36) as usize;
+                let (x, y) = (self.read_word(extension_addr + 2), self.read_word(extension_addr + 4));
+                self.write_word(addr, y);
+                self.write_word(addr + 2, x);
+            },
+            &ZOP::EraseWindow{value} => if value < 1 { self.cursor = (1, 1) },
+            &ZOP::SetColor{..} | &ZOP::SetColorVar{..} | &ZOP::SetTextStyle{..} | &ZOP::MouseWindow{..} |
+            &ZOP::SplitWindow{..} | &ZOP::SetWindow{..} | &ZOP::SetCursor{..} => (),
+
+            // the saved game is kept in memory instead of a file
+            &ZOP::Save{ref result} => {
+                self.saved = Some(self.snapshot(result.id));
+                self.write_var(result.id, 1);
+            },
+            &ZOP::Restore{ref result} => {
+                let saved = self.saved.clone();
+                self.restore(saved, result.id);
+            },
+            &ZOP::SaveUndo{ref result} => {
+                if self.undo.len() == MAX_UNDO {
+                    self.undo.remove(0);
+                }
+                let snapshot = self.snapshot(result.id);
+                self.undo.push(snapshot);
+                self.write_var(result.id, 1);
+            },
+            &ZOP::RestoreUndo{ref result} => {
+                let undo = self.undo.pop();
+                self.restore(undo, result.id);
+            },
+            &ZOP::Quit | &ZOP::Restart => self.finished = true,
+
+            _ => panic!("zrun can't run {:?}", op)
+        }
+
+        true
+    }
+}
+
+fn char_of(c: u16) -> char {
+    match ::std::char::from_u32(c as u32) {
+        Some(c) => c,
+        None => '?'
+    }
+}
+
+fn add(a: i16, b: i16) -> i16 { a.wrapping_add(b) }
+fn sub(a: i16, b: i16) -> i16 { a.wrapping_sub(b) }
+fn mul(a: i16, b: i16) -> i16 { a.wrapping_mul(b) }
+fn div(a: i16, b: i16) -> i16 {
+    assert!(b != 0, "division by zero");
+    a.wrapping_div(b)
+}
+fn rem(a: i16, b: i16) -> i16 {
+    assert!(b != 0, "division by zero");
+    a.wrapping_rem(b)
+}
+fn or(a: i16, b: i16) -> i16 { a | b }
+fn and(a: i16, b: i16) -> i16 { a & b }
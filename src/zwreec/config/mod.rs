@@ -165,9 +165,34 @@ pub struct Config {
     pub force: bool,
     /// Add easter egg to compiler
     pub easter_egg: bool,
+    /// The easter egg was enabled with `-F easter-egg`, so a version of the
+    /// Z-Machine without it is an error instead of leaving it out
+    pub easter_egg_requested: bool,
     pub force_unicode: bool,
+    /// Take an undo snapshot before every passage jump and offer an undo key
+    pub undo: bool,
+    /// Let the player click on links
+    pub mouse: bool,
+    /// Allow decimal numbers, they are compiled to fixed-point numbers with
+    /// two decimal places
+    pub fixed_point: bool,
     /// Instruct compiler to run these test-cases
     pub test_cases: Vec<TestCase>,
+    /// Name of the input file, shown in error messages and used to find the
+    /// files of `StoryIncludes` (see `compile`)
+    pub input_name: Option<String>,
+    /// The format of the input file
+    pub input_format: InputFormat,
+    /// How formatting without a matching Z-Machine text style is rendered
+    pub format_fallback: FormatFallback,
+    /// How the player selects a link at the end of a passage
+    pub link_selection: LinkSelection,
+    /// The version of the Z-Machine the story is compiled for (3, 4, 5 or 8)
+    pub zversion: u8,
+    /// The format of the output file
+    pub output_format: OutputFormat,
+    /// Name of the PNG or JPEG image added to a Blorb file as its cover
+    pub cover_image: Option<String>,
 }
 
 impl Config {
@@ -183,8 +208,19 @@ impl Config {
         Config{
             force: false,
             easter_egg: true,
+            easter_egg_requested: false,
             force_unicode: false,
+            undo: false,
+            mouse: false,
+            fixed_point: false,
             test_cases: Vec::new(),
+            input_name: None,
+            input_format: InputFormat::Auto,
+            format_fallback: FormatFallback::Style,
+            link_selection: LinkSelection::Digits,
+            zversion: 8,
+            output_format: OutputFormat::Zcode,
+            cover_image: None,
         }
     }
 
@@ -220,17 +256,101 @@ impl Config {
             cfg.force = true;
         }
 
+        if let Some(name) = matches.free.first() {
+            cfg.input_name = Some(name.clone());
+        }
+
+        if let Some(s) = matches.opt_str("input-format") {
+            match s.as_ref() {
+                "auto" => cfg.input_format = InputFormat::Auto,
+                "twee" => cfg.input_format = InputFormat::Twee,
+                "twine2" => cfg.input_format = InputFormat::Twine2,
+                _ => {
+                    error!("Cannot use input format {} - expected auto, twee or twine2.", s);
+                }
+            }
+            debug!("using input format {:?}", cfg.input_format);
+        }
+
+        if let Some(s) = matches.opt_str("format-fallback") {
+            match s.as_ref() {
+                "style" => cfg.format_fallback = FormatFallback::Style,
+                "text" => cfg.format_fallback = FormatFallback::Text,
+                "plain" => cfg.format_fallback = FormatFallback::Plain,
+                _ => {
+                    error!("Cannot use format fallback {} - expected style, text or plain.", s);
+                }
+            }
+            debug!("using format fallback {:?}", cfg.format_fallback);
+        }
+
+        if let Some(s) = matches.opt_str("link-selection") {
+            match s.as_ref() {
+                "digits" => cfg.link_selection = LinkSelection::Digits,
+                "letters" => cfg.link_selection = LinkSelection::Letters,
+                "cursor" => cfg.link_selection = LinkSelection::Cursor,
+                _ => {
+                    error!("Cannot use link selection {} - expected digits, letters or cursor.", s);
+                }
+            }
+            debug!("using link selection {:?}", cfg.link_selection);
+        }
+
+        if let Some(s) = matches.opt_str("zversion") {
+            match s.as_ref() {
+                "3" => cfg.zversion = 3,
+                "4" => cfg.zversion = 4,
+                "5" => cfg.zversion = 5,
+                "8" => cfg.zversion = 8,
+                _ => {
+                    error!("Cannot compile for Z-Machine version {} - expected 3, 4, 5 or 8.", s);
+                }
+            }
+            debug!("using Z-Machine version {}", cfg.zversion);
+        }
+
+        if let Some(s) = matches.opt_str("output-format") {
+            match s.as_ref() {
+                "zcode" => cfg.output_format = OutputFormat::Zcode,
+                "blorb" => cfg.output_format = OutputFormat::Blorb,
+                _ => {
+                    error!("Cannot use output format {} - expected zcode or blorb.", s);
+                }
+            }
+            debug!("using output format {:?}", cfg.output_format);
+        }
+
+        if let Some(s) = matches.opt_str("cover") {
+            if cfg.output_format != OutputFormat::Blorb {
+                warn!("The cover image {} is only used with --output-format blorb", s);
+            }
+            cfg.cover_image = Some(s);
+        }
+
         // TODO: Find a way to make these two loops somewhat less.. repetitive
         for s in matches.opt_strs("F") {
             match s.as_ref() {
                 "easter-egg" => {
                      cfg.easter_egg = true;
+                     cfg.easter_egg_requested = true;
                      debug!("enabled easter-egg");
                 },
                 "force-unicode" => {
                      cfg.force_unicode = true;
                      debug!("enabled force-unicode");
                 },
+                "undo" => {
+                     cfg.undo = true;
+                     debug!("enabled undo");
+                },
+                "mouse" => {
+                     cfg.mouse = true;
+                     debug!("enabled mouse");
+                },
+                "fixed-point" => {
+                     cfg.fixed_point = true;
+                     debug!("enabled fixed-point");
+                },
                 _ => {
                     error!("Cannot enable feature {} - feature not known.", s);
                 }
@@ -241,12 +361,25 @@ impl Config {
             match s.as_ref() {
                 "easter-egg" => {
                     cfg.easter_egg = false;
+                    cfg.easter_egg_requested = false;
                     debug!("disabled easter-egg");
                 },
                 "force-unicode" => {
                      cfg.force_unicode = false;
                      debug!("enabled force-unicode");
                 },
+                "undo" => {
+                     cfg.undo = false;
+                     debug!("disabled undo");
+                },
+                "mouse" => {
+                     cfg.mouse = false;
+                     debug!("disabled mouse");
+                },
+                "fixed-point" => {
+                     cfg.fixed_point = false;
+                     debug!("disabled fixed-point");
+                },
                 _ => {
                     error!("Cannot disable feature {} - feature not known.", s);
                 }
@@ -257,6 +390,59 @@ impl Config {
     }
 }
 
+/// The format of the input file
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum InputFormat {
+    /// Twine 2 HTML is detected by its `<tw-storydata>` element, everything
+    /// else is read as Twee
+    Auto,
+    /// Twee source
+    Twee,
+    /// A Twine 2 archive or published story (see `frontend::twine2`)
+    Twine2,
+}
+
+/// The rendering of underline, strikethrough, superscript and subscript text
+///
+/// The Z-Machine only knows bold, italic, monospace and reverse text, so these
+/// formats are approximated.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum FormatFallback {
+    /// Underline is rendered in italics, the other formats are marked with
+    /// characters as in `Text`
+    Style,
+    /// All formats are marked with characters: `_underline_`, `-strike-`,
+    /// `^(sup)` and `_(sub)`
+    Text,
+    /// The text is printed without any formatting
+    Plain,
+}
+
+/// The keys used to select a link at the end of a passage
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum LinkSelection {
+    /// Links are numbered. With up to nine links a single digit selects one,
+    /// otherwise the number is typed and confirmed with Enter
+    Digits,
+    /// Links are marked with the letters `a` to `z`, the reserved keys for
+    /// save, restore, restart and undo are upper case. A passage can have at
+    /// most 26 links
+    Letters,
+    /// The links are listed in a menu, the arrow keys move the reverse video
+    /// highlight and Enter selects the link
+    Cursor,
+}
+
+/// The format of the output file
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum OutputFormat {
+    /// The story file that interpreters run
+    Zcode,
+    /// A Blorb file with the story, its iFiction metadata and an optional
+    /// cover image (see `backend::blorb`)
+    Blorb,
+}
+
 // TODO: If this stays only one Test Case, enum should be removed
 /// The Type used to define backend tests for the compiler
 #[derive(PartialEq)]
@@ -332,6 +518,19 @@ pub fn zwreec_options(mut opts: getopts::Options) -> getopts::Options {
         For more information about the supported features run --help with -v and see the feature
         list at the end of the output", "FEAT");
     opts.optflag("e", "generate-sample-zcode", "Write out a sample zcode file, input file is not used and can be omitted");
+    opts.optopt("", "input-format", "Read the input as twee or as Twine 2 HTML (twine2). Default is
+        auto, which detects Twine 2 HTML by its <tw-storydata> element", "FORMAT");
+    opts.optopt("", "format-fallback", "Render underline, strikethrough, superscript and subscript text
+        as style (underline in italics, the others marked with characters), text (all marked with
+        characters) or plain (no formatting). Default is style", "MODE");
+    opts.optopt("", "link-selection", "Select links with digits (multiple digits are confirmed
+        with Enter), letters (a to z) or a cursor menu (arrow keys and Enter). Default is digits", "MODE");
+    opts.optopt("z", "zversion", "Compile for version 3, 4, 5 or 8 of the Z-Machine. Undo, mouse
+        clicks and the easter egg need version 5, text styles and the cursor menu version 4.
+        Default is 8", "VERSION");
+    opts.optopt("", "output-format", "Write the story as zcode or as a Blorb file (blorb) with
+        its IFID, title and author. Default is zcode", "FORMAT");
+    opts.optopt("", "cover", "Add a PNG or JPEG image as cover to the Blorb file", "IMAGE");
 
     opts
 }
@@ -350,7 +549,18 @@ pub fn zwreec_usage(verbose: bool, opts: getopts::Options, brief: &str) -> Strin
     force-unicode (disabled)
         Force the generation of unicode print opcodes every time a unicode
         character is encountered. This disables the generation of the unicode
-        translation table"
+        translation table
+    undo (disabled)
+        Save the game state before every passage jump, so the player can take
+        back the last choices with the u key or the story with <<undo>>. How
+        many choices can be undone depends on the interpreter
+    mouse (disabled)
+        Record the screen positions of the links, so the player can select a
+        link by clicking on it in interpreters with mouse support. Scrolling
+        caused by lines that the interpreter wraps shifts the positions
+    fixed-point (disabled)
+        Allow decimal numbers like 1.25 in expressions. They are stored with
+        two decimal places between -327.67 and 327.67"
     } else {
         "Additional help:
     --help -v           Print the full set of options zwreec accepts"
@@ -378,6 +588,12 @@ mod tests {
         let cfg = config_from_args(vec!["-F".to_string(), "easter-egg".to_string()]);
 
         assert_eq!(cfg.easter_egg, true);
+        assert_eq!(cfg.easter_egg_requested, true);
+
+        // the default doesn't count as requested
+        let cfg = config_from_args(vec![]);
+        assert_eq!(cfg.easter_egg, true);
+        assert_eq!(cfg.easter_egg_requested, false);
     }
 
     #[test]
@@ -398,6 +614,96 @@ mod tests {
         assert_eq!(cfg.easter_egg, false);
     }
 
+    #[test]
+    fn test_feature_undo() {
+        let cfg = config_from_args(vec![]);
+        assert_eq!(cfg.undo, false);
+
+        let cfg = config_from_args(vec!["-F".to_string(), "undo".to_string()]);
+        assert_eq!(cfg.undo, true);
+    }
+
+    #[test]
+    fn test_feature_mouse() {
+        let cfg = config_from_args(vec![]);
+        assert_eq!(cfg.mouse, false);
+
+        let cfg = config_from_args(vec!["-F".to_string(), "mouse".to_string()]);
+        assert_eq!(cfg.mouse, true);
+    }
+
+    #[test]
+    fn test_feature_fixed_point() {
+        let cfg = config_from_args(vec![]);
+        assert_eq!(cfg.fixed_point, false);
+
+        let cfg = config_from_args(vec!["-F".to_string(), "fixed-point".to_string()]);
+        assert_eq!(cfg.fixed_point, true);
+    }
+
+    #[test]
+    fn test_input_format() {
+        let cfg = config_from_args(vec![]);
+        assert_eq!(cfg.input_format, InputFormat::Auto);
+
+        let cfg = config_from_args(vec!["--input-format".to_string(), "twee".to_string()]);
+        assert_eq!(cfg.input_format, InputFormat::Twee);
+
+        let cfg = config_from_args(vec!["--input-format".to_string(), "twine2".to_string()]);
+        assert_eq!(cfg.input_format, InputFormat::Twine2);
+    }
+
+    #[test]
+    fn test_format_fallback() {
+        let cfg = config_from_args(vec![]);
+        assert_eq!(cfg.format_fallback, FormatFallback::Style);
+
+        let cfg = config_from_args(vec!["--format-fallback".to_string(), "text".to_string()]);
+        assert_eq!(cfg.format_fallback, FormatFallback::Text);
+
+        let cfg = config_from_args(vec!["--format-fallback".to_string(), "plain".to_string()]);
+        assert_eq!(cfg.format_fallback, FormatFallback::Plain);
+    }
+
+    #[test]
+    fn test_link_selection() {
+        let cfg = config_from_args(vec![]);
+        assert_eq!(cfg.link_selection, LinkSelection::Digits);
+
+        let cfg = config_from_args(vec!["--link-selection".to_string(), "letters".to_string()]);
+        assert_eq!(cfg.link_selection, LinkSelection::Letters);
+
+        let cfg = config_from_args(vec!["--link-selection".to_string(), "cursor".to_string()]);
+        assert_eq!(cfg.link_selection, LinkSelection::Cursor);
+    }
+
+    #[test]
+    fn test_zversion() {
+        let cfg = config_from_args(vec![]);
+        assert_eq!(cfg.zversion, 8);
+
+        let cfg = config_from_args(vec!["--zversion".to_string(), "3".to_string()]);
+        assert_eq!(cfg.zversion, 3);
+
+        let cfg = config_from_args(vec!["-z".to_string(), "5".to_string()]);
+        assert_eq!(cfg.zversion, 5);
+
+        let cfg = config_from_args(vec!["-z".to_string(), "6".to_string()]);
+        assert_eq!(cfg.zversion, 8);
+    }
+
+    #[test]
+    fn test_output_format() {
+        let cfg = config_from_args(vec![]);
+        assert_eq!(cfg.output_format, OutputFormat::Zcode);
+        assert_eq!(cfg.cover_image, None);
+
+        let cfg = config_from_args(vec!["--output-format".to_string(), "blorb".to_string(),
+                                        "--cover".to_string(), "cover.png".to_string()]);
+        assert_eq!(cfg.output_format, OutputFormat::Blorb);
+        assert_eq!(cfg.cover_image, Some("cover.png".to_string()));
+    }
+
     #[test]
     fn test_generate_sample_zcode() {
         let cfg = config_from_args(vec!["-e".to_string()]);
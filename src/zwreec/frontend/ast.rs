@@ -3,41 +3,70 @@
 
 use backend::zcode::zfile;
 use backend::zcode::zfile::{ZOP, Type};
+use config::Config;
 use frontend::codegen;
 use frontend::expressionparser;
 use frontend::lexer::Token;
-use frontend::lexer::Token::{TokMacroIf, TokMacroElseIf, TokExpression, TokPassage};
+use frontend::lexer::Token::{TokMacroIf, TokMacroElseIf, TokExpression, TokPassage, TokPassageMetadata, TokTag, TokText, TokNewLine};
+use utils::error::{Diagnostic, DiagnosticKind};
+use utils::json;
 
 //==============================
 // ast
 
 pub struct AST {
     passages: Vec<ASTNode>,
+    start: String,
+    ifid: Option<String>,
     path: Vec<usize>,
-    is_in_if_expression: bool
+    is_in_if_expression: bool,
+    errors: Vec<Diagnostic>,
+    /// the expressions are parsed and the start passage exists, so the code
+    /// generator can run even if there are errors
+    complete: bool,
 }
 
 pub enum ASTOperation {
     AddPassage(Token),
+    AddTag(Token),
+    AddMetadata(Token),
     AddChild(Token),
     ChildDown(Token),
     Up,
     UpChild(Token),
     UpChildDown(Token),
     UpSpecial,
+    AddError(Diagnostic),
 }
 
 impl AST {
     pub fn build<I: Iterator<Item=ASTOperation>>(ops: I) -> AST {
+        AST::build_with_start(ops, "Start")
+    }
+
+    /// builds the ast with another passage than `Start` as the first passage
+    /// of the story, e.g. the start node of a Twine 2 story
+    pub fn build_with_start<I: Iterator<Item=ASTOperation>>(ops: I, start: &str) -> AST {
         let mut ast = AST {
             passages: Vec::new(),
+            start: start.to_string(),
+            ifid: None,
             path: Vec::new(),
             is_in_if_expression: false,
+            errors: Vec::new(),
+            complete: false,
         };
         for op in ops {
             ast.operation(op);
         }
-        ast.parse_expressions();
+        ast.read_story_data();
+        // the tree is incomplete if the parser reported errors
+        if ast.errors.is_empty() {
+            ast.parse_expressions();
+            // an expression that can't be parsed leaves the tree incomplete
+            ast.complete = ast.errors.is_empty();
+        }
+        ast.check_passages();
 
         ast
     }
@@ -46,19 +75,84 @@ impl AST {
         use self::ASTOperation::*;
         match op {
             AddPassage(passage) => self.add_passage(passage),
+            AddTag(tag) => self.add_tag(tag),
+            AddMetadata(metadata) => self.add_metadata(metadata),
             AddChild(child) => self.add_child(child),
             ChildDown(child) => self.child_down(child),
             Up => self.up(),
             UpChild(child) => self.up_child(child),
             UpChildDown(child) => self.up_child_down(child),
             UpSpecial => self.up_special(),
+            AddError(error) => self.errors.push(error),
+        }
+    }
+
+    /// returns all errors found while building the ast
+    pub fn errors(&self) -> &Vec<Diagnostic> {
+        &self.errors
+    }
+
+    /// returns false if the errors left the ast incomplete, then it can't be
+    /// compiled even with `cfg.force`
+    pub fn is_complete(&self) -> bool {
+        self.complete
+    }
+
+    /// checks for duplicate passage names and a missing start passage
+    fn check_passages(&mut self) {
+        let mut names: Vec<(String, (u64, u64))> = Vec::new();
+        for child in &self.passages {
+            if let TokPassage {ref name, location} = child.category() {
+                let first_definition = names.iter().find(|&&(ref other, _)| other == name).map(|&(_, first)| first);
+                match first_definition {
+                    Some(first) => self.errors.push(Diagnostic::new(DiagnosticKind::AST,
+                        format!("Passage '{}' is defined more than once", name), location)
+                        .with_note("first defined".to_string(), first)),
+                    None => names.push((name.clone(), location)),
+                }
+            }
+        }
+
+        if !names.iter().any(|&(ref name, _)| *name == self.start) {
+            self.complete = false;
+            self.errors.push(Diagnostic::new(DiagnosticKind::AST,
+                format!("No passage named '{}' found", self.start), (0, 0)));
+        }
+    }
+
+    /// reads the start passage and the ifid from the json object in the
+    /// `StoryData` passage of twee 3
+    fn read_story_data(&mut self) {
+        let (text, location) = match self.passages.iter().find(|child| child.is_passage("StoryData")) {
+            Some(child) => (self.passage_text("StoryData").unwrap_or(String::new()), child.category().location()),
+            None => return
+        };
+
+        let members = match json::parse_object(&text) {
+            Ok(members) => members,
+            Err(why) => {
+                self.errors.push(Diagnostic::new(DiagnosticKind::AST,
+                    format!("Invalid StoryData: {}", why), location));
+                return;
+            }
+        };
+
+        if let Some(start) = json::member(&members, "start") {
+            self.start = start.clone();
+        }
+        match json::member(&members, "ifid") {
+            Some(ifid) => self.ifid = Some(ifid.to_uppercase()),
+            None => warn!("StoryData without an ifid"),
+        }
+        if let Some(format) = json::member(&members, "format") {
+            debug!("Story format {}", format);
         }
     }
 
     /// goes through the whole tree and parse the expressions
     fn parse_expressions(&mut self) {
         for child in &mut self.passages {
-            child.parse_expressions();
+            child.parse_expressions(&mut self.errors);
         }
     }
 
@@ -67,12 +161,36 @@ impl AST {
         self.path.clear();
         let ast_count_passages = self.count_childs(self.path.to_vec());
 
-        let node = ASTNode::Passage(NodePassage { category: token, childs: Vec::new() });
+        let node = ASTNode::Passage(NodePassage { category: token, childs: Vec::new(), tags: Vec::new(), metadata: Vec::new() });
         self.passages.push(node);
 
         self.path.push(ast_count_passages);
     }
 
+    /// adds a tag to the last added passage
+    pub fn add_tag(&mut self, token: Token) {
+        if let TokTag {tag_name, ..} = token {
+            if let Some(&mut ASTNode::Passage(ref mut node)) = self.passages.last_mut() {
+                node.tags.push(tag_name);
+            }
+        }
+    }
+
+    /// adds the twee 3 metadata to the last added passage
+    pub fn add_metadata(&mut self, token: Token) {
+        if let TokPassageMetadata {ref metadata, location} = token {
+            match json::parse_object(metadata) {
+                Ok(members) => {
+                    if let Some(&mut ASTNode::Passage(ref mut node)) = self.passages.last_mut() {
+                        node.metadata = members;
+                    }
+                },
+                Err(why) => self.errors.push(Diagnostic::new(DiagnosticKind::AST,
+                    format!("Invalid passage metadata: {}", why), location)),
+            }
+        }
+    }
+
     /// adds a child to the path in the ast
     pub fn add_child(&mut self, token: Token) {
         if let Some(index) = self.path.first() {
@@ -136,9 +254,9 @@ impl AST {
     }
 
 
-    /// convert ast to zcode
-    pub fn to_zcode(&self, out: &mut zfile::Zfile) {
-        let mut manager = codegen::CodeGenManager::new();
+    /// convert ast to zcode, returns the errors found by the code generator
+    pub fn to_zcode<'a>(&'a self, cfg: &'a Config, out: &mut zfile::Zfile) -> Vec<Diagnostic> {
+        let mut manager = codegen::CodeGenManager::new(cfg);
 
         // adds a vec of passagenames to the manager
         manager.passages = self.passage_nodes_to_string();
@@ -146,13 +264,64 @@ impl AST {
         // Insert temp variables for internal calculations
         manager.symbol_table.insert_new_symbol("int0", Type::Integer);
 
+        // StoryInit is run before Start and the widgets are the macros
+        // `<<name>>` that can be used in every passage, so the variables they
+        // set are known in all other passages
+        let is_init = |child: &&ASTNode| child.has_tag("widget") || child.is_passage("StoryInit");
+        let init_passages = self.passages.iter().filter(&is_init);
+        let other_passages = self.passages.iter().filter(|child| !is_init(child));
+
         let mut code: Vec<ZOP> = vec![];
-        for child in &self.passages {
+        for child in init_passages.chain(other_passages) {
             for instr in codegen::gen_zcode(child, out, &mut manager) {
                 code.push(instr);
             }
         }
         out.emit(code);
+        out.routine_passage_address(&manager.passages);
+        out.routine_visits(&manager.passages);
+
+        manager.errors
+    }
+
+    /// returns the special passages of the story
+    pub fn story_info(&self) -> zfile::StoryInfo {
+        zfile::StoryInfo {
+            start: self.start.clone(),
+            ifid: self.ifid.clone(),
+            title: self.passage_text("StoryTitle"),
+            author: self.passage_text("StoryAuthor"),
+            init: self.passages.iter().any(|child| child.is_passage("StoryInit")),
+            menu: self.passages.iter().any(|child| child.is_passage("StoryMenu")),
+        }
+    }
+
+    /// returns the text of a passage with the lines joined by spaces, macros
+    /// and formatting are ignored
+    fn passage_text(&self, name: &str) -> Option<String> {
+        for child in &self.passages {
+            if let &ASTNode::Passage(ref node) = child {
+                if !child.is_passage(name) {
+                    continue;
+                }
+
+                let mut text = String::new();
+                for content in &node.childs {
+                    match content.category() {
+                        TokText {text: ref line, .. } => text.push_str(line),
+                        TokNewLine { .. } => text.push_str(" "),
+                        _ => ()
+                    }
+                }
+
+                let text = text.trim();
+                if !text.is_empty() {
+                    return Some(text.to_string());
+                }
+            }
+        }
+
+        None
     }
 
     /// prints the tree
@@ -215,7 +384,9 @@ pub enum ASTNode {
 pub struct NodePassage {
     pub category: Token,
     pub childs: Vec<ASTNode>,
-    /*tags: Vec<ASTNode>*/
+    pub tags: Vec<String>,
+    /// the members of the twee 3 metadata, e.g. `("position", "100,200")`
+    pub metadata: Vec<(String, String)>,
 }
 
 #[derive(Clone)]
@@ -284,6 +455,22 @@ impl ASTNode {
         }
     }
 
+    /// checks if the node is the passage with the name
+    pub fn is_passage(&self, passage_name: &str) -> bool {
+        match self {
+            &ASTNode::Passage(NodePassage { category: TokPassage {ref name, .. }, .. }) => name == passage_name,
+            _ => false
+        }
+    }
+
+    /// checks if the node is a passage tagged with `tag`
+    pub fn has_tag(&self, tag: &str) -> bool {
+        match self {
+            &ASTNode::Passage(ref node) => node.tags.iter().any(|t| t == tag),
+            _ => false
+        }
+    }
+
     pub fn category(&self) -> Token {
         match self {
             &ASTNode::Passage(ref t) => {
@@ -326,6 +513,8 @@ impl ASTNode {
         }
     }
 
+    /// the passages are the roots of the tree, all other nodes are default
+    /// nodes
     pub fn as_default(&self) -> &NodeDefault {
         match self {
             &ASTNode::Default(ref def) => def,
@@ -333,24 +522,29 @@ impl ASTNode {
         }
     }
 
-    /// goes through the whole tree and parse the expressions
-    fn parse_expressions(&mut self) {
+    /// goes through the whole tree and parse the expressions, expressions
+    /// that can't be parsed are added to the errors
+    fn parse_expressions(&mut self, errors: &mut Vec<Diagnostic>) {
         match self {
             &mut ASTNode::Passage(ref mut node) => {
                 for mut child in node.childs.iter_mut() {
-                    child.parse_expressions();
+                    child.parse_expressions(errors);
                 }
             },
             &mut ASTNode::Default(ref mut node) => {
                 match &node.category {
                     &TokExpression => {
-                        expressionparser::ExpressionParser::parse(node);
+                        // the expression has the location of its first token
+                        let location = node.childs.first().map_or((0, 0), |child| child.category().location());
+                        if let Err(why) = expressionparser::ExpressionParser::parse(node) {
+                            errors.push(Diagnostic::new(DiagnosticKind::AST, why, location));
+                        }
                     },
                     _ => ()
                 }
 
                 for mut child in node.childs.iter_mut() {
-                    child.parse_expressions();
+                    child.parse_expressions(errors);
                 }
             }
         }
@@ -409,6 +603,45 @@ mod tests {
         test_expected(expected, ast);
     }
 
+    #[test]
+    fn story_info_test() {
+        let ast = test_ast("::Start\nText\n::StoryTitle\nThe Title\n::StoryAuthor\nSomeone\n::StoryInit\n<<set $a = 1>>");
+        let story = ast.story_info();
+
+        assert_eq!(story.title, Some("The Title".to_string()));
+        assert_eq!(story.author, Some("Someone".to_string()));
+        assert_eq!(story.init, true);
+        assert_eq!(story.menu, false);
+    }
+
+    #[test]
+    fn metadata_test() {
+        let ast = test_ast(":: First [tag] {\"position\":\"100,200\",\"size\":\"100,100\"}\nText\n\
+            :: StoryData\n{\n\"ifid\": \"d674c58c-defa-4f70-b7a2-27742230c0ff\",\n\"start\": \"First\"\n}");
+
+        let metadata = match ast.passages[0] {
+            ASTNode::Passage(ref node) => node.metadata.clone(),
+            ASTNode::Default(_) => Vec::new()
+        };
+        assert_eq!(metadata, vec![("position".to_string(), "100,200".to_string()),
+                                  ("size".to_string(), "100,100".to_string())]);
+
+        let story = ast.story_info();
+        assert_eq!(story.start, "First".to_string());
+        assert_eq!(story.ifid, Some("D674C58C-DEFA-4F70-B7A2-27742230C0FF".to_string()));
+        assert!(ast.errors().is_empty());
+    }
+
+    #[test]
+    fn tags_test() {
+        let ast = test_ast("::Start [nobr]\nText\n::Macros [widget special]\n<<set $a = 1>>");
+
+        assert!(ast.passages[0].has_tag("nobr"));
+        assert!(!ast.passages[0].has_tag("widget"));
+        assert!(ast.passages[1].has_tag("widget"));
+        assert!(ast.passages[1].has_tag("special"));
+    }
+
     #[test]
     fn test_expression() {
         let ast = test_ast("::Passage\n<<print 1-2*3-4*5>>");
@@ -430,4 +663,22 @@ mod tests {
 
         test_expected(expected, ast);
     }
+
+    #[test]
+    fn test_multiple_assignments() {
+        let ast = test_ast("::Start\n<<set $a = 1; $b = 2, $c to 3>>Text");
+
+        let expected = vec!(
+            (vec![0]      , TokPassage { location: (0, 0), name: "Start".to_string() }),
+            (vec![0,0]    , TokAssign { location: (0, 0), var_name: "$a".to_string(), op_name: "=".to_string() }),
+            (vec![0,0,0]  , TokExpression),
+            (vec![0,1]    , TokAssign { location: (0, 0), var_name: "$b".to_string(), op_name: "=".to_string() }),
+            (vec![0,1,0]  , TokExpression),
+            (vec![0,2]    , TokAssign { location: (0, 0), var_name: "$c".to_string(), op_name: "to".to_string() }),
+            (vec![0,2,0]  , TokExpression),
+            (vec![0,3]    , TokText { location: (0, 0), text: "Text".to_string() }),
+        );
+
+        test_expected(expected, ast);
+    }
 }
@@ -4,25 +4,58 @@ use std::collections::HashMap;
 use std::error::Error;
 use std::io::Write;
 
-use backend::zcode::zfile::{FormattingState, Operand, Variable, ZOP, Zfile, Type};
-use config::Config;
+use backend::blorb;
+use backend::zcode::zfile::{FormattingState, Operand, Variable, ZOP, Zfile, Type, MAX_LINKS};
+use config::{Config, FormatFallback, LinkSelection, OutputFormat};
 use frontend::ast;
 use frontend::ast::ASTNode;
-use frontend::evaluate_expression::evaluate_expression;
+use frontend::evaluate_expression::{evaluate_expression, evaluate_compound_assignment, evaluate_element_assignment, fixed_const, is_element_type};
+use frontend::lexer::Token;
 use frontend::lexer::Token::*;
+use utils::error::{CompileReport, Diagnostic, DiagnosticKind};
 
 
-pub fn generate_zcode<W: Write>(cfg: &Config, ast: ast::AST, output: &mut W) {
+/// Generates the zcode for the ast and writes it to the output.
+///
+/// Returns the number of written bytes together with the errors that were
+/// ignored because `Config::force` is set. Without `force` nothing is written
+/// if the code generator reported errors.
+///
+/// With `Config::output_format` set to `Blorb` the zcode is packed into a
+/// Blorb file together with its metadata and the cover image (see
+/// `backend::blorb`).
+pub fn generate_zcode<W: Write>(cfg: &Config, ast: ast::AST, output: &mut W) -> Result<CompileReport, Vec<Diagnostic>> {
     let mut codegenerator = Codegen::new(cfg, ast);
-    codegenerator.start_codegen();
-    match output.write_all(&(*codegenerator.zfile_bytes())) {
+    let mut errors = codegenerator.start_codegen();
+    if !errors.is_empty() && !cfg.force {
+        return Err(errors);
+    }
+
+    let bytes: Vec<u8> = match cfg.output_format {
+        OutputFormat::Zcode => codegenerator.zfile_bytes().clone(),
+        OutputFormat::Blorb => match codegenerator.blorb_bytes() {
+            Ok(bytes) => bytes,
+            Err(why) => {
+                errors.push(Diagnostic::new(DiagnosticKind::Codegen, why, (0, 0)));
+                return Err(errors);
+            }
+        },
+    };
+
+    match output.write_all(&bytes) {
         Err(why) => {
-            panic!("Could not write to output: {}", Error::description(&why));
+            errors.push(Diagnostic::new(DiagnosticKind::Codegen,
+                format!("Could not write to output: {}", Error::description(&why)), (0, 0)));
+            Err(errors)
         },
         Ok(_) => {
-            info!("Wrote zcode to output");
+            info!("Wrote {:?} to output", cfg.output_format);
+            Ok(CompileReport {
+                bytes_written: bytes.len(),
+                diagnostics: errors,
+            })
         }
-    };
+    }
 }
 
 #[allow(dead_code)]
@@ -41,24 +74,74 @@ impl<'a> Codegen<'a> {
         }
     }
 
-    pub fn start_codegen(&mut self) {
+    /// generates the zcode and returns the errors found in the ast
+    pub fn start_codegen(&mut self) -> Vec<Diagnostic> {
+        self.zfile.story = self.ast.story_info();
         self.zfile.start();
         //self.zfile.op_quit();
         //self.zfile.routine("main", 0);
 
-        self.ast.to_zcode(&mut self.zfile);
+        let mut errors = self.ast.to_zcode(self.cfg, &mut self.zfile);
         
         self.zfile.op_quit();
 
         self.zfile.end();
+
+        for warning in &self.zfile.warnings {
+            warn!("{}", warning);
+        }
+
+        let version = self.zfile.version;
+        if version < 4 && self.cfg.link_selection == LinkSelection::Cursor {
+            errors.push(Diagnostic::new(DiagnosticKind::Codegen,
+                format!("The cursor menu for links needs version 4 of the Z-Machine, not {}", version), (0, 0)));
+        }
+
+        // the easter egg is enabled by default, it is only left out silently
+        let features = [("undo", self.cfg.undo), ("mouse", self.cfg.mouse), ("easter-egg", self.cfg.easter_egg_requested)];
+        for &(feature, requested) in features.iter() {
+            if version < 5 && requested {
+                errors.push(Diagnostic::new(DiagnosticKind::Codegen,
+                    format!("The {} feature needs version 5 of the Z-Machine, not {}", feature, version), (0, 0)));
+            }
+        }
+
+        let length = self.zfile.data.len();
+        if length > self.zfile.max_file_length() {
+            errors.push(Diagnostic::new(DiagnosticKind::Codegen,
+                format!("The story needs {} bytes, but version {} of the Z-Machine allows only {} bytes",
+                    length, version, self.zfile.max_file_length()), (0, 0)));
+        }
+
+        errors
     }
 
     pub fn zfile_bytes(&self) -> &Vec<u8> {
         &self.zfile.data.bytes
     }
+
+    /// returns the zcode packed into a Blorb file
+    pub fn blorb_bytes(&self) -> Result<Vec<u8>, String> {
+        let cover: Option<Vec<u8>> = match self.cfg.cover_image {
+            Some(ref name) => Some(try!(blorb::read_image(name))),
+            None => None,
+        };
+
+        blorb::blorb(&self.zfile.data.bytes, &self.zfile.story, cover.as_ref().map(|image| &image[..]))
+    }
 }
 
 
+/// generates the zcode of the ast and keeps the ops, so the tests can run
+/// them (see `frontend::testing`)
+#[cfg(test)]
+pub fn generate_test_zfile(cfg: &Config, ast: ast::AST) -> (Zfile, Vec<Diagnostic>) {
+    let mut codegen = Codegen::new(cfg, ast);
+    codegen.zfile.record_ops();
+    let errors = codegen.start_codegen();
+    (codegen.zfile, errors)
+}
+
 /// add zcode based on tokens
 pub fn gen_zcode<'a>(node: &'a ASTNode, mut out: &mut Zfile, mut manager: &mut CodeGenManager<'a>) -> Vec<ZOP> {
     let mut state_copy = manager.format_state.clone();
@@ -68,8 +151,36 @@ pub fn gen_zcode<'a>(node: &'a ASTNode, mut out: &mut Zfile, mut manager: &mut C
         &ASTNode::Passage(ref node) => {
             let mut code: Vec<ZOP> = vec![];
             match &node.category {
-                &TokPassage {ref name, .. } => {
+                &TokPassage {ref name, location } => {
                     code.push(ZOP::Routine{name: name.to_string(), count_variables: 15});
+                    manager.line_state = LineState::new();
+                    manager.line_state.nobr = node.tags.iter().any(|tag| tag == "nobr");
+
+                    let links = node.childs.iter().fold(0, |count, child| count + count_links(child));
+                    if links > MAX_LINKS as usize {
+                        manager.report(format!("The passage '{}' has {} links, but at most {} are possible", name, links, MAX_LINKS), location);
+                    } else if links > 26 && manager.cfg.link_selection == LinkSelection::Letters {
+                        // there are no letters behind `z`
+                        manager.report(format!("The passage '{}' has {} links, but at most 26 can be selected with letters, \
+                            select them with `--link-selection digits` instead", name, links), location);
+                    }
+
+                    // the tags of the passage are known to tags() and the
+                    // visit is counted, but not when it is included with <<display>>
+                    let after_tags = format!("after_tags_{}", name);
+                    code.push(ZOP::JE{operand1: Operand::new_var(17), operand2: Operand::new_const(1), jump_to_label: after_tags.clone()});
+                    code.push(ZOP::StoreVariable{variable: Variable::new(20), value: Operand::new_large_const(out.write_tags_table(&node.tags) as i16)});
+
+                    // StoryInit, StoryMenu and the widgets don't count as visits
+                    let special = name == "StoryInit" || name == "StoryMenu" || node.tags.iter().any(|tag| tag == "widget");
+                    if !special {
+                        if let Some(index) = manager.passages.iter().position(|passage| passage == name) {
+                            let name_addr = out.write_string(name);
+                            code.push(ZOP::CallVNA2{jump_to_label: "system_visit".to_string(),
+                                arg1: Operand::new_large_const(index as i16 + 1), arg2: Operand::new_large_const(name_addr as i16)});
+                        }
+                    }
+                    code.push(ZOP::Label{name: after_tags});
                 },
                 _ => {
                     debug!("no match 1");
@@ -82,18 +193,114 @@ pub fn gen_zcode<'a>(node: &'a ASTNode, mut out: &mut Zfile, mut manager: &mut C
                 }
             }
 
-            code.push(ZOP::Newline);
-            code.push(ZOP::Call1N{jump_to_label: "mem_free".to_string()});
+            if manager.line_state.heading {
+                code.push(ZOP::SetTextStyle{bold: false, reverse: false, monospace: false, italic: false});
+            }
+
+            // StoryMenu is called by system_check_links after the passage,
+            // which ends the line of the menu
+            let menu = match &node.category {
+                &TokPassage {ref name, .. } => name == "StoryMenu",
+                _ => false
+            };
+            if !menu {
+                code.push(ZOP::Newline);
+                code.push(ZOP::Call1N{jump_to_label: "mem_free".to_string()});
+            }
             code.push(ZOP::Ret{value: Operand::new_const(0)});
             code
         },
         &ASTNode::Default(ref t) => {
+            // indent blocks are indented before the first output of every line
+            let indentation = match &t.category {
+                &TokNewLine { .. } | &TokFormatHorizontalLine { .. } | &TokFormatIndentBlock { .. } |
+                &TokMacroSet { .. } | &TokMacroIf { .. } | &TokMacroElse { .. } | &TokMacroElseIf { .. } |
+                &TokMacroEndIf { .. } | &TokMacroSilently { .. } | &TokAssign { .. } | &TokVariable { .. } |
+                &TokMacroSave { .. } | &TokMacroRestore { .. } | &TokMacroUndo { .. } => vec![],
+                _ => {
+                    let indent = manager.line_state.indent && manager.line_state.at_line_start;
+                    manager.line_state.at_line_start = false;
+                    if indent { vec![ZOP::Call1N{jump_to_label: "system_indent_start".to_string()}] } else { vec![] }
+                }
+            };
+
+            // text styles exist since version 4
+            if out.version < 4 {
+                match &t.category {
+                    &TokFormatBoldStart {location} | &TokFormatMonoStart {location} |
+                    &TokFormatItalicStart {location} | &TokFormatHeading {location, .. } => {
+                        manager.report(format!("Text styles need version 4 of the Z-Machine, not {}", out.version), location);
+                    },
+                    &TokFormatUnderStart {location} if manager.cfg.format_fallback == FormatFallback::Style => {
+                        manager.report(format!("Text styles need version 4 of the Z-Machine, not {}", out.version), location);
+                    },
+                    _ => ()
+                }
+            }
+
             let mut code: Vec<ZOP> = match &t.category {
+                &TokText {ref text, .. } if manager.line_state.indent => {
+                    indented_text(text)
+                },
                 &TokText {ref text, .. } => {
                     vec![ZOP::PrintOps{text: text.to_string()}]
                 },
                 &TokNewLine { .. } => {
-                    vec![ZOP::Newline]
+                    let mut code: Vec<ZOP> = vec![];
+                    if manager.line_state.heading {
+                        // end of the heading, followed by a blank line
+                        manager.line_state.heading = false;
+                        code.push(ZOP::SetTextStyle{bold: false, reverse: false, monospace: false, italic: false});
+                        let state = manager.format_state;
+                        code.push(ZOP::SetTextStyle{bold: state.bold, reverse: state.inverted, monospace: state.mono, italic: state.italic});
+                        code.push(ZOP::Newline);
+                    }
+
+                    // a line that is not a numbered list item ends the list
+                    if !manager.line_state.numb_item {
+                        manager.line_state.numb_count = 0;
+                    }
+                    manager.line_state.numb_item = false;
+                    if manager.line_state.nobr {
+                        // empty lines are dropped, all other lines are joined
+                        if !manager.line_state.at_line_start {
+                            code.push(ZOP::Print{text: " ".to_string()});
+                        }
+                    } else {
+                        code.push(ZOP::Newline);
+                    }
+                    manager.line_state.at_line_start = true;
+                    manager.line_state.first_line = false;
+                    code
+                },
+                &TokFormatHeading {rank, .. } => {
+                    let mut code: Vec<ZOP> = vec![];
+                    if !manager.line_state.first_line {
+                        code.push(ZOP::Newline);
+                    }
+                    manager.line_state.heading = true;
+
+                    // the main heading is shown in reverse video, the others in bold
+                    code.push(ZOP::SetTextStyle{bold: true, reverse: rank == 1, monospace: false, italic: false});
+                    code
+                },
+                &TokFormatBulList { .. } => {
+                    vec![ZOP::PrintOps{text: "  \u{2022} ".to_string()}]
+                },
+                &TokFormatNumbList { .. } => {
+                    manager.line_state.numb_count += 1;
+                    manager.line_state.numb_item = true;
+                    vec![ZOP::Print{text: format!("  {}. ", manager.line_state.numb_count)}]
+                },
+                &TokFormatHorizontalLine { .. } => {
+                    manager.line_state.numb_count = 0;
+                    manager.line_state.at_line_start = true;
+                    vec![ZOP::Call1N{jump_to_label: "system_print_line".to_string()}]
+                },
+                &TokFormatIndentBlock { .. } => {
+                    manager.line_state.indent = !manager.line_state.indent;
+                    manager.line_state.at_line_start = true;
+                    vec![]
                 },
                 &TokFormatBoldStart { .. } => {
                     state_copy.bold = true;
@@ -105,62 +312,241 @@ pub fn gen_zcode<'a>(node: &'a ASTNode, mut out: &mut Zfile, mut manager: &mut C
                     set_formatting = true;
                     vec![ZOP::SetTextStyle{bold: state_copy.bold, reverse: state_copy.inverted, monospace: state_copy.mono, italic: state_copy.italic}]
                 },
+                &TokFormatUnderStart { .. } if manager.cfg.format_fallback == FormatFallback::Style => {
+                    // most interpreters show underlined text in italics
+                    state_copy.italic = true;
+                    set_formatting = true;
+                    vec![ZOP::SetTextStyle{bold: state_copy.bold, reverse: state_copy.inverted, monospace: state_copy.mono, italic: state_copy.italic}]
+                },
+                &TokFormatUnderStart { .. } |
+                &TokFormatStrikeStart { .. } |
+                &TokFormatSupStart { .. } |
+                &TokFormatSubStart { .. } => {
+                    let (start, end) = match manager.cfg.format_fallback {
+                        FormatFallback::Plain => ("", ""),
+                        _ => format_markers(&t.category),
+                    };
+
+                    let mut code: Vec<ZOP> = vec![];
+                    if !start.is_empty() {
+                        code.push(ZOP::PrintOps{text: start.to_string()});
+                    }
+                    for child in &t.childs {
+                        for instr in gen_zcode(child, out, manager) {
+                            code.push(instr);
+                        }
+                    }
+                    if !end.is_empty() {
+                        code.push(ZOP::PrintOps{text: end.to_string()});
+                    }
+                    code
+                },
                 &TokFormatItalicStart { .. } => {
                     state_copy.italic = true;
                     set_formatting = true;
                     vec![ZOP::SetTextStyle{bold: state_copy.bold, reverse: state_copy.inverted, monospace: state_copy.mono, italic: state_copy.italic}]
                 },
-                &TokPassageLink {ref display_name, ref passage_name, .. } => {
+                &TokPassageLink {ref display_name, ref passage_name, location } if passage_name.starts_with("$") => {
+                    // the passage is looked up at runtime by the name in the variable
+                    let var = match manager.symbol_table.get_symbol_id(passage_name) {
+                        Some(var) => var,
+                        None => {
+                            manager.report(format!("Variable '{}' is used before it is set", passage_name), location);
+                            return vec![];
+                        }
+                    };
+                    if var.vartype != Type::String {
+                        manager.report(format!("Link target '{}' has to be a string", passage_name), location);
+                        return vec![];
+                    }
+                    let after_link_label = format!("after_link_{}", manager.ids_link.start_next());
+                    manager.ids_link.pop_id();
                     set_formatting = true;
-                    vec![
-                    ZOP::Call2NWithAddress{jump_to_label: "system_add_link".to_string(), address: passage_name.to_string()},
-                    ZOP::SetColor{foreground: 8, background: 2},
-                    ZOP::Print{text: format!("{}[", display_name)},
-                    ZOP::PrintNumVar{variable: Variable::new(16)},
-                    ZOP::Print{text: "]".to_string()},
-                    ZOP::SetColor{foreground: 9, background: 2},
-                    ]
+
+                    let mut code: Vec<ZOP> = vec![];
+                    if manager.cfg.link_selection == LinkSelection::Cursor {
+                        let text = if display_name == passage_name {
+                            Operand::new_var(var.id)
+                        } else {
+                            Operand::new_large_const(out.write_string(display_name) as i16)
+                        };
+                        code.push(ZOP::StoreVariable{variable: Variable::new(19), value: text});
+                    }
+                    // the result is pushed to the stack (variable 0) and popped by je
+                    code.push(ZOP::Call2S{jump_to_label: "system_add_link_by_name".to_string(), arg: Operand::new_var(var.id), result: Variable::new(0)});
+                    code.push(ZOP::JE{operand1: Operand::new_var(0), operand2: Operand::new_const(0), jump_to_label: after_link_label.to_string()});
+                    code.push(ZOP::SetColor{foreground: 8, background: 2});
+                    for instr in link_position(out, 0) {
+                        code.push(instr);
+                    }
+                    if display_name == passage_name {
+                        code.push(ZOP::PrintUnicodeStr{address: Operand::new_var(var.id)});
+                    } else {
+                        code.push(ZOP::Print{text: display_name.to_string()});
+                    }
+                    for instr in link_marker(manager) {
+                        code.push(instr);
+                    }
+                    for instr in link_position(out, 1) {
+                        code.push(instr);
+                    }
+                    code.push(ZOP::SetColor{foreground: 9, background: 2});
+                    code.push(ZOP::Label{name: after_link_label});
+                    code
                 },
-                &TokAssign {ref var_name, ref op_name, .. } => {
-                    if op_name == "=" || op_name == "to" {
-                        let mut code: Vec<ZOP> = vec![];
-                        if t.childs.len() == 1 {
-                            let expression_node = &t.childs[0].as_default();
-                            let result = match expression_node.category {
-                                TokExpression => {
-                                    if expression_node.childs.len() != 1 {
-                                        panic!("Unsupported expression!")
+                &TokPassageLink {ref display_name, ref passage_name, location } => {
+                    if !manager.passages.contains(passage_name) {
+                        manager.report(format!("Link to unknown passage '{}'", passage_name), location);
+                        return vec![];
+                    }
+                    set_formatting = true;
+                    let mut code: Vec<ZOP> = vec![];
+                    if manager.cfg.link_selection == LinkSelection::Cursor {
+                        let text = out.write_string(display_name);
+                        code.push(ZOP::StoreVariable{variable: Variable::new(19), value: Operand::new_large_const(text as i16)});
+                    }
+                    code.push(ZOP::Call2NWithAddress{jump_to_label: "system_add_link".to_string(), address: passage_name.to_string()});
+                    code.push(ZOP::SetColor{foreground: 8, background: 2});
+                    for instr in link_position(out, 0) {
+                        code.push(instr);
+                    }
+                    code.push(ZOP::Print{text: display_name.to_string()});
+                    for instr in link_marker(manager) {
+                        code.push(instr);
+                    }
+                    for instr in link_position(out, 1) {
+                        code.push(instr);
+                    }
+                    code.push(ZOP::SetColor{foreground: 9, background: 2});
+                    code
+                },
+                &TokAssign {ref var_name, ref op_name, location } => {
+                    let mut code: Vec<ZOP> = vec![];
+                    if t.childs.len() == 1 {
+                        let expression_node = &t.childs[0].as_default();
+                        if expression_node.category != TokExpression || expression_node.childs.len() != 1 {
+                            manager.report(format!("Unsupported expression in assignment to '{}'", var_name), location);
+                            return vec![];
+                        }
+                        let result = match &**op_name {
+                            "=" | "to" => evaluate_expression(&expression_node.childs[0], &mut code, manager, &mut out),
+                            "+=" | "-=" | "*=" | "/=" => {
+                                let variable = match manager.symbol_table.get_symbol_id(var_name) {
+                                    Some(variable) => variable,
+                                    None => {
+                                        manager.report(format!("Variable '{}' is used before it is set", var_name), location);
+                                        return vec![];
                                     }
-                                    evaluate_expression(&expression_node.childs[0], &mut code, manager, &mut out)
-                                }, _ => panic!("Unsupported expression!")
-                            };
-                            if !manager.symbol_table.is_known_symbol(var_name) {
-                                let vartype = match result {
-                                    Operand::StringRef(_) => Type::String,
-                                    Operand::Var(ref var) => var.vartype.clone(),
-                                    _ => Type::Integer
                                 };
-                                manager.symbol_table.insert_new_symbol(&var_name, vartype);
+                                if variable.vartype == Type::String && op_name != "+=" {
+                                    manager.report(format!("Operator `{}` can not be used on the string variable '{}'", op_name, var_name), location);
+                                    return vec![];
+                                }
+
+                                // "+=" uses the operator "+"
+                                evaluate_compound_assignment(&variable, &op_name[..1], location, &expression_node.childs[0], &mut code, manager, &mut out)
+                            },
+                            _ => {
+                                manager.report(format!("Unsupported assignment operator `{}`", op_name), location);
+                                return vec![];
+                            }
+                        };
+                        if !manager.symbol_table.is_known_symbol(var_name) {
+                            let vartype = match result {
+                                Operand::StringRef(_) => Type::String,
+                                Operand::Var(ref var) => var.vartype.clone(),
+                                _ => Type::Integer
+                            };
+                            if !manager.symbol_table.insert_new_symbol(&var_name, vartype) {
+                                manager.report(too_many_variables(var_name), location);
+                                return vec![];
+                            }
+                        } else if manager.symbol_table.get_symbol_type(var_name) == Some(Type::Array(None)) {
+                            // the elements of an empty array get their type
+                            if let Operand::Var(Variable{vartype: Type::Array(Some(ref element_type)), ..}) = result {
+                                manager.symbol_table.set_symbol_type(var_name, Type::Array(Some(element_type.clone())));
                             }
-                            let symbol_id = manager.symbol_table.get_symbol_id(var_name);
-                            code.push(ZOP::StoreVariable{variable: symbol_id, value: result});
-                            code
-                        } else {
-                            debug!("Assign Expression currently not supported.");
-                            vec![]
                         }
-                    } else { vec![] }
+                        let symbol_id = match manager.symbol_table.get_symbol_id(var_name) {
+                            Some(symbol_id) => symbol_id,
+                            None => return vec![]
+                        };
+                        match (symbol_id.vartype.clone(), result) {
+                            (Type::Integer, Operand::Var(Variable{vartype: Type::Fixed, ..})) |
+                            (Type::Bool, Operand::Var(Variable{vartype: Type::Fixed, ..})) => {
+                                manager.report(format!("Variable '{}' is an integer and can not store a decimal number", var_name), location);
+                                return vec![];
+                            },
+                            (Type::Array(Some(ref element_type)), Operand::Var(Variable{vartype: Type::Array(Some(ref value_type)), ..}))
+                                    if !is_element_type(&Some((**element_type).clone()), value_type) => {
+                                manager.report(format!("All elements of the array '{}' need the same type", var_name), location);
+                                return vec![];
+                            },
+                            (Type::Array(_), array @ Operand::Var(Variable{vartype: Type::Array(_), ..})) => {
+                                code.push(ZOP::StoreVariable{variable: symbol_id, value: array});
+                            },
+                            (Type::Array(_), _) => {
+                                manager.report(format!("Variable '{}' is an array and can only store an array", var_name), location);
+                                return vec![];
+                            },
+                            (_, Operand::Var(Variable{vartype: Type::Array(_), ..})) => {
+                                manager.report(format!("Variable '{}' is not an array and can not store one", var_name), location);
+                                return vec![];
+                            },
+                            // integers are scaled when they are stored in a fixed-point variable
+                            (Type::Fixed, Operand::Const(c)) => {
+                                let value = fixed_const(c.value as i16, location, manager);
+                                code.push(ZOP::StoreVariable{variable: symbol_id, value: value});
+                            },
+                            (Type::Fixed, Operand::LargeConst(c)) => {
+                                let value = fixed_const(c.value, location, manager);
+                                code.push(ZOP::StoreVariable{variable: symbol_id, value: value});
+                            },
+                            (Type::Fixed, Operand::Var(Variable{id, vartype: Type::Integer})) |
+                            (Type::Fixed, Operand::Var(Variable{id, vartype: Type::Bool})) => {
+                                code.push(ZOP::Call2S{jump_to_label: "system_to_fixed".to_string(), arg: Operand::new_var(id), result: symbol_id});
+                            },
+                            (_, result) => code.push(ZOP::StoreVariable{variable: symbol_id, value: result})
+                        }
+                        code
+                    } else {
+                        debug!("Assign Expression currently not supported.");
+                        vec![]
+                    }
                 },
-                &TokMacroIf { .. } => {
-                    if t.childs.len() < 2 {
-                        panic!("Unsupported if-expression!");
+                &TokVariable {ref name, location } => {
+                    // an assignment to an element of an array or a method
+                    // like $inv.push("key") in <<set>>
+                    let mut code: Vec<ZOP> = vec![];
+                    match t.childs.last().map(|child| child.category()) {
+                        Some(TokAssign {ref op_name, location, .. }) => {
+                            if op_name != "=" && op_name != "to" {
+                                manager.report(format!("Unsupported assignment operator `{}` for an element of an array", op_name), location);
+                                return vec![];
+                            }
+                            if t.childs.len() != 2 || t.childs[1].as_default().childs.len() != 1 {
+                                manager.report(format!("Unsupported expression in assignment to an element of '{}'", name), location);
+                                return vec![];
+                            }
+                            evaluate_element_assignment(name, location, &t.childs[0], &t.childs[1].as_default().childs[0], &mut code, manager, &mut out);
+                        },
+                        Some(_) => {
+                            // the result of the method is not used
+                            evaluate_expression(node, &mut code, manager, &mut out);
+                        },
+                        None => {
+                            manager.report(format!("Unsupported assignment to '{}'", name), location);
+                        }
                     }
-
+                    code
+                },
+                &TokMacroIf { location } => {
                     // check if the first node is an expression node
-                    let expression_node = match t.childs[0].as_default().category {
-                        TokExpression => t.childs[0].as_default(),
-                        _ =>  panic!("Unsupported if-expression!")
-                    };
+                    if t.childs.len() < 2 || t.childs[0].as_default().category != TokExpression {
+                        manager.report("Unsupported if-expression".to_string(), location);
+                        return vec![];
+                    }
+                    let expression_node = t.childs[0].as_default();
 
                     let mut code: Vec<ZOP> = vec![];
 
@@ -185,18 +571,15 @@ pub fn gen_zcode<'a>(node: &'a ASTNode, mut out: &mut Zfile, mut manager: &mut C
                     code.push(ZOP::Label{name: after_if_label});
                     code
                 },
-                &TokMacroElseIf { .. } => {
-                    if t.childs.len() < 2 {
-                        panic!("Unsupported elseif-expression!");
+                &TokMacroElseIf { location } => {
+                    // check if the first node is an expression node
+                    if t.childs.len() < 2 || t.childs[0].as_default().category != TokExpression {
+                        manager.report("Unsupported elseif-expression".to_string(), location);
+                        return vec![];
                     }
 
                     let mut code: Vec<ZOP> = vec![];
-
-                    // check if the first node is an expression node
-                    let expression_node = match t.childs[0].as_default().category {
-                        TokExpression => t.childs[0].as_default(),
-                        _ =>  panic!("Unsupported elseif-expression!")
-                    };
+                    let expression_node = t.childs[0].as_default();
 
                     // Evaluate the contained expression
                     let result = evaluate_expression(&expression_node.childs[0], &mut code, manager, &mut out);
@@ -234,20 +617,48 @@ pub fn gen_zcode<'a>(node: &'a ASTNode, mut out: &mut Zfile, mut manager: &mut C
                     vec![ZOP::Label{name: after_else_label}]
                 },
 
-                &TokMacroDisplay {ref passage_name, .. } => {
-                    let var = Variable::new(17);
-                    vec![
-                    // activates the display-modus
-                    ZOP::StoreVariable{variable: var.clone(), value: Operand::new_const(1)},
-                    ZOP::Call1N{jump_to_label: passage_name.to_string()},
+                &TokMacroDisplay {ref passage_name, location } if passage_name.is_empty() => {
+                    // the passage is looked up at runtime by the evaluated name
+                    if t.childs.len() != 1 || t.childs[0].as_default().category != TokExpression {
+                        manager.report("Display needs exactly one argument".to_string(), location);
+                        return vec![];
+                    }
+                    let expression_node = t.childs[0].as_default();
 
-                    // deactivates the display-modus
-                    ZOP::StoreVariable{variable: var.clone(), value: Operand::new_const(0)},
-                    ]
+                    // a constant name is checked like <<display Name>>
+                    if let ASTNode::Default(ref node) = expression_node.childs[0] {
+                        if let TokString {ref value, location } = node.category {
+                            if !manager.passages.contains(value) {
+                                manager.report(format!("Display of unknown passage '{}'", value), location);
+                                return vec![];
+                            }
+                            return display_passage(value);
+                        }
+                    }
+
+                    let mut code: Vec<ZOP> = vec![];
+                    let name = match evaluate_expression(&expression_node.childs[0], &mut code, manager, &mut out) {
+                        Operand::Var(ref var) if var.vartype == Type::String => Operand::new_var(var.id),
+                        Operand::StringRef(addr) => Operand::new_large_const(addr.value),
+                        _ => {
+                            manager.report("The passage name of display has to be a string".to_string(), location);
+                            return vec![];
+                        }
+                    };
+                    code.push(ZOP::Call2NWithArg{jump_to_label: "system_display_by_name".to_string(), arg: name});
+                    code
                 },
-                &TokMacroPrint { .. } => {
+                &TokMacroDisplay {ref passage_name, location } => {
+                    if !manager.passages.contains(passage_name) {
+                        manager.report(format!("Display of unknown passage '{}'", passage_name), location);
+                        return vec![];
+                    }
+                    display_passage(passage_name)
+                },
+                &TokMacroPrint { location } => {
                     if t.childs.len() != 1 {
-                        panic!("Doesn't support print with 0 or more than one argument");
+                        manager.report("Print needs exactly one argument".to_string(), location);
+                        return vec![];
                     }
 
                     let mut code: Vec<ZOP> = vec![];
@@ -258,21 +669,65 @@ pub fn gen_zcode<'a>(node: &'a ASTNode, mut out: &mut Zfile, mut manager: &mut C
                         TokExpression => {
                             let eval = evaluate_expression(&child.childs[0], &mut code, manager, &mut out);
                             match eval {
-                                Operand::Var(var) => if var.vartype == Type::String { code.push(ZOP::PrintUnicodeStr{address: Operand::new_var_string(var.id)}); } else { code.push(ZOP::PrintNumVar{variable: var}); },
+                                Operand::Var(var) => match var.vartype {
+                                    Type::String => code.push(ZOP::PrintUnicodeStr{address: Operand::new_var_string(var.id)}),
+                                    Type::Array(ref element_type) => code.push(print_array(var.id, element_type)),
+                                    Type::Fixed => code.push(ZOP::Call2NWithArg{jump_to_label: "system_print_fixed".to_string(), arg: Operand::new_var(var.id)}),
+                                    _ => code.push(ZOP::PrintNumVar{variable: var.clone()})
+                                },
                                 Operand::StringRef(addr) => code.push(ZOP::PrintUnicodeStr{address: Operand::new_large_const(addr.value)}),
                                 Operand::Const(c) => code.push(ZOP::Print{text: format!("{}", c.value)}),
                                 Operand::LargeConst(c) => code.push(ZOP::Print{text: format!("{}", c.value)})
                             };
                         },
                         _ => {
-                            panic!("Unsupported Expression");
+                            manager.report("Unsupported expression in print".to_string(), location);
                         }
                     };
                     code
                 },
-                &TokMacroContentVar {ref var_name, .. } => {
-                    let var_id = manager.symbol_table.get_symbol_id(&*var_name);
-                    match manager.symbol_table.get_symbol_type(&*var_name) {
+                &TokMacroTextInput { location } => {
+                    if t.childs.len() != 1 || t.childs[0].as_default().category != TokExpression ||
+                            t.childs[0].as_default().childs.len() != 1 {
+                        manager.report("Textinput needs exactly one variable".to_string(), location);
+                        return vec![];
+                    }
+
+                    let var_node = t.childs[0].as_default().childs[0].as_default();
+                    let var_name = match var_node.category {
+                        TokVariable { ref name, .. } if var_node.childs.is_empty() => name,
+                        _ => {
+                            manager.report("The argument of textinput has to be a variable".to_string(), location);
+                            return vec![];
+                        }
+                    };
+
+                    if !manager.symbol_table.is_known_symbol(var_name) {
+                        if !manager.symbol_table.insert_new_symbol(var_name, Type::String) {
+                            manager.report(too_many_variables(var_name), location);
+                            return vec![];
+                        }
+                    }
+                    let symbol_id = match manager.symbol_table.get_symbol_id(var_name) {
+                        Some(ref symbol_id) if symbol_id.vartype == Type::String => symbol_id.clone(),
+                        _ => {
+                            manager.report(format!("Textinput can not store a string in the variable '{}'", var_name), location);
+                            return vec![];
+                        }
+                    };
+
+                    // the line typed by the player is a new string on the heap
+                    vec![ZOP::Call2S{jump_to_label: "system_read_line".to_string(), arg: Operand::new_const(0), result: symbol_id}]
+                },
+                &TokMacroContentVar {ref var_name, location } => {
+                    let var_id = match manager.symbol_table.get_symbol_id(&*var_name) {
+                        Some(var_id) => var_id,
+                        None => {
+                            manager.report(format!("Variable '{}' is used before it is set", var_name), location);
+                            return vec![];
+                        }
+                    };
+                    match var_id.vartype.clone() {
                         Type::Integer => {
                             vec![ZOP::PrintNumVar{variable: var_id}]
                         },
@@ -281,9 +736,89 @@ pub fn gen_zcode<'a>(node: &'a ASTNode, mut out: &mut Zfile, mut manager: &mut C
                         },
                         Type::Bool => {
                             vec![ZOP::PrintNumVar{variable: var_id}]
+                        },
+                        Type::Fixed => {
+                            vec![ZOP::Call2NWithArg{jump_to_label: "system_print_fixed".to_string(), arg: Operand::new_var(var_id.id)}]
+                        },
+                        Type::Array(ref element_type) => {
+                            vec![print_array(var_id.id, element_type)]
                         }
                     }
                 },
+                &TokMacroSilently { .. } => {
+                    let silently_id = manager.ids_silently.start_next();
+                    let after_silently_label = format!("after_silently_{}", silently_id);
+
+                    // global 18 counts the open silently blocks, so nested
+                    // blocks and displayed passages don't enable the screen too early
+                    let mut code: Vec<ZOP> = vec![
+                        ZOP::Inc{variable: 18},
+                        ZOP::OutputStream{stream: -1},
+                    ];
+
+                    for child in &t.childs {
+                        for instr in gen_zcode(child, out, manager) {
+                            code.push(instr);
+                        }
+                    }
+
+                    code.push(ZOP::Dec{variable: 18});
+                    code.push(ZOP::JG{operand1: Operand::new_var(18), operand2: Operand::new_const(0), jump_to_label: after_silently_label.to_string()});
+                    code.push(ZOP::OutputStream{stream: 1});
+                    code.push(ZOP::Label{name: after_silently_label});
+                    manager.ids_silently.pop_id();
+                    code
+                },
+                &TokMacroSave { .. } => {
+                    let after_save_label = format!("after_save_{}", manager.ids_save.start_next());
+                    manager.ids_save.pop_id();
+
+                    // the result is 0 if saving failed, 1 after saving and 2 after a restore
+                    vec![
+                        ZOP::Save{result: Variable::new(0)},
+                        ZOP::JNE{operand1: Operand::new_var(0), operand2: Operand::new_const(0), jump_to_label: after_save_label.to_string()},
+                        ZOP::Print{text: "Save failed.".to_string()},
+                        ZOP::Newline,
+                        ZOP::Label{name: after_save_label},
+                    ]
+                },
+                &TokMacroRestore { .. } => {
+                    let after_restore_label = format!("after_restore_{}", manager.ids_save.start_next());
+                    manager.ids_save.pop_id();
+
+                    // a successful restore continues at the save point, so
+                    // this code only proceeds if restoring failed
+                    vec![
+                        ZOP::Restore{result: Variable::new(0)},
+                        ZOP::JNE{operand1: Operand::new_var(0), operand2: Operand::new_const(0), jump_to_label: after_restore_label.to_string()},
+                        ZOP::Print{text: "Restore failed.".to_string()},
+                        ZOP::Newline,
+                        ZOP::Label{name: after_restore_label},
+                    ]
+                },
+                &TokMacroUndo { location } => {
+                    if out.version < 5 {
+                        manager.report(format!("`<<undo>>` needs version 5 of the Z-Machine, not {}", out.version), location);
+                        return vec![];
+                    }
+                    if !manager.cfg.undo {
+                        manager.report("`<<undo>>` needs the undo feature, which is enabled with `-F undo`".to_string(), location);
+                        return vec![];
+                    }
+
+                    let after_undo_label = format!("after_undo_{}", manager.ids_save.start_next());
+                    manager.ids_save.pop_id();
+
+                    // a successful restore_undo continues in system_check_links
+                    // before the jump to this passage
+                    vec![
+                        ZOP::RestoreUndo{result: Variable::new(0)},
+                        ZOP::JNE{operand1: Operand::new_var(0), operand2: Operand::new_const(0), jump_to_label: after_undo_label.to_string()},
+                        ZOP::Print{text: "Nothing to undo.".to_string()},
+                        ZOP::Newline,
+                        ZOP::Label{name: after_undo_label},
+                    ]
+                },
                 _ => {
                     debug!("no match if");
                     vec![]
@@ -299,19 +834,138 @@ pub fn gen_zcode<'a>(node: &'a ASTNode, mut out: &mut Zfile, mut manager: &mut C
                 let state = manager.format_state;
                 code.push(ZOP::SetTextStyle{bold: state.bold, reverse: state.inverted, monospace: state.mono, italic: state.italic});
             }
+            if !indentation.is_empty() {
+                let mut indented = indentation;
+                indented.extend(code.into_iter());
+                code = indented;
+            }
             code
         }
     }
 }
 
-/// random(from, to) -> zcode op_random(0, range)
-pub fn function_random(arg_from: &Operand, arg_to: &Operand,
-        code: &mut Vec<ZOP>, temp_ids: &mut Vec<u8>) -> Operand {
+/// calls the passage without showing its links
+fn display_passage(passage_name: &str) -> Vec<ZOP> {
+    let var = Variable::new(17);
+    vec![
+    // activates the display-modus
+    ZOP::StoreVariable{variable: var.clone(), value: Operand::new_const(1)},
+    ZOP::Call1N{jump_to_label: passage_name.to_string()},
+
+    // deactivates the display-modus
+    ZOP::StoreVariable{variable: var.clone(), value: Operand::new_const(0)},
+    ]
+}
+
+/// the characters that mark formatting which has no z-machine text style
+fn format_markers(token: &Token) -> (&'static str, &'static str) {
+    match token {
+        &TokFormatUnderStart { .. } => ("_", "_"),
+        &TokFormatStrikeStart { .. } => ("-", "-"),
+        &TokFormatSupStart { .. } => ("^(", ")"),
+        &TokFormatSubStart { .. } => ("_(", ")"),
+        _ => ("", ""),
+    }
+}
+
+/// prints the text of an indent block word by word, system_indent_word
+/// prints the spaces in front of each word or wraps the line before it
+fn indented_text(text: &str) -> Vec<ZOP> {
+    let mut code: Vec<ZOP> = vec![];
+    let mut spaces = 0;
+    let mut word = String::new();
+    // the space at the end prints the last word
+    for character in text.chars().chain(" ".chars()) {
+        if character != ' ' {
+            word.push(character);
+            continue;
+        }
+        if !word.is_empty() {
+            code.push(ZOP::CallVNA2{jump_to_label: "system_indent_word".to_string(),
+                arg1: Operand::new_large_const(spaces as i16), arg2: Operand::new_large_const(word.chars().count() as i16)});
+            code.push(ZOP::PrintOps{text: word});
+            word = String::new();
+            spaces = 0;
+        }
+        spaces += 1;
+    }
+
+    // the spaces at the end of the text, without the added one
+    let spaces = spaces - 1;
+    if spaces > 0 {
+        code.push(ZOP::CallVNA2{jump_to_label: "system_indent_word".to_string(),
+            arg1: Operand::new_large_const(spaces as i16), arg2: Operand::new_const(0)});
+    }
+    code
+}
+
+/// the key to select a link, printed behind the link (global 16 is the
+/// number of the link)
+fn link_marker(manager: &CodeGenManager) -> Vec<ZOP> {
+    match manager.cfg.link_selection {
+        LinkSelection::Digits => vec![
+            ZOP::Print{text: "[".to_string()},
+            ZOP::PrintNumVar{variable: Variable::new(16)},
+            ZOP::Print{text: "]".to_string()},
+        ],
+        LinkSelection::Letters => vec![
+            ZOP::Print{text: "[".to_string()},
+            // the first link is 'a', pushed to the stack and popped by the print
+            ZOP::Add{operand1: Operand::new_var(16), operand2: Operand::new_const(96), save_variable: Variable::new(0)},
+            ZOP::PrintUnicodeVar{var: Variable::new(0)},
+            ZOP::Print{text: "]".to_string()},
+        ],
+        // the links are selected in the menu
+        LinkSelection::Cursor => vec![],
+    }
+}
+
+/// counts the links in the node and its children
+fn count_links(node: &ASTNode) -> usize {
+    match node {
+        &ASTNode::Default(ref t) => {
+            let link = match &t.category {
+                &TokPassageLink { .. } => 1,
+                _ => 0
+            };
+            t.childs.iter().fold(link, |count, child| count + count_links(child))
+        },
+        &ASTNode::Passage(ref node) => node.childs.iter().fold(0, |count, child| count + count_links(child))
+    }
+}
 
-    let range_var: Variable = match temp_ids.pop() {
-        Some(var) => Variable::new(var),
-        None      => panic!{"Function random has no range variable"}
+/// records the start (0) or the end (1) of the link on the screen, so the
+/// link can be clicked (the zfile turns the mouse off before version 5)
+fn link_position(out: &Zfile, end: u8) -> Vec<ZOP> {
+    if out.mouse && out.link_selection != LinkSelection::Cursor {
+        vec![ZOP::Call2NWithArg{jump_to_label: "system_link_position".to_string(), arg: Operand::new_const(end)}]
+    } else {
+        vec![]
+    }
+}
+
+/// the message for a variable that doesn't fit into the global variables
+fn too_many_variables(name: &str) -> String {
+    format!("Too many variables, there is no global variable left for '{}'", name)
+}
+
+/// prints the elements of the array separated by commas
+fn print_array(id: u8, element_type: &Option<Box<Type>>) -> ZOP {
+    // the kind of the elements is 0 for numbers, 1 for strings and 2 for
+    // fixed-point numbers
+    let kind = match element_type {
+        &Some(ref element_type) if **element_type == Type::String => 1,
+        &Some(ref element_type) if **element_type == Type::Fixed => 2,
+        _ => 0
     };
+    ZOP::CallVNA2{jump_to_label: "system_print_array".to_string(), arg1: Operand::new_var(id), arg2: Operand::new_const(kind)}
+}
+
+/// random(from, to) -> zcode op_random(0, range)
+pub fn function_random<'a>(arg_from: &Operand, arg_to: &Operand, location: (u64, u64),
+        code: &mut Vec<ZOP>, temp_ids: &mut Vec<u8>, manager: &mut CodeGenManager<'a>) -> Operand {
+
+    let range_var = Variable::new(manager.pop_temp_var(temp_ids, location));
 
     // Calculate range = to - from + 1
     code.push(ZOP::Sub{
@@ -325,10 +979,7 @@ pub fn function_random(arg_from: &Operand, arg_to: &Operand,
         save_variable: range_var.clone()
     });
 
-    let var: Variable = match temp_ids.pop() {
-        Some(var) => Variable::new(var),
-        None      => panic!{"Function random has no variable"}
-    };
+    let var = Variable::new(manager.pop_temp_var(temp_ids, location));
 
     // get a random number between 1 and range
     code.push(ZOP::Random {range: Operand::new_var(range_var.id), variable: var.clone()} );
@@ -348,12 +999,157 @@ pub fn function_random(arg_from: &Operand, arg_to: &Operand,
     Operand::new_var(var.id)
 }
 
+/// tags() -> the tags of the current passage separated by spaces,
+/// tags(name) -> true if the current passage has the tag
+pub fn function_tags<'a>(arg_tag: Option<&Operand>, location: (u64, u64), code: &mut Vec<ZOP>,
+        temp_ids: &mut Vec<u8>, manager: &mut CodeGenManager<'a>) -> Operand {
+    let id = manager.pop_temp_var(temp_ids, location);
+
+    match arg_tag {
+        Some(tag) => {
+            let var = Variable::new_bool(id);
+            code.push(ZOP::Call2S{jump_to_label: "system_has_tag".to_string(), arg: tag.clone(), result: var.clone()});
+            Operand::Var(var)
+        },
+        None => {
+            // the first entry of the tags table is the joined string
+            let var = Variable::new_string(id);
+            code.push(ZOP::StoreVariable{variable: Variable::new(id), value: Operand::new_const(0)});
+            code.push(ZOP::LoadW{array_address: Operand::new_var(20), index: Variable::new(id), variable: var.clone()});
+            Operand::Var(var)
+        }
+    }
+}
+
+/// prints the prompt and returns the line typed by the player as string
+pub fn function_prompt<'a>(arg_prompt: Option<&Operand>, location: (u64, u64), code: &mut Vec<ZOP>,
+        temp_ids: &mut Vec<u8>, manager: &mut CodeGenManager<'a>) -> Operand {
+    let id = manager.pop_temp_var(temp_ids, location);
+
+    if let Some(prompt) = arg_prompt {
+        let address = match prompt {
+            &Operand::StringRef(ref addr) => Operand::new_large_const(addr.value),
+            _ => prompt.clone()
+        };
+        code.push(ZOP::PrintUnicodeStr{address: address});
+    }
+
+    let var = Variable::new_string(id);
+    code.push(ZOP::Call2S{jump_to_label: "system_read_line".to_string(), arg: Operand::new_const(0), result: var.clone()});
+    Operand::Var(var)
+}
+
+/// visited() -> the number of visits of the current passage,
+/// visited(name) -> the number of visits of the passage
+pub fn function_visited<'a>(arg_passage: Option<&Operand>, location: (u64, u64), code: &mut Vec<ZOP>,
+        temp_ids: &mut Vec<u8>, manager: &mut CodeGenManager<'a>) -> Operand {
+    let id = manager.pop_temp_var(temp_ids, location);
+
+    // global 23 is the name of the current passage
+    let passage = match arg_passage {
+        Some(passage) => passage.clone(),
+        None => Operand::new_var(23)
+    };
+    let var = Variable::new(id);
+    code.push(ZOP::Call2S{jump_to_label: "system_visited".to_string(), arg: passage, result: var.clone()});
+    Operand::Var(var)
+}
+
+/// previous() -> the name of the previous passage (global 22),
+/// passage() -> the name of the current passage (global 23)
+pub fn function_passage_name<'a>(previous: bool, location: (u64, u64), code: &mut Vec<ZOP>,
+        temp_ids: &mut Vec<u8>, manager: &mut CodeGenManager<'a>) -> Operand {
+    let id = manager.pop_temp_var(temp_ids, location);
+
+    let global = if previous { 22 } else { 23 };
+    let var = Variable::new_string(id);
+    code.push(ZOP::StoreVariable{variable: var.clone(), value: Operand::new_var(global)});
+    Operand::Var(var)
+}
+
+/// turns() -> the number of passages the player has seen (global 24)
+pub fn function_turns<'a>(location: (u64, u64), code: &mut Vec<ZOP>, temp_ids: &mut Vec<u8>,
+        manager: &mut CodeGenManager<'a>) -> Operand {
+    let id = manager.pop_temp_var(temp_ids, location);
+
+    let var = Variable::new(id);
+    code.push(ZOP::StoreVariable{variable: var.clone(), value: Operand::new_var(24)});
+    Operand::Var(var)
+}
+
+/// Math.round(x), Math.floor(x) and Math.ceil(x) -> the integer next to the
+/// fixed-point number, integers are returned unchanged
+pub fn function_math<'a>(name: &str, arg_number: &Operand, location: (u64, u64), code: &mut Vec<ZOP>,
+        temp_ids: &mut Vec<u8>, manager: &mut CodeGenManager<'a>) -> Operand {
+    match arg_number {
+        &Operand::Var(Variable{vartype: Type::Fixed, ..}) => (),
+        _ => return arg_number.clone()
+    }
+
+    let var = Variable::new(manager.pop_temp_var(temp_ids, location));
+
+    match name {
+        "Math.round" => {
+            code.push(ZOP::Call2S{jump_to_label: "system_fixed_round".to_string(), arg: arg_number.clone(), result: var.clone()});
+        },
+        "Math.floor" => {
+            code.push(ZOP::Call2S{jump_to_label: "system_fixed_floor".to_string(), arg: arg_number.clone(), result: var.clone()});
+        },
+        "Math.ceil" => {
+            // ceil(x) = -floor(-x)
+            code.push(ZOP::Sub{operand1: Operand::new_const(0), operand2: arg_number.clone(), save_variable: var.clone()});
+            code.push(ZOP::Call2S{jump_to_label: "system_fixed_floor".to_string(), arg: Operand::new_var(var.id), result: var.clone()});
+            code.push(ZOP::Sub{operand1: Operand::new_const(0), operand2: Operand::new_var(var.id), save_variable: var.clone()});
+        },
+        _ => {
+            manager.report(format!("Unsupported function: {}", name), location);
+            return Operand::new_const(0);
+        }
+    }
+
+    if let &Operand::Var(ref number) = arg_number {
+        if CodeGenManager::is_temp_var(number) {
+            temp_ids.push(number.id);
+        }
+    }
+    Operand::Var(var)
+}
+
 pub struct CodeGenManager<'a> {
+    pub cfg: &'a Config,
     pub ids_if: IdentifierProvider,
     pub ids_expr: IdentifierProvider,
+    pub ids_silently: IdentifierProvider,
+    pub ids_link: IdentifierProvider,
+    pub ids_save: IdentifierProvider,
     pub passages: Vec<String>,
     pub symbol_table: SymbolTable<'a>,
-    pub format_state: FormattingState
+    pub format_state: FormattingState,
+    pub line_state: LineState,
+    pub errors: Vec<Diagnostic>,
+}
+
+/// The state of the current output line, used for headings, lists and indent blocks
+pub struct LineState {
+    /// nothing was printed since the last newline
+    pub at_line_start: bool,
+    /// the first line of the passage
+    pub first_line: bool,
+    /// the line is a heading
+    pub heading: bool,
+    /// the line is an item of a numbered list
+    pub numb_item: bool,
+    /// the number of the last item of the numbered list
+    pub numb_count: u32,
+    /// inside of an indent block (`<<<`)
+    ///
+    /// The Z-Machine has no margins, so the text of an indent block is
+    /// wrapped word by word at runtime and every line starts with spaces.
+    /// Only the text of the passage is counted, links and the values of
+    /// variables in an indent block don't move the wrap position.
+    pub indent: bool,
+    /// the passage is tagged with `nobr`, lines are joined with spaces
+    pub nobr: bool,
 }
 
 pub struct IdentifierProvider {
@@ -362,19 +1158,45 @@ pub struct IdentifierProvider {
 }
 
 pub struct SymbolTable<'a> {
-    current_id: u8,
+    current_id: u16,
     symbol_map: HashMap<&'a str, (Variable, Type)>
 }
 
 impl <'a> CodeGenManager<'a> {
-    pub fn new() -> CodeGenManager<'a> {
+    pub fn new(cfg: &'a Config) -> CodeGenManager<'a> {
         CodeGenManager {
+            cfg: cfg,
             ids_if: IdentifierProvider::new(),
             ids_expr: IdentifierProvider::new(),
+            ids_silently: IdentifierProvider::new(),
+            ids_link: IdentifierProvider::new(),
+            ids_save: IdentifierProvider::new(),
             passages: Vec::new(),
             symbol_table: SymbolTable::new(),
             format_state: FormattingState {bold: false, italic: false, mono: false, inverted: false},
+            line_state: LineState::new(),
+            errors: Vec::new(),
+        }
+    }
+
+    /// Stores an error found while generating the code for a node
+    pub fn report(&mut self, message: String, location: (u64, u64)) {
+        self.errors.push(Diagnostic::new(DiagnosticKind::Codegen, message, location));
+    }
+
+    /// Takes a free temporary variable for the expression at the location,
+    /// an expression that needs more than the 13 temporary variables is
+    /// reported and gets the first one
+    pub fn pop_temp_var(&mut self, temp_ids: &mut Vec<u8>, location: (u64, u64)) -> u8 {
+        if let Some(id) = temp_ids.pop() {
+            return id;
         }
+
+        let message = "The expression is too complex, it needs more than 13 temporary variables".to_string();
+        if self.errors.last().map_or(true, |error| error.message != message) {
+            self.report(message, location);
+        }
+        2
     }
 
     pub fn new_temp_var_vec() -> Vec<u8> {
@@ -386,6 +1208,20 @@ impl <'a> CodeGenManager<'a> {
     }
 }
 
+impl LineState {
+    pub fn new() -> LineState {
+        LineState {
+            at_line_start: true,
+            first_line: true,
+            heading: false,
+            numb_item: false,
+            numb_count: 0,
+            indent: false,
+            nobr: false,
+        }
+    }
+}
+
 impl IdentifierProvider {
     pub fn new() -> IdentifierProvider {
         IdentifierProvider {
@@ -430,10 +1266,15 @@ impl <'a> SymbolTable<'a> {
     }
 
     // Inserts a symbol into the table, assigning a new id
-    pub fn insert_new_symbol(&mut self, symbol: &'a str, t: Type) {
+    // (returns false if all global variables are used)
+    pub fn insert_new_symbol(&mut self, symbol: &'a str, t: Type) -> bool {
+        if self.current_id > 255 {
+            return false;
+        }
         debug!("Assigned id {} to variable {}", self.current_id, symbol);
-        self.symbol_map.insert(symbol, (Variable{id: self.current_id, vartype: t.clone()}, t));
+        self.symbol_map.insert(symbol, (Variable{id: self.current_id as u8, vartype: t.clone()}, t));
         self.current_id += 1;
+        true
     }
 
     // Checks if the symbol is already existent in the table
@@ -441,47 +1282,684 @@ impl <'a> SymbolTable<'a> {
         self.symbol_map.contains_key(symbol)
     }
 
-    // Returns the id for a given symbol
-    // (check if is_known_symbol, otherwise panics)
-    pub fn get_symbol_id(&self, symbol: &str) -> Variable {
-        if let Some(temp) = self.symbol_map.get(symbol) {
-            return temp.0.clone()
+    // Returns the id for a given symbol, None if the symbol is unknown
+    pub fn get_symbol_id(&self, symbol: &str) -> Option<Variable> {
+        self.symbol_map.get(symbol).map(|temp| temp.0.clone())
+    }
+
+    pub fn get_symbol_type(&self, symbol: &str) -> Option<Type> {
+        self.symbol_map.get(symbol).map(|temp| temp.1.clone())
+    }
+
+    // Changes the type of a known symbol, an empty array gets the type of
+    // its elements when the first one is added
+    // (unknown symbols are ignored)
+    pub fn set_symbol_type(&mut self, symbol: &str, t: Type) {
+        if let Some(temp) = self.symbol_map.get_mut(symbol) {
+            temp.0.vartype = t.clone();
+            temp.1 = t;
+        }
+    }
+
+    pub fn has_var_id(&self, id: u8) -> bool {
+        self.symbol_map.values().any(|temp| temp.0.id == id)
+    }
+
+    pub fn get_symbol_type_by_id(&self, id: u8) -> Option<Type> {
+        self.symbol_map.values().find(|temp| temp.0.id == id).map(|temp| temp.1.clone())
+    }
+}
+
+// ================================
+// test functions
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use backend::zcode::zfile::{Operand, Type, ZOP, Zfile};
+    use backend::zcode::zrun::Machine;
+    use config::{Config, FormatFallback, LinkSelection};
+    use frontend::ast::{ASTNode, NodeDefault};
+    use frontend::evaluate_expression::evaluate_expression;
+    use frontend::lexer::Token;
+    use frontend::lexer::Token::*;
+    use frontend::testing::*;
+
+    #[test]
+    fn compound_assignment_test() {
+        let output = test_run("::Start\n<<set $a = 10>><<set $a += 5>>a=<<print $a>>;\
+            <<set $a -= 3>>b=<<print $a>>;<<set $a *= 2 + 1>>c=<<print $a>>;\
+            <<set $a /= 4>>d=<<print $a>>;<<set $a -= 10>>e=<<print $a>>;\
+            <<set $s = \"Zw\">><<set $s += \"reec\">>f=<<print $s>>;<<set $s += $a>>g=<<print $s>>;", "");
+
+        assert_output(&output, &["a=15;", "b=12;", "c=36;", "d=9;", "e=-1;", "f=Zwreec;", "g=Zwreec-1;"]);
+    }
+
+    #[test]
+    fn compound_assignment_ops_test() {
+        let cfg = Config::default_config();
+        let zfile = test_compile(&cfg, "::Start\n<<set $a = 7>><<set $a *= 4>>");
+
+        // the variable is multiplied with the constant
+        let a = 25;
+        assert!(zfile.ops().iter().any(|op| match op {
+            &ZOP::Mul{operand1: Operand::Var(ref var), operand2: Operand::Const(ref c), ..} => var.id == a && c.value == 4,
+            _ => false
+        }));
+    }
+
+    #[test]
+    fn compound_assignment_string_test() {
+        assert_eq!(test_errors("::Start\n<<set $s = \"a\">><<set $s -= 1>>"),
+            vec!["Operator `-=` can not be used on the string variable '$s'".to_string()]);
+        assert_eq!(test_errors("::Start\n<<set $a += 1>>"),
+            vec!["Variable '$a' is used before it is set".to_string()]);
+    }
+
+    #[test]
+    fn multiple_assignments_test() {
+        let output = test_run("::Start\n<<set $a = 1; $b = $a + 1, $c to $b * 3>>\
+            a=<<print $a>>;b=<<print $b>>;c=<<print $c>>;", "");
+
+        assert_output(&output, &["a=1;", "b=2;", "c=6;"]);
+    }
+
+    #[test]
+    fn silently_test() {
+        let output = test_run("::Start\nbefore;<<silently>>hidden<<set $a = 3>>\
+            <<silently>>nested<<endsilently>>still hidden<<display Other>><<endsilently>>\
+            after;a=<<print $a>>;<<display Other>>\n::Other\nother;", "");
+
+        assert_output(&output, &["before;", "after;a=3;other;"]);
+        assert!(!output.contains("hidden") && !output.contains("nested"), "{}", output);
+        assert_eq!(output.matches("other;").count(), 1);
+    }
+
+    #[test]
+    fn horizontal_line_test() {
+        // the interpreter of the tests has 80 columns
+        let output = test_run("::Start\nbefore\n----\nafter", "");
+        let dashes: String = (0..79).map(|_| '-').collect();
+        let line = format!("\n{}\n", dashes);
+        assert_output(&output, &["before", &line, "after"]);
+        assert!(!output.contains(&format!("{}-", dashes)), "{}", output);
+    }
+
+    #[test]
+    fn indent_block_test() {
+        let output = test_run("::Start\nnormal\n<<<\nfirst\nsecond\n<<<\nnormal again", "");
+        assert_output(&output, &["normal\n", "\n    first\n    second\n", "\nnormal again"]);
+    }
+
+    #[test]
+    fn indent_block_wrap_test() {
+        // 17 words of 7 characters need two lines of the 80 columns
+        let words: Vec<String> = (10..27).map(|i| format!("word_{}", i)).collect();
+        let input = format!("::Start\n<<<\n{}  ''end''\n<<<\n", words.connect(" "));
+        let output = test_run(&input, "");
+
+        let lines: Vec<&str> = output.lines().filter(|line| line.starts_with("    ")).collect();
+        assert_eq!(lines.len(), 2, "{}", output);
+        assert_eq!(lines[0], format!("    {}", words[..9].connect(" ")));
+        assert_eq!(lines[1], format!("    {}  end", words[9..].connect(" ")));
+        assert!(lines.iter().all(|line| line.len() < 80), "{}", output);
+    }
+
+    #[test]
+    fn format_fallback_test() {
+        let input = "::Start\n__under__;==strike==;x^^2^^;H~~2~~O;";
+
+        let mut cfg = Config::default_config();
+        cfg.format_fallback = FormatFallback::Text;
+        let output = test_run_with_cfg(&cfg, input, "");
+        assert_output(&output, &["_under_;-strike-;x^(2);H_(2)O;"]);
+
+        cfg.format_fallback = FormatFallback::Plain;
+        let output = test_run_with_cfg(&cfg, input, "");
+        assert_output(&output, &["under;strike;x2;H2O;"]);
+
+        // underlined text is shown in italics
+        cfg.format_fallback = FormatFallback::Style;
+        let zfile = test_compile(&cfg, input);
+        assert!(zfile.ops().iter().any(|op| match op {
+            &ZOP::SetTextStyle{italic, ..} => italic,
+            _ => false
+        }));
+        let mut machine = Machine::new(&zfile);
+        machine.run();
+        assert_output(&machine.output, &["under;-strike-;x^(2);H_(2)O;"]);
+    }
+
+    #[test]
+    fn variable_link_test() {
+        let output = test_run("::Start\n<<set $t = \"Second\">><<set $m = \"Nowhere\">>\
+            [[Go|$t]];[[Lost|$m]];[[$t]];\n::Second\nreached second;", "2");
+
+        assert_output(&output, &["Go[1];", "[no passage named \"Nowhere\"];", "Second[2];", "reached second;"]);
+    }
+
+    #[test]
+    fn variable_link_errors_test() {
+        assert_eq!(test_errors("::Start\n[[Go|$t]]\n::Second\nText"),
+            vec!["Variable '$t' is used before it is set".to_string()]);
+        assert_eq!(test_errors("::Start\n<<set $t = 1>>[[Go|$t]]"),
+            vec!["Link target '$t' has to be a string".to_string()]);
+    }
+
+    #[test]
+    fn display_expression_test() {
+        let output = test_run("::Start\n<<set $room = \"Kitchen\">><<set $n = 2>>\
+            a=<<display $room>>;b=<<display $room + $n>>;c=<<display \"Kitchen\">>;\
+            d=<<display (\"Kit\" + \"chen\")>>;e=<<display $room + 3>>;f=<<display Room (old)>>;\
+            \n::Kitchen\nkitchen\n::Kitchen2\nroom 2\n::Room (old)\nold room", "");
+
+        // the newline at the end of Kitchen is displayed as well
+        assert_output(&output, &["a=kitchen", "b=room 2;", "c=kitchen", "d=kitchen",
+            "e=[no passage named \"Kitchen3\"];", "f=old room"]);
+    }
+
+    #[test]
+    fn display_errors_test() {
+        assert_eq!(test_errors("::Start\n<<display \"Nowhere\">>"),
+            vec!["Display of unknown passage 'Nowhere'".to_string()]);
+        assert_eq!(test_errors("::Start\n<<display (1 + 2)>>"),
+            vec!["The passage name of display has to be a string".to_string()]);
+    }
+
+    #[test]
+    fn tags_test() {
+        let input = "::Start [nobr intro]\nline one\nline two\n\
+            <<if tags(\"intro\")>>intro;<<endif>><<if not tags(\"widget\")>>plain;<<endif>>tags=<<print tags()>>;[[Next]]\n\
+            ::Next\nno tags=\"<<print tags()>>\";<<display \"Other\">>\n\
+            ::Other [other]\nshown=\"<<print tags()>>\";";
+
+        // the tags of a passage included with <<display>> are not shown
+        let output = test_run(input, "1");
+        assert_output(&output, &["line one line two intro;plain;tags=nobr intro;", "no tags=\"\";", "shown=\"\";"]);
+    }
+
+    #[test]
+    fn special_passages_test() {
+        let input = "::Start\nstart;[[Next]]\n::Next\nnext;\n::StoryInit\n[[Next]]\n\
+            ::StoryMenu\nmenu;[[Start]]\n::StoryTitle\nThe Title\n::StoryAuthor\nThe Author";
+        let zfile = test_compile(&Config::default_config(), input);
+
+        // the title and the author are written to the story file
+        let bytes = String::from_utf8_lossy(&zfile.data.bytes).into_owned();
+        assert!(bytes.contains("StoryTitle://The Title//StoryAuthor://The Author//"));
+
+        // only the links of Start and StoryMenu can be chosen, the link of
+        // StoryInit is not counted
+        let mut machine = Machine::new(&zfile);
+        machine.run();
+        assert_eq!(machine.global(16), 2);
+        assert_output(&machine.output, &["The Title\nby The Author\n", "start;Next[1]\n", "menu;Start[2]\npress a key"]);
+
+        // StoryMenu doesn't free the memory of the passage
+        let menu = zfile.ops().iter().position(|op| match op {
+            &ZOP::Routine{ref name, ..} => name == "StoryMenu",
+            _ => false
+        }).unwrap();
+        assert!(!zfile.ops()[menu + 1..].iter().take_while(|op| match op {
+            &ZOP::Routine{..} => false,
+            _ => true
+        }).any(|op| match op {
+            &ZOP::Call1N{ref jump_to_label} => jump_to_label == "mem_free",
+            _ => false
+        }));
+    }
+
+    #[test]
+    fn widget_test() {
+        let input = "::Start\n<<Count>><<Count>>[[Next]]\n::Next\n<<Count>>\n::StoryInit\n<<set $n = 0>>\n\
+            ::Count [widget]\n<<set $n += 1>>count=<<print $n>>;";
+
+        // a widget only runs where it is used
+        let output = test_run(input, "1");
+        assert_output(&output, &["count=1;", "count=2;", "count=3;"]);
+        assert!(!output.contains("count=4;"), "{}", output);
+
+        assert_eq!(test_errors("::Start\n<<Nowhere>>"), vec!["Display of unknown passage 'Nowhere'".to_string()]);
+    }
+
+    #[test]
+    fn save_restore_test() {
+        let input = "::Start\n<<set $n = 0>>[[Next]]\n::Next\n<<set $n += 1>>n=<<print $n>>;[[Next]]";
+
+        // restoring before saving fails, the restored game continues at the
+        // menu of the passage it was saved in
+        let output = test_run(input, "r1s1r1");
+        assert_output(&output, &["Restore failed.", "n=1;", "Game saved.", "Game restored."]);
+        assert_eq!(output.matches("n=2;").count(), 2, "{}", output);
+        assert!(!output.contains("n=3;"), "{}", output);
+
+        let cfg = Config::default_config();
+        let (zfile, _) = test_codegen(&cfg, input);
+        let mut machine = Machine::new(&zfile);
+        machine.type_keys("1n");
+        machine.run();
+        assert!(machine.finished);
+    }
+
+    #[test]
+    fn save_macros_test() {
+        let output = test_run("::Start\n<<restore>>before;<<save>>after;", "");
+        assert_output(&output, &["Restore failed.", "before;after;"]);
+        assert!(!output.contains("Save failed."), "{}", output);
+    }
+
+    #[test]
+    fn undo_test() {
+        let input = "::Start\n<<set $n = 0>>start;[[Next]]\n::Next\n<<set $n += 1>>n=<<print $n>>;[[Next]]";
+        let mut cfg = Config::default_config();
+        cfg.undo = true;
+
+        // the passage before the undone choice is shown again, without
+        // keeping the changes of the redraw
+        let output = test_run_with_cfg(&cfg, input, "1uu1");
+        let undone = output.find("Previous choice undone.").expect(&output);
+        assert!(output[..undone].rfind("start;").unwrap() > output.find("n=1;").unwrap(), "{}", output);
+        assert_eq!(output.matches("start;").count(), 2, "{}", output);
+        assert_eq!(output.matches("n=1;").count(), 2, "{}", output);
+        assert!(!output.contains("n=2;"), "{}", output);
+        assert_output(&output, &["Nothing to undo."]);
+
+        // the undo key is only offered with the undo feature
+        let output = test_run(input, "1u");
+        assert!(!output.contains("undo"), "{}", output);
+    }
+
+    #[test]
+    fn undo_twice_test() {
+        let input = "::Start\n<<set $n = 0>>start;[[Next]]\n::Next\n<<set $n += 1>>n=<<print $n>>;[[Next]]";
+        let mut cfg = Config::default_config();
+        cfg.undo = true;
+
+        // the second undo goes back to the start, the redraws of the
+        // passages don't take an undo level
+        let output = test_run_with_cfg(&cfg, input, "11uu1u");
+        assert_eq!(output.matches("Previous choice undone.").count(), 3, "{}", output);
+        assert_eq!(output.matches("start;").count(), 3, "{}", output);
+        let second = output.match_indices("Previous choice undone.").nth(1).unwrap().0;
+        assert!(output[..second].rfind("start;").unwrap() > output.rfind("n=2;").unwrap(), "{}", output);
+        assert!(!output.contains("n=3;"), "{}", output);
+        assert!(!output.contains("Nothing to undo."), "{}", output);
+    }
+
+    #[test]
+    fn textinput_test() {
+        let input = "::Start\n<<textinput $name>>name=<<print $name>>;";
+
+        // the case is kept, delete removes the last letter and prints the
+        // rest again, the cursor keys are ignored
+        let output = test_run(input, "Ab\x08\u{81}c\n");
+        assert_output(&output, &["Ab\nAc\n", "name=Ac;"]);
+
+        let output = test_run(input, "\x08Zw\n");
+        assert_output(&output, &["name=Zw;"]);
+    }
+
+    #[test]
+    fn array_bounds_test() {
+        let input = "::Start\n<<set $a = [1, 2]>><<set $i = 0 - 1>><<set $a[2] = 7>><<set $a[$i] = 8>>\
+            a=<<print $a>>;b=<<print $a[2]>>;c=<<print $a[$i]>>;<<set $s = [\"x\"]>>d=<<print $s[1]>>;";
+
+        // indexes out of range are ignored and read 0 or the empty string
+        let output = test_run(input, "");
+        assert_output(&output, &["a=1, 2;", "b=0;", "c=0;", "d=;"]);
+    }
+
+    #[test]
+    fn array_push_test() {
+        let mut input = "::Start\n<<set $a = [0]>>".to_string();
+        for i in 1..12 {
+            input.push_str(&format!("<<set $a.push({})>>", i));
+        }
+        input.push_str("n=<<print $a.length>>;a=<<print $a>>;");
+
+        // the array is moved to a bigger block twice
+        let output = test_run(&input, "");
+        assert_output(&output, &["n=12;", "a=0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11;"]);
+    }
+
+    #[test]
+    fn fixed_point_rounding_test() {
+        let mut cfg = Config::default_config();
+        cfg.fixed_point = true;
+        let input = "::Start\n<<set $a = 2.5>><<set $b = -1.5>><<set $c = -1.51>><<set $m = 327.6>>\
+            a=<<print Math.round($a)>>;b=<<print Math.round($b)>>;c=<<print Math.round($c)>>;\
+            m=<<print Math.round($m)>>;f=<<print Math.floor($b)>>;e=<<print Math.ceil($b)>>;\
+            d=<<print 0.125>>;t=<<print 10 / 3.0>>;n=<<print 0 - 10 / 3.0>>;";
+
+        // halves are rounded up, the constants to two decimal places
+        let output = test_run_with_cfg(&cfg, input, "");
+        assert_output(&output, &["a=3;", "b=-1;", "c=-2;", "m=328;", "f=-2;", "e=-1;", "d=0.13;", "t=3.33;", "n=-3.33;"]);
+    }
+
+    #[test]
+    fn fixed_point_range_test() {
+        let mut cfg = Config::default_config();
+        cfg.fixed_point = true;
+
+        let (_, errors) = test_codegen(&cfg, "::Start\n<<set $a = 400.5>><<set $b = 1.5>><<set $b = 400>>\
+            <<print $b + 328>><<print $b / 0>><<print 7 % 0>>");
+        let errors: Vec<String> = errors.into_iter().map(|error| error.message).collect();
+        assert_eq!(errors, vec![
+            "The decimal number 400.5 is out of range, it has to be between -327.67 and 327.67".to_string(),
+            "The number 400 is out of range for a decimal number, it has to be between -327.67 and 327.67".to_string(),
+            "The number 328 is out of range for a decimal number, it has to be between -327.67 and 327.67".to_string(),
+            "Division by zero".to_string(),
+            "Division by zero".to_string()]);
+
+        // variables are checked when they are converted
+        let output = test_run_with_cfg(&cfg, "::Start\n<<set $a = 1.5>><<set $i = 400>><<set $a = $i>>a=<<print $a>>;\
+            <<set $z = 0.0>>b=<<print $a / $z>>;c=<<print $a % $z>>;d=<<print $i * 1.0>>;", "");
+        assert_output(&output, &["[number out of range]a=327.67;", "b=[division by zero]0.00;",
+            "c=[division by zero]0.00;", "d=[number out of range]327.67;"]);
+
+        // results out of range are replaced by the nearest number in range
+        let output = test_run_with_cfg(&cfg, "::Start\ne=<<print 200.0 * 2.0>>;f=<<print 0 - 200.0 * 2.0>>;\
+            g=<<print 300.0 + 100.0>>;h=<<print 0 - 300.0 - 100.0>>;i=<<print 300.0 / 0.5>>;j=<<print 0 - 300.0 / 0.5>>;\
+            k=<<print 163.0 * 2.01>>;l=<<print 0 - 1.5 * 3.25>>;m=<<print 0.5 * 0.5>>;\
+            n=<<print 70.0 / 70.01>>;o=<<print 0 - 327.67 / 3.0>>;p=<<print 327.67 / 0.01>>;", "");
+        assert_output(&output, &["e=[number out of range]327.67;", "f=[number out of range]-327.67;",
+            "g=[number out of range]327.67;", "h=[number out of range]-327.67;", "i=[number out of range]327.67;",
+            "j=[number out of range]-327.67;", "k=327.63;", "l=-4.87;", "m=0.25;", "n=0.99;", "o=-109.22;",
+            "p=[number out of range]327.67;"]);
+
+        // the smallest word has no positive counterpart
+        let zfile = test_compile(&cfg, "::Start\n<<print 1.5>>");
+        let mut machine = Machine::new(&zfile);
+        machine.call("system_print_fixed", &[::std::i16::MIN]);
+        assert_eq!(machine.output, "-327.68");
+        let string = machine.call("system_ftoa", &[::std::i16::MIN]);
+        assert_eq!(machine.string(string as u16), "-327.68");
+    }
+
+    #[test]
+    fn history_test() {
+        let input = "::Start\nv=<<print visited()>>;t=<<print turns()>>;r=<<print visited(\"Room\")>>;\
+            l=<<print visited(\"Lamp\")>>;[[Room]]\n\
+            ::Room\nroom=<<print visited()>>;p=<<print previous()>>;c=<<print passage()>>;t=<<print turns()>>;\
+            <<display \"Lamp\">>[[Start]]\n\
+            ::Lamp\nlamp=<<print passage()>>;";
+
+        // the displayed passage is not visited and doesn't change passage()
+        let output = test_run(input, "11");
+        assert_output(&output, &["v=1;t=1;r=0;l=0;", "room=1;p=Start;c=Room;t=2;", "lamp=Room;", "v=2;t=3;r=1;l=0;"]);
+        assert!(!output.contains("t=4;"), "{}", output);
+    }
+
+    #[test]
+    fn link_limit_test() {
+        let input = start_links_story(128);
+        assert_eq!(test_errors(&input), vec!["The passage 'Start' has 128 links, but at most 127 are possible".to_string()]);
+
+        // the link of StoryMenu is left out, the passage has all links
+        let input = start_links_story(127) + "\n::StoryMenu\n[[Start]]";
+        let zfile = test_compile(&Config::default_config(), &input);
+        let mut machine = Machine::new(&zfile);
+        machine.run();
+        assert_eq!(machine.global(16), 127);
+    }
+
+    #[test]
+    fn letters_link_limit_test() {
+        let mut cfg = Config::default_config();
+        cfg.link_selection = LinkSelection::Letters;
+        let (_, errors) = test_codegen(&cfg, &start_links_story(27));
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].message, "The passage 'Start' has 27 links, but at most 26 can be selected with letters, \
+            select them with `--link-selection digits` instead".to_string());
+
+        let output = test_run_with_cfg(&cfg, &start_links_story(26), "");
+        assert_output(&output, &["Start[a]", "Start[z]"]);
+    }
+
+    #[test]
+    fn version_warnings_test() {
+        let mut cfg = Config::default_config();
+        cfg.zversion = 3;
+        let zfile = test_compile(&cfg, "::Start\nText [[Start]]");
+        assert!(zfile.warnings.contains(&"Version 3 of the Z-Machine has no colours, they are left out".to_string()));
+        assert!(zfile.warnings.contains(&"Version 3 of the Z-Machine can't erase the screen, the passages follow each other".to_string()));
+
+        cfg.zversion = 5;
+        let (zfile, _) = test_codegen(&cfg, "::Start\nText [[Start]]");
+        assert!(zfile.warnings.is_empty());
+
+        // the easter egg is left out silently, features the user enabled
+        // are errors
+        cfg.zversion = 4;
+        cfg.mouse = true;
+        cfg.easter_egg = true;
+        let (_, errors) = test_codegen(&cfg, "::Start\nText [[Start]]");
+        let messages: Vec<String> = errors.into_iter().map(|error| error.message).collect();
+        assert_eq!(messages, vec!["The mouse feature needs version 5 of the Z-Machine, not 4".to_string()]);
+    }
+
+    #[test]
+    fn link_selection_test() {
+        let input = many_links_story();
+        let mut cfg = Config::default_config();
+
+        // more than nine links are selected by typing the number and enter
+        let output = test_run_with_cfg(&cfg, &input, "12\n");
+        assert_output(&output, &["P12[12]", "in p12;"]);
+
+        // a wrong number doesn't select a link
+        let output = test_run_with_cfg(&cfg, &input, "13\n");
+        assert!(!output.contains("in p"), "{}", output);
+
+        cfg.link_selection = LinkSelection::Letters;
+        let output = test_run_with_cfg(&cfg, &input, "l");
+        assert_output(&output, &["in p12;"]);
+
+        cfg.link_selection = LinkSelection::Cursor;
+        let zfile = test_compile(&cfg, &input);
+        let mut machine = Machine::new(&zfile);
+        for _ in 0..12 {
+            machine.type_key(130);
         }
+        machine.type_key(129);
+        machine.type_key(13);
+        machine.run();
+        assert_output(&machine.output, &["in p11;"]);
+    }
+
+    #[test]
+    fn mouse_click_test() {
+        let input = "::Start\nGo [[A]] or [[B]]\n::A\nin a;\n::B\nin b;";
+        let mut cfg = Config::default_config();
+        cfg.mouse = true;
+        let zfile = test_compile(&cfg, input);
 
-        panic!{"symbol_map is empty, get_symbol_id wasn't possible."}
+        // a click beside the links is ignored, a click on the last
+        // character of a link selects it
+        let mut machine = Machine::new(&zfile);
+        machine.run();
+        let (row, column) = screen_position(&machine.output, "B[2]", 25);
+        machine.click(row, column - 1);
+        machine.run();
+        assert!(!machine.output.contains("in a;") && !machine.output.contains("in b;"), "{}", machine.output);
+        machine.click(row, column + 3);
+        machine.run();
+        assert_output(&machine.output, &["in b;"]);
+        assert!(!machine.output.contains("in a;"), "{}", machine.output);
+
+        // the rows of the cursor menu are the links
+        cfg.link_selection = LinkSelection::Cursor;
+        let zfile = test_compile(&cfg, input);
+        let mut machine = Machine::new(&zfile);
+        machine.click(1, 5);
+        machine.run();
+        assert_output(&machine.output, &["in a;"]);
+
+        // clicks are only read with the mouse feature
+        let zfile = test_compile(&Config::default_config(), input);
+        assert!(!zfile.ops().iter().any(|op| match op {
+            &ZOP::Routine{ref name, ..} => name == "system_link_at_mouse",
+            _ => false
+        }));
     }
 
-    pub fn get_symbol_type(&self, symbol: &str) -> Type {
-        if let Some(temp) = self.symbol_map.get(symbol) {
-            return temp.1.clone()
+    #[test]
+    fn mouse_scrolled_click_test() {
+        // the lines after the links scroll the screen, so the links move up
+        let mut input = "::Start\n".to_string();
+        for i in 0..12 {
+            input.push_str(&format!("line {}\n", i));
         }
+        input.push_str("Go [[A]] or [[B]]\nafter\nthe\nlinks\n::A\nin a;\n::B\nin b;");
+        let mut cfg = Config::default_config();
+        cfg.mouse = true;
+        let zfile = test_compile(&cfg, &input);
 
-        panic!{"symbol_map is empty, get get_symbol_type wasn't possible."}
+        let mut machine = Machine::new(&zfile);
+        machine.set_screen_height(10);
+        machine.run();
+        let (row, column) = screen_position(&machine.output, "B[2]", 10);
+        assert!(row < 7, "{}", row);
+        machine.click(row, column);
+        machine.run();
+        assert_output(&machine.output, &["in b;"]);
+        assert!(!machine.output.contains("in a;"), "{}", machine.output);
     }
 
-    pub fn has_var_id(&self, id: u8) -> bool {
-        for name in self.symbol_map.keys() {
-            if let Some(temp) = self.symbol_map.get(name) {
-                if temp.0.clone().id == id {
-                    return true;
-                }
-            } else {
-                panic!{"symbol_map is empty, has_var_id wasn't possible."}
+    #[test]
+    fn mouse_link_limit_test() {
+        // the link of StoryMenu is left out, so its position doesn't replace
+        // the one of the last link of the passage
+        let mut input = "::Start\n".to_string();
+        for i in 1..128 {
+            input.push_str(if i == 127 { "[[End]]" } else { "[[Start]]" });
+            if i % 10 == 0 {
+                input.push_str("\n");
             }
         }
-        false
+        input.push_str("\n::End\nthe end;\n::StoryMenu\n[[Start]]");
+        let mut cfg = Config::default_config();
+        cfg.mouse = true;
+        let zfile = test_compile(&cfg, &input);
+
+        let mut machine = Machine::new(&zfile);
+        machine.run();
+        let (row, column) = screen_position(&machine.output, "End[127]", 25);
+        machine.click(row, column);
+        machine.run();
+        assert_output(&machine.output, &["the end;"]);
     }
 
-    pub fn get_symbol_type_by_id(&self, id: u8) -> Type {
-        for name in self.symbol_map.keys() {
-            if let Some(temp) = self.symbol_map.get(name) {
-                if temp.0.clone().id == id {
-                    return temp.1.clone();
-                }
-            } else {
-                panic!{"symbol_map is empty, get_symbol_type_by_id wasn't possible."}
+    /// evaluates the expression node outside of a passage and returns the
+    /// messages of the reported errors
+    fn expression_errors(node: &ASTNode) -> Vec<String> {
+        let cfg = Config::default_config();
+        let mut zfile = Zfile::new_with_cfg(&cfg);
+        let mut manager = CodeGenManager::new(&cfg);
+        let mut code: Vec<ZOP> = vec![];
+        evaluate_expression(node, &mut code, &mut manager, &mut zfile);
+        manager.errors.into_iter().map(|error| error.message).collect()
+    }
+
+    fn expression_node(category: Token, childs: Vec<ASTNode>) -> ASTNode {
+        ASTNode::Default(NodeDefault {category: category, childs: childs})
+    }
+
+    fn int_node(value: i32) -> ASTNode {
+        expression_node(TokInt {location: (2, 3), value: value}, vec![])
+    }
+
+    #[test]
+    fn number_range_test() {
+        let errors = test_errors("::Start\n<<print 40000>><<print 99999999999>><<print -32768>>");
+        let message = "The number is out of range, integers have to be between -32768 and 32767";
+        assert_eq!(errors.iter().filter(|error| *error == message).count(), 2, "{:?}", errors);
+
+        // constants wrap around like the numbers of the Z-Machine
+        let output = test_run("::Start\n<<set $a = -32768>>a=<<print $a>>;<<set $b = 32767 + 1>>b=<<print $b>>;", "");
+        assert_output(&output, &["a=-32768;", "b=-32768;"]);
+    }
+
+    #[test]
+    fn operator_arguments_test() {
+        let num_op = expression_node(TokNumOp {location: (2, 3), op_name: "+".to_string()}, vec![int_node(1)]);
+        assert_eq!(expression_errors(&num_op), vec!["The operator `+` needs two arguments".to_string()]);
+
+        let comp_op = expression_node(TokCompOp {location: (2, 3), op_name: "<".to_string()}, vec![int_node(1)]);
+        assert_eq!(expression_errors(&comp_op), vec!["The operator `<` needs two arguments".to_string()]);
+
+        let log_op = expression_node(TokLogOp {location: (2, 3), op_name: "not".to_string()}, vec![int_node(1), int_node(2)]);
+        assert_eq!(expression_errors(&log_op), vec!["The operator `not` needs 1 argument".to_string()]);
+
+        let minus = expression_node(TokUnaryMinus {location: (2, 3)}, vec![]);
+        assert_eq!(expression_errors(&minus), vec!["The operator `-` needs one argument".to_string()]);
+    }
+
+    #[test]
+    fn unsupported_operator_test() {
+        let num_op = expression_node(TokNumOp {location: (2, 3), op_name: "^".to_string()}, vec![int_node(1), int_node(2)]);
+        assert_eq!(expression_errors(&num_op), vec!["Unsupported operator `^`".to_string()]);
+
+        let comp_op = expression_node(TokCompOp {location: (2, 3), op_name: "<>".to_string()}, vec![int_node(1), int_node(2)]);
+        assert_eq!(expression_errors(&comp_op), vec!["Unsupported operator `<>`".to_string()]);
+
+        let log_op = expression_node(TokLogOp {location: (2, 3), op_name: "xor".to_string()}, vec![int_node(1), int_node(2)]);
+        assert_eq!(expression_errors(&log_op), vec!["Unsupported operator `xor`".to_string()]);
+    }
+
+    #[test]
+    fn temp_vars_test() {
+        // every level of the expression keeps one temporary variable
+        let mut expression = "$x".to_string();
+        for _ in 0..20 {
+            expression = format!("$x * 2 + ({})", expression);
+        }
+        let errors = test_errors(&format!("::Start\n<<set $x = 1>><<print {}>>", expression));
+        assert_eq!(errors, vec!["The expression is too complex, it needs more than 13 temporary variables".to_string()]);
+
+        // the functions take a temporary variable for their result
+        let mut expression = "random(1, 6)".to_string();
+        for _ in 0..13 {
+            expression = format!("$x * 2 + ({})", expression);
+        }
+        let errors = test_errors(&format!("::Start\n<<set $x = 1>><<print {}>>", expression));
+        assert_eq!(errors, vec!["The expression is too complex, it needs more than 13 temporary variables".to_string()]);
+    }
+
+    #[test]
+    fn tags_temp_vars_test() {
+        let mut expression = "tags()".to_string();
+        for _ in 0..13 {
+            expression = format!("$x * 2 + ({})", expression);
+        }
+        let errors = test_errors(&format!("::Start\n<<set $x = 1>><<print {}>>", expression));
+        assert_eq!(errors, vec!["The expression is too complex, it needs more than 13 temporary variables".to_string()]);
+    }
+
+    #[test]
+    fn history_temp_vars_test() {
+        for function in &["visited()", "previous()", "passage()", "turns()"] {
+            let mut expression = function.to_string();
+            for _ in 0..13 {
+                expression = format!("$x * 2 + ({})", expression);
             }
+            let errors = test_errors(&format!("::Start\n<<set $x = 1>><<print {}>>", expression));
+            assert_eq!(errors, vec!["The expression is too complex, it needs more than 13 temporary variables".to_string()]);
         }
-        panic!("should never happen: could not find the requested ID in symbol table")
+    }
+
+    #[test]
+    fn too_many_variables_test() {
+        let sets: Vec<String> = (0..240).map(|i| format!("<<set $v{} = {}>>", i, i)).collect();
+        let errors = test_errors(&format!("::Start\n{}<<textinput $name>>", sets.concat()));
+        assert_eq!(errors[0], "Too many variables, there is no global variable left for '$v230'");
+        assert_eq!(errors.last().unwrap(), "Too many variables, there is no global variable left for '$name'");
+
+        let mut symbols = SymbolTable::new();
+        assert!(symbols.get_symbol_id("$a").is_none());
+        symbols.set_symbol_type("$a", Type::String);
+        assert!(symbols.get_symbol_type("$a").is_none());
+        assert!(symbols.insert_new_symbol("$a", Type::String));
+        assert_eq!(symbols.get_symbol_type_by_id(25), Some(Type::String));
+        assert_eq!(symbols.get_symbol_type_by_id(26), None);
     }
 }
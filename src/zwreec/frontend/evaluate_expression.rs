@@ -1,11 +1,12 @@
 //! The `evaluate_expressions` module...
 
 
-use backend::zcode::zfile::{ZOP, Operand, Variable, Constant, LargeConstant, Zfile, Type};
+use backend::zcode::zfile::{ZOP, Operand, Variable, Constant, LargeConstant, Zfile, Type, FIXED_SCALE};
 use frontend::ast::{ASTNode};
 use frontend::codegen;
 use frontend::codegen::{CodeGenManager};
-use frontend::lexer::Token::{TokNumOp, TokCompOp, TokLogOp, TokInt, TokBoolean, TokVariable, TokFunction, TokString, TokUnaryMinus};
+use frontend::lexer::Token::{TokNumOp, TokCompOp, TokLogOp, TokInt, TokFloat, TokBoolean, TokVariable, TokFunction, TokString, TokUnaryMinus,
+    TokArrayStart, TokExpression, TokProperty, TokMethod};
 
 
 
@@ -14,48 +15,146 @@ pub fn evaluate_expression<'a>(node: &'a ASTNode, code: &mut Vec<ZOP>, mut manag
     evaluate_expression_internal(node, code, &mut temp_ids, manager, &mut out)
 }
 
+/// Evaluates a compound assignment like `<<set $gold += 5>>` to zCode.
+///
+/// Applies the numeric operator `op_name` to the variable and the expression
+/// and returns the result. `+` on strings concatenates them using `strcat`.
+pub fn evaluate_compound_assignment<'a>(variable: &Variable, op_name: &str, location: (u64, u64), node: &'a ASTNode, code: &mut Vec<ZOP>,
+        mut manager: &mut CodeGenManager<'a>, mut out: &mut Zfile) -> Operand {
+    let mut temp_ids = CodeGenManager::new_temp_var_vec();
+    let value = evaluate_expression_internal(node, code, &mut temp_ids, manager, &mut out);
+    eval_num_op(&Operand::Var(variable.clone()), &value, op_name, location, code, &mut temp_ids, manager)
+}
+
+/// Evaluates an assignment to an element of an array like
+/// `<<set $inv[0] = "key">>` to zCode.
+///
+/// Indexes out of range are ignored at runtime.
+pub fn evaluate_element_assignment<'a>(name: &str, location: (u64, u64), index_node: &'a ASTNode, value_node: &'a ASTNode,
+        code: &mut Vec<ZOP>, mut manager: &mut CodeGenManager<'a>, mut out: &mut Zfile) {
+    let mut temp_ids = CodeGenManager::new_temp_var_vec();
+    let variable = match manager.symbol_table.get_symbol_id(name) {
+        Some(variable) => variable,
+        None => {
+            manager.report(format!("Variable '{}' is used before it is set", name), location);
+            return;
+        }
+    };
+    let element_type = match variable.vartype {
+        Type::Array(ref element_type) => element_type.clone().map(|t| *t),
+        _ => {
+            manager.report(format!("Variable '{}' is not an array", name), location);
+            return;
+        }
+    };
+
+    let index_node = index_node.as_default();
+    let value_node = value_node.as_default();
+    if index_node.category != TokExpression || index_node.childs.len() != 1 ||
+            value_node.category != TokExpression || value_node.childs.len() != 1 {
+        manager.report(format!("Unsupported expression in assignment to an element of '{}'", name), location);
+        return;
+    }
+
+    let index = evaluate_expression_internal(&index_node.childs[0], code, &mut temp_ids, manager, &mut out);
+    if !is_index(&index) {
+        manager.report(format!("The index of the array '{}' has to be an integer", name), location);
+        return;
+    }
+    let value = evaluate_expression_internal(&value_node.childs[0], code, &mut temp_ids, manager, &mut out);
+    let value_type = operand_type(&value);
+    if !is_element_type(&element_type, &value_type) {
+        manager.report(format!("All elements of the array '{}' need the same type", name), location);
+        return;
+    }
+    if element_type.is_none() {
+        manager.symbol_table.set_symbol_type(name, Type::Array(Some(Box::new(value_type))));
+    }
+
+    code.push(ZOP::CallVNA3{jump_to_label: "system_array_set".to_string(), arg1: Operand::Var(variable), arg2: index, arg3: value});
+}
+
 /// Evaluates an expression node to zCode.
 fn evaluate_expression_internal<'a>(node: &'a ASTNode, code: &mut Vec<ZOP>,
         temp_ids: &mut Vec<u8>, mut manager: &mut CodeGenManager<'a>, mut out: &mut Zfile) -> Operand {
     let n = node.as_default();
 
     match n.category {
-        TokNumOp { ref op_name, .. } => {
+        TokNumOp { ref op_name, location } => {
             if n.childs.len() != 2 {
-                panic!("Numeric operators need two arguments!")
+                manager.report(format!("The operator `{}` needs two arguments", op_name), location);
+                return Operand::new_const(0);
             }
             let eval0 = evaluate_expression_internal(&n.childs[0], code, temp_ids, manager, &mut out);
             let eval1 = evaluate_expression_internal(&n.childs[1], code, temp_ids, manager, &mut out);
-            eval_num_op(&eval0, &eval1, &**op_name, code, temp_ids)
+            eval_num_op(&eval0, &eval1, &**op_name, location, code, temp_ids, manager)
         },
-        TokCompOp { ref op_name, .. } => {
+        TokCompOp { ref op_name, location } => {
             if n.childs.len() != 2 {
-                panic!("Numeric operators need two arguments!")
+                manager.report(format!("The operator `{}` needs two arguments", op_name), location);
+                return Operand::new_const(0);
             }
             let eval0 = evaluate_expression_internal(&n.childs[0], code, temp_ids, manager, &mut out);
             let eval1 = evaluate_expression_internal(&n.childs[1], code, temp_ids, manager, &mut out);
-            eval_comp_op(&eval0, &eval1, &**op_name, code, temp_ids, manager)
+            eval_comp_op(&eval0, &eval1, &**op_name, location, code, temp_ids, manager)
         },
-        TokLogOp { ref op_name, .. } => {
-            let eval0 = evaluate_expression_internal(&n.childs[0], code, temp_ids, manager, &mut out);
+        TokLogOp { ref op_name, location } => {
+            let arguments = match &**op_name {
+                "and" | "&&" | "or" | "||" => 2,
+                "not" | "!" => 1,
+                _ => {
+                    manager.report(format!("Unsupported operator `{}`", op_name), location);
+                    return Operand::new_const(0);
+                }
+            };
+            if n.childs.len() != arguments {
+                manager.report(format!("The operator `{}` needs {} argument{}", op_name, arguments,
+                    if arguments == 1 { "" } else { "s" }), location);
+                return Operand::new_const(0);
+            }
 
-            match &**op_name {
-                "and" | "&&" | "or" | "||" => {
-                    let eval1 = evaluate_expression_internal(&n.childs[1], code, temp_ids, manager, &mut out);
-                    eval_and_or(&eval0, &eval1, &**op_name, code, temp_ids)
-                },
-                "not" | "!" => {
-                    eval_not(&eval0, code, temp_ids, manager)
-                },
-                _ => panic!("unhandled op")
+            let eval0 = evaluate_expression_internal(&n.childs[0], code, temp_ids, manager, &mut out);
+            if arguments == 2 {
+                let eval1 = evaluate_expression_internal(&n.childs[1], code, temp_ids, manager, &mut out);
+                eval_and_or(&eval0, &eval1, &**op_name, location, code, temp_ids, manager)
+            } else {
+                eval_not(&eval0, location, code, temp_ids, manager)
             }
         },
-        TokUnaryMinus { .. } => {
+        TokUnaryMinus { location } => {
+            if n.childs.len() != 1 {
+                manager.report("The operator `-` needs one argument".to_string(), location);
+                return Operand::new_const(0);
+            }
+            // -32768 is a number, but 32768 is not
+            if let TokInt { value: 32768, .. } = n.childs[0].category() {
+                return Operand::new_large_const(::std::i16::MIN);
+            }
             let eval = evaluate_expression_internal(&n.childs[0], code, temp_ids, manager, &mut out);
-            eval_unary_minus(&eval, code, temp_ids)
+            eval_unary_minus(&eval, location, code, temp_ids, manager)
         },
-        TokInt { ref value, .. } => {
-            Operand::new_large_const(*value as i16)
+        TokInt { value, location } => {
+            if value > ::std::i16::MAX as i32 {
+                manager.report("The number is out of range, integers have to be between -32768 and 32767".to_string(), location);
+                return Operand::new_const(0);
+            }
+            Operand::new_large_const(value as i16)
+        },
+        TokFloat { value, location } => {
+            if !manager.cfg.fixed_point {
+                manager.report("Decimal numbers need the fixed-point feature, enable it with -F fixed-point".to_string(), location);
+                return Operand::new_const(0);
+            }
+            // constants have no type, so the number is stored in a variable
+            let scaled = (value * FIXED_SCALE as f32).round();
+            if !(scaled <= ::std::i16::MAX as f32 && scaled >= -::std::i16::MAX as f32) {
+                manager.report(format!("The decimal number {} is out of range, it has to be between -327.67 and 327.67", value), location);
+                return Operand::new_const(0);
+            }
+            let scaled = scaled as i16;
+            let id = manager.pop_temp_var(temp_ids, location);
+            code.push(ZOP::StoreVariable{variable: Variable::new_fixed(id), value: Operand::new_large_const(scaled)});
+            Operand::Var(Variable::new_fixed(id))
         },
         TokBoolean { ref value, .. } => {
             boolstr_to_const(&**value)
@@ -63,19 +162,34 @@ fn evaluate_expression_internal<'a>(node: &'a ASTNode, code: &mut Vec<ZOP>,
         TokString {ref value, .. } => {
             Operand::new_string_ref(out.write_string(value) as i16)
         },
-        TokVariable { ref name, .. } => {
-            Operand::Var(manager.symbol_table.get_symbol_id(name))
+        TokVariable { ref name, location } => {
+            let variable = match manager.symbol_table.get_symbol_id(name) {
+                Some(variable) => variable,
+                None => {
+                    manager.report(format!("Variable '{}' is used before it is set", name), location);
+                    return Operand::new_const(0);
+                }
+            };
+            match n.childs.first() {
+                Some(access) => eval_array_access(&variable, name, location, access, code, temp_ids, manager, &mut out),
+                None => Operand::Var(variable)
+            }
+        },
+        TokArrayStart { location } => {
+            eval_array_literal(&n.childs, location, code, temp_ids, manager, &mut out)
         },
-        TokFunction { ref name, .. } => {
+        TokFunction { ref name, location } => {
             match &**name {
                 "random" => {
                     let args = &node.as_default().childs;
                     if args.len() != 2 {
-                        panic!("Function random only supports 2 args");
+                        manager.report("Function random needs exactly 2 arguments".to_string(), location);
+                        return Operand::new_const(0);
                     }
 
                     if args[0].as_default().childs.len() != 1 || args[1].as_default().childs.len() != 1 {
-                        panic!("Unsupported Expression");
+                        manager.report("Unsupported expression as argument of random".to_string(), location);
+                        return Operand::new_const(0);
                     }
 
                     let from = &args[0].as_default().childs[0];
@@ -83,42 +197,322 @@ fn evaluate_expression_internal<'a>(node: &'a ASTNode, code: &mut Vec<ZOP>,
 
                     let from_value = evaluate_expression_internal(from, code, temp_ids, manager, &mut out);
                     let to_value = evaluate_expression_internal(to, code, temp_ids, manager, &mut out);
-                    codegen::function_random(&from_value, &to_value, code, temp_ids)
+                    codegen::function_random(&from_value, &to_value, location, code, temp_ids, manager)
+                },
+                "tags" => {
+                    let args = &node.as_default().childs;
+                    if args.len() > 1 {
+                        manager.report("Function tags needs at most 1 argument".to_string(), location);
+                        return Operand::new_const(0);
+                    }
+
+                    if args.is_empty() {
+                        return codegen::function_tags(None, location, code, temp_ids, manager);
+                    }
+
+                    if args[0].as_default().childs.len() != 1 {
+                        manager.report("Unsupported expression as argument of tags".to_string(), location);
+                        return Operand::new_const(0);
+                    }
+
+                    let tag = &args[0].as_default().childs[0];
+                    let tag_value = evaluate_expression_internal(tag, code, temp_ids, manager, &mut out);
+                    match tag_value {
+                        Operand::StringRef(_) | Operand::Var(Variable{vartype: Type::String, ..}) => (),
+                        _ => {
+                            manager.report("The argument of tags must be a string".to_string(), location);
+                            return Operand::new_const(0);
+                        }
+                    }
+                    codegen::function_tags(Some(&tag_value), location, code, temp_ids, manager)
+                },
+                "prompt" => {
+                    let args = &node.as_default().childs;
+                    if args.len() > 1 {
+                        manager.report("Function prompt needs at most 1 argument".to_string(), location);
+                        return Operand::new_const(0);
+                    }
+
+                    if args.is_empty() {
+                        return codegen::function_prompt(None, location, code, temp_ids, manager);
+                    }
+
+                    if args[0].as_default().childs.len() != 1 {
+                        manager.report("Unsupported expression as argument of prompt".to_string(), location);
+                        return Operand::new_const(0);
+                    }
+
+                    let prompt = &args[0].as_default().childs[0];
+                    let prompt_value = evaluate_expression_internal(prompt, code, temp_ids, manager, &mut out);
+                    match prompt_value {
+                        Operand::StringRef(_) | Operand::Var(Variable{vartype: Type::String, ..}) => (),
+                        _ => {
+                            manager.report("The argument of prompt must be a string".to_string(), location);
+                            return Operand::new_const(0);
+                        }
+                    }
+                    codegen::function_prompt(Some(&prompt_value), location, code, temp_ids, manager)
+                },
+                "visited" => {
+                    let args = &node.as_default().childs;
+                    if args.len() > 1 {
+                        manager.report("Function visited needs at most 1 argument".to_string(), location);
+                        return Operand::new_const(0);
+                    }
+
+                    if args.is_empty() {
+                        return codegen::function_visited(None, location, code, temp_ids, manager);
+                    }
+
+                    if args[0].as_default().childs.len() != 1 {
+                        manager.report("Unsupported expression as argument of visited".to_string(), location);
+                        return Operand::new_const(0);
+                    }
+
+                    let passage = &args[0].as_default().childs[0];
+                    let passage_value = evaluate_expression_internal(passage, code, temp_ids, manager, &mut out);
+                    if !is_string(&passage_value) {
+                        manager.report("The argument of visited must be a string".to_string(), location);
+                        return Operand::new_const(0);
+                    }
+                    let result = codegen::function_visited(Some(&passage_value), location, code, temp_ids, manager);
+                    free_var_if_temp(&passage_value, temp_ids);
+                    result
+                },
+                "previous" | "passage" | "turns" => {
+                    if !node.as_default().childs.is_empty() {
+                        manager.report(format!("Function {} needs no arguments", name), location);
+                        return Operand::new_const(0);
+                    }
+
+                    match &**name {
+                        "turns" => codegen::function_turns(location, code, temp_ids, manager),
+                        _ => codegen::function_passage_name(&**name == "previous", location, code, temp_ids, manager)
+                    }
+                },
+                "Math.round" | "Math.floor" | "Math.ceil" => {
+                    let args = &node.as_default().childs;
+                    if args.len() != 1 {
+                        manager.report(format!("Function {} needs exactly 1 argument", name), location);
+                        return Operand::new_const(0);
+                    }
+
+                    if args[0].as_default().childs.len() != 1 {
+                        manager.report(format!("Unsupported expression as argument of {}", name), location);
+                        return Operand::new_const(0);
+                    }
+
+                    let number = &args[0].as_default().childs[0];
+                    let number_value = evaluate_expression_internal(number, code, temp_ids, manager, &mut out);
+                    if is_string(&number_value) {
+                        manager.report(format!("The argument of {} must be a number", name), location);
+                        return Operand::new_const(0);
+                    }
+                    codegen::function_math(&**name, &number_value, location, code, temp_ids, manager)
                 },
-                _ => { panic!("Unsupported function: {}", name)}
+                _ => {
+                    manager.report(format!("Unsupported function: {}", name), location);
+                    Operand::new_const(0)
+                }
             }
         },
-        _ => panic!("unhandled token {:?}", n.category)
+        _ => {
+            manager.report(format!("Unsupported token in expression: {:?}", n.category), n.category.location());
+            Operand::new_const(0)
+        }
     }
 }
 
-fn eval_num_op<'a>(eval0: &Operand, eval1: &Operand, op_name: &str, code: &mut Vec<ZOP>, temp_ids: &mut Vec<u8>) -> Operand {
+/// Evaluates an array literal like `["key", "lamp"]`, a new array on the heap
+fn eval_array_literal<'a>(elements: &'a Vec<ASTNode>, location: (u64, u64), code: &mut Vec<ZOP>,
+        temp_ids: &mut Vec<u8>, mut manager: &mut CodeGenManager<'a>, mut out: &mut Zfile) -> Operand {
+    let id = manager.pop_temp_var(temp_ids, location);
+    code.push(ZOP::Call2S{jump_to_label: "system_array_new".to_string(), arg: Operand::new_large_const(elements.len() as i16), result: Variable::new(id)});
+
+    // the first element determines the type of all elements
+    let mut element_type: Option<Type> = None;
+    for (i, element) in elements.iter().enumerate() {
+        if element.as_default().childs.len() != 1 {
+            manager.report("Unsupported expression in array".to_string(), location);
+            return Operand::new_const(0);
+        }
+
+        let value = evaluate_expression_internal(&element.as_default().childs[0], code, temp_ids, manager, &mut out);
+        let value_type = operand_type(&value);
+        if !is_element_type(&element_type, &value_type) {
+            manager.report("All elements of an array need the same type".to_string(), location);
+            return Operand::new_const(0);
+        }
+        if element_type.is_none() {
+            element_type = Some(value_type);
+        }
+
+        code.push(ZOP::CallVNA3{jump_to_label: "system_array_set".to_string(), arg1: Operand::new_var(id),
+            arg2: Operand::new_large_const(i as i16), arg3: value.clone()});
+        free_var_if_temp(&value, temp_ids);
+    }
+
+    Operand::Var(Variable::new_array(id, element_type))
+}
+
+/// Evaluates the access to an element, a property or a method of an array
+/// like `$inv[0]`, `$inv.length` or `$inv.contains("key")`
+fn eval_array_access<'a>(variable: &Variable, name: &str, location: (u64, u64), access: &'a ASTNode, code: &mut Vec<ZOP>,
+        temp_ids: &mut Vec<u8>, mut manager: &mut CodeGenManager<'a>, mut out: &mut Zfile) -> Operand {
+    let element_type = match variable.vartype {
+        Type::Array(ref element_type) => element_type.clone().map(|t| *t),
+        _ => {
+            manager.report(format!("Variable '{}' is not an array", name), location);
+            return Operand::new_const(0);
+        }
+    };
+
+    let access = access.as_default();
+    match access.category {
+        TokExpression => {
+            if access.childs.len() != 1 {
+                manager.report(format!("Unsupported expression as index of '{}'", name), location);
+                return Operand::new_const(0);
+            }
+
+            let index = evaluate_expression_internal(&access.childs[0], code, temp_ids, manager, &mut out);
+            if !is_index(&index) {
+                manager.report(format!("The index of the array '{}' has to be an integer", name), location);
+                return Operand::new_const(0);
+            }
+            // indexes out of range return 0 or the empty string
+            let default = match element_type {
+                Some(Type::String) => Operand::new_large_const(out.write_string("") as i16),
+                _ => Operand::new_const(0)
+            };
+            let id = manager.pop_temp_var(temp_ids, location);
+            let result = Variable{id: id, vartype: element_type.unwrap_or(Type::Integer)};
+            code.push(ZOP::CallVSA3{jump_to_label: "system_array_get".to_string(), arg1: Operand::Var(variable.clone()),
+                arg2: index.clone(), arg3: default, result: result.clone()});
+            free_var_if_temp(&index, temp_ids);
+            Operand::Var(result)
+        },
+        TokProperty { name: ref property, location } => {
+            match &**property {
+                "length" => array_length(variable, location, code, temp_ids, manager),
+                _ => {
+                    manager.report(format!("Unsupported property `.{}`", property), location);
+                    Operand::new_const(0)
+                }
+            }
+        },
+        TokMethod { name: ref method, location } => {
+            let args = &access.childs;
+            if args.len() != 1 {
+                manager.report(format!("Method {} needs exactly 1 argument", method), location);
+                return Operand::new_const(0);
+            }
+            if args[0].as_default().childs.len() != 1 {
+                manager.report(format!("Unsupported expression as argument of {}", method), location);
+                return Operand::new_const(0);
+            }
+
+            match &**method {
+                "push" => {
+                    let value = evaluate_expression_internal(&args[0].as_default().childs[0], code, temp_ids, manager, &mut out);
+                    let value_type = operand_type(&value);
+                    if !is_element_type(&element_type, &value_type) {
+                        manager.report(format!("All elements of the array '{}' need the same type", name), location);
+                        return Operand::new_const(0);
+                    }
+
+                    // a full array is moved to a bigger block, so the
+                    // variable gets the address returned by push
+                    let array = match element_type {
+                        Some(element_type) => Variable::new_array(variable.id, Some(element_type)),
+                        None => {
+                            manager.symbol_table.set_symbol_type(name, Type::Array(Some(Box::new(value_type.clone()))));
+                            Variable::new_array(variable.id, Some(value_type))
+                        }
+                    };
+                    code.push(ZOP::CallVSA2{jump_to_label: "system_array_push".to_string(), arg1: Operand::new_var(array.id),
+                        arg2: value.clone(), result: Variable::new(array.id)});
+                    free_var_if_temp(&value, temp_ids);
+                    array_length(&array, location, code, temp_ids, manager)
+                },
+                "contains" => {
+                    let value = evaluate_expression_internal(&args[0].as_default().childs[0], code, temp_ids, manager, &mut out);
+                    if !is_element_type(&element_type, &operand_type(&value)) {
+                        manager.report(format!("The elements of the array '{}' can not be compared with the argument of contains", name), location);
+                        return Operand::new_const(0);
+                    }
+                    let routine = if element_type == Some(Type::String) {
+                        "system_array_contains_string"
+                    } else {
+                        "system_array_contains"
+                    };
+                    let id = manager.pop_temp_var(temp_ids, location);
+                    let result = Variable::new_bool(id);
+                    code.push(ZOP::CallVSA2{jump_to_label: routine.to_string(), arg1: Operand::Var(variable.clone()),
+                        arg2: value.clone(), result: result.clone()});
+                    free_var_if_temp(&value, temp_ids);
+                    Operand::Var(result)
+                },
+                _ => {
+                    manager.report(format!("Unsupported method `.{}()`", method), location);
+                    Operand::new_const(0)
+                }
+            }
+        },
+        _ => {
+            manager.report(format!("Unsupported token in expression: {:?}", access.category), location);
+            Operand::new_const(0)
+        }
+    }
+}
+
+/// the length is the second word of the array, after the size of the block
+fn array_length<'a>(array: &Variable, location: (u64, u64), code: &mut Vec<ZOP>, temp_ids: &mut Vec<u8>,
+        manager: &mut CodeGenManager<'a>) -> Operand {
+    let id = manager.pop_temp_var(temp_ids, location);
+    code.push(ZOP::StoreVariable{variable: Variable::new(id), value: Operand::new_const(1)});
+    code.push(ZOP::LoadW{array_address: Operand::new_var(array.id), index: Variable::new(id), variable: Variable::new(id)});
+    Operand::new_var(id)
+}
+
+fn eval_num_op<'a>(eval0: &Operand, eval1: &Operand, op_name: &str, location: (u64, u64), code: &mut Vec<ZOP>,
+        temp_ids: &mut Vec<u8>, mut manager: &mut CodeGenManager<'a>) -> Operand {
+    if (op_name == "/" || op_name == "%") && eval1.is_const() && eval1.const_value() == 0 {
+        manager.report("Division by zero".to_string(), location);
+        return Operand::new_const(0);
+    }
+    match op_name {
+        "+" | "-" | "*" | "/" | "%" => (),
+        _ => {
+            manager.report(format!("Unsupported operator `{}`", op_name), location);
+            return Operand::new_const(0);
+        }
+    }
     if count_constants(eval0, eval1) == 2 {
         return direct_eval_num_op(eval0, eval1, op_name);
     }
-    let save_var = determine_save_var(eval0, eval1, temp_ids);
+    if is_fixed_op(eval0, eval1) {
+        let fixed0 = to_fixed(eval0, location, code, temp_ids, manager);
+        let fixed1 = to_fixed(eval1, location, code, temp_ids, manager);
+        return eval_fixed_op(&fixed0, &fixed1, op_name, location, code, temp_ids, manager);
+    }
+    let save_var = determine_save_var(eval0, eval1, location, temp_ids, manager);
     match op_name {
         "+" => {
             if save_var.vartype == Type::String {
-                let a1: Variable = match temp_ids.pop() {
-                    Some(var) => Variable::new(var),
-                    None      => panic!{"Stack temp_ids is empty, pop wasn't possible."}
-                };
+                let a1 = Variable::new(manager.pop_temp_var(temp_ids, location));
                 let o1 = Operand::new_var(a1.id);
-                let a2: Variable = match temp_ids.pop() {
-                    Some(var) => Variable::new(var),
-                    None      => panic!{"Stack temp_ids is empty, pop wasn't possible."}
-                };
+                let a2 = Variable::new(manager.pop_temp_var(temp_ids, location));
                 let o2 = Operand::new_var(a2.id);
                 let addr1 = match eval0 {
                     &Operand::StringRef(_) => eval0,
                     &Operand::Var(Variable{id: _, vartype: Type::String}) => eval0,
-                    _ => { code.push(ZOP::Call2S{jump_to_label: "itoa".to_string(), arg: eval0.clone(), result: a1.clone()}); &o1 }
+                    _ => { code.push(ZOP::Call2S{jump_to_label: to_string_routine(eval0).to_string(), arg: eval0.clone(), result: a1.clone()}); &o1 }
                 };
                 let addr2 = match eval1 {
                     &Operand::StringRef(_) => eval1,
                     &Operand::Var(Variable{id: _, vartype: Type::String}) => eval1,
-                    _ => { code.push(ZOP::Call2S{jump_to_label: "itoa".to_string(), arg: eval1.clone(), result: a2.clone()}); &o2 }
+                    _ => { code.push(ZOP::Call2S{jump_to_label: to_string_routine(eval1).to_string(), arg: eval1.clone(), result: a2.clone()}); &o2 }
                 };
                 code.push(ZOP::CallVSA2{jump_to_label: "strcat".to_string(), arg1: addr1.clone(), arg2: addr2.clone(), result: save_var.clone()});
                 free_var_if_temp(&Operand::new_var(a1.id), temp_ids);
@@ -136,10 +530,38 @@ fn eval_num_op<'a>(eval0: &Operand, eval1: &Operand, op_name: &str, code: &mut V
         "/" => {
             code.push(ZOP::Div{operand1: eval0.clone(), operand2: eval1.clone(), save_variable: save_var.clone()});
         },
-        "%" => {
+        _ => {
             code.push(ZOP::Mod{operand1: eval0.clone(), operand2: eval1.clone(), save_variable: save_var.clone()});
+        }
+    };
+
+    free_var_if_both_temp(eval0, eval1, temp_ids);
+
+    Operand::Var(save_var)
+}
+
+/// Evaluates a numeric operation on two fixed-point numbers.
+/// Every operation is a routine, which reports results out of range and
+/// division by zero while the story runs.
+fn eval_fixed_op<'a>(eval0: &Operand, eval1: &Operand, op_name: &str, location: (u64, u64), code: &mut Vec<ZOP>,
+        temp_ids: &mut Vec<u8>, manager: &mut CodeGenManager<'a>) -> Operand {
+    let save_var = determine_save_var(eval0, eval1, location, temp_ids, manager);
+    match op_name {
+        "+" => {
+            code.push(ZOP::CallVSA2{jump_to_label: "system_fixed_add".to_string(), arg1: eval0.clone(), arg2: eval1.clone(), result: save_var.clone()});
         },
-        _ => panic!("unhandled op")
+        "-" => {
+            code.push(ZOP::CallVSA2{jump_to_label: "system_fixed_sub".to_string(), arg1: eval0.clone(), arg2: eval1.clone(), result: save_var.clone()});
+        },
+        "*" => {
+            code.push(ZOP::CallVSA2{jump_to_label: "system_fixed_mul".to_string(), arg1: eval0.clone(), arg2: eval1.clone(), result: save_var.clone()});
+        },
+        "/" => {
+            code.push(ZOP::CallVSA2{jump_to_label: "system_fixed_div".to_string(), arg1: eval0.clone(), arg2: eval1.clone(), result: save_var.clone()});
+        },
+        _ => {
+            code.push(ZOP::CallVSA2{jump_to_label: "system_fixed_mod".to_string(), arg1: eval0.clone(), arg2: eval1.clone(), result: save_var.clone()});
+        }
     };
 
     free_var_if_both_temp(eval0, eval1, temp_ids);
@@ -147,32 +569,61 @@ fn eval_num_op<'a>(eval0: &Operand, eval1: &Operand, op_name: &str, code: &mut V
     Operand::Var(save_var)
 }
 
+/// Scales an integer constant to a fixed-point number, numbers outside of
+/// -327.67 to 327.67 are reported
+pub fn fixed_const<'a>(value: i16, location: (u64, u64), mut manager: &mut CodeGenManager<'a>) -> Operand {
+    let scaled = value as i32 * FIXED_SCALE as i32;
+    if scaled > ::std::i16::MAX as i32 || scaled < -(::std::i16::MAX as i32) {
+        manager.report(format!("The number {} is out of range for a decimal number, it has to be between -327.67 and 327.67", value), location);
+        return Operand::new_const(0);
+    }
+    Operand::new_large_const(scaled as i16)
+}
+
+/// Converts a number to a fixed-point number, fixed-point numbers are
+/// returned unchanged
+/// variables are converted by `system_to_fixed`, which checks the range
+fn to_fixed<'a>(operand: &Operand, location: (u64, u64), code: &mut Vec<ZOP>, temp_ids: &mut Vec<u8>,
+        mut manager: &mut CodeGenManager<'a>) -> Operand {
+    if is_fixed(operand) {
+        return operand.clone();
+    }
+    if operand.is_const() {
+        return fixed_const(operand.const_value(), location, manager);
+    }
 
+    let save_var = match operand {
+        &Operand::Var(ref var) if CodeGenManager::is_temp_var(var) => Variable::new_fixed(var.id),
+        _ => Variable::new_fixed(manager.pop_temp_var(temp_ids, location))
+    };
+    code.push(ZOP::Call2S{jump_to_label: "system_to_fixed".to_string(), arg: operand.clone(), result: save_var.clone()});
+    Operand::Var(save_var)
+}
 
+/// Directly evaluates the given numeric operation.
+/// Both operands must be constants, the divisor must not be zero. The
+/// result wraps around like the arithmetic of the Z-Machine.
 fn direct_eval_num_op(eval0: &Operand, eval1: &Operand, op_name: &str) -> Operand {
-    let mut out_large = false;
     let val0 = eval0.const_value();
     let val1 = eval1.const_value();
-    match eval0 { &Operand::LargeConst(_) => {out_large = true; }, _ => {} };
-    match eval1 { &Operand::LargeConst(_) => {out_large = true; }, _ => {} };
     let result = match op_name {
         "+" => {
-            val0 + val1
+            val0.wrapping_add(val1)
         },
         "-" => {
-            val0 - val1
+            val0.wrapping_sub(val1)
         },
         "*" => {
-            val0 * val1
+            val0.wrapping_mul(val1)
         },
         "/" => {
-            val0 / val1
+            val0.wrapping_div(val1)
         },
-        "%" => {
-            val0 % val1
-        },
-        _ => panic!("unhandled op")
+        _ => {
+            val0.wrapping_rem(val1)
+        }
     };
+    let out_large = is_large_const(eval0) || is_large_const(eval1) || result < 0 || result > 255;
     if out_large {
         Operand::LargeConst(LargeConstant { value: result })
     } else {
@@ -180,15 +631,40 @@ fn direct_eval_num_op(eval0: &Operand, eval1: &Operand, op_name: &str) -> Operan
     }
 }
 
-fn eval_comp_op<'a>(eval0: &Operand, eval1: &Operand, op_name: &str, code: &mut Vec<ZOP>,
+fn eval_comp_op<'a>(eval0: &Operand, eval1: &Operand, op_name: &str, location: (u64, u64), code: &mut Vec<ZOP>,
         temp_ids: &mut Vec<u8>, mut manager: &mut CodeGenManager<'a>) -> Operand {
+    match op_name {
+        "is" | "==" | "eq" | "neq" | "<" | "lt" | "<=" | "lte" | ">=" | "gte" | ">" | "gt" => (),
+        _ => {
+            manager.report(format!("Unsupported operator `{}`", op_name), location);
+            return Operand::new_const(0);
+        }
+    }
     if count_constants(eval0, eval1) == 2 {
         return direct_eval_comp_op(eval0, eval1, op_name);
     }
-    let save_var: Variable = match temp_ids.pop() {
-        Some(var) => Variable::new(var),
-        None      => panic!{"Stack temp_ids is empty, pop wasn't possible."}
+    // integers are compared to fixed-point numbers after scaling them
+    let fixed = if is_fixed_op(eval0, eval1) {
+        Some((to_fixed(eval0, location, code, temp_ids, manager), to_fixed(eval1, location, code, temp_ids, manager)))
+    } else {
+        None
+    };
+    let (eval0, eval1) = match fixed {
+        Some((ref fixed0, ref fixed1)) => (fixed0, fixed1),
+        None => (eval0, eval1)
     };
+    let save_var = Variable::new(manager.pop_temp_var(temp_ids, location));
+    // strings are equal if they have the same characters, not the same address
+    if is_string(eval0) && is_string(eval1) && (op_name == "is" || op_name == "==" || op_name == "eq" || op_name == "neq") {
+        code.push(ZOP::CallVSA2{jump_to_label: "strcmp".to_string(), arg1: eval0.clone(), arg2: eval1.clone(), result: save_var.clone()});
+        if op_name == "neq" {
+            code.push(ZOP::Sub{operand1: Operand::new_const(1), operand2: Operand::new_var(save_var.id), save_variable: save_var.clone()});
+        }
+        free_var_if_temp(eval0, temp_ids);
+        free_var_if_temp(eval1, temp_ids);
+        return Operand::Var(save_var);
+    }
+
     let label = format!("expr_{}", manager.ids_expr.start_next());
     let const_true = Operand::new_const(1);
     let const_false = Operand::new_const(0);
@@ -218,12 +694,12 @@ fn eval_comp_op<'a>(eval0: &Operand, eval1: &Operand, op_name: &str, code: &mut
             code.push(ZOP::JL{operand1: eval0.clone(), operand2: eval1.clone(), jump_to_label: label.to_string()});
             code.push(ZOP::StoreVariable{ variable: save_var.clone(), value: const_true});
         },
-        ">" | "gt" => {
+        _ => {
+            // ">" and "gt"
             code.push(ZOP::StoreVariable{ variable: save_var.clone(), value: const_true});
             code.push(ZOP::JG{operand1: eval0.clone(), operand2: eval1.clone(), jump_to_label: label.to_string()});
             code.push(ZOP::StoreVariable{ variable: save_var.clone(), value: const_false});
-        },
-        _ => panic!("unhandled op")
+        }
     };
     code.push(ZOP::Label {name: label.to_string()});
     free_var_if_temp(eval0, temp_ids);
@@ -242,8 +718,7 @@ fn direct_eval_comp_op(eval0: &Operand, eval1: &Operand, op_name: &str) -> Opera
         "<" | "lt" =>  { val0 < val1 },
         "<=" | "lte" => { val0 <= val1 },
         ">=" | "gte" => { val0 >= val1 },
-        ">" | "gt" => { val0 > val1 },
-        _ => panic!("unhandled op")
+        _ => { val0 > val1 }
     };
     if result {
         Operand::Const(Constant {value: 1})
@@ -252,8 +727,8 @@ fn direct_eval_comp_op(eval0: &Operand, eval1: &Operand, op_name: &str) -> Opera
     }
 }
 
-fn eval_and_or(eval0: &Operand, eval1: &Operand, op_name: &str, code: &mut Vec<ZOP>,
-        temp_ids: &mut Vec<u8>) -> Operand {
+fn eval_and_or<'a>(eval0: &Operand, eval1: &Operand, op_name: &str, location: (u64, u64), code: &mut Vec<ZOP>,
+        temp_ids: &mut Vec<u8>, manager: &mut CodeGenManager<'a>) -> Operand {
     if count_constants(&eval0, &eval1) == 2 {
         let mut out_large = false;
         let val0 = eval0.const_value();
@@ -272,7 +747,7 @@ fn eval_and_or(eval0: &Operand, eval1: &Operand, op_name: &str, code: &mut Vec<Z
         }
     }
 
-    let save_var = determine_save_var(eval0, eval1, temp_ids);
+    let save_var = determine_save_var(eval0, eval1, location, temp_ids, manager);
     if op_name == "or" || op_name == "||" {
         code.push(ZOP::Or{operand1: eval0.clone(), operand2: eval1.clone(), save_variable: save_var.clone()});
     } else {
@@ -282,17 +757,14 @@ fn eval_and_or(eval0: &Operand, eval1: &Operand, op_name: &str, code: &mut Vec<Z
     Operand::Var(save_var)
 }
 
-fn eval_not<'a>(eval: &Operand, code: &mut Vec<ZOP>,
+fn eval_not<'a>(eval: &Operand, location: (u64, u64), code: &mut Vec<ZOP>,
         temp_ids: &mut Vec<u8>, mut manager: &mut CodeGenManager<'a>) -> Operand {
     if eval.is_const() {
         let val = eval.const_value();
         let result: u8 = if val > 0 { 0 } else { 1 };
         return Operand::Const(Constant { value: result });
     }
-    let save_var: Variable = match temp_ids.pop() {
-        Some(var) => Variable::new(var),
-        None      => panic!{"Stack temp_ids is empty, pop wasn't possible."}
-    };
+    let save_var = Variable::new(manager.pop_temp_var(temp_ids, location));
     let label = format!("expr_{}", manager.ids_expr.start_next());
     code.push(ZOP::StoreVariable{ variable: save_var.clone(), value: Operand::new_const(0)});
     code.push(ZOP::JG{operand1: eval.clone(), operand2: Operand::new_const(0), jump_to_label: label.to_string()});
@@ -302,39 +774,30 @@ fn eval_not<'a>(eval: &Operand, code: &mut Vec<ZOP>,
     Operand::Var(save_var)
 }
 
-fn eval_unary_minus(eval: &Operand, code: &mut Vec<ZOP>, temp_ids: &mut Vec<u8>) -> Operand {
+fn eval_unary_minus<'a>(eval: &Operand, location: (u64, u64), code: &mut Vec<ZOP>, temp_ids: &mut Vec<u8>,
+        manager: &mut CodeGenManager<'a>) -> Operand {
     if eval.is_const() {
-        let large = match eval { &Operand::LargeConst(_) => { true }, _ => { false } };
-        if large {
-            return Operand::new_large_const(-eval.const_value());
-        } else {
-            return Operand::new_const(-eval.const_value() as u8);
-        }
+        // a negative number doesn't fit into a small constant
+        return Operand::new_large_const(eval.const_value().wrapping_neg());
     }
 
+    // the negative of a fixed-point number is a fixed-point number
+    let vartype = if is_fixed(eval) { Type::Fixed } else { Type::Integer };
     let save_var = match eval {
         &Operand::Var(ref var) => {
             if CodeGenManager::is_temp_var(var) {
-                Variable::new(var.id)
+                Variable{id: var.id, vartype: vartype}
             } else {
-                if let Some(temp) = temp_ids.pop() {
-                    Variable::new(temp)
-                } else {
-                    panic!{"Stack temp_ids is empty, pop wasn't possible."}
-                }
+                Variable{id: manager.pop_temp_var(temp_ids, location), vartype: vartype}
             }
         }, _ => {
-            if let Some(temp) = temp_ids.pop() {
-                Variable::new(temp)
-            } else {
-                panic!{"Stack temp_ids is empty, pop wasn't possible."}
-            }
+            Variable::new(manager.pop_temp_var(temp_ids, location))
         }
     };
 
     code.push(ZOP::Sub {operand1: Operand::new_const(0), operand2: eval.clone(), save_variable: save_var.clone()});
 
-    Operand::new_var(save_var.id)
+    Operand::Var(save_var)
 }
 
 fn free_var_if_both_temp (eval0: &Operand, eval1: &Operand, temp_ids: &mut Vec<u8>) {
@@ -366,12 +829,15 @@ fn free_var_if_temp (operand: &Operand, temp_ids: &mut Vec<u8>) {
 fn determine_result_type(a: Type, b: Type) -> Type {
     if a == Type::String || b == Type::String {
         Type::String
+    } else if a == Type::Fixed || b == Type::Fixed {
+        Type::Fixed
     } else {
         Type::Integer
     }
 }
 
-fn determine_save_var(operand1: &Operand, operand2: &Operand, temp_ids: &mut Vec<u8>) -> Variable {
+fn determine_save_var<'a>(operand1: &Operand, operand2: &Operand, location: (u64, u64), temp_ids: &mut Vec<u8>,
+        manager: &mut CodeGenManager<'a>) -> Variable {
     let type1 = match operand1 {
         &Operand::Var(ref var) => var.vartype.clone(),
         &Operand::StringRef(_) => Type::String,
@@ -397,11 +863,7 @@ fn determine_save_var(operand1: &Operand, operand2: &Operand, temp_ids: &mut Vec
             }
         }, _ => {}
     };
-    if let Some(temp) = temp_ids.pop() {
-        return Variable{ id: temp, vartype: vartype };
-    } else {
-        panic!{"Stack temp_ids is empty, pop wasn't possible."}
-    }
+    Variable{ id: manager.pop_temp_var(temp_ids, location), vartype: vartype }
 }
 
 fn count_constants(operand1: &Operand, operand2: &Operand) -> u8 {
@@ -415,6 +877,66 @@ fn count_constants(operand1: &Operand, operand2: &Operand) -> u8 {
     const_count
 }
 
+fn is_large_const(operand: &Operand) -> bool {
+    match operand {
+        &Operand::LargeConst(_) => true,
+        _ => false
+    }
+}
+
+fn is_string(operand: &Operand) -> bool {
+    match operand {
+        &Operand::StringRef(_) | &Operand::Var(Variable{vartype: Type::String, ..}) => true,
+        _ => false
+    }
+}
+
+fn is_fixed(operand: &Operand) -> bool {
+    match operand {
+        &Operand::Var(Variable{vartype: Type::Fixed, ..}) => true,
+        _ => false
+    }
+}
+
+/// numbers are computed as fixed-point numbers if one of them is one
+fn is_fixed_op(operand1: &Operand, operand2: &Operand) -> bool {
+    !is_string(operand1) && !is_string(operand2) && (is_fixed(operand1) || is_fixed(operand2))
+}
+
+/// the routine converting the number to a string
+fn to_string_routine(operand: &Operand) -> &'static str {
+    if is_fixed(operand) { "system_ftoa" } else { "itoa" }
+}
+
+fn operand_type(operand: &Operand) -> Type {
+    match operand {
+        &Operand::Var(ref var) => var.vartype.clone(),
+        &Operand::StringRef(_) => Type::String,
+        _ => Type::Integer
+    }
+}
+
+/// strings and fixed-point numbers can't be mixed with other types in an
+/// array, integers and booleans can, the elements of an empty array can have
+/// any type except array
+pub fn is_element_type(element_type: &Option<Type>, value_type: &Type) -> bool {
+    match (element_type, value_type) {
+        (_, &Type::Array(_)) => false,
+        (&None, _) => true,
+        (&Some(Type::String), value_type) => *value_type == Type::String,
+        (&Some(Type::Fixed), value_type) => *value_type == Type::Fixed,
+        (&Some(_), value_type) => *value_type != Type::String && *value_type != Type::Fixed
+    }
+}
+
+/// strings, fixed-point numbers and arrays can't be used as an index
+fn is_index(operand: &Operand) -> bool {
+    match operand_type(operand) {
+        Type::Integer | Type::Bool => true,
+        _ => false
+    }
+}
+
 fn boolstr_to_const(string: &str) -> Operand {
     match string {
         "true" => Operand::Const(Constant { value: 1 }),
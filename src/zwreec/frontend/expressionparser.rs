@@ -14,23 +14,27 @@ pub struct ExpressionParser {
 }
 
 impl ExpressionParser {
-    pub fn parse(node: &mut NodeDefault) {
+    /// parses the expression node, returns the message of the error if the
+    /// expression is incomplete
+    pub fn parse(node: &mut NodeDefault) -> Result<(), String> {
         let mut expr_parser = ExpressionParser {
             expr_stack: Vec::new(),
             oper_stack: Vec::new(),
         };
-        expr_parser.parse_expressions(node);
+        expr_parser.parse_expressions(node)
     }
 
     /// parse the expression node and creates mutliple ast nodes
-    fn parse_expressions(&mut self, node: &mut NodeDefault) {
+    fn parse_expressions(&mut self, node: &mut NodeDefault) -> Result<(), String> {
         node.childs.reverse();
         while let Some(top) = node.childs.pop() {
             match top.category() {
                 tok @ TokBoolean  { .. } |
                 tok @ TokInt      { .. } |
+                tok @ TokFloat    { .. } |
                 tok @ TokString   { .. } |
                 tok @ TokFunction { .. } |
+                tok @ TokArrayStart { .. } |
                 tok @ TokVariable { .. } => {
                     let childs_copy = top.as_default().childs.to_vec();
                     self.expr_stack.push( ASTNode::Default(NodeDefault { category: tok.clone(), childs: childs_copy }) );
@@ -51,10 +55,10 @@ impl ExpressionParser {
                         let i_rev = length - i - 1;
                         let token: Token = match self.oper_stack.get(i_rev) {
                             Some(tok) => tok.clone(),
-                            None      => panic!{"No token in the operators stack."}
+                            None      => break
                         };
-                        if is_ranking_not_higher(token.clone(), tok.clone()) {
-                            self.new_operator_node();
+                        if try!(is_ranking_not_higher(token.clone(), tok.clone())) {
+                            try!(self.new_operator_node());
                         }
                         
                     }
@@ -71,12 +75,12 @@ impl ExpressionParser {
                     // and then parse it again
                     let childs_copy = top.as_default().childs.to_vec();
                     let mut ast_node = NodeDefault { category: tok.clone(), childs: childs_copy };
-                    ExpressionParser::parse(&mut ast_node);
+                    try!(ExpressionParser::parse(&mut ast_node));
 
                     if let Some(temp) = ast_node.childs.get(0) {
                         self.expr_stack.push(temp.clone());
                     } else {
-                        panic!{"no parsable sub-expression"}
+                        return Err("The parentheses contain no expression".to_string());
                     }
                 },
                 _ => ()
@@ -86,25 +90,31 @@ impl ExpressionParser {
         // to avoid endless loop we try max stack.len()
         for _ in 0..self.expr_stack.len() {
             if self.expr_stack.len() > 0 {
-                self.new_operator_node();
+                try!(self.new_operator_node());
             }
         }
         // multiple operators could be on the stack becouse if the unary ops
         for _ in 0..self.oper_stack.len() {
             if self.oper_stack.len() > 0 {
-                self.new_operator_node();
+                try!(self.new_operator_node());
             }
         }
 
         // finished. so add the root of the expressions as child.
-        assert!{self.expr_stack.len() == 1, "Only one expression can be the root. But there are {:?}.", self.expr_stack.len()};
+        if self.expr_stack.is_empty() {
+            return Err("The expression is empty".to_string());
+        }
+        if self.expr_stack.len() != 1 {
+            return Err(format!("The expression could not be parsed, it has {} parts instead of one", self.expr_stack.len()));
+        }
         if let Some(root) = self.expr_stack.pop() {
             node.childs.push(root);
         }
+        Ok(())
     }
 
     /// creates a node with an operator on top
-    fn new_operator_node(&mut self) {
+    fn new_operator_node(&mut self) -> Result<(), String> {
         if let Some(top_op) = self.oper_stack.pop() {
 
             let is_unary: bool = match top_op.clone() {
@@ -117,20 +127,15 @@ impl ExpressionParser {
                 _  => false
             };
 
-            if self.expr_stack.len() > 0 {
-                let e2: ASTNode = match self.expr_stack.pop() {
-                    Some(tok) => tok,
-                    None      => panic!{"Not enough elements on the stack to create a node"}
-                };
-
-                let mut new_node: ASTNode;
+            if let Some(e2) = self.expr_stack.pop() {
+                let new_node: ASTNode;
 
                 if is_unary {
                     new_node = ASTNode::Default(NodeDefault { category: top_op.clone(), childs: vec![e2] });
                 } else {
                     let e1: ASTNode = match self.expr_stack.pop() {
                         Some(tok) => tok,
-                        None      => panic!{"Missing Node to create binary node"}
+                        None      => return Err(format!("The operator `{}` needs two arguments", try!(operator_name(&top_op))))
                     };
                     new_node = ASTNode::Default(NodeDefault { category: top_op.clone(), childs: vec![e1, e2] });
                 }
@@ -141,52 +146,51 @@ impl ExpressionParser {
                 self.oper_stack.push(top_op.clone());
             }
         }
+        Ok(())
+    }
+}
+
+/// the name of the operator of the token, `_` is the unary minus
+fn operator_name(token: &Token) -> Result<String, String> {
+    match token {
+        &TokUnaryMinus{ .. } => Ok("_".to_string()),
+        &TokNumOp     { ref op_name, .. } |
+        &TokCompOp    { ref op_name, .. } |
+        &TokLogOp     { ref op_name, .. } => {
+            Ok(op_name.clone())
+        },
+        _ => Err(format!("Unsupported operator: {:?}", token))
     }
 }
 
 /// checks the operatores of two tokens returns true if operator of token1
 /// is more important then operator of token2
 /// the ranking is set in "operator_precedence"
-fn is_ranking_not_higher(token1: Token, token2: Token) -> bool {
-    let op1: String = match token1 {
-        TokUnaryMinus{ .. } => "_".to_string(),
-        TokNumOp     { op_name, .. } |
-        TokCompOp    { op_name, .. } |
-        TokLogOp     { op_name, .. } => {
-            op_name.clone()
-        },
-        _ => panic!{"not allowed operator"}
-    };
-    let op2: String = match token2 {
-        TokUnaryMinus{ .. } => "_".to_string(),
-        TokNumOp     { op_name, .. } |
-        TokCompOp    { op_name, .. } |
-        TokLogOp     { op_name, .. } => {
-            op_name.clone()
-        },
-        _ => panic!{"not allowed operator"}
-    };
+fn is_ranking_not_higher(token1: Token, token2: Token) -> Result<bool, String> {
+    let op1: String = try!(operator_name(&token1));
+    let op2: String = try!(operator_name(&token2));
+    let rank1 = try!(operator_rank(&op1));
+    let rank2 = try!(operator_rank(&op2));
 
 
     // special handling for the unary operators (two unary operators in a row)
     //let op1_copy: &str = op1.as_slice();
-    if (op1 == "_" || op1 == "not" || op1 == "!") &&
-        operator_rank(op1.clone()) == operator_rank(op2.clone()) {
+    if (op1 == "_" || op1 == "not" || op1 == "!") && rank1 == rank2 {
 
-        return false
+        return Ok(false)
     }
 
     //
-    if operator_rank(op1) >= operator_rank(op2) {
-        return true
+    if rank1 >= rank2 {
+        return Ok(true)
     }
 
-    false
+    Ok(false)
 }
 
 /// ranking of the operators
-fn operator_rank(op: String) -> u8 {
-    match op.as_ref() {
+fn operator_rank(op: &str) -> Result<u8, String> {
+    let rank = match op {
         "or" | "||"         => 1,
         "and" | "&&"        => 2,
         "is" | "==" | "eq" | "neq" | ">" | "gt" | ">=" | "gte" | "<" | "lt" | "<=" | "lte"
@@ -194,6 +198,46 @@ fn operator_rank(op: String) -> u8 {
         "+" | "-"           => 4,
         "*" | "/" | "%"     => 5,
         "_" | "not" | "!"   => 6, // _ is unary minus
-        _                   => panic!{"This operator is not implemented"}
+        _                   => return Err(format!("Unsupported operator `{}`", op))
+    };
+    Ok(rank)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use frontend::ast::{ASTNode, NodeDefault};
+    use frontend::lexer::Token;
+    use frontend::lexer::Token::*;
+
+    fn leaf(category: Token) -> ASTNode {
+        ASTNode::Default(NodeDefault { category: category, childs: Vec::new() })
+    }
+
+    #[test]
+    fn missing_operand_test() {
+        let mut node = NodeDefault { category: TokExpression, childs: vec![
+            leaf(TokInt {location: (2, 9), value: 1}),
+            leaf(TokNumOp {location: (2, 11), op_name: "+".to_string()}),
+        ]};
+        assert_eq!(ExpressionParser::parse(&mut node), Err("The operator `+` needs two arguments".to_string()));
+    }
+
+    #[test]
+    fn empty_parentheses_test() {
+        let mut node = NodeDefault { category: TokExpression, childs: vec![leaf(TokExpression)] };
+        assert_eq!(ExpressionParser::parse(&mut node), Err("The expression is empty".to_string()));
+    }
+
+    #[test]
+    fn unsupported_operator_test() {
+        let mut node = NodeDefault { category: TokExpression, childs: vec![
+            leaf(TokInt {location: (2, 9), value: 1}),
+            leaf(TokNumOp {location: (2, 11), op_name: "^".to_string()}),
+            leaf(TokInt {location: (2, 13), value: 2}),
+            leaf(TokNumOp {location: (2, 15), op_name: "+".to_string()}),
+            leaf(TokInt {location: (2, 17), value: 3}),
+        ]};
+        assert_eq!(ExpressionParser::parse(&mut node), Err("Unsupported operator `^`".to_string()));
     }
 }
@@ -81,10 +81,6 @@ pub fn lex<'a, R: Read>(cfg: &'a Config, input: &'a mut R) -> FilteringScan<Peek
                 }
 
                 match elem {
-                    (x @ TokError {..}, _) => {
-                        error_panic!(state.cfg => x);
-                        None
-                    }
                     (TokText {location, text}, Some(TokText{ .. })) => {
                         if state.current_text.len() == 0 {
                             state.current_text_location = location;
@@ -103,6 +99,10 @@ pub fn lex<'a, R: Read>(cfg: &'a Config, input: &'a mut R) -> FilteringScan<Peek
                         state.current_text.clear();
                         Some(val)
                     },
+                    (TokMacroDisplay {ref passage_name, ..}, Some(TokMacroDisplay { .. })) if passage_name.is_empty() => {
+                        // <<display Name>> without an expression, the next token has the name
+                        None
+                    },
                     (TokVariable {location, name: var}, Some(TokAssign {op_name: op, ..} )) => {
                         state.skip_next = true;
                         Some(TokAssign {location: location, var_name: var, op_name: op} )
@@ -129,6 +129,7 @@ pub enum Token {
     TokVarSetEnd              {location: (u64, u64)},
     TokPassageLink            {location: (u64, u64), display_name: String, passage_name: String},
     TokTag                    {location: (u64, u64), tag_name: String},
+    TokPassageMetadata        {location: (u64, u64), metadata: String},
     TokText                   {location: (u64, u64), text: String},
     TokFormatBoldStart        {location: (u64, u64)}, TokFormatBoldEnd   {location: (u64, u64)},
     TokFormatItalicStart      {location: (u64, u64)}, TokFormatItalicEnd {location: (u64, u64)},
@@ -154,6 +155,10 @@ pub enum Token {
     TokMacroDisplay           {location: (u64, u64), passage_name: String},
     TokMacroSilently          {location: (u64, u64)},
     TokMacroEndSilently       {location: (u64, u64)},
+    TokMacroSave              {location: (u64, u64)},
+    TokMacroRestore           {location: (u64, u64)},
+    TokMacroUndo              {location: (u64, u64)},
+    TokMacroTextInput         {location: (u64, u64)},
     TokParenOpen              {location: (u64, u64)},
     TokParenClose             {location: (u64, u64)},
     TokVariable               {location: (u64, u64), name: String},
@@ -166,6 +171,8 @@ pub enum Token {
     TokArgsEnd                {location: (u64, u64)},
     TokArrayStart             {location: (u64, u64)},
     TokArrayEnd               {location: (u64, u64)},
+    TokProperty               {location: (u64, u64), name: String},
+    TokMethod                 {location: (u64, u64), name: String},
     TokAssign                 {location: (u64, u64), var_name: String, op_name: String},
     TokNumOp                  {location: (u64, u64), op_name: String},
     TokCompOp                 {location: (u64, u64), op_name: String},
@@ -188,6 +195,7 @@ impl Token {
             &TokVarSetEnd{location} |
             &TokPassageLink{location, ..} |
             &TokTag{location, ..} |
+            &TokPassageMetadata{location, ..} |
             &TokText{location, ..} |
             &TokFormatBoldStart{location} |
             &TokFormatBoldEnd{location} |
@@ -220,6 +228,10 @@ impl Token {
             &TokMacroDisplay{location, ..} |
             &TokMacroSilently{location} |
             &TokMacroEndSilently{location} |
+            &TokMacroSave{location} |
+            &TokMacroRestore{location} |
+            &TokMacroUndo{location} |
+            &TokMacroTextInput{location} |
             &TokParenOpen{location} |
             &TokParenClose{location} |
             &TokVariable{location, ..} |
@@ -232,6 +244,8 @@ impl Token {
             &TokArgsEnd{location} |
             &TokArrayStart{location} |
             &TokArrayEnd{location} |
+            &TokProperty{location, ..} |
+            &TokMethod{location, ..} |
             &TokAssign{location, ..} |
             &TokNumOp{location, ..} |
             &TokCompOp{location, ..} |
@@ -258,6 +272,7 @@ impl Token {
             (&TokVarSetEnd{..}, &TokVarSetEnd{..}) => true,
             (&TokPassageLink{..}, &TokPassageLink{..}) => true,
             (&TokTag{..}, &TokTag{..}) => true,
+            (&TokPassageMetadata{..}, &TokPassageMetadata{..}) => true,
             (&TokText{..}, &TokText{..}) => true,
             (&TokFormatBoldStart{..}, &TokFormatBoldStart{..}) => true,
             (&TokFormatBoldEnd{..}, &TokFormatBoldEnd{..}) => true,
@@ -290,6 +305,10 @@ impl Token {
             (&TokMacroDisplay{..}, &TokMacroDisplay{..}) => true,
             (&TokMacroSilently{..}, &TokMacroSilently{..}) => true,
             (&TokMacroEndSilently{..}, &TokMacroEndSilently{..}) => true,
+            (&TokMacroSave{..}, &TokMacroSave{..}) => true,
+            (&TokMacroRestore{..}, &TokMacroRestore{..}) => true,
+            (&TokMacroUndo{..}, &TokMacroUndo{..}) => true,
+            (&TokMacroTextInput{..}, &TokMacroTextInput{..}) => true,
             (&TokParenOpen{..}, &TokParenOpen{..}) => true,
             (&TokParenClose{..}, &TokParenClose{..}) => true,
             (&TokVariable{..}, &TokVariable{..}) => true,
@@ -302,6 +321,8 @@ impl Token {
             (&TokArgsEnd{..}, &TokArgsEnd{..}) => true,
             (&TokArrayStart{..}, &TokArrayStart{..}) => true,
             (&TokArrayEnd{..}, &TokArrayEnd{..}) => true,
+            (&TokProperty{..}, &TokProperty{..}) => true,
+            (&TokMethod{..}, &TokMethod{..}) => true,
             (&TokAssign{..}, &TokAssign{..}) => true,
             (&TokNumOp{..}, &TokNumOp{..}) => true,
             (&TokCompOp{..}, &TokCompOp{..}) => true,
@@ -340,6 +361,24 @@ fn unescape(s: String) -> String {
     unescaped
 }
 
+/// removes the backslashes in front of escaped brackets and braces in a
+/// passage name
+fn unescape_passage_name(s: &str) -> String {
+    let mut unescaped = String::new();
+
+    for (c, peek) in s.chars().peeking() {
+        if let Some(nextc) = peek {
+            if c == '\\' && "[]{}".contains(nextc) {
+                continue;
+            }
+        }
+
+        unescaped.push(c);
+    }
+
+    unescaped
+}
+
 
 // ================================
 // test functions
@@ -491,6 +530,42 @@ mod tests {
         assert_tok_eq(expected, tokens);
     }
 
+    #[test]
+    fn metadata_test() {
+        // twee 3 passages can have a json object after the tags
+        let tokens = test_lex(":: Start [tag] {\"position\":\"100,200\"}\nContent\n:: Other {\"size\":\"200,100\"}\n");
+        let expected = vec!(
+            TokPassage {name: "Start".to_string(), location: (1, 3)},
+            TokTagStart {location: (1, 10)},
+            TokTag {location: (1, 11), tag_name: "tag".to_string()},
+            TokTagEnd {location: (1, 14)},
+            TokPassageMetadata {location: (1, 15), metadata: "{\"position\":\"100,200\"}".to_string()},
+            TokText {location: (2, 1), text: "Content".to_string()},
+            TokNewLine {location: (2, 8)},
+            TokPassage {name: "Other".to_string(), location: (3, 3)},
+            TokPassageMetadata {location: (3, 10), metadata: "{\"size\":\"200,100\"}".to_string()},
+        );
+
+        assert_tok_eq(expected, tokens);
+
+        // the braces of nested objects and strings are part of the metadata,
+        // braces in the passage name are escaped
+        let tokens = test_lex(":: A \\{b\\} c\n:: D {\"a\":{\"b\":\"}\\\"\"}} \n:: E {\"a\":1} f\n:: G {\"a\":{}\n");
+        let expected = vec!(
+            TokPassage {name: "A {b} c".to_string(), location: (1, 3)},
+            TokPassage {name: "D".to_string(), location: (2, 3)},
+            TokPassageMetadata {location: (2, 6), metadata: "{\"a\":{\"b\":\"}\\\"\"}}".to_string()},
+            TokPassage {name: "E".to_string(), location: (3, 3)},
+            TokError {location: (3, 12), message: "The metadata has to be at the end of the passage header, \
+                braces in the passage name are escaped as \\{ and \\}".to_string()},
+            TokPassageMetadata {location: (3, 6), metadata: "{\"a\":1}".to_string()},
+            TokPassage {name: "G".to_string(), location: (4, 3)},
+            TokError {location: (4, 6), message: "The metadata of the passage is missing a closing brace".to_string()},
+        );
+
+        assert_tok_eq(expected, tokens);
+    }
+
     #[test]
     fn macro_set_test() {
         // This should return a passage with a set macro
@@ -551,6 +626,19 @@ mod tests {
         assert_tok_eq(expected, tokens);
     }
 
+    #[test]
+    fn macro_textinput_test() {
+        let tokens = test_lex("::Passage\n<<textinput $name>>");
+        let expected = vec!(
+            TokPassage {name: "Passage".to_string(), location: (1, 3)},
+            TokMacroTextInput {location: (2, 3)},
+            TokVariable {location: (2, 13), name: "$name".to_string()},
+            TokMacroEnd {location: (2, 18)}
+        );
+
+        assert_tok_eq(expected, tokens);
+    }
+
     #[test]
     fn macro_display_test() {
         let tokens = test_lex("::Passage\n<<display Passage>>\n<<display  Passage  >>\n<<display  Passage\n>>\n<<display \'Passage\'>>\n<<display  \'Passage\'  >>\n<<display  \'Passage\'\n>>\n<<display \"Passage\">>\n<<display  \"Passage\"  >>\n<<display  \"Passage\"\n>>\n<<display Passage Passage>>\n<<display  Passage Passage  >>\n<<display  Passage Passage\n>>\n<<display \'Passage Passage\'>>\n<<display  \'Passage Passage\'  >>\n<<display  \'Passage Passage\'\n>>\n<<display \"Passage Passage\">>\n<<display  \"Passage Passage\"  >>\n<<display  \"Passage Passage\"\n>>\n<<display \"Passage\" 0+1>>\n<<display \"Passage\" 5+6\"P\" assage>>\n<<display Passage >Passage>>");
@@ -623,6 +711,46 @@ mod tests {
         assert_tok_eq(expected, tokens);
     }
 
+    #[test]
+    fn macro_display_expression_test() {
+        let tokens = test_lex("::Start\n<<display $room + \"B\">>\n<<display (\"Pass\" + \"age\")>>\n<<display Room (old)>>\n<<display Price$>>\n<<display (either(\"A\", \"B\"))>>");
+        let expected = vec![
+            TokPassage {name: "Start".to_string(), location: (1, 3)},
+            TokMacroDisplay {location: (2, 3), passage_name: "".to_string()},
+            TokVariable {location: (2, 11), name: "$room".to_string()},
+            TokNumOp {location: (2, 17), op_name: "+".to_string()},
+            TokString {location: (2, 19), value: "B".to_string()},
+            TokMacroEnd {location: (2, 22)},
+            TokNewLine {location: (2, 24)},
+            TokMacroDisplay {location: (3, 3), passage_name: "".to_string()},
+            TokParenOpen {location: (3, 11)},
+            TokString {location: (3, 12), value: "Pass".to_string()},
+            TokNumOp {location: (3, 19), op_name: "+".to_string()},
+            TokString {location: (3, 21), value: "age".to_string()},
+            TokParenClose {location: (3, 26)},
+            TokMacroEnd {location: (3, 27)},
+            TokNewLine {location: (3, 29)},
+            // parentheses and dollar signs inside a name are no expression
+            TokMacroDisplay {location: (4, 11), passage_name: "Room (old)".to_string()},
+            TokMacroEnd {location: (4, 21)},
+            TokNewLine {location: (4, 23)},
+            TokMacroDisplay {location: (5, 11), passage_name: "Price$".to_string()},
+            TokMacroEnd {location: (5, 17)},
+            TokNewLine {location: (5, 19)},
+            TokMacroDisplay {location: (6, 3), passage_name: "".to_string()},
+            TokParenOpen {location: (6, 11)},
+            TokFunction {location: (6, 12), name: "either".to_string()},
+            TokString {location: (6, 19), value: "A".to_string()},
+            TokColon {location: (6, 22)},
+            TokString {location: (6, 24), value: "B".to_string()},
+            TokArgsEnd {location: (6, 27)},
+            TokParenClose {location: (6, 28)},
+            TokMacroEnd {location: (6, 29)}
+        ];
+
+        assert_tok_eq(expected, tokens);
+    }
+
     #[test]
     fn macro_display_short_test() {
         let tokens = test_lex("::Passage\n<<Passage>>\n<<Passage   >>\n<<Passage\n>>\n<<Passage 5>>\n<<Passage \"test\">>\n<<Passage \"test\"+5>>\n<<\"Passage\'>>");
@@ -672,4 +800,47 @@ mod tests {
 
         assert_tok_eq(expected, tokens);
     }
+
+    #[test]
+    fn macro_set_array_test() {
+        let tokens = test_lex("::Start\n<<set $inv[0] = $inv.length>>\n<<set $inv.push(\"key\")>>");
+        let expected = vec![
+            TokPassage {name: "Start".to_string(), location: (1, 3)},
+            TokMacroSet {location: (2, 3)},
+            TokVariable {location: (2, 7), name: "$inv".to_string()},
+            TokArrayStart {location: (2, 11)},
+            TokInt {value: 0, location: (2, 12)},
+            TokArrayEnd {location: (2, 13)},
+            TokAssign {location: (2, 15), var_name: "".to_string(), op_name: "=".to_string()},
+            TokVariable {location: (2, 17), name: "$inv".to_string()},
+            TokProperty {location: (2, 21), name: "length".to_string()},
+            TokMacroEnd {location: (2, 28)},
+            TokNewLine {location: (2, 30)},
+            TokMacroSet {location: (3, 3)},
+            TokVariable {location: (3, 7), name: "$inv".to_string()},
+            TokMethod {location: (3, 11), name: "push".to_string()},
+            TokString {location: (3, 17), value: "key".to_string()},
+            TokArgsEnd {location: (3, 22)},
+            TokMacroEnd {location: (3, 23)}
+        ];
+
+        assert_tok_eq(expected, tokens);
+    }
+
+    #[test]
+    fn macro_print_float_test() {
+        let tokens = test_lex("::Start\n<<print Math.round(1.5 * 2)>>");
+        let expected = vec![
+            TokPassage {name: "Start".to_string(), location: (1, 3)},
+            TokMacroPrint {location: (2, 3)},
+            TokFunction {name: "Math.round".to_string(), location: (2, 9)},
+            TokFloat {value: 1.5, location: (2, 20)},
+            TokNumOp {op_name: "*".to_string(), location: (2, 24)},
+            TokInt {value: 2, location: (2, 26)},
+            TokArgsEnd {location: (2, 27)},
+            TokMacroEnd {location: (2, 28)}
+        ];
+
+        assert_tok_eq(expected, tokens);
+    }
 }
@@ -25,6 +25,9 @@
 //!     p.parse(tokens)
 //! );
 //! ```
+//!
+//! Stories in the HTML format of Twine 2 are converted to Twee by the
+//! `twine2` module first.
 
 pub mod ast;
 pub mod codegen;
@@ -32,3 +35,7 @@ pub mod evaluate_expression;
 pub mod expressionparser;
 pub mod lexer;
 pub mod parser;
+pub mod sources;
+#[cfg(test)]
+pub mod testing;
+pub mod twine2;
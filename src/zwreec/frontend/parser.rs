@@ -24,6 +24,7 @@ use frontend::lexer::Token::*;
 use frontend::ast::ASTOperation;
 use frontend::ast::ASTOperation::*;
 use utils::extensions::{ParserExt, ParseResult};
+use utils::error::{Diagnostic, DiagnosticKind};
 use self::NonTerminalType::*;
 use self::Elem::*;
 
@@ -32,12 +33,97 @@ use self::Elem::*;
 
 #[derive(Debug)]
 pub enum ParserError {
-    TokenDoNotMatch { token: Option<Token>, stack: Token },
+    TokenDoNotMatch { token: Option<Token>, stack: Token, open_macro: Option<Token> },
     StackIsEmpty { token: Token },
     NoProjection { token: Token, stack: NonTerminalType },
     NonTerminalEnd { stack: NonTerminalType },
 }
 
+impl ParserError {
+    /// Returns the location of the token that caused the error.
+    ///
+    /// Errors at the end of the input have no token and return `(0, 0)`.
+    pub fn location(&self) -> (u64, u64) {
+        match self {
+            &ParserError::TokenDoNotMatch{token: Some(ref token), ..} => token.location(),
+            &ParserError::StackIsEmpty{ref token} => token.location(),
+            &ParserError::NoProjection{ref token, ..} => token.location(),
+            _ => (0, 0),
+        }
+    }
+
+    /// Wraps the error in a `Diagnostic` that can be passed to the AST.
+    pub fn to_diagnostic(&self) -> Diagnostic {
+        Diagnostic::new(DiagnosticKind::Parser, format!("{}", self), self.location())
+    }
+}
+
+/// Creates the `ASTOperation` that passes the error on to the AST.
+fn report(error: ParserError) -> Option<ASTOperation> {
+    Some(AddError(error.to_diagnostic()))
+}
+
+/// Checks if the token opens a macro (`<<name ...>>`).
+fn is_macro_start(token: &Token) -> bool {
+    match token {
+        &TokMacroDisplay{..} | &TokMacroSet{..} | &TokMacroIf{..} |
+        &TokMacroElse{..} | &TokMacroElseIf{..} | &TokMacroEndIf{..} |
+        &TokMacroPrint{..} | &TokMacroContentVar{..} |
+        &TokMacroSilently{..} | &TokMacroEndSilently{..} |
+        &TokMacroSave{..} | &TokMacroRestore{..} | &TokMacroUndo{..} |
+        &TokMacroTextInput{..} => true,
+        _ => false,
+    }
+}
+
+/// Panic-mode error recovery.
+///
+/// After an error all tokens are skipped until the parser reaches a point
+/// where it can safely continue:
+///
+/// * `TokPassage`: the stack is reset and parsing starts with the new passage.
+/// * `TokMacroEnd`: if the error was caused by a misplaced macro (e.g. a
+///   second `<<else>>`), the stack from before the macro is restored.
+///   Otherwise the rest of the broken macro is dropped from the stack.
+///
+/// This way every syntax error of the input is reported in a single run.
+fn synchronize(state: &mut ParseState, token: Option<Token>) -> (ParseResult, Option<ASTOperation>) {
+    match token {
+        Some(TokPassage { .. }) => {
+            debug!("synchronized at passage");
+            state.stack = vec![NonTerminal(S)];
+            state.resync_stack = None;
+            state.recovering = false;
+
+            (ParseResult::Halt, None)
+        },
+        Some(TokMacroEnd { .. }) => {
+            if let Some(stack) = state.resync_stack.take() {
+                debug!("synchronized at macro end, skipped macro");
+                state.stack = stack;
+                state.recovering = false;
+            } else if let Some(pos) = state.stack.iter().rposition(|elem| match elem {
+                &Terminal(TokMacroEnd { .. }) => true,
+                _ => false,
+            }) {
+                debug!("synchronized at macro end");
+                state.stack.truncate(pos);
+                state.recovering = false;
+            }
+            state.new_token = true;
+
+            (ParseResult::Continue, None)
+        },
+        Some(tok) => {
+            debug!("skipped {:?}", tok);
+            state.new_token = true;
+
+            (ParseResult::Continue, None)
+        },
+        None => (ParseResult::End, None),
+    }
+}
+
 /// The Type of nonterminal encountered by the parser.
 ///
 /// These are the nonterminals of the underlying LL(1) grammar. For the full grammar,
@@ -52,10 +138,15 @@ pub enum NonTerminalType {
     PassageContent,
     Tags,
     Tagsf,
+    Metadata,
     Formating,
     BoldFormatting,
     ItalicFormatting,
     MonoFormatting,
+    UnderFormatting,
+    StrikeFormatting,
+    SupFormatting,
+    SubFormatting,
     MonoContent,
     Link,
     Macro,
@@ -82,6 +173,16 @@ pub enum NonTerminalType {
     H,
     DataType,
     AssignVariable,
+    /// Assignments of a `<<set>>`, separated by `;` or `,`
+    AssignList,
+    AssignListf,
+    /// The element, property or method after a variable
+    Variablef,
+    VariableEnd,
+    /// The elements of an array literal
+    Arrayf,
+    /// Assignment to an element of an array in a `<<set>>`
+    AssignElement,
 }
 
 /// The Type that represents an element of the grammar
@@ -91,6 +192,7 @@ pub enum NonTerminalType {
 ///
 /// For the full grammar, take a look at Zwreec's
 /// [Wiki](https://github.com/Drakulix/zwreec/wiki/Underlying-Twee-Grammar#grammar).
+#[derive(Clone)]
 pub enum Elem {
     NonTerminal(NonTerminalType),
     Terminal(Token)
@@ -103,7 +205,17 @@ pub enum Elem {
 pub struct ParseState<'a> {
     stack: Vec<Elem>,
     grammar_func: Box<Fn(&Config, NonTerminalType, Option<Token>, &mut Vec<Elem>) -> Option<ASTOperation>>,
-    cfg: &'a Config
+    cfg: &'a Config,
+    /// the stack before the current token was processed
+    checkpoint: Vec<Elem>,
+    /// true if the next call of the parsing routine gets a new token
+    new_token: bool,
+    /// true after an error until the parser is synchronized again
+    recovering: bool,
+    /// the stack to restore at the next `TokMacroEnd` (see `synchronize`)
+    resync_stack: Option<Vec<Elem>>,
+    /// the last macro that was opened, used in error messages
+    open_macro: Option<Token>,
 }
 
 //==============================
@@ -134,36 +246,74 @@ impl<'a> Parser<'a> {
                 stack: stack,
                 grammar_func: Box::new(Parser::apply_grammar),
                 cfg: self.cfg,
+                checkpoint: Vec::new(),
+                new_token: true,
+                recovering: false,
+                resync_stack: None,
+                open_macro: None,
             },
             {
                 /// the predictive stack ll(1) parsing routine
                 fn parse(state: &mut ParseState, token: Option<Token>) -> (ParseResult, Option<ASTOperation>) {
-                    match token {
+                    if let Some(TokError {location, message}) = token {
+                        // the lexer could not match the input, skip it
+                        state.new_token = true;
+                        return (ParseResult::Continue, Some(AddError(Diagnostic::new(DiagnosticKind::Lexer, message, location))));
+                    }
+
+                    if state.new_token {
+                        state.checkpoint = state.stack.clone();
+                        state.new_token = false;
+                    }
+
+                    if state.recovering {
+                        return synchronize(state, token);
+                    }
+
+                    let current = token.clone();
+                    let (result, op) = match token {
                         Some(token) => match state.stack.pop() {
                             Some(Elem::NonTerminal(non_terminal)) => (ParseResult::Halt, (state.grammar_func)(state.cfg, non_terminal, Some(token), &mut state.stack)),
                             Some(Elem::Terminal(stack_token)) => {
                                 if stack_token.is_same_token(&token) {
+                                    if is_macro_start(&token) {
+                                        state.open_macro = Some(token);
+                                    }
                                     (ParseResult::Continue, None)
                                 } else {
-                                    error_panic!(state.cfg => ParserError::TokenDoNotMatch{token: Some(token), stack: stack_token.clone()});
-                                    state.stack.push(Elem::Terminal(stack_token));
-                                    (ParseResult::Continue, None)
+                                    state.stack.push(Elem::Terminal(stack_token.clone()));
+                                    (ParseResult::Continue, report(ParserError::TokenDoNotMatch{token: Some(token), stack: stack_token, open_macro: state.open_macro.clone()}))
                                 }
                             },
                             None => {
-                                error_panic!(state.cfg => ParserError::StackIsEmpty{token: token});
-                                (ParseResult::End, None)
+                                (ParseResult::End, report(ParserError::StackIsEmpty{token: token}))
                             },
                         },
                         None => match state.stack.pop() {
                             Some(Elem::NonTerminal(non_terminal)) => (ParseResult::Continue, (state.grammar_func)(state.cfg, non_terminal, None, &mut state.stack)),
                             Some(Elem::Terminal(stack_token)) => {
-                                error_panic!(state.cfg => ParserError::TokenDoNotMatch{token: token, stack: stack_token});
-                                (ParseResult::Continue, None)
+                                (ParseResult::Continue, report(ParserError::TokenDoNotMatch{token: token, stack: stack_token, open_macro: state.open_macro.clone()}))
                             },
                             None => (ParseResult::End, None),
                         }
+                    };
+
+                    if let Some(AddError(_)) = op {
+                        state.recovering = true;
+                        state.resync_stack = match current {
+                            Some(ref tok) if is_macro_start(tok) => Some(state.checkpoint.clone()),
+                            _ => None,
+                        };
+
+                        // look at the token again to synchronize
+                        return (ParseResult::Halt, op);
                     }
+
+                    if let ParseResult::Continue = result {
+                        state.new_token = true;
+                    }
+
+                    (result, op)
                 }
                 parse
             }
@@ -173,7 +323,7 @@ impl<'a> Parser<'a> {
     /// apply the ll(1) grammar
     /// the match-statement simulates the parsing-table behavior
     ///
-    fn apply_grammar(cfg: &Config, top: NonTerminalType, maybe_token: Option<Token>, stack: &mut Vec<Elem>) -> Option<ASTOperation> {
+    fn apply_grammar(_cfg: &Config, top: NonTerminalType, maybe_token: Option<Token>, stack: &mut Vec<Elem>) -> Option<ASTOperation> {
         if let Some(token) = maybe_token {
 
             let state = (top, token);
@@ -208,6 +358,7 @@ impl<'a> Parser<'a> {
                 // Passagef
                 (Passagef, tok @ TokTagStart { .. } ) => {
                     stack.push(NonTerminal(PassageContent));
+                    stack.push(NonTerminal(Metadata));
                     stack.push(Terminal(TokTagEnd{location: (0, 0)}));
                     stack.push(NonTerminal(Tags));
                     stack.push(Terminal(tok));
@@ -216,6 +367,7 @@ impl<'a> Parser<'a> {
                 },
                 (Passagef, _ ) => {
                     stack.push(NonTerminal(PassageContent));
+                    stack.push(NonTerminal(Metadata));
 
                     None
                 },
@@ -223,9 +375,9 @@ impl<'a> Parser<'a> {
                 // Tags
                 (Tags, tok @ TokTag { .. } ) => {                    
                     stack.push(NonTerminal(Tagsf));
-                    stack.push(Terminal(tok));
+                    stack.push(Terminal(tok.clone()));
 
-                    None
+                    Some(AddTag(tok))
                 },
 
                 // tagsf
@@ -240,6 +392,18 @@ impl<'a> Parser<'a> {
                     None
                 },
 
+                // Metadata
+                (Metadata, tok @ TokPassageMetadata { .. } ) => {
+                    stack.push(Terminal(tok.clone()));
+
+                    Some(AddMetadata(tok))
+                },
+                (Metadata, _ ) => {
+                    // Metadata -> ε
+
+                    None
+                },
+
                 // PassageContent
                 (PassageContent, tok @ TokText { .. } ) => {
                     stack.push(NonTerminal(PassageContent));
@@ -249,6 +413,10 @@ impl<'a> Parser<'a> {
                 },
                 (PassageContent, TokFormatBoldStart   { .. }) |
                 (PassageContent, TokFormatItalicStart { .. }) |
+                (PassageContent, TokFormatUnderStart  { .. }) |
+                (PassageContent, TokFormatStrikeStart { .. }) |
+                (PassageContent, TokFormatSupStart    { .. }) |
+                (PassageContent, TokFormatSubStart    { .. }) |
                 (PassageContent, TokFormatMonoStart   { .. }) => {
                     stack.push(NonTerminal(PassageContent));
                     stack.push(NonTerminal(Formating));
@@ -267,10 +435,25 @@ impl<'a> Parser<'a> {
 
                     Some(AddChild(tok))
                 },
+                (PassageContent, tok @ TokFormatHeading { .. }) |
+                (PassageContent, tok @ TokFormatBulList { .. }) |
+                (PassageContent, tok @ TokFormatNumbList { .. }) |
+                (PassageContent, tok @ TokFormatHorizontalLine { .. }) |
+                (PassageContent, tok @ TokFormatIndentBlock { .. }) => {
+                    stack.push(NonTerminal(PassageContent));
+                    stack.push(Terminal(tok.clone()));
+
+                    Some(AddChild(tok))
+                },
                 (PassageContent, TokMacroDisplay { .. } ) |
                 (PassageContent, TokMacroSet { .. } ) |
                 (PassageContent, TokMacroIf  { .. } ) |
                 (PassageContent, TokMacroPrint { .. } ) |
+                (PassageContent, TokMacroSilently { .. } ) |
+                (PassageContent, TokMacroSave { .. } ) |
+                (PassageContent, TokMacroRestore { .. } ) |
+                (PassageContent, TokMacroUndo { .. } ) |
+                (PassageContent, TokMacroTextInput { .. } ) |
                 (PassageContent, TokVariable { .. } ) |
                 (PassageContent, TokMacroContentVar { .. } ) => {
                     stack.push(NonTerminal(PassageContent));
@@ -288,7 +471,15 @@ impl<'a> Parser<'a> {
                     // jump one ast-level higher
                     Some(Up)
                 },
-                (PassageContent, TokFormatItalicEnd { .. } ) => {
+                (PassageContent, TokFormatItalicEnd { .. } ) |
+                (PassageContent, TokFormatUnderEnd  { .. } ) |
+                (PassageContent, TokFormatStrikeEnd { .. } ) |
+                (PassageContent, TokFormatSupEnd    { .. } ) |
+                (PassageContent, TokFormatSubEnd    { .. } ) => {
+                    // jump one ast-level higher
+                    Some(Up)
+                },
+                (PassageContent, TokMacroEndSilently { .. } ) => {
                     // jump one ast-level higher
                     Some(Up)
                 },
@@ -313,6 +504,26 @@ impl<'a> Parser<'a> {
 
                     None
                 },
+                (Formating, TokFormatUnderStart { .. } ) => {
+                    stack.push(NonTerminal(UnderFormatting));
+
+                    None
+                },
+                (Formating, TokFormatStrikeStart { .. } ) => {
+                    stack.push(NonTerminal(StrikeFormatting));
+
+                    None
+                },
+                (Formating, TokFormatSupStart { .. } ) => {
+                    stack.push(NonTerminal(SupFormatting));
+
+                    None
+                },
+                (Formating, TokFormatSubStart { .. } ) => {
+                    stack.push(NonTerminal(SubFormatting));
+
+                    None
+                },
 
                 //BoldFormatting
                 (BoldFormatting, tok @ TokFormatBoldStart { .. } ) => {
@@ -332,6 +543,42 @@ impl<'a> Parser<'a> {
                     Some(ChildDown(tok))
                 },
 
+                // UnderFormatting
+                (UnderFormatting, tok @ TokFormatUnderStart { .. } ) => {
+                    stack.push(Terminal(TokFormatUnderEnd {location: (0, 0)} ));
+                    stack.push(NonTerminal(PassageContent));
+                    stack.push(Terminal(tok.clone()));
+
+                    Some(ChildDown(tok))
+                },
+
+                // StrikeFormatting
+                (StrikeFormatting, tok @ TokFormatStrikeStart { .. } ) => {
+                    stack.push(Terminal(TokFormatStrikeEnd {location: (0, 0)} ));
+                    stack.push(NonTerminal(PassageContent));
+                    stack.push(Terminal(tok.clone()));
+
+                    Some(ChildDown(tok))
+                },
+
+                // SupFormatting
+                (SupFormatting, tok @ TokFormatSupStart { .. } ) => {
+                    stack.push(Terminal(TokFormatSupEnd {location: (0, 0)} ));
+                    stack.push(NonTerminal(PassageContent));
+                    stack.push(Terminal(tok.clone()));
+
+                    Some(ChildDown(tok))
+                },
+
+                // SubFormatting
+                (SubFormatting, tok @ TokFormatSubStart { .. } ) => {
+                    stack.push(Terminal(TokFormatSubEnd {location: (0, 0)} ));
+                    stack.push(NonTerminal(PassageContent));
+                    stack.push(Terminal(tok.clone()));
+
+                    Some(ChildDown(tok))
+                },
+
                 // MonoFormatting
                 (MonoFormatting, tok @ TokFormatMonoStart { .. } ) => {
                     stack.push(Terminal(TokFormatMonoEnd {location: (0, 0)} ));
@@ -368,6 +615,15 @@ impl<'a> Parser<'a> {
                 },
 
                 // Macro
+                (Macro, TokMacroDisplay { location, ref passage_name } ) if passage_name.is_empty() => {
+                    // the passage name is an expression, e.g. <<display $var>>
+                    let tok = TokMacroDisplay {location: location, passage_name: String::new()};
+                    stack.push(Terminal(TokMacroEnd {location: (0, 0)} ));
+                    stack.push(NonTerminal(ExpressionList));
+                    stack.push(Terminal(tok.clone()));
+
+                    Some(ChildDown(tok))
+                },
                 (Macro, tok @ TokMacroDisplay { .. } ) => {
                     stack.push(Terminal(TokMacroEnd {location: (0, 0)} ));
                     stack.push(Terminal(tok.clone()));
@@ -376,7 +632,7 @@ impl<'a> Parser<'a> {
                 },
                 (Macro, tok @ TokMacroSet { .. } ) => {
                     stack.push(Terminal(TokMacroEnd {location: (0, 0)} ));
-                    stack.push(NonTerminal(ExpressionList));
+                    stack.push(NonTerminal(AssignList));
                     stack.push(Terminal(tok));
 
                     None
@@ -391,7 +647,8 @@ impl<'a> Parser<'a> {
 
                     Some(ChildDown(tok))
                 },
-                (Macro, tok @ TokMacroPrint { .. } ) => {
+                (Macro, tok @ TokMacroPrint { .. } ) |
+                (Macro, tok @ TokMacroTextInput { .. } ) => {
                     stack.push(Terminal(TokMacroEnd {location: (0, 0)} ));
                     stack.push(NonTerminal(ExpressionList));
                     stack.push(Terminal(tok.clone()));
@@ -399,6 +656,25 @@ impl<'a> Parser<'a> {
                     Some(ChildDown(tok))
                 }
 
+                (Macro, tok @ TokMacroSilently { .. } ) => {
+                    stack.push(Terminal(TokMacroEnd {location: (0, 0)} ));
+                    stack.push(Terminal(TokMacroEndSilently {location: (0, 0)} ));
+                    stack.push(NonTerminal(PassageContent));
+                    stack.push(Terminal(TokMacroEnd {location: (0, 0)} ));
+                    stack.push(Terminal(tok.clone()));
+
+                    Some(ChildDown(tok))
+                },
+
+                (Macro, tok @ TokMacroSave { .. } ) |
+                (Macro, tok @ TokMacroRestore { .. } ) |
+                (Macro, tok @ TokMacroUndo { .. } ) => {
+                    stack.push(Terminal(TokMacroEnd {location: (0, 0)} ));
+                    stack.push(Terminal(tok.clone()));
+
+                    Some(AddChild(tok))
+                },
+
                 // means <<$var>>
                 (Macro, tok @ TokMacroContentVar { .. }) => {
                     stack.push(Terminal(TokMacroEnd {location: (0, 0)} ));
@@ -442,33 +718,35 @@ impl<'a> Parser<'a> {
                 // ExpressionList
                 (ExpressionList, TokVariable { .. } ) |
                 (ExpressionList, TokInt      { .. } ) |
+                (ExpressionList, TokFloat    { .. } ) |
                 (ExpressionList, TokString   { .. } ) |
                 (ExpressionList, TokBoolean  { .. } ) |
                 (ExpressionList, TokAssign   { .. } ) |
                 (ExpressionList, TokFunction { .. } ) |
+                (ExpressionList, TokArrayStart{ .. } ) |
                 (ExpressionList, TokParenOpen{ .. } ) => {
                     stack.push(NonTerminal(ExpressionListf));
                     stack.push(NonTerminal(Expression));
 
                     None
                 },
-                (ExpressionList, TokNumOp { op_name: op, .. }) =>  match &*op {
+                (ExpressionList, TokNumOp { location, op_name: op }) =>  match &*op {
                     "-" => {
                         stack.push(NonTerminal(ExpressionListf));
                         stack.push(NonTerminal(Expression));
 
                         None
                     }
-                    _ => None
+                    _ => report(ParserError::NoProjection{token: TokNumOp{location: location.clone(), op_name: op.clone()}, stack: ExpressionList})
                 },
-                (ExpressionList, TokLogOp { op_name: op, .. }) =>  match &*op {
+                (ExpressionList, TokLogOp { location, op_name: op }) =>  match &*op {
                     "not" | "!" => {
                         stack.push(NonTerminal(ExpressionListf));
                         stack.push(NonTerminal(Expression));
 
                         None
                     }
-                    _ => None
+                    _ => report(ParserError::NoProjection{token: TokLogOp{location: location.clone(), op_name: op.clone()}, stack: ExpressionList})
                 },
 
                 // ExpressionListf
@@ -486,9 +764,11 @@ impl<'a> Parser<'a> {
                 // Expression
                 (Expression, TokVariable { .. } ) |
                 (Expression, TokInt      { .. } ) |
+                (Expression, TokFloat    { .. } ) |
                 (Expression, TokString   { .. } ) |
                 (Expression, TokBoolean  { .. } ) |
                 (Expression, TokFunction { .. } ) |
+                (Expression, TokArrayStart{ .. } ) |
                 (Expression, TokParenOpen{ .. } ) => {
                     stack.push(NonTerminal(E));
 
@@ -500,29 +780,31 @@ impl<'a> Parser<'a> {
                     None
                 },
 
-                (Expression, TokNumOp { op_name: op, .. }) =>  match &*op {
+                (Expression, TokNumOp { location, op_name: op }) =>  match &*op {
                     "-" => {
                         stack.push(NonTerminal(E));
 
                         None
                     }
-                    _ => None
+                    _ => report(ParserError::NoProjection{token: TokNumOp{location: location.clone(), op_name: op.clone()}, stack: Expression})
                 },
-                (Expression, TokLogOp { op_name: op, .. }) =>  match &*op {
+                (Expression, TokLogOp { location, op_name: op }) =>  match &*op {
                     "not" | "!" => {
                         stack.push(NonTerminal(E));
 
                         None
                     }
-                    _ => None
+                    _ => report(ParserError::NoProjection{token: TokLogOp{location: location.clone(), op_name: op.clone()}, stack: Expression})
                 },
 
                 // E
                 (E, TokVariable { .. } ) |
                 (E, TokInt      { .. } ) |
+                (E, TokFloat    { .. } ) |
                 (E, TokString   { .. } ) |
                 (E, TokBoolean  { .. } ) |
                 (E, TokFunction { .. } ) |
+                (E, TokArrayStart{ .. } ) |
                 (E, TokParenOpen{ .. } ) => {
                     stack.push(NonTerminal(E2));
                     stack.push(NonTerminal(T));
@@ -530,23 +812,23 @@ impl<'a> Parser<'a> {
                     //None
                     Some(ChildDown(TokExpression))
                 },
-                (E, TokNumOp { op_name: op, .. }) =>  match &*op {
+                (E, TokNumOp { location, op_name: op }) =>  match &*op {
                     "-" => {
                         stack.push(NonTerminal(E2));
                         stack.push(NonTerminal(T));
 
                         Some(ChildDown(TokExpression))
                     }
-                    _ => None
+                    _ => report(ParserError::NoProjection{token: TokNumOp{location: location.clone(), op_name: op.clone()}, stack: E})
                 },
-                (E, TokLogOp { op_name: op, .. }) =>  match &*op {
+                (E, TokLogOp { location, op_name: op }) =>  match &*op {
                     "not" | "!" => {
                         stack.push(NonTerminal(E2));
                         stack.push(NonTerminal(T));
 
                         Some(ChildDown(TokExpression))
                     }
-                    _ => None
+                    _ => report(ParserError::NoProjection{token: TokLogOp{location: location.clone(), op_name: op.clone()}, stack: E})
                 },
 
                 // E2
@@ -569,32 +851,34 @@ impl<'a> Parser<'a> {
                 // T
                 (T, TokVariable { .. } ) |
                 (T, TokInt      { .. } ) |
+                (T, TokFloat    { .. } ) |
                 (T, TokString   { .. } ) |
                 (T, TokBoolean  { .. } ) |
                 (T, TokFunction { .. } ) |
+                (T, TokArrayStart{ .. } ) |
                 (T, TokParenOpen{ .. } )=> {
                     stack.push(NonTerminal(T2));
                     stack.push(NonTerminal(B));
 
                     None
                 },
-                (T, TokNumOp { op_name: op, .. }) =>  match &*op {
+                (T, TokNumOp { location, op_name: op }) =>  match &*op {
                     "-" => {
                         stack.push(NonTerminal(T2));
                         stack.push(NonTerminal(B));
 
                         None
                     }
-                    _ => None
+                    _ => report(ParserError::NoProjection{token: TokNumOp{location: location.clone(), op_name: op.clone()}, stack: T})
                 },
-                (T, TokLogOp { op_name: op, .. }) =>  match &*op {
+                (T, TokLogOp { location, op_name: op }) =>  match &*op {
                     "not" | "!" => {
                         stack.push(NonTerminal(T2));
                         stack.push(NonTerminal(B));
 
                         None
                     }
-                    _ => None
+                    _ => report(ParserError::NoProjection{token: TokLogOp{location: location.clone(), op_name: op.clone()}, stack: T})
                 },
 
                 // T2
@@ -616,32 +900,34 @@ impl<'a> Parser<'a> {
                 // B
                 (B, TokVariable { .. } ) |
                 (B, TokInt      { .. } ) |
+                (B, TokFloat    { .. } ) |
                 (B, TokString   { .. } ) |
                 (B, TokBoolean  { .. } ) |
                 (B, TokFunction { .. } ) |
+                (B, TokArrayStart{ .. } ) |
                 (B, TokParenOpen{ .. } ) => {
                     stack.push(NonTerminal(B2));
                     stack.push(NonTerminal(F));
 
                     None
                 },
-                (B, TokNumOp { op_name: op, .. }) =>  match &*op {
+                (B, TokNumOp { location, op_name: op }) =>  match &*op {
                     "-" => {
                         stack.push(NonTerminal(B2));
                         stack.push(NonTerminal(F));
 
                         None
                     }
-                    _ => None
+                    _ => report(ParserError::NoProjection{token: TokNumOp{location: location.clone(), op_name: op.clone()}, stack: B})
                 },
-                (B, TokLogOp { op_name: op, .. }) =>  match &*op {
+                (B, TokLogOp { location, op_name: op }) =>  match &*op {
                     "not" | "!" => {
                         stack.push(NonTerminal(B2));
                         stack.push(NonTerminal(F));
 
                         None
                     }
-                    _ => None
+                    _ => report(ParserError::NoProjection{token: TokLogOp{location: location.clone(), op_name: op.clone()}, stack: B})
                 },
 
                 // B2
@@ -663,32 +949,34 @@ impl<'a> Parser<'a> {
                 // F
                 (F, TokVariable { .. } ) |
                 (F, TokInt      { .. } ) |
+                (F, TokFloat    { .. } ) |
                 (F, TokString   { .. } ) |
                 (F, TokBoolean  { .. } ) |
                 (F, TokFunction { .. } ) |
+                (F, TokArrayStart{ .. } ) |
                 (F, TokParenOpen{ .. } ) => {
                     stack.push(NonTerminal(F2));
                     stack.push(NonTerminal(G));
 
                     None
                 },
-                (F, TokNumOp { op_name: op, .. }) =>  match &*op {
+                (F, TokNumOp { location, op_name: op }) =>  match &*op {
                     "-" => {
                         stack.push(NonTerminal(F2));
                         stack.push(NonTerminal(G));
 
                         None
                     }
-                    _ => None
+                    _ => report(ParserError::NoProjection{token: TokNumOp{location: location.clone(), op_name: op.clone()}, stack: F})
                 },
-                (F, TokLogOp { op_name: op, .. }) =>  match &*op {
+                (F, TokLogOp { location, op_name: op }) =>  match &*op {
                     "not" | "!" => {
                         stack.push(NonTerminal(F2));
                         stack.push(NonTerminal(G));
 
                         None
                     }
-                    _ => None
+                    _ => report(ParserError::NoProjection{token: TokLogOp{location: location.clone(), op_name: op.clone()}, stack: F})
                 },
 
                 // F2
@@ -710,32 +998,34 @@ impl<'a> Parser<'a> {
                 // G
                 (G, TokVariable { .. } ) |
                 (G, TokInt      { .. } ) |
+                (G, TokFloat    { .. } ) |
                 (G, TokString   { .. } ) |
                 (G, TokBoolean  { .. } ) |
                 (G, TokFunction { .. } ) |
+                (G, TokArrayStart{ .. } ) |
                 (G, TokParenOpen{ .. } ) => {
                     stack.push(NonTerminal(G2));
                     stack.push(NonTerminal(H));
 
                     None
                 },
-                (G, TokNumOp { op_name: op, .. }) =>  match &*op {
+                (G, TokNumOp { location, op_name: op }) =>  match &*op {
                     "-" => {
                         stack.push(NonTerminal(G2));
                         stack.push(NonTerminal(H));
 
                         None
                     }
-                    _ => None
+                    _ => report(ParserError::NoProjection{token: TokNumOp{location: location.clone(), op_name: op.clone()}, stack: G})
                 },
-                (G, TokLogOp { op_name: op, .. }) =>  match &*op {
+                (G, TokLogOp { location, op_name: op }) =>  match &*op {
                     "not" | "!" => {
                         stack.push(NonTerminal(G2));
                         stack.push(NonTerminal(H));
 
                         None
                     }
-                    _ => None
+                    _ => report(ParserError::NoProjection{token: TokLogOp{location: location.clone(), op_name: op.clone()}, stack: G})
                 },
 
                 // G2
@@ -755,7 +1045,8 @@ impl<'a> Parser<'a> {
                 (G2, TokCompOp     { .. } ) |
                 (G2, TokArgsEnd    { .. } ) |
                 (G2, TokColon      { .. } ) |
-                (G2, TokParenClose { .. } ) => {
+                (G2, TokParenClose { .. } ) |
+                (G2, TokArrayEnd   { .. } ) => {
                     // G2 -> ε
                     None
                 },
@@ -764,12 +1055,9 @@ impl<'a> Parser<'a> {
                         // G2 -> ε =>
                         None
                     },
-                    _ => {
-                        error_panic!(cfg => ParserError::NoProjection{token: TokLogOp{location: location.clone(), op_name: op.clone()}, stack: G2});
-                        None
-                    },
+                    _ => report(ParserError::NoProjection{token: TokLogOp{location: location.clone(), op_name: op.clone()}, stack: G2}),
                 },
-                (G2, tok) => { error_panic!(cfg => ParserError::NoProjection{token: tok, stack: G2}); None },
+                (G2, tok) => report(ParserError::NoProjection{token: tok, stack: G2}),
 
                 // H
                 (H, TokNumOp { location, op_name: op }) =>  match &*op {
@@ -779,7 +1067,7 @@ impl<'a> Parser<'a> {
 
                         Some(AddChild(TokUnaryMinus{location: location}))
                     }
-                    _ => None
+                    _ => report(ParserError::NoProjection{token: TokNumOp{location: location.clone(), op_name: op.clone()}, stack: H})
                 },
                 (H, TokLogOp { location, op_name: op }) =>  match &*op {
                     "not" | "!" => {
@@ -788,9 +1076,10 @@ impl<'a> Parser<'a> {
 
                         Some(AddChild(TokLogOp{ location: location, op_name: op }))
                     }
-                    _ => None
+                    _ => report(ParserError::NoProjection{token: TokLogOp{location: location.clone(), op_name: op.clone()}, stack: H})
                 },
                 (H, TokInt     { .. } ) |
+                (H, TokFloat   { .. } ) |
                 (H, TokString  { .. } ) |
                 (H, TokBoolean { .. } ) => {
                     stack.push(NonTerminal(DataType));
@@ -798,9 +1087,17 @@ impl<'a> Parser<'a> {
                     None
                 },
                 (H, tok @ TokVariable { .. } ) => {
+                    stack.push(NonTerminal(VariableEnd));
+                    stack.push(NonTerminal(Variablef));
                     stack.push(Terminal(tok.clone()));
 
-                    Some(AddChild(tok))
+                    Some(ChildDown(tok))
+                },
+                (H, tok @ TokArrayStart { .. } ) => {
+                    stack.push(NonTerminal(Arrayf));
+                    stack.push(Terminal(tok.clone()));
+
+                    Some(ChildDown(tok))
                 },
                 (H, TokFunction { .. } ) => {
                     stack.push(NonTerminal(Function));
@@ -815,7 +1112,50 @@ impl<'a> Parser<'a> {
                     None
                 },
 
+                // Variablef
+                (Variablef, tok @ TokArrayStart { .. } ) => {
+                    stack.push(Terminal(TokArrayEnd {location: (0, 0)} ));
+                    stack.push(NonTerminal(Expression));
+                    stack.push(Terminal(tok));
+
+                    None
+                },
+                (Variablef, tok @ TokProperty { .. } ) => {
+                    stack.push(Terminal(tok.clone()));
+
+                    Some(AddChild(tok))
+                },
+                (Variablef, TokMethod { .. } ) => {
+                    stack.push(NonTerminal(Function));
+
+                    None
+                },
+                (Variablef, _) => {
+                    // Variablef -> ε
+                    None
+                },
+
+                // VariableEnd
+                (VariableEnd, _) => {
+                    // leave the variable with its element, property or method
+                    Some(Up)
+                },
+
+                // Arrayf
+                (Arrayf, tok @ TokArrayEnd { .. } ) => {
+                    stack.push(Terminal(tok));
+
+                    Some(Up)
+                },
+                (Arrayf, _) => {
+                    stack.push(Terminal(TokArrayEnd {location: (0, 0)} ));
+                    stack.push(NonTerminal(Arguments));
+
+                    None
+                },
+
                 // Function
+                (Function, tok @ TokMethod { .. } ) |
                 (Function, tok @ TokFunction { .. } ) => {
                     stack.push(NonTerminal(Functionf));
                     stack.push(Terminal(tok.clone()));
@@ -827,13 +1167,16 @@ impl<'a> Parser<'a> {
                 (Functionf, tok @ TokArgsEnd { .. } ) => {
                     stack.push(Terminal(tok));
 
-                    None
+                    // leave the function without arguments
+                    Some(Up)
                 },
                 (Functionf, TokVariable { .. } ) |
                 (Functionf, TokInt      { .. } ) |
+                (Functionf, TokFloat    { .. } ) |
                 (Functionf, TokString   { .. } ) |
                 (Functionf, TokBoolean  { .. } ) |
-                (Functionf, TokFunction { .. } ) => {
+                (Functionf, TokFunction { .. } ) |
+                (Functionf, TokArrayStart { .. } ) => {
                     stack.push(Terminal(TokArgsEnd {location: (0, 0)} ));
                     stack.push(NonTerminal(Arguments));
 
@@ -843,9 +1186,11 @@ impl<'a> Parser<'a> {
                 // Arguments
                 (Arguments, TokVariable { .. } ) |
                 (Arguments, TokInt      { .. } ) |
+                (Arguments, TokFloat    { .. } ) |
                 (Arguments, TokString   { .. } ) |
                 (Arguments, TokBoolean  { .. } ) |
-                (Arguments, TokFunction { .. } ) => {
+                (Arguments, TokFunction { .. } ) |
+                (Arguments, TokArrayStart { .. } ) => {
                     stack.push(NonTerminal(Argumentsf));
                     stack.push(NonTerminal(Expression));
 
@@ -853,6 +1198,7 @@ impl<'a> Parser<'a> {
                 },
 
                 // Argumentsf
+                (Argumentsf, TokArrayEnd { .. } ) |
                 (Argumentsf, TokArgsEnd { .. } ) => {
                     // Argumentsf -> ε
 
@@ -869,6 +1215,39 @@ impl<'a> Parser<'a> {
                     None
                 },
 
+                // AssignList
+                (AssignList, TokAssign { .. } ) => {
+                    stack.push(NonTerminal(AssignListf));
+                    stack.push(NonTerminal(AssignVariable));
+
+                    None
+                },
+
+                (AssignList, tok @ TokVariable { .. } ) => {
+                    // an element of an array or a method like $inv.push("key")
+                    stack.push(NonTerminal(AssignListf));
+                    stack.push(NonTerminal(AssignElement));
+                    stack.push(NonTerminal(Variablef));
+                    stack.push(Terminal(tok.clone()));
+
+                    Some(ChildDown(tok))
+                },
+
+                // AssignListf
+                (AssignListf, tok @ TokSemiColon { .. } ) |
+                (AssignListf, tok @ TokColon     { .. } ) => {
+                    stack.push(NonTerminal(AssignList));
+                    stack.push(Terminal(tok));
+
+                    // leave the previous assignment
+                    Some(Up)
+                },
+                (AssignListf, _) => {
+                    // AssignListf -> ε
+                    debug!("pop AssignListf -> ε");
+                    Some(Up)
+                },
+
                 // AssignVariable
                 (AssignVariable, tok @ TokAssign { .. } ) => {
                     stack.push(NonTerminal(E));
@@ -877,12 +1256,30 @@ impl<'a> Parser<'a> {
                     Some(ChildDown(tok))
                 },
 
+                // AssignElement
+                (AssignElement, tok @ TokAssign { .. } ) => {
+                    stack.push(NonTerminal(VariableEnd));
+                    stack.push(NonTerminal(E));
+                    stack.push(Terminal(tok.clone()));
+
+                    Some(ChildDown(tok))
+                },
+                (AssignElement, _) => {
+                    // AssignElement -> ε
+                    None
+                },
+
                 // DataType
                 (DataType, tok @ TokInt { .. } ) => {
                     stack.push(Terminal(tok.clone()));
 
                     Some(AddChild(tok))
                 },
+                (DataType, tok @ TokFloat { .. } ) => {
+                    stack.push(Terminal(tok.clone()));
+
+                    Some(AddChild(tok))
+                },
                 (DataType, tok @ TokString { .. } ) => {
                     stack.push(Terminal(tok.clone()));
 
@@ -894,8 +1291,7 @@ impl<'a> Parser<'a> {
                     Some(AddChild(tok))
                 },
                 (x, tok) => {
-                    error_panic!(cfg => ParserError::NoProjection{token: tok, stack: x});
-                    None
+                    report(ParserError::NoProjection{token: tok, stack: x})
                 }
             }
 
@@ -910,8 +1306,7 @@ impl<'a> Parser<'a> {
                     None
                 },
                 _ => {
-                    error_panic!(cfg => ParserError::NonTerminalEnd{stack: top});
-                    None
+                    report(ParserError::NonTerminalEnd{stack: top})
                 }
             }
         }
@@ -7,6 +7,9 @@ rustlex! TweeLexer {
     property format_sub_open:bool = false;
     property format_sup_open:bool = false;
     property function_parens:usize = 0;
+    property metadata:String = String::new();
+    property metadata_depth:usize = 0;
+    property metadata_location:(u64, u64) = (0, 0);
 
     // Regular Expressions
     let WHITESPACE = ' ' | '\t';
@@ -26,14 +29,26 @@ rustlex! TweeLexer {
     let TEXT_MONO = TEXT_MONO_CHAR+ | "}" | "}}";
 
     let PASSAGE_START = "::" ':'*;
-    let PASSAGE_CHAR_NORMAL = [^"[]$<>:|" '\n'];
-    let PASSAGE_CHAR = PASSAGE_CHAR_NORMAL | ':' PASSAGE_CHAR_NORMAL;
-    let PASSAGE_NAME = PASSAGE_CHAR_NORMAL PASSAGE_CHAR* ':'?;
+    let PASSAGE_CHAR_NORMAL = [^"[]$<>:|{" '\n'];
+    // twee 3 escapes the characters that start the tags and the metadata
+    let PASSAGE_CHAR_ESCAPED = '\\' ["[]{}"];
+    let PASSAGE_CHAR = PASSAGE_CHAR_NORMAL | PASSAGE_CHAR_ESCAPED | ':' PASSAGE_CHAR_NORMAL;
+    let PASSAGE_NAME = (PASSAGE_CHAR_NORMAL | PASSAGE_CHAR_ESCAPED) PASSAGE_CHAR* ':'?;
 
     let TAG = ['a'-'z''A'-'Z''0'-'9''.''_']+;
     let TAG_START = '[';
     let TAG_END = ']';
 
+    // the json object of twee 3 at the end of the header, e.g.
+    // {"position":"100,200"}, the braces are counted in METADATA
+    let METADATA_START = WHITESPACE* '{';
+    let METADATA_OPEN = '{';
+    let METADATA_CLOSE = '}';
+    let METADATA_STRING = '"' ([^'\\''"''\n']|'\\'[^'\n'])* '"';
+    let METADATA_TEXT = [^"{}\"" '\n']+ | '"';
+    let METADATA_END = WHITESPACE* NEWLINE;
+    let METADATA_TRAILING_TEXT = WHITESPACE* [^" "'\t''\n'] [^'\n']*;
+
     let FORMAT_ITALIC = "//";
     let FORMAT_BOLD = "''";
     let FORMAT_UNDER = "__";
@@ -65,7 +80,7 @@ rustlex! TweeLexer {
     let VAR_NAME = '$' (LETTER | UNDERSCORE) VAR_CHAR*;
 
     let INT = /*"-"?*/ DIGIT+;
-    let FLOAT = "-"? (DIGIT+ "." DIGIT*) | "-"? (DIGIT* "." DIGIT+) | "-"? "Infinity";
+    let FLOAT = /*"-"?*/ (DIGIT+ "." DIGIT*) | (DIGIT* "." DIGIT+) | "Infinity";
 
     let STRING = '"' ([^'\\''"']|'\\'.)* '"' | "'" ([^'\\'"'"]|'\\'.)* "'";
 
@@ -74,10 +89,20 @@ rustlex! TweeLexer {
     let COLON = ',';
 
     let FUNCTION_NAME = (LETTER | UNDERSCORE) VAR_CHAR*;
-    let FUNCTION = FUNCTION_NAME '(';
+    // Math.round(), Math.floor() and Math.ceil() are single functions
+    let FUNCTION = ("Math.")? FUNCTION_NAME '(';
+
+    // the elements, properties and methods of arrays, e.g. $inv[0], $inv.length
+    // and $inv.push("key")
+    let ARRAY_START = '[';
+    let ARRAY_END = ']';
+    let PROPERTY = '.' FUNCTION_NAME;
+    let METHOD = '.' FUNCTION_NAME '(';
 
     let MACRO_NAME = [^" >"'\n']* ( WHITESPACE+ "if")?;
-    let MACRO_DISPLAY_PASSAGE_NAME = [^'"''>'' ''\t''\n'] ([^">"]*(">"[^">"])?)* [^'"''>'' ''\t''\n'] | [^"'>"' ''\t''\n'] ([^">"]*(">"[^">"])?)* [^"'>"' ''\t''\n'];
+    // a passage name without quotes, names that start with a variable or a
+    // parenthesis are expressions and have to be quoted
+    let MACRO_DISPLAY_PASSAGE_NAME = [^'"''>'' ''\t''\n''$''('] ([^">"]*(">"[^">"])?)* [^'"''>'' ''\t''\n'] | [^"'>"' ''\t''\n''$''('] ([^">"]*(">"[^">"])?)* [^"'>"' ''\t''\n'];
 
     let ASSIGN = "=" | "to" | "+=" | "-=" | "*=" | "/=";
     let SEMI_COLON = ';';
@@ -89,8 +114,12 @@ rustlex! TweeLexer {
     let LINK_CLOSE = ']';
     let LINK_TEXT = [^'\n'"|[]"]+;
 
-    let LINK_SIMPLE = "[[" (PASSAGE_NAME | VAR_NAME) "]";
-    let LINK_LABELED = "[[" LINK_TEXT "|" (PASSAGE_NAME | VAR_NAME) "]";
+    // the names of the linked passages can contain braces
+    let LINK_PASSAGE_CHAR = PASSAGE_CHAR_NORMAL | '{';
+    let LINK_PASSAGE_NAME = LINK_PASSAGE_CHAR (LINK_PASSAGE_CHAR | ':' LINK_PASSAGE_CHAR)* ':'?;
+
+    let LINK_SIMPLE = "[[" (LINK_PASSAGE_NAME | VAR_NAME) "]";
+    let LINK_LABELED = "[[" LINK_TEXT "|" (LINK_PASSAGE_NAME | VAR_NAME) "]";
 
     let COMMENT = "/%" ([^"%"]*(("%")*[^"%/"])?)* ("%")* "%/";
 
@@ -200,14 +229,20 @@ rustlex! TweeLexer {
     I_EXPRESSION {
         VAR_NAME => |lexer:&mut TweeLexer<R>| Some(TokVariable{location: lexer.yylloc(), name: lexer.yystr()} )
         STRING =>   |lexer:&mut TweeLexer<R>| Some(TokString  {location: lexer.yylloc(), value: unescape(lexer.yystr())} )
-        FLOAT =>    |lexer:&mut TweeLexer<R>| Some(TokFloat   {location: lexer.yylloc(), value: lexer.yystr()[..].parse().unwrap()} )
-        INT =>        |lexer:&mut TweeLexer<R>| Some(TokInt       {location: lexer.yylloc(), value: lexer.yystr()[..].parse().unwrap()} )
+        FLOAT =>    |lexer:&mut TweeLexer<R>| Some(TokFloat   {location: lexer.yylloc(), value: lexer.yystr()[..].parse().unwrap_or(::std::f32::INFINITY)} )
+        INT =>      |lexer:&mut TweeLexer<R>| Some(TokInt     {location: lexer.yylloc(), value: lexer.yystr()[..].parse().unwrap_or(::std::i32::MAX)} )
         BOOL =>     |lexer:&mut TweeLexer<R>| Some(TokBoolean {location: lexer.yylloc(), value: lexer.yystr()} )
         NUM_OP =>   |lexer:&mut TweeLexer<R>| Some(TokNumOp   {location: lexer.yylloc(), op_name: lexer.yystr()} )
         COMP_OP =>  |lexer:&mut TweeLexer<R>| Some(TokCompOp  {location: lexer.yylloc(), op_name: lexer.yystr()} )
         LOG_OP =>   |lexer:&mut TweeLexer<R>| Some(TokLogOp   {location: lexer.yylloc(), op_name: lexer.yystr()} )
     }
 
+    I_ARRAY {
+        ARRAY_START => |lexer:&mut TweeLexer<R>| Some(TokArrayStart {location: lexer.yylloc()} )
+        ARRAY_END =>   |lexer:&mut TweeLexer<R>| Some(TokArrayEnd   {location: lexer.yylloc()} )
+        PROPERTY =>    |lexer:&mut TweeLexer<R>| Some(TokProperty   {location: lexer.yylloc(), name: lexer.yystr()[1..].to_string()} )
+    }
+
     I_EXPRESSION_SIMPLE {
         PAREN_OPEN => |lexer:&mut TweeLexer<R>| Some(TokParenOpen {location: lexer.yylloc()} )
         PAREN_CLOSE =>|lexer:&mut TweeLexer<R>| Some(TokParenClose{location: lexer.yylloc()} )
@@ -250,7 +285,14 @@ rustlex! TweeLexer {
     }
 
     PASSAGE {
-        PASSAGE_NAME => |lexer:&mut TweeLexer<R>| Some(TokPassage {name: lexer.yystr().trim().to_string(), location: lexer.yylloc()} )
+        PASSAGE_NAME => |lexer:&mut TweeLexer<R>| Some(TokPassage {name: unescape_passage_name(lexer.yystr().trim()), location: lexer.yylloc()} )
+        METADATA_START => |lexer:&mut TweeLexer<R>| -> Option<Token> {
+            lexer.metadata = "{".to_string();
+            lexer.metadata_depth = 1;
+            lexer.metadata_location = lexer.yylloc();
+            lexer.METADATA();
+            None
+        }
         TAG_START => |lexer:&mut TweeLexer<R>| {
             lexer.TAGS();
             Some(TokTagStart {location: lexer.yylloc()})
@@ -261,6 +303,49 @@ rustlex! TweeLexer {
         }
     }
 
+    METADATA {
+        METADATA_OPEN => |lexer:&mut TweeLexer<R>| -> Option<Token> {
+            lexer.metadata_depth += 1;
+            lexer.metadata.push('{');
+            None
+        }
+        METADATA_CLOSE => |lexer:&mut TweeLexer<R>| -> Option<Token> {
+            lexer.metadata_depth -= 1;
+            lexer.metadata.push('}');
+            if lexer.metadata_depth == 0 {
+                lexer.METADATA_AFTER();
+            }
+            None
+        }
+        // braces in strings are not counted
+        METADATA_STRING => |lexer:&mut TweeLexer<R>| -> Option<Token> {
+            let s = lexer.yystr();
+            lexer.metadata.push_str(&s);
+            None
+        }
+        METADATA_TEXT => |lexer:&mut TweeLexer<R>| -> Option<Token> {
+            let s = lexer.yystr();
+            lexer.metadata.push_str(&s);
+            None
+        }
+        NEWLINE => |lexer:&mut TweeLexer<R>| {
+            lexer.NEWLINE();
+            Some(TokError {location: lexer.metadata_location, message: "The metadata of the passage is missing a closing brace".to_string()} )
+        }
+    }
+
+    METADATA_AFTER {
+        METADATA_END => |lexer:&mut TweeLexer<R>| {
+            lexer.NEWLINE();
+            Some(TokPassageMetadata {location: lexer.metadata_location, metadata: lexer.metadata.clone()} )
+        }
+        // the metadata is still read at the end of the line
+        METADATA_TRAILING_TEXT => |lexer:&mut TweeLexer<R>| {
+            Some(TokError {location: lexer.yylloc(), message: "The metadata has to be at the end of the passage header, \
+                braces in the passage name are escaped as \\{ and \\}".to_string()} )
+        }
+    }
+
     TAGS {
         :I_IGNORE_WHITESPACE
         TAG => |lexer:&mut TweeLexer<R>| Some(TokTag {location: lexer.yylloc(), tag_name: lexer.yystr()} )
@@ -317,8 +402,9 @@ rustlex! TweeLexer {
                     Some(TokMacroPrint {location: lexer.yylloc()} )
                 },
                 "display" => {
+                    // the name is added by MACRO_CONTENT_DISPLAY if it is no expression
                     lexer.MACRO_CONTENT_DISPLAY();
-                    None
+                    Some(TokMacroDisplay {location: lexer.yylloc(), passage_name: String::new()} )
                 },
                 "silently" => {
                     lexer.MACRO_CONTENT();
@@ -328,6 +414,22 @@ rustlex! TweeLexer {
                     lexer.MACRO_CONTENT();
                     Some(TokMacroEndSilently {location: lexer.yylloc()} )
                 },
+                "restore" => {
+                    lexer.MACRO_CONTENT();
+                    Some(TokMacroRestore {location: lexer.yylloc()} )
+                },
+                "undo" => {
+                    lexer.MACRO_CONTENT();
+                    Some(TokMacroUndo {location: lexer.yylloc()} )
+                },
+                "save" => {
+                    lexer.MACRO_CONTENT();
+                    Some(TokMacroSave {location: lexer.yylloc()} )
+                },
+                "textinput" => {
+                    lexer.MACRO_CONTENT();
+                    Some(TokMacroTextInput {location: lexer.yylloc()} )
+                },
                 _ => {
                     lexer.MACRO_CONTENT_SHORT_DISPLAY();
                     Some(TokMacroDisplay {location: lexer.yylloc(), passage_name: replaced_string.to_string()} )
@@ -344,6 +446,7 @@ rustlex! TweeLexer {
     MACRO_CONTENT {
         :I_EXPRESSION
         :I_EXPRESSION_SIMPLE
+        :I_ARRAY
         :I_IGNORE_NEWLINE
         :I_IGNORE_WHITESPACE
         :I_EMIT_COLON
@@ -357,6 +460,13 @@ rustlex! TweeLexer {
             lexer.FUNCTION_ARGS();
             Some(TokFunction {location: lexer.yylloc(), name: name.clone()} )
         }
+        METHOD =>  |lexer:&mut TweeLexer<R>| {
+            let s =  lexer.yystr();
+            let name = s[1 .. s.len()-1].to_string();
+            lexer.function_parens = 1;
+            lexer.FUNCTION_ARGS();
+            Some(TokMethod {location: lexer.yylloc(), name: name} )
+        }
         // Expression Stuff End
 
         MACRO_END => |lexer:&mut TweeLexer<R>| {
@@ -377,6 +487,17 @@ rustlex! TweeLexer {
             Some(TokMacroDisplay {passage_name: unescape(lexer.yystr()), location: lexer.yylloc()})
         }
 
+        // a variable or a parenthesis start an expression, which is lexed
+        // like the argument of <<print>>
+        VAR_NAME => |lexer:&mut TweeLexer<R>| {
+            lexer.MACRO_CONTENT();
+            Some(TokVariable {location: lexer.yylloc(), name: lexer.yystr()} )
+        }
+        PAREN_OPEN => |lexer:&mut TweeLexer<R>| {
+            lexer.MACRO_CONTENT();
+            Some(TokParenOpen {location: lexer.yylloc()} )
+        }
+
         MACRO_END => |lexer:&mut TweeLexer<R>| {
             lexer.NON_NEWLINE();
             Some(TokMacroEnd {location: lexer.yylloc()} )
@@ -422,6 +543,7 @@ rustlex! TweeLexer {
 
     FUNCTION_ARGS {
         :I_EXPRESSION
+        :I_ARRAY
         :I_IGNORE_WHITESPACE
         :I_EMIT_COLON
 
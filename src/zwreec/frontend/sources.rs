@@ -0,0 +1,383 @@
+//! Combines the input files of a story.
+//!
+//! A story can be split across several Twee files, and Twine 1 stories list
+//! further files in their `StoryIncludes` passage, one file name per line.
+//! The files are joined to a single Twee source for the lexer. The locations
+//! of the diagnostics refer to that source, `Sources::locate` maps them back
+//! to the file and the line inside of it.
+//!
+//! # Example
+//!
+//! ```
+//! # extern crate zwreec;
+//! let cfg = zwreec::config::Config::default_config();
+//! let mut sources = zwreec::frontend::sources::Sources::new();
+//!
+//! sources.add(&cfg, "start.twee", b"::Start\nHello [[World]]").unwrap();
+//! sources.add(&cfg, "world.twee", b"::World\nHello World").unwrap();
+//!
+//! assert_eq!(sources.twee(), "::Start\nHello [[World]]\n::World\nHello World\n".to_string());
+//! ```
+
+use std::error::Error;
+use std::fs;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use config::{Config, InputFormat};
+use frontend::twine2;
+use utils::error::{Diagnostic, DiagnosticKind};
+
+
+/// An input file of the story
+struct SourceFile {
+    name: String,
+    /// the twee source, twine 2 stories are already converted
+    text: String,
+    /// the line of the combined source the file starts with
+    first_line: u64,
+    line_count: u64,
+    /// the html of a twine 2 story and the location in it of every line of
+    /// the twee
+    html: Option<(String, Vec<(u64, u64)>)>,
+}
+
+impl SourceFile {
+    /// maps a location in the file to the html of a twine 2 story, the
+    /// columns of the passage headers are those of `<tw-passagedata>`
+    fn original_location(&self, location: (u64, u64)) -> (u64, u64) {
+        let (line, column) = location;
+        match self.html {
+            Some((ref html, ref locations)) if line > 0 && line as usize <= locations.len() => {
+                let (html_line, html_column) = locations[line as usize - 1];
+                let is_header = self.text.lines().nth(line as usize - 1).map_or(false, |l| l.starts_with("::"));
+                if is_header || column == 0 {
+                    (html_line, html_column)
+                } else {
+                    // entities like `&amp;` are longer in the html
+                    let line_text = html.lines().nth(html_line as usize - 1).unwrap_or("");
+                    let rest = match line_text.char_indices().nth(html_column as usize - 1) {
+                        Some((index, _)) => &line_text[index..],
+                        None => "",
+                    };
+                    (html_line, html_column + twine2::escaped_length(rest, column - 1))
+                }
+            },
+            _ => location
+        }
+    }
+
+    /// the source the diagnostics show
+    fn original_text(&self) -> &str {
+        match self.html {
+            Some((ref html, _)) => &html[..],
+            None => &self.text[..]
+        }
+    }
+}
+
+/// The input files of a story
+pub struct Sources {
+    files: Vec<SourceFile>,
+    /// the start node of the first twine 2 story
+    pub start: Option<String>,
+}
+
+impl Sources {
+    pub fn new() -> Sources {
+        Sources {
+            files: Vec::new(),
+            start: None,
+        }
+    }
+
+    /// adds an input file, twine 2 stories are converted to twee (see
+    /// `Config::input_format`)
+    pub fn add(&mut self, cfg: &Config, name: &str, source: &[u8]) -> Result<(), Diagnostic> {
+        let mut text = String::from_utf8_lossy(source).into_owned();
+        let mut html: Option<(String, Vec<(u64, u64)>)> = None;
+
+        let is_twine2 = match cfg.input_format {
+            InputFormat::Auto => twine2::is_twine2(&text),
+            InputFormat::Twee => false,
+            InputFormat::Twine2 => true,
+        };
+        if is_twine2 {
+            let story = match twine2::parse(&text) {
+                Ok(story) => story,
+                Err(error) => return Err(error.with_source(name, &text))
+            };
+            let (twee, locations) = match story.to_twee_with_locations() {
+                Ok(converted) => converted,
+                Err(error) => return Err(error.with_source(name, &text))
+            };
+
+            // the start node of a twine 2 story doesn't have to be named Start
+            if self.start.is_none() {
+                self.start = story.start.clone();
+            }
+            html = Some((text, locations));
+            text = twee;
+        }
+
+        // the next file has to start on a new line
+        if !text.ends_with("\n") {
+            text.push_str("\n");
+        }
+
+        let first_line = match self.files.last() {
+            Some(file) => file.first_line + file.line_count,
+            None => 1
+        };
+        let line_count = text.chars().filter(|&c| c == '\n').count() as u64;
+        debug!("Added input {} with {} lines", name, line_count);
+
+        self.files.push(SourceFile {
+            name: name.to_string(),
+            text: text,
+            first_line: first_line,
+            line_count: line_count,
+            html: html,
+        });
+
+        Ok(())
+    }
+
+    /// adds the files listed in the `StoryIncludes` passages, the names are
+    /// relative to the file with the passage
+    ///
+    /// Included files can include further files, every file is only added
+    /// once, even if it is named by different paths. Text in front of the
+    /// first passage of an included file is an error, it would be added to
+    /// the last passage of the file before.
+    pub fn add_includes(&mut self, cfg: &Config) -> Vec<Diagnostic> {
+        let mut errors: Vec<Diagnostic> = Vec::new();
+        let mut added: Vec<PathBuf> = self.files.iter().map(|file| canonical_path(Path::new(&file.name))).collect();
+
+        let mut index = 0;
+        while index < self.files.len() {
+            let including = self.files[index].name.clone();
+            let directory = match Path::new(&including).parent() {
+                Some(directory) => directory.to_path_buf(),
+                None => Path::new("").to_path_buf(),
+            };
+
+            for (line, include) in story_includes(&self.files[index].text) {
+                let path = directory.join(&include);
+                let name = format!("{}", path.display());
+                let canonical = canonical_path(&path);
+                if added.contains(&canonical) {
+                    continue;
+                }
+                added.push(canonical);
+
+                let result = match read_file(&path) {
+                    Ok(source) => self.add(cfg, &name, &source),
+                    Err(why) => Err(Diagnostic::new(DiagnosticKind::Lexer,
+                        format!("Could not read included file {}: {}", name, why), (line, 1))
+                        .with_source(&including, &self.files[index].text)),
+                };
+                if let Err(error) = result {
+                    errors.push(error);
+                    continue;
+                }
+                info!("Included {} from {}", name, including);
+
+                let file = self.files.last().unwrap();
+                if let Some(text_line) = text_before_passages(&file.text) {
+                    let location = file.original_location((text_line, 1));
+                    errors.push(Diagnostic::new(DiagnosticKind::Lexer,
+                        format!("The included file {} has text before its first passage", name), location)
+                        .with_source(&file.name, file.original_text()));
+                }
+            }
+
+            index += 1;
+        }
+
+        errors
+    }
+
+    /// returns all files joined to one twee source
+    pub fn twee(&self) -> String {
+        let mut twee = String::new();
+        for file in &self.files {
+            twee.push_str(&file.text);
+        }
+
+        twee
+    }
+
+    /// maps the location of a diagnostic in the joined source to the file
+    /// and the line inside of it, and adds the file name and the source line
+    ///
+    /// The locations in twine 2 stories refer to the html, the text of a
+    /// passage to the `<tw-passagedata>` element it is in.
+    ///
+    /// Diagnostics that already have a file name are returned unchanged.
+    pub fn locate(&self, mut diagnostic: Diagnostic) -> Diagnostic {
+        if diagnostic.file_name.is_some() {
+            return diagnostic;
+        }
+
+        if let Some(ref mut note) = diagnostic.note {
+            if let Some(file) = self.file_of_line(note.location.0) {
+                note.location = file.original_location((note.location.0 - file.first_line + 1, note.location.1));
+                note.file_name = Some(file.name.clone());
+            }
+        }
+
+        let (line, column) = diagnostic.location;
+        match self.file_of_line(line) {
+            Some(file) => {
+                diagnostic.location = file.original_location((line - file.first_line + 1, column));
+                diagnostic.with_source(&file.name, file.original_text())
+            },
+            None => {
+                // errors that are not bound to a location belong to the first file
+                let name = match self.files.first() {
+                    Some(file) => file.name.clone(),
+                    None => "<input>".to_string(),
+                };
+                diagnostic.with_source(&name, "")
+            }
+        }
+    }
+
+    /// returns the file that contains the line of the joined source
+    fn file_of_line(&self, line: u64) -> Option<&SourceFile> {
+        if line == 0 {
+            return None;
+        }
+
+        self.files.iter().rev().find(|file| file.first_line <= line)
+    }
+}
+
+/// returns the file names in the `StoryIncludes` passage together with their
+/// line numbers
+fn story_includes(text: &str) -> Vec<(u64, String)> {
+    let mut includes: Vec<(u64, String)> = Vec::new();
+
+    let mut in_includes = false;
+    for (index, line) in text.lines().enumerate() {
+        if line.starts_with("::") {
+            // the name ends before the tags or the metadata
+            let name = line.trim_left_matches(':').split(|c: char| c == '[' || c == '{').next().unwrap_or("");
+            in_includes = name.trim() == "StoryIncludes";
+        } else if in_includes && !line.trim().is_empty() {
+            includes.push((index as u64 + 1, line.trim().to_string()));
+        }
+    }
+
+    includes
+}
+
+/// returns the line of the first text in front of the first passage
+fn text_before_passages(text: &str) -> Option<u64> {
+    for (index, line) in text.lines().enumerate() {
+        if line.starts_with("::") {
+            return None;
+        } else if !line.trim().is_empty() {
+            return Some(index as u64 + 1);
+        }
+    }
+
+    None
+}
+
+/// returns the absolute path without `.`, `..` and links, so a file is
+/// found under every name, or the path itself if the file doesn't exist
+fn canonical_path(path: &Path) -> PathBuf {
+    fs::canonicalize(path).unwrap_or(path.to_path_buf())
+}
+
+/// reads the whole file
+fn read_file(path: &Path) -> Result<Vec<u8>, String> {
+    let mut file = match File::open(path) {
+        Ok(file) => file,
+        Err(why) => return Err(Error::description(&why).to_string())
+    };
+
+    let mut source: Vec<u8> = Vec::new();
+    match file.read_to_end(&mut source) {
+        Ok(_) => Ok(source),
+        Err(why) => Err(Error::description(&why).to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use config::Config;
+    use utils::error::{Diagnostic, DiagnosticKind};
+
+    #[test]
+    fn locate_test() {
+        let cfg = Config::default_config();
+        let mut sources = Sources::new();
+        sources.add(&cfg, "first.twee", b"::Start\nText\n\n::Other\nText").unwrap();
+        sources.add(&cfg, "second.twee", b"::Other\nText\n").unwrap();
+
+        let error = sources.locate(Diagnostic::new(DiagnosticKind::AST, "Duplicate".to_string(), (6, 3))
+            .with_note("first defined".to_string(), (4, 3)));
+
+        assert_eq!(error.file_name, Some("second.twee".to_string()));
+        assert_eq!(error.location, (1, 3));
+        assert_eq!(error.source_line, Some("::Other".to_string()));
+
+        let note = error.note.unwrap();
+        assert_eq!(note.file_name, Some("first.twee".to_string()));
+        assert_eq!(note.location, (4, 3));
+    }
+
+    #[test]
+    fn locate_twine2_test() {
+        let cfg = Config::default_config();
+        let mut sources = Sources::new();
+        sources.add(&cfg, "story.html", b"<tw-storydata startnode=\"1\">\n\
+            <tw-passagedata pid=\"1\" name=\"Start\">Text\n[[Other]]</tw-passagedata>\n\
+            <tw-passagedata pid=\"2\" name=\"Start\">Text</tw-passagedata>\n</tw-storydata>").unwrap();
+
+        // the link is in the second line of the text, the duplicate passage
+        // points to its element
+        let error = sources.locate(Diagnostic::new(DiagnosticKind::Codegen, "Link".to_string(), (3, 1)));
+        assert_eq!(error.location, (3, 1));
+        assert_eq!(error.source_line, Some("[[Other]]</tw-passagedata>".to_string()));
+
+        let error = sources.locate(Diagnostic::new(DiagnosticKind::AST, "Duplicate".to_string(), (5, 3))
+            .with_note("first defined".to_string(), (1, 3)));
+        assert_eq!(error.location, (4, 1));
+        assert_eq!(error.note.unwrap().location, (2, 1));
+
+        let error = sources.locate(Diagnostic::new(DiagnosticKind::Lexer, "Text".to_string(), (2, 2)));
+        assert_eq!(error.location, (2, 39));
+    }
+
+    #[test]
+    fn locate_twine2_entities_test() {
+        let cfg = Config::default_config();
+        let mut sources = Sources::new();
+        sources.add(&cfg, "story.html", b"<tw-storydata startnode=\"1\">\n\
+            <tw-passagedata pid=\"1\" name=\"Start\">\n&lt;&lt;if &amp;&gt;&gt; [[Other]]</tw-passagedata>\n</tw-storydata>").unwrap();
+
+        // the link follows the `<<if &>>` in the twee, `&lt;&lt;if &amp;&gt;&gt;`
+        // in the html
+        let error = sources.locate(Diagnostic::new(DiagnosticKind::Codegen, "Link".to_string(), (3, 10)));
+        assert_eq!(error.location, (3, 26));
+    }
+
+    #[test]
+    fn text_before_passages_test() {
+        assert_eq!(super::text_before_passages("\n  \n::Start\nText"), None);
+        assert_eq!(super::text_before_passages("\nText\n::Start\nText"), Some(2));
+    }
+
+    #[test]
+    fn story_includes_test() {
+        let includes = super::story_includes("::Start\nText\n::StoryIncludes [tag]\nchapter1.twee\n\n  chapter2.twee \n::Other\nfile.twee");
+
+        assert_eq!(includes, vec![(4, "chapter1.twee".to_string()), (6, "chapter2.twee".to_string())]);
+    }
+}
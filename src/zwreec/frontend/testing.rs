@@ -0,0 +1,88 @@
+//! Helpers for the tests that compile a story and run the generated code
+//! with `zrun`.
+
+use std::io::Cursor;
+
+use backend::zcode::zfile::Zfile;
+use backend::zcode::zrun::Machine;
+use config::Config;
+use frontend::{codegen, lexer, parser};
+use frontend::ast::AST;
+use utils::error::Diagnostic;
+
+/// generates the zcode for the input and keeps the ops, so they can be run
+pub fn test_codegen(cfg: &Config, input: &str) -> (Zfile, Vec<Diagnostic>) {
+    let mut cursor: Cursor<Vec<u8>> = Cursor::new(input.to_string().into_bytes());
+    let tokens = lexer::lex(cfg, &mut cursor);
+    let parser = parser::Parser::new(cfg);
+    let ast = AST::build(parser.parse(tokens));
+    assert!(ast.errors().is_empty(), "{:?}", ast.errors());
+
+    codegen::generate_test_zfile(cfg, ast)
+}
+
+/// generates the zcode for an input without errors
+pub fn test_compile(cfg: &Config, input: &str) -> Zfile {
+    let (zfile, errors) = test_codegen(cfg, input);
+    assert!(errors.is_empty(), "{:?}", errors);
+    zfile
+}
+
+/// returns the messages of the errors the code generator reports
+pub fn test_errors(input: &str) -> Vec<String> {
+    let cfg = Config::default_config();
+    let (_, errors) = test_codegen(&cfg, input);
+    errors.into_iter().map(|error| error.message).collect()
+}
+
+/// runs the story with the typed keys and returns the printed text
+pub fn test_run_with_cfg(cfg: &Config, input: &str, keys: &str) -> String {
+    let zfile = test_compile(cfg, input);
+    let mut machine = Machine::new(&zfile);
+    machine.type_keys(keys);
+    machine.run();
+    machine.output
+}
+
+pub fn test_run(input: &str, keys: &str) -> String {
+    test_run_with_cfg(&Config::default_config(), input, keys)
+}
+
+/// checks that the output contains every expected text
+pub fn assert_output(output: &str, expected: &[&str]) {
+    for text in expected {
+        assert!(output.contains(text), "{:?} not in the output:\n{}", text, output);
+    }
+}
+
+/// a story whose start passage links to twelve passages
+pub fn many_links_story() -> String {
+    let mut input = "::Start\n".to_string();
+    for i in 1..13 {
+        input.push_str(&format!("[[P{}]]", i));
+    }
+    for i in 1..13 {
+        input.push_str(&format!("\n::P{}\nin p{};", i, i));
+    }
+    input
+}
+
+/// a story whose start passage links to itself the number of times
+pub fn start_links_story(count: usize) -> String {
+    let mut input = "::Start\n".to_string();
+    for _ in 0..count {
+        input.push_str("[[Start]]");
+    }
+    input
+}
+
+/// returns the row and column of the last printed text on a screen with
+/// the height, the first lines scroll out of the screen
+pub fn screen_position(output: &str, text: &str, height: usize) -> (u16, u16) {
+    let index = output.rfind(text).expect(output);
+    let lines = output.matches('\n').count();
+    let line = output[..index].matches('\n').count();
+    let row = ::std::cmp::min(lines + 1, height) - (lines - line);
+    let column = index - output[..index].rfind('\n').map_or(0, |i| i + 1) + 1;
+    (row as u16, column as u16)
+}
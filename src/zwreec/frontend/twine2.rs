@@ -0,0 +1,452 @@
+//! Reads stories in the HTML format of Twine 2.
+//!
+//! Twine 2 stores a story as a `<tw-storydata>` element with a
+//! `<tw-passagedata>` element for every passage, both in its archive files and
+//! in published stories:
+//!
+//! ```text
+//! <tw-storydata name="The Story" startnode="1">
+//!   <tw-passagedata pid="1" name="First" tags="nobr">Hello [[World]]</tw-passagedata>
+//!   <tw-passagedata pid="2" name="World">Hello &amp; Goodbye</tw-passagedata>
+//! </tw-storydata>
+//! ```
+//!
+//! The passages are converted to Twee, so they are processed by the same lexer,
+//! parser and ast as a Twee file.
+//!
+//! # Example
+//!
+//! ```
+//! let html = "<tw-storydata startnode=\"1\">\
+//!     <tw-passagedata pid=\"1\" name=\"First\">Hello World</tw-passagedata>\
+//!     </tw-storydata>";
+//!
+//! let story = zwreec::frontend::twine2::parse(html).unwrap();
+//! assert_eq!(story.start, Some("First".to_string()));
+//!
+//! let twee = story.to_twee().unwrap();
+//! assert_eq!(twee, "::First\nHello World\n\n".to_string());
+//! ```
+
+use std::char;
+
+use utils::error::{Diagnostic, DiagnosticKind};
+
+
+/// A passage of a Twine 2 story
+#[derive(Debug, Clone, PartialEq)]
+pub struct Passage {
+    /// the id used by the `startnode` of the story
+    pub pid: Option<String>,
+    pub name: String,
+    pub tags: Vec<String>,
+    /// the unescaped text of the passage
+    pub text: String,
+    /// the line and column of the `<tw-passagedata>` element
+    pub location: (u64, u64),
+    /// the line and column where the text starts
+    pub text_location: (u64, u64),
+}
+
+/// A Twine 2 story
+#[derive(Debug, Clone, PartialEq)]
+pub struct Story {
+    /// the name of the story
+    pub name: Option<String>,
+    /// the name of the passage given by the `startnode` of the story
+    pub start: Option<String>,
+    pub passages: Vec<Passage>,
+    /// the line and column of the `<tw-storydata>` element
+    pub location: (u64, u64),
+}
+
+impl Story {
+    /// returns the story as twee
+    ///
+    /// Twine 2 keeps the name of the story outside of the passages, so it is
+    /// added as `StoryTitle` if the story has no such passage.
+    pub fn to_twee(&self) -> Result<String, Diagnostic> {
+        self.to_twee_with_locations().map(|(twee, _)| twee)
+    }
+
+    /// returns the story as twee together with the location in the html of
+    /// the first column of every line of the twee
+    ///
+    /// Passages that can't be written as twee are an error: a line of the
+    /// text starting with `::` would start a new passage, and `[`, `]`, `{`
+    /// and `|` in names or tags would change the header or the links.
+    pub fn to_twee_with_locations(&self) -> Result<(String, Vec<(u64, u64)>), Diagnostic> {
+        let mut twee = String::new();
+        let mut locations: Vec<(u64, u64)> = Vec::new();
+        for passage in &self.passages {
+            let invalid_name = |name: &str| name.contains(|c: char| c == '[' || c == ']' || c == '{' || c == '|');
+            if invalid_name(&passage.name) {
+                return Err(Diagnostic::new(DiagnosticKind::Lexer,
+                    format!("The passage name '{}' contains one of the characters [ ] {{ |", passage.name), passage.location));
+            }
+            if let Some(tag) = passage.tags.iter().find(|tag| invalid_name(tag)) {
+                return Err(Diagnostic::new(DiagnosticKind::Lexer,
+                    format!("The tag '{}' contains one of the characters [ ] {{ |", tag), passage.location));
+            }
+
+            twee.push_str("::");
+            twee.push_str(&passage.name);
+            if !passage.tags.is_empty() {
+                twee.push_str(&format!(" [{}]", passage.tags.connect(" ")));
+            }
+            twee.push_str("\n");
+            locations.push(passage.location);
+
+            let (text_line, text_column) = passage.text_location;
+            for (i, line) in passage.text.split('\n').enumerate() {
+                let location = (text_line + i as u64, if i == 0 { text_column } else { 1 });
+                if line.starts_with("::") {
+                    return Err(Diagnostic::new(DiagnosticKind::Lexer,
+                        format!("The line starts with `::` in the text of the passage '{}'", passage.name), location));
+                }
+                twee.push_str(line);
+                twee.push_str("\n");
+                locations.push(location);
+            }
+
+            // the empty line belongs to the end of the passage
+            twee.push_str("\n");
+            let last = *locations.last().unwrap();
+            locations.push(last);
+        }
+
+        if let Some(ref name) = self.name {
+            if !self.passages.iter().any(|passage| passage.name == "StoryTitle") {
+                twee.push_str(&format!("::StoryTitle\n{}\n", name));
+                locations.push(self.location);
+                locations.push(self.location);
+            }
+        }
+
+        Ok((twee, locations))
+    }
+}
+
+/// An element of the html input
+struct Element<'a> {
+    /// the index of the start tag
+    start: usize,
+    /// the attributes inside of the start tag
+    attributes: &'a str,
+    /// the index of the content
+    content_start: usize,
+    /// the text between the start and the end tag
+    content: &'a str,
+    /// the index after the end tag
+    end: usize,
+    /// the end tag was found, otherwise the content reaches to the end of the input
+    closed: bool,
+}
+
+/// checks if the input contains a Twine 2 story
+pub fn is_twine2(source: &str) -> bool {
+    find_element(source, "tw-storydata", 0).is_some()
+}
+
+/// reads the first story of a Twine 2 archive or published story
+pub fn parse(source: &str) -> Result<Story, Diagnostic> {
+    let story_data = match find_element(source, "tw-storydata", 0) {
+        Some(element) => element,
+        None => return Err(Diagnostic::new(DiagnosticKind::Lexer,
+            "No <tw-storydata> element found in the Twine 2 story".to_string(), (0, 0))),
+    };
+    let name = attribute(story_data.attributes, "name");
+
+    // an archive contains all stories of the library
+    if find_element(source, "tw-storydata", story_data.end).is_some() {
+        warn!("The input contains more than one story, only the first one is compiled");
+    }
+
+    let mut passages: Vec<Passage> = Vec::new();
+    let mut pos = 0;
+    while let Some(element) = find_element(story_data.content, "tw-passagedata", pos) {
+        let location = line_column(source, story_data.content_start + element.start);
+        if !element.closed {
+            return Err(Diagnostic::new(DiagnosticKind::Lexer,
+                "Missing </tw-passagedata> at the end of the passage".to_string(), location));
+        }
+
+        let passage_name = match attribute(element.attributes, "name") {
+            Some(passage_name) => passage_name,
+            None => return Err(Diagnostic::new(DiagnosticKind::Lexer,
+                "Passage without a name".to_string(), location)),
+        };
+        let tags = match attribute(element.attributes, "tags") {
+            Some(tags) => tags.split(' ').filter(|tag| !tag.is_empty()).map(|tag| tag.to_string()).collect(),
+            None => Vec::new(),
+        };
+
+        passages.push(Passage {
+            pid: attribute(element.attributes, "pid"),
+            name: passage_name,
+            tags: tags,
+            text: unescape_html(element.content),
+            location: location,
+            text_location: line_column(source, story_data.content_start + element.content_start),
+        });
+        pos = element.end;
+    }
+
+    let start = match attribute(story_data.attributes, "startnode") {
+        Some(pid) => passages.iter().find(|passage| passage.pid.as_ref() == Some(&pid)).map(|passage| passage.name.clone()),
+        None => None,
+    };
+    debug!("Twine 2 story {:?} with {} passages, starting at {:?}", name, passages.len(), start);
+
+    Ok(Story {
+        name: name,
+        start: start,
+        passages: passages,
+        location: line_column(source, story_data.start),
+    })
+}
+
+/// replaces the html entities in the text by their characters, unknown
+/// entities are kept
+pub fn unescape_html(text: &str) -> String {
+    let mut result = String::new();
+    let mut rest = text;
+    while let Some(index) = rest.find('&') {
+        result.push_str(&rest[..index]);
+        rest = &rest[index..];
+
+        let decoded = match rest.find(';') {
+            Some(end) => decode_entity(&rest[1..end]).map(|c| (c, end)),
+            None => None,
+        };
+        match decoded {
+            Some((c, end)) => {
+                result.push(c);
+                rest = &rest[end + 1..];
+            },
+            None => {
+                result.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    result.push_str(rest);
+
+    result
+}
+
+/// returns how many characters of the html the first `count` characters of
+/// its unescaped text take, entities are decoded like `unescape_html` does
+pub fn escaped_length(html: &str, count: u64) -> u64 {
+    let mut length = 0;
+    let mut rest = html;
+    for _ in 0..count {
+        let entity = if rest.starts_with('&') {
+            rest.find(';').and_then(|end| decode_entity(&rest[1..end]).map(|_| end + 1))
+        } else {
+            None
+        };
+        let size = match entity {
+            Some(size) => size,
+            None => match rest.chars().next() {
+                Some(c) => c.len_utf8(),
+                None => break,
+            },
+        };
+        length += rest[..size].chars().count() as u64;
+        rest = &rest[size..];
+    }
+
+    length
+}
+
+/// returns the character of an html entity without `&` and `;`
+fn decode_entity(entity: &str) -> Option<char> {
+    match entity {
+        "amp" => Some('&'),
+        "lt" => Some('<'),
+        "gt" => Some('>'),
+        "quot" => Some('"'),
+        "apos" => Some('\''),
+        "nbsp" => Some(' '),
+        _ if entity.starts_with("#x") || entity.starts_with("#X") => {
+            u32::from_str_radix(&entity[2..], 16).ok().and_then(char::from_u32)
+        },
+        _ if entity.starts_with("#") => {
+            entity[1..].parse::<u32>().ok().and_then(char::from_u32)
+        },
+        _ => None
+    }
+}
+
+/// finds the first element `tag` starting at the index `from`
+fn find_element<'a>(source: &'a str, tag: &str, from: usize) -> Option<Element<'a>> {
+    let start_tag = format!("<{}", tag);
+    let end_tag = format!("</{}>", tag);
+
+    let mut pos = from;
+    while let Some(index) = source[pos..].find(&*start_tag) {
+        let start = pos + index;
+        let attributes_start = start + start_tag.len();
+        pos = attributes_start;
+
+        // the name of the tag has to end here, so that scripts mentioning
+        // the tag are skipped
+        match source[attributes_start..].chars().next() {
+            Some(c) if c == '>' || c.is_whitespace() => (),
+            _ => continue
+        }
+
+        let content_start = match source[attributes_start..].find('>') {
+            Some(i) => attributes_start + i + 1,
+            None => return None
+        };
+        let (content_end, end, closed) = match source[content_start..].find(&*end_tag) {
+            Some(i) => (content_start + i, content_start + i + end_tag.len(), true),
+            None => (source.len(), source.len(), false)
+        };
+
+        return Some(Element {
+            start: start,
+            attributes: &source[attributes_start..content_start - 1],
+            content_start: content_start,
+            content: &source[content_start..content_end],
+            end: end,
+            closed: closed,
+        });
+    }
+
+    None
+}
+
+/// returns the unescaped value of the attribute `name`
+fn attribute(attributes: &str, name: &str) -> Option<String> {
+    let mut rest = attributes;
+    loop {
+        rest = rest.trim_left();
+        if rest.is_empty() {
+            return None;
+        }
+
+        let name_end = rest.find(|c: char| c == '=' || c.is_whitespace()).unwrap_or(rest.len());
+        let attribute_name = &rest[..name_end];
+        rest = rest[name_end..].trim_left();
+
+        // attributes without a value are empty
+        let mut value = "";
+        if rest.starts_with("=") {
+            rest = rest[1..].trim_left();
+            match rest.chars().next() {
+                Some(quote) if quote == '"' || quote == '\'' => {
+                    let value_end = rest[1..].find(quote).map(|i| i + 1).unwrap_or(rest.len());
+                    value = &rest[1..value_end];
+                    rest = if value_end < rest.len() { &rest[value_end + 1..] } else { "" };
+                },
+                _ => {
+                    let value_end = rest.find(|c: char| c.is_whitespace()).unwrap_or(rest.len());
+                    value = &rest[..value_end];
+                    rest = &rest[value_end..];
+                }
+            }
+        }
+
+        if attribute_name == name {
+            return Some(unescape_html(value));
+        }
+    }
+}
+
+/// returns the line and the column of the index, like `Token::location()`
+fn line_column(source: &str, index: usize) -> (u64, u64) {
+    let before = &source[..index];
+    let line = before.chars().filter(|&c| c == '\n').count() + 1;
+    let column = match before.rfind('\n') {
+        Some(newline) => before[newline + 1..].chars().count() + 1,
+        None => before.chars().count() + 1,
+    };
+
+    (line as u64, column as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static STORY: &'static str = "<html><body>
+<tw-storydata name=\"The &quot;Story&quot;\" startnode=\"2\" format=\"SugarCube\">
+<style role=\"stylesheet\" id=\"twine-user-stylesheet\" type=\"text/twine-css\"></style>
+<tw-passagedata pid=\"1\" name=\"Other\" tags=\"\" position=\"100,100\">Back to [[the start|First Passage]]</tw-passagedata>
+<tw-passagedata pid=\"2\" name=\"First Passage\" tags=\"nobr  intro\" position=\"200,100\">&lt;&lt;set $a = 1&gt;&gt;
+Tom &amp; Jerry&#39;s [[Other]]</tw-passagedata>
+</tw-storydata>
+</body></html>";
+
+    #[test]
+    fn parse_test() {
+        let story = parse(STORY).unwrap();
+
+        assert_eq!(story.name, Some("The \"Story\"".to_string()));
+        assert_eq!(story.start, Some("First Passage".to_string()));
+        assert_eq!(story.passages.len(), 2);
+
+        assert_eq!(story.passages[0].name, "Other".to_string());
+        assert!(story.passages[0].tags.is_empty());
+        assert_eq!(story.passages[1].tags, vec!["nobr".to_string(), "intro".to_string()]);
+        assert_eq!(story.passages[1].text, "<<set $a = 1>>\nTom & Jerry's [[Other]]".to_string());
+    }
+
+    #[test]
+    fn to_twee_test() {
+        let story = parse(STORY).unwrap();
+
+        assert_eq!(story.to_twee().unwrap(), "::Other\nBack to [[the start|First Passage]]\n\n\
+            ::First Passage [nobr intro]\n<<set $a = 1>>\nTom & Jerry's [[Other]]\n\n\
+            ::StoryTitle\nThe \"Story\"\n".to_string());
+    }
+
+    #[test]
+    fn to_twee_errors_test() {
+        // a line starting with :: would be a new passage
+        let story = parse("<tw-storydata>\n<tw-passagedata name=\"Start\">Text\n::Other</tw-passagedata></tw-storydata>").unwrap();
+        let error = story.to_twee().unwrap_err();
+        assert_eq!(error.message, "The line starts with `::` in the text of the passage 'Start'".to_string());
+        assert_eq!(error.location, (3, 1));
+
+        let story = parse("<tw-storydata>\n<tw-passagedata name=\"A [b]\">Text</tw-passagedata></tw-storydata>").unwrap();
+        assert_eq!(story.to_twee().unwrap_err().location, (2, 1));
+
+        let story = parse("<tw-storydata><tw-passagedata name=\"A\" tags=\"x|y\">Text</tw-passagedata></tw-storydata>").unwrap();
+        assert!(story.to_twee().is_err());
+
+        // the text may contain :: elsewhere
+        let story = parse("<tw-storydata><tw-passagedata name=\"A\">Text ::\n ::</tw-passagedata></tw-storydata>").unwrap();
+        assert!(story.to_twee().is_ok());
+    }
+
+    #[test]
+    fn is_twine2_test() {
+        assert!(is_twine2(STORY));
+        assert!(!is_twine2("::Start\nHello World"));
+        assert!(!is_twine2("::Start\n<tw-storydataX>"));
+    }
+
+    #[test]
+    fn unescape_html_test() {
+        assert_eq!(unescape_html("a &lt;&lt;b&gt;&gt; &amp;amp; &#65;&#x42; & &unknown; c"),
+            "a <<b>> &amp; AB & &unknown; c".to_string());
+    }
+
+    #[test]
+    fn escaped_length_test() {
+        assert_eq!(escaped_length("a &lt;&lt;b", 4), 10);
+        assert_eq!(escaped_length("& &unknown; &#65;c", 13), 17);
+        assert_eq!(escaped_length("ab", 5), 2);
+    }
+
+    #[test]
+    fn missing_end_tag_test() {
+        let error = parse("<tw-storydata>\n<tw-passagedata name=\"Start\">Text</tw-storydata>").unwrap_err();
+
+        assert_eq!(error.location, (2, 1));
+    }
+}
@@ -72,7 +72,9 @@
 //!         Err(why) => { panic!("Couldn't open output: {}", Error::description(&why)); }
 //!     };
 //!
-//!     zwreec::compile(cfg, &mut input, &mut output);
+//!     if let Err(errors) = zwreec::compile(cfg, &mut input, &mut output) {
+//!         panic!("Compilation failed with {} errors", errors.len());
+//!     }
 //! }
 //! ```
 //!
@@ -109,7 +111,9 @@ pub mod frontend;
 pub mod backend;
 
 use config::{Config,TestCase};
-use std::io::{Read,Write};
+use utils::error::{CompileReport, Diagnostic, DiagnosticKind, DIAGNOSTIC_TARGET};
+use std::error::Error;
+use std::io::{Cursor,Read,Write};
 
 
 /// Compiles a Twee Input to Zcode
@@ -119,6 +123,20 @@ use std::io::{Read,Write};
 /// to generate a tokenstream, `frontend::parser` and `frontend::ast` to generate
 /// the Abstract Syntax Tree and lastly `frontend::codegen` to generate the Zcode.
 ///
+/// Twine 2 stories are converted to Twee by `frontend::twine2` first, either if
+/// `cfg.input_format` says so or if they are detected automatically. The
+/// diagnostics are mapped back by `frontend::sources` and point to the line
+/// and column in the html, entities like `&amp;` included.
+///
+/// Errors in the input do not abort the compilation. Every stage reports them
+/// as `utils::error::Diagnostic` and they are returned as `Err` once the chain
+/// is done. If `cfg.force` is set, the errors are ignored, the Zcode is written
+/// anyway and the errors are part of the returned `CompileReport`. Syntax errors
+/// and a missing start passage can't be ignored, the story is incomplete then.
+///
+/// Every diagnostic is also logged with the target `utils::error::DIAGNOSTIC_TARGET`,
+/// showing the affected line of the input and `cfg.input_name` as file name.
+///
 /// # Example
 ///
 /// ```no_run
@@ -133,24 +151,114 @@ use std::io::{Read,Write};
 /// let mut input = File::open(Path::new(&args[1])).unwrap();
 /// let mut output = File::create(Path::new("a.z8")).unwrap();
 ///
-/// zwreec::compile(cfg, &mut input, &mut output);
+/// match zwreec::compile(cfg, &mut input, &mut output) {
+///     Ok(report) => println!("Wrote {} bytes", report.bytes_written),
+///     Err(errors) => for error in errors.iter() { println!("{}", error); },
+/// }
 /// ```
-#[allow(unused_variables)]
-pub fn compile<R: Read, W: Write>(cfg: Config, input: &mut R, output: &mut W) {
+pub fn compile<R: Read, W: Write>(cfg: Config, input: &mut R, output: &mut W) -> Result<CompileReport, Vec<Diagnostic>> {
+    let file_name = cfg.input_name.clone().unwrap_or("<input>".to_string());
+    compile_inputs(cfg, vec![(file_name, input)], output)
+}
+
+/// Compiles a story that is split across several inputs to Zcode
+///
+/// Works like `compile`, but takes a list of inputs together with their file
+/// names. The passages of all inputs form one story. Files listed in the
+/// `StoryIncludes` passage are read relative to the input that lists them.
+/// The diagnostics point to the file and the line inside of it (see
+/// `frontend::sources`).
+///
+/// # Example
+///
+/// ```no_run
+/// # use std::fs::File;
+/// # use std::path::Path;
+/// let cfg = zwreec::config::Config::default_config();
+/// let inputs = vec![
+///     ("start.twee".to_string(), File::open(Path::new("start.twee")).unwrap()),
+///     ("chapter1.twee".to_string(), File::open(Path::new("chapter1.twee")).unwrap()),
+/// ];
+/// let mut output = File::create(Path::new("a.z8")).unwrap();
+///
+/// match zwreec::compile_inputs(cfg, inputs, &mut output) {
+///     Ok(report) => println!("Wrote {} bytes", report.bytes_written),
+///     Err(errors) => for error in errors.iter() { println!("{}", error); },
+/// }
+/// ```
+pub fn compile_inputs<R: Read, W: Write>(cfg: Config, inputs: Vec<(String, R)>, output: &mut W) -> Result<CompileReport, Vec<Diagnostic>> {
+    // keep the sources to show them in error messages
+    let mut sources = frontend::sources::Sources::new();
+    let mut errors: Vec<Diagnostic> = Vec::new();
+    for (file_name, mut input) in inputs {
+        let mut source: Vec<u8> = Vec::new();
+        if let Err(why) = input.read_to_end(&mut source) {
+            errors.push(Diagnostic::new(DiagnosticKind::Lexer,
+                format!("Could not read input: {}", Error::description(&why)), (0, 0))
+                .with_source(&file_name, ""));
+            continue;
+        }
+        if let Err(error) = sources.add(&cfg, &file_name, &source) {
+            errors.push(error);
+        }
+    }
+    if errors.is_empty() {
+        errors.extend(sources.add_includes(&cfg).into_iter());
+    }
+    if !errors.is_empty() {
+        for error in errors.iter() {
+            error!(target: DIAGNOSTIC_TARGET, "{}", error);
+        }
+        return Err(errors);
+    }
+
+    let start = sources.start.clone().unwrap_or("Start".to_string());
+    let mut cursor = Cursor::new(sources.twee().into_bytes());
+
     // tokenize
-    let tokens = frontend::lexer::lex(&cfg, input);
+    let tokens = frontend::lexer::lex(&cfg, &mut cursor);
 
     //create parser
     let parser = frontend::parser::Parser::new(&cfg);
 
     //build up ast from tokens
-    let ast = frontend::ast::AST::build(parser.parse(tokens.inspect(|ref token| {
+    let ast = frontend::ast::AST::build_with_start(parser.parse(tokens.inspect(|ref token| {
         debug!("{:?}", token);
-    })));
+    })), &start);
     ast.print(false);
 
+    let errors: Vec<Diagnostic> = ast.errors().iter()
+        .map(|error| sources.locate(error.clone()))
+        .collect();
+    for error in errors.iter() {
+        error!(target: DIAGNOSTIC_TARGET, "{}", error);
+    }
+    if !errors.is_empty() && !cfg.force {
+        return Err(errors);
+    }
+    if !ast.is_complete() {
+        // syntax errors leave unparsed expressions in the ast
+        warn!("The errors can't be ignored with --force, no Zcode is written");
+        return Err(errors);
+    }
+
     // create code
-    frontend::codegen::generate_zcode(&cfg, ast, output);
+    let result = frontend::codegen::generate_zcode(&cfg, ast, output);
+    let codegen_errors: Vec<Diagnostic> = match result {
+        Ok(ref report) => report.diagnostics.clone(),
+        Err(ref codegen_errors) => codegen_errors.clone(),
+    }.into_iter().map(|error| sources.locate(error)).collect();
+    for error in codegen_errors.iter() {
+        error!(target: DIAGNOSTIC_TARGET, "{}", error);
+    }
+
+    // errors of the earlier stages come first
+    let mut diagnostics = errors;
+    diagnostics.extend(codegen_errors.into_iter());
+    match result {
+        Ok(report) => Ok(CompileReport { bytes_written: report.bytes_written, diagnostics: diagnostics }),
+        Err(_) => Err(diagnostics),
+    }
 }
 
 /// Run internal library tests.
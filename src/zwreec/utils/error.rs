@@ -1,42 +1,298 @@
+//! Error reporting for the compiler chain.
+//!
+//! Every stage of the compiler reports problems with the input as a `Diagnostic`
+//! instead of aborting. The diagnostics are collected and returned by
+//! `zwreec::compile`.
+//!
+//! A `Diagnostic` is displayed similar to the errors of rustc:
+//!
+//! ```text
+//! parser error: expected `>>` to close `<<print>>`, found number `2`
+//!  --> story.twee:2:11
+//!   |
+//! 2 | <<print 1 2>>
+//!   |           ^
+//! ```
+
 use std::fmt::{Display, Formatter, Result, Write};
 
 use frontend::lexer::Token;
-use frontend::parser::ParserError;
-
-macro_rules! error_panic(
-    ($cfg:expr => $($arg:tt)+) => (
-        {
-            error!("{}", $($arg)*);
-            if !$cfg.force {
-                panic!()
-            }
+use frontend::lexer::Token::*;
+use frontend::parser::{ParserError, NonTerminalType};
+use frontend::parser::NonTerminalType::*;
+
+/// The log target used by `zwreec::compile` to log diagnostics.
+///
+/// Loggers can use it to highlight the source snippets.
+pub const DIAGNOSTIC_TARGET: &'static str = "zwreec::diagnostic";
+
+/// The compiler stage that reported a `Diagnostic`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum DiagnosticKind {
+    /// The input could not be split into tokens
+    Lexer,
+    /// The token stream does not match the twee grammar
+    Parser,
+    /// The syntax tree is not a valid story (e.g. duplicate passages)
+    AST,
+    /// The story uses a feature the code generator can not translate
+    Codegen,
+}
+
+/// A single error found in the compiled story.
+///
+/// Stores the stage that found the error, a human readable message and the
+/// location inside the twee input as `(line, column)` (see `Token::location()`).
+/// A location of `(0, 0)` means the error is not bound to a specific token.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub kind: DiagnosticKind,
+    pub message: String,
+    pub location: (u64, u64),
+    /// Name of the input file (see `Config::input_name`)
+    pub file_name: Option<String>,
+    /// The line of the input the location points to
+    pub source_line: Option<String>,
+    /// Another location that belongs to the error
+    pub note: Option<Note>,
+}
+
+/// A second location of a `Diagnostic`, e.g. the first definition of a
+/// duplicate passage.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Note {
+    pub message: String,
+    pub location: (u64, u64),
+    /// Name of the input file, if it differs from the one of the diagnostic
+    pub file_name: Option<String>,
+}
+
+impl Diagnostic {
+    pub fn new(kind: DiagnosticKind, message: String, location: (u64, u64)) -> Diagnostic {
+        Diagnostic {
+            kind: kind,
+            message: message,
+            location: location,
+            file_name: None,
+            source_line: None,
+            note: None,
         }
-    )
-);
+    }
 
-impl Display for Token {
+    /// Adds a note pointing to another location of the input.
+    pub fn with_note(mut self, message: String, location: (u64, u64)) -> Diagnostic {
+        self.note = Some(Note {
+            message: message,
+            location: location,
+            file_name: None,
+        });
+        self
+    }
+
+    /// Creates a `Diagnostic` for the given token, using its location.
+    pub fn from_token(kind: DiagnosticKind, message: String, token: &Token) -> Diagnostic {
+        Diagnostic::new(kind, message, token.location())
+    }
+
+    /// Adds the file name and the affected line of the input, which are
+    /// shown when the diagnostic is displayed.
+    pub fn with_source(mut self, file_name: &str, source: &str) -> Diagnostic {
+        let (line, _) = self.location;
+        self.file_name = Some(file_name.to_string());
+        if line > 0 {
+            self.source_line = source.lines().nth(line as usize - 1).map(|l| l.to_string());
+        }
+        self
+    }
+}
+
+/// Summary of a successful compilation, returned by `zwreec::compile`.
+#[derive(Debug)]
+pub struct CompileReport {
+    /// Number of bytes written to the output
+    pub bytes_written: usize,
+    /// Errors that were ignored because `Config::force` was set
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl Display for DiagnosticKind {
     fn fmt(&self, f: &mut Formatter) -> Result {
-        match self {
-            &Token::TokError{ref message, ..} => try!(f.write_str(&*message)),
-            _ => try!(f.write_fmt(format_args!("{:?}", self))),
+        f.write_str(match self {
+            &DiagnosticKind::Lexer => "lexer",
+            &DiagnosticKind::Parser => "parser",
+            &DiagnosticKind::AST => "ast",
+            &DiagnosticKind::Codegen => "codegen",
+        })
+    }
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut Formatter) -> Result {
+        let (line, column) = self.location;
+        let file_name = match self.file_name {
+            Some(ref name) => &**name,
+            None => "<input>",
         };
+
+        try!(f.write_fmt(format_args!("{} error: {}", self.kind, self.message)));
+        if line == 0 {
+            return f.write_fmt(format_args!("\n --> {}", file_name));
+        }
+
+        let gutter = format!("{}", line).len();
+        try!(f.write_fmt(format_args!("\n{0:1$}--> {2}:{3}:{4}", "", gutter, file_name, line, column)));
+
+        if let Some(ref source_line) = self.source_line {
+            // keep tabs so the caret lines up with the source
+            let indent: String = source_line.chars()
+                .take(column.saturating_sub(1) as usize)
+                .map(|c| if c == '\t' { '\t' } else { ' ' })
+                .collect();
+
+            try!(f.write_fmt(format_args!("\n{0:1$} |", "", gutter)));
+            try!(f.write_fmt(format_args!("\n{} | {}", line, source_line)));
+            try!(f.write_fmt(format_args!("\n{0:1$} | {2}^", "", gutter, indent)));
+        }
+
+        if let Some(ref note) = self.note {
+            let note_file_name = match note.file_name {
+                Some(ref name) => &**name,
+                None => file_name,
+            };
+            try!(f.write_fmt(format_args!("\n{0:1$} = note: {2} at {3}:{4}:{5}", "", gutter,
+                note.message, note_file_name, note.location.0, note.location.1)));
+        }
         Ok(())
     }
 }
 
+/// Describes the token in plain words, e.g. "`<<endif>>`" or "number `2`".
+impl Display for Token {
+    fn fmt(&self, f: &mut Formatter) -> Result {
+        match self {
+            &TokPassage{ref name, ..} => f.write_fmt(format_args!("passage `::{}`", name)),
+            &TokTagStart{..} => f.write_str("`[`"),
+            &TokTagEnd{..} => f.write_str("`]`"),
+            &TokTag{ref tag_name, ..} => f.write_fmt(format_args!("tag `{}`", tag_name)),
+            &TokPassageMetadata{..} => f.write_str("passage metadata"),
+            &TokVarSetStart{..} | &TokVarSetEnd{..} => f.write_str("variable assignment"),
+            &TokPassageLink{ref passage_name, ..} => f.write_fmt(format_args!("link to `{}`", passage_name)),
+            &TokText{..} => f.write_str("text"),
+            &TokFormatBoldStart{..} | &TokFormatBoldEnd{..} => f.write_str("`''`"),
+            &TokFormatItalicStart{..} | &TokFormatItalicEnd{..} => f.write_str("`//`"),
+            &TokFormatUnderStart{..} | &TokFormatUnderEnd{..} => f.write_str("`__`"),
+            &TokFormatStrikeStart{..} | &TokFormatStrikeEnd{..} => f.write_str("`==`"),
+            &TokFormatSubStart{..} | &TokFormatSubEnd{..} => f.write_str("`~~`"),
+            &TokFormatSupStart{..} | &TokFormatSupEnd{..} => f.write_str("`^^`"),
+            &TokFormatMonoStart{..} => f.write_str("`{{{`"),
+            &TokFormatMonoEnd{..} => f.write_str("`}}}`"),
+            &TokFormatBulList{..} => f.write_str("list item `*`"),
+            &TokFormatNumbList{..} => f.write_str("list item `#`"),
+            &TokFormatIndentBlock{..} => f.write_str("`<<<`"),
+            &TokFormatHorizontalLine{..} => f.write_str("`----`"),
+            &TokFormatHeading{..} => f.write_str("heading"),
+            &TokMacroStart{..} => f.write_str("`<<`"),
+            &TokMacroEnd{..} => f.write_str("`>>`"),
+            &TokMacroContentVar{ref var_name, ..} => f.write_fmt(format_args!("`<<{}>>`", var_name)),
+            &TokMacroSet{..} => f.write_str("`<<set>>`"),
+            &TokMacroIf{..} => f.write_str("`<<if>>`"),
+            &TokMacroElse{..} => f.write_str("`<<else>>`"),
+            &TokMacroElseIf{..} => f.write_str("`<<else if>>`"),
+            &TokMacroEndIf{..} => f.write_str("`<<endif>>`"),
+            &TokMacroPrint{..} => f.write_str("`<<print>>`"),
+            &TokMacroDisplay{..} => f.write_str("`<<display>>`"),
+            &TokMacroSilently{..} => f.write_str("`<<silently>>`"),
+            &TokMacroEndSilently{..} => f.write_str("`<<endsilently>>`"),
+            &TokMacroRestore{..} => f.write_str("`<<restore>>`"),
+            &TokMacroUndo{..} => f.write_str("`<<undo>>`"),
+            &TokMacroTextInput{..} => f.write_str("`<<textinput>>`"),
+            &TokMacroSave{..} => f.write_str("`<<save>>`"),
+            &TokParenOpen{..} => f.write_str("`(`"),
+            &TokParenClose{..} | &TokArgsEnd{..} => f.write_str("`)`"),
+            &TokVariable{ref name, ..} => f.write_fmt(format_args!("variable `{}`", name)),
+            &TokInt{value, ..} => f.write_fmt(format_args!("number `{}`", value)),
+            &TokFloat{value, ..} => f.write_fmt(format_args!("number `{}`", value)),
+            &TokString{ref value, ..} => f.write_fmt(format_args!("string \"{}\"", value)),
+            &TokBoolean{ref value, ..} => f.write_fmt(format_args!("`{}`", value)),
+            &TokFunction{ref name, ..} => f.write_fmt(format_args!("function `{}()`", name)),
+            &TokColon{..} => f.write_str("`,`"),
+            &TokArrayStart{..} => f.write_str("`[`"),
+            &TokArrayEnd{..} => f.write_str("`]`"),
+            &TokProperty{ref name, ..} => f.write_fmt(format_args!("property `.{}`", name)),
+            &TokMethod{ref name, ..} => f.write_fmt(format_args!("method `.{}()`", name)),
+            &TokAssign{ref op_name, ..} => f.write_fmt(format_args!("assignment `{}`", op_name)),
+            &TokNumOp{ref op_name, ..} |
+            &TokCompOp{ref op_name, ..} |
+            &TokLogOp{ref op_name, ..} => f.write_fmt(format_args!("operator `{}`", op_name)),
+            &TokSemiColon{..} => f.write_str("`;`"),
+            &TokNewLine{..} => f.write_str("end of line"),
+            &TokUnaryMinus{..} => f.write_str("`-`"),
+            &TokExpression => f.write_str("expression"),
+            &TokError{ref message, ..} => f.write_str(&*message),
+        }
+    }
+}
+
+/// Describes what the parser expected when it was at the nonterminal.
+impl Display for NonTerminalType {
+    fn fmt(&self, f: &mut Formatter) -> Result {
+        f.write_str(match self {
+            &S | &Sf | &Passage => "a passage (`::Name`)",
+            &Passagef | &PassageContent => "passage content",
+            &Tags | &Tagsf => "a tag",
+            &Metadata => "passage metadata",
+            &Formating | &BoldFormatting | &ItalicFormatting | &MonoFormatting | &MonoContent |
+            &UnderFormatting | &StrikeFormatting | &SupFormatting | &SubFormatting => "formatted text",
+            &Link => "a link",
+            &Macro => "a macro",
+            &ElseIf | &EndIf => "`<<endif>>`",
+            &Function | &Functionf | &Arguments | &Argumentsf => "function arguments",
+            &AssignVariable | &AssignList | &AssignElement => "an assignment",
+            &Arrayf => "array elements",
+            &H | &DataType => "a value",
+            _ => "an expression",
+        })
+    }
+}
+
+/// Describes which construct is closed by the expected terminal.
+fn closes(expected: &Token, open_macro: &Option<Token>) -> String {
+    match (expected, open_macro) {
+        (&TokMacroEnd{..}, &Some(ref open)) => format!(" to close {}", open),
+        (&TokMacroEndIf{..}, _) => " to close `<<if>>`".to_string(),
+        (&TokMacroEndSilently{..}, _) => " to close `<<silently>>`".to_string(),
+        (&TokFormatBoldEnd{..}, _) => " to close bold text".to_string(),
+        (&TokFormatItalicEnd{..}, _) => " to close italic text".to_string(),
+        (&TokFormatMonoEnd{..}, _) => " to close monospace text".to_string(),
+        (&TokFormatUnderEnd{..}, _) => " to close underlined text".to_string(),
+        (&TokFormatStrikeEnd{..}, _) => " to close strikethrough text".to_string(),
+        (&TokFormatSupEnd{..}, _) => " to close superscript text".to_string(),
+        (&TokFormatSubEnd{..}, _) => " to close subscript text".to_string(),
+        (&TokParenClose{..}, _) => " to close `(`".to_string(),
+        (&TokArgsEnd{..}, _) => " to close the function arguments".to_string(),
+        (&TokArrayEnd{..}, _) => " to close `[`".to_string(),
+        (&TokTagEnd{..}, _) => " to close the tag list".to_string(),
+        _ => String::new(),
+    }
+}
+
 impl Display for ParserError {
     fn fmt(&self, f: &mut Formatter) -> Result{
-        try!(f.write_str("[!!!] Critical Parser Error [!!!]"));
         match self {
-            &ParserError::TokenDoNotMatch{ref token, ref stack} =>
+            &ParserError::TokenDoNotMatch{ref token, ref stack, ref open_macro} =>
                 match token {
-                    &Some(ref token) => try!(f.write_fmt(format_args!("Stack Token does not match. Token:{:?} Stack:{:?}", token, stack))),
-                    &None => try!(f.write_fmt(format_args!("No Tokens left, but Terminal is left on Stack. Token:{:?}", stack))),
+                    &Some(ref token) => f.write_fmt(format_args!("expected {}{}, found {}", stack, closes(stack, open_macro), token)),
+                    &None => f.write_fmt(format_args!("expected {}{}, found end of input", stack, closes(stack, open_macro))),
                 },
-            &ParserError::StackIsEmpty{ref token} => try!(f.write_fmt(format_args!("Tokens left but Stack is empty. Token:{:?}", token))),
-            &ParserError::NoProjection{ref token, ref stack} => try!(f.write_fmt(format_args!("No Projection found for Token:{:?} and NonTerminal:{:?}", token, stack))),
-            &ParserError::NonTerminalEnd{ref stack} => try!(f.write_fmt(format_args!("NonTerminal:{:?} is no allowed End", stack))),
-        };
-        Ok(())
+            &ParserError::StackIsEmpty{ref token} => match token {
+                &TokMacroEndIf{..} | &TokMacroElse{..} | &TokMacroElseIf{..} =>
+                    f.write_fmt(format_args!("found {} without a matching `<<if>>`", token)),
+                &TokMacroEndSilently{..} =>
+                    f.write_fmt(format_args!("found {} without a matching `<<silently>>`", token)),
+                _ => f.write_fmt(format_args!("unexpected {}", token)),
+            },
+            &ParserError::NoProjection{ref token, ref stack} => f.write_fmt(format_args!("expected {}, found {}", stack, token)),
+            &ParserError::NonTerminalEnd{ref stack} => f.write_fmt(format_args!("expected {}, found end of input", stack)),
+        }
     }
 }
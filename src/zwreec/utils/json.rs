@@ -0,0 +1,191 @@
+//! Reads the JSON objects of Twee 3.
+//!
+//! Twee 3 stores the metadata of a passage (`{"position":"100,200"}`) and the
+//! content of the `StoryData` passage as JSON objects. Only the members of the
+//! outer object are used, so nested objects and arrays are kept as JSON text.
+//!
+//! # Example
+//!
+//! ```
+//! use zwreec::utils::json;
+//!
+//! let members = json::parse_object("{\"position\":\"100,200\", \"zoom\": 1}").unwrap();
+//! assert_eq!(members, vec![("position".to_string(), "100,200".to_string()),
+//!                          ("zoom".to_string(), "1".to_string())]);
+//! ```
+
+/// Returns the members of a JSON object in the order they are written.
+///
+/// String values are unescaped, all other values are returned as written.
+pub fn parse_object(text: &str) -> Result<Vec<(String, String)>, String> {
+    let mut reader = JsonReader { chars: text.chars().collect(), pos: 0 };
+    let mut members: Vec<(String, String)> = Vec::new();
+
+    try!(reader.expect('{'));
+    reader.skip_whitespace();
+    if reader.peek() == Some('}') {
+        reader.pos += 1;
+    } else {
+        loop {
+            reader.skip_whitespace();
+            let name = try!(reader.read_string());
+            try!(reader.expect(':'));
+            let value = try!(reader.read_value());
+            members.push((name, value));
+
+            reader.skip_whitespace();
+            match reader.next() {
+                Some(',') => (),
+                Some('}') => break,
+                _ => return Err("expected `,` or `}` after a member of the object".to_string())
+            }
+        }
+    }
+
+    reader.skip_whitespace();
+    if reader.peek().is_some() {
+        return Err("unexpected text after the object".to_string());
+    }
+
+    Ok(members)
+}
+
+/// Returns the value of the member `name`
+pub fn member<'a>(members: &'a Vec<(String, String)>, name: &str) -> Option<&'a String> {
+    members.iter().find(|&&(ref key, _)| key == name).map(|&(_, ref value)| value)
+}
+
+struct JsonReader {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl JsonReader {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).cloned()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek();
+        self.pos += 1;
+        c
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.peek().map_or(false, |c| c.is_whitespace()) {
+            self.pos += 1;
+        }
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), String> {
+        self.skip_whitespace();
+        if self.next() == Some(expected) {
+            Ok(())
+        } else {
+            Err(format!("expected `{}`", expected))
+        }
+    }
+
+    /// reads a string and replaces the escape sequences
+    fn read_string(&mut self) -> Result<String, String> {
+        if self.next() != Some('"') {
+            return Err("expected a string".to_string());
+        }
+
+        let mut string = String::new();
+        loop {
+            match self.next() {
+                Some('"') => return Ok(string),
+                Some('\\') => {
+                    let c = match self.next() {
+                        Some('n') => '\n',
+                        Some('t') => '\t',
+                        Some('r') => '\r',
+                        Some('b') => '\u{8}',
+                        Some('f') => '\u{c}',
+                        Some('u') => {
+                            let hex: String = self.chars.iter().skip(self.pos).take(4).cloned().collect();
+                            self.pos += 4;
+                            match u32::from_str_radix(&hex, 16).ok().and_then(::std::char::from_u32) {
+                                Some(c) => c,
+                                None => return Err(format!("invalid escape sequence `\\u{}`", hex))
+                            }
+                        },
+                        Some(c) => c,
+                        None => return Err("unterminated string".to_string())
+                    };
+                    string.push(c);
+                },
+                Some(c) => string.push(c),
+                None => return Err("unterminated string".to_string())
+            }
+        }
+    }
+
+    /// reads a value, only strings are unescaped
+    fn read_value(&mut self) -> Result<String, String> {
+        self.skip_whitespace();
+        let start = self.pos;
+        match self.peek() {
+            Some('"') => self.read_string(),
+            Some('{') | Some('[') => {
+                let mut depth = 0;
+                loop {
+                    match self.peek() {
+                        Some('"') => { try!(self.read_string()); },
+                        Some('{') | Some('[') => { depth += 1; self.pos += 1; },
+                        Some('}') | Some(']') => {
+                            depth -= 1;
+                            self.pos += 1;
+                            if depth == 0 {
+                                break;
+                            }
+                        },
+                        Some(_) => self.pos += 1,
+                        None => return Err("unterminated object or array".to_string())
+                    }
+                }
+                Ok(self.chars[start..self.pos].iter().cloned().collect())
+            },
+            Some(_) => {
+                while self.peek().map_or(false, |c| !(c == ',' || c == '}' || c == ']' || c.is_whitespace())) {
+                    self.pos += 1;
+                }
+                if self.pos == start {
+                    return Err("expected a value".to_string());
+                }
+                Ok(self.chars[start..self.pos].iter().cloned().collect())
+            },
+            None => Err("expected a value".to_string())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_object_test() {
+        let members = parse_object("{\n  \"ifid\": \"D674C58C-DEFA-4F70-B7A2-27742230C0FF\",\n  \
+            \"tag-colors\": {\"bar\": \"green\"},\n  \"zoom\": 1.5,\n  \"name\": \"a \\\"b\\\" \\u0063\"\n}").unwrap();
+
+        assert_eq!(members, vec![
+            ("ifid".to_string(), "D674C58C-DEFA-4F70-B7A2-27742230C0FF".to_string()),
+            ("tag-colors".to_string(), "{\"bar\": \"green\"}".to_string()),
+            ("zoom".to_string(), "1.5".to_string()),
+            ("name".to_string(), "a \"b\" c".to_string()),
+        ]);
+        assert_eq!(member(&members, "zoom"), Some(&"1.5".to_string()));
+        assert_eq!(member(&members, "start"), None);
+    }
+
+    #[test]
+    fn parse_object_error_test() {
+        assert_eq!(parse_object("{}"), Ok(vec![]));
+        assert!(parse_object("").is_err());
+        assert!(parse_object("{\"position\" \"1,2\"}").is_err());
+        assert!(parse_object("{\"position\":\"1,2\"").is_err());
+        assert!(parse_object("{\"position\":\"1,2\"} x").is_err());
+    }
+}
@@ -1,3 +1,4 @@
 pub mod file;
 pub mod extensions;
-#[macro_use] pub mod error;
+pub mod error;
+pub mod json;
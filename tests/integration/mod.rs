@@ -7,10 +7,19 @@ use std::error::Error;
 use std::io::Cursor;
 use std::vec::Vec;
 
+use zwreec::utils::error::{CompileReport, Diagnostic, DiagnosticKind};
+use zwreec::utils::error::DiagnosticKind::{Lexer, Parser, AST, Codegen};
+
 static TESTFOLDER_PASS: &'static str = "./tests/integration/should-compile/";
 static TESTFOLDER_FAIL: &'static str = "./tests/integration/should-fail/";
 
-fn test_compile(input_filename: String) {
+/// compiles the file and returns the result together with the written bytes
+fn compile_file(input_filename: String) -> (Result<CompileReport, Vec<Diagnostic>>, Vec<u8>) {
+    compile_file_with_cfg(input_filename, zwreec::config::Config::default_config())
+}
+
+/// compiles the file with the given config
+fn compile_file_with_cfg(input_filename: String, cfg: zwreec::config::Config) -> (Result<CompileReport, Vec<Diagnostic>>, Vec<u8>) {
     let path = Path::new(&input_filename);
     let mut input = match File::open(path) {
         Err(why) => {
@@ -25,17 +34,59 @@ fn test_compile(input_filename: String) {
     let vec: Vec<u8> = vec![];
     let mut output = Cursor::new(vec);
 
-    let cfg = zwreec::config::Config::default_config();
+    let result = zwreec::compile(cfg, &mut input, &mut output);
+
+    (result, output.into_inner())
+}
+
+/// compiles the files as one story
+fn compile_files(input_filenames: Vec<String>) -> (Result<CompileReport, Vec<Diagnostic>>, Vec<u8>) {
+    let mut inputs: Vec<(String, File)> = Vec::new();
+    for input_filename in input_filenames {
+        let file = match File::open(Path::new(&input_filename)) {
+            Err(why) => panic!("Couldn't open {}: {}", input_filename, Error::description(&why)),
+            Ok(file) => file
+        };
+        inputs.push((input_filename, file));
+    }
+
+    let vec: Vec<u8> = vec![];
+    let mut output = Cursor::new(vec);
+
+    let result = zwreec::compile_inputs(zwreec::config::Config::default_config(), inputs, &mut output);
+
+    (result, output.into_inner())
+}
+
+fn test_compile(input_filename: String) {
+    test_compile_with_cfg(input_filename, zwreec::config::Config::default_config());
+}
 
-    zwreec::compile(cfg, &mut input, &mut output);
+fn test_compile_with_cfg(input_filename: String, cfg: zwreec::config::Config) {
+    let (result, outvec) = compile_file_with_cfg(input_filename, cfg);
 
-    let outvec = output.into_inner();
+    assert!(result.is_ok(), "{:?}", result);
 
     // check that the z-code version is 8
     // this ensures that at least some z-code was emitted
     assert_eq!(0x08, outvec[0]);
 }
 
+/// compiles the file and checks that exactly the expected errors are reported,
+/// given as their kind and line
+fn test_compile_fail(input_filename: String, expected: Vec<(DiagnosticKind, u64)>) {
+    let (result, outvec) = compile_file(input_filename);
+
+    let errors: Vec<(DiagnosticKind, u64)> = match result {
+        Ok(report) => panic!("Compiled without errors: {:?}", report),
+        Err(errors) => errors.iter().map(|error| (error.kind, error.location.0)).collect(),
+    };
+    assert_eq!(expected, errors);
+
+    // nothing is written if the input contains errors
+    assert!(outvec.is_empty());
+}
+
 #[test]
 fn expressions_test() {
     test_compile(TESTFOLDER_PASS.to_string() + "Expressions.twee");
@@ -86,73 +137,386 @@ fn if_else_test() {
     test_compile(TESTFOLDER_PASS.to_string() + "If-Else.twee");
 }
 
+#[test]
+fn compound_assignment_test() {
+    test_compile(TESTFOLDER_PASS.to_string() + "CompoundAssignment.twee");
+}
+
+#[test]
+fn multiple_assignments_test() {
+    test_compile(TESTFOLDER_PASS.to_string() + "MultipleAssignments.twee");
+}
+
+#[test]
+fn formatting_fallback_test() {
+    test_compile(TESTFOLDER_PASS.to_string() + "FormattingFallback.twee");
+}
+
+#[test]
+fn block_formatting_test() {
+    test_compile(TESTFOLDER_PASS.to_string() + "BlockFormatting.twee");
+}
+
+#[test]
+fn variable_link_test() {
+    test_compile(TESTFOLDER_PASS.to_string() + "VariableLink.twee");
+}
+
+#[test]
+fn dynamic_display_test() {
+    test_compile(TESTFOLDER_PASS.to_string() + "DynamicDisplay.twee");
+}
+
+#[test]
+fn save_restore_test() {
+    test_compile(TESTFOLDER_PASS.to_string() + "SaveRestore.twee");
+}
+
+#[test]
+fn undo_test() {
+    let mut cfg = zwreec::config::Config::default_config();
+    cfg.undo = true;
+    test_compile_with_cfg(TESTFOLDER_PASS.to_string() + "Undo.twee", cfg);
+}
+
+#[test]
+fn undo_disabled_test() {
+    let (result, outvec) = compile_file(TESTFOLDER_PASS.to_string() + "Undo.twee");
+
+    let errors: Vec<DiagnosticKind> = result.unwrap_err().iter().map(|error| error.kind).collect();
+    assert!(!errors.is_empty() && errors.iter().all(|kind| *kind == Codegen), "{:?}", errors);
+    assert!(outvec.is_empty());
+}
+
+#[test]
+fn many_links_test() {
+    test_compile(TESTFOLDER_PASS.to_string() + "ManyLinks.twee");
+}
+
+#[test]
+fn many_links_letters_test() {
+    let mut cfg = zwreec::config::Config::default_config();
+    cfg.link_selection = zwreec::config::LinkSelection::Letters;
+    test_compile_with_cfg(TESTFOLDER_PASS.to_string() + "ManyLinks.twee", cfg);
+}
+
+#[test]
+fn many_links_cursor_test() {
+    let mut cfg = zwreec::config::Config::default_config();
+    cfg.link_selection = zwreec::config::LinkSelection::Cursor;
+    test_compile_with_cfg(TESTFOLDER_PASS.to_string() + "ManyLinks.twee", cfg);
+}
+
+#[test]
+fn many_links_no_mouse_test() {
+    let mut cfg = zwreec::config::Config::default_config();
+    cfg.mouse = false;
+    test_compile_with_cfg(TESTFOLDER_PASS.to_string() + "ManyLinks.twee", cfg);
+}
+
+#[test]
+fn special_passages_test() {
+    test_compile(TESTFOLDER_PASS.to_string() + "SpecialPassages.twee");
+}
+
+#[test]
+fn tags_test() {
+    test_compile(TESTFOLDER_PASS.to_string() + "Tags.twee");
+}
+
+#[test]
+fn history_test() {
+    test_compile(TESTFOLDER_PASS.to_string() + "History.twee");
+}
+
+#[test]
+fn twine2_test() {
+    test_compile(TESTFOLDER_PASS.to_string() + "Twine2.html");
+
+    let mut cfg = zwreec::config::Config::default_config();
+    cfg.input_format = zwreec::config::InputFormat::Twine2;
+    test_compile_with_cfg(TESTFOLDER_PASS.to_string() + "Twine2.html", cfg);
+}
+
+#[test]
+fn twee3_test() {
+    test_compile(TESTFOLDER_PASS.to_string() + "Twee3.twee");
+}
+
+#[test]
+fn multiple_files_test() {
+    let folder = TESTFOLDER_PASS.to_string() + "MultipleFiles/";
+    let (result, outvec) = compile_files(vec![folder.clone() + "Start.twee", folder + "Chapter1.twee"]);
+
+    // Chapter2.twee is added by the StoryIncludes passage, the other names
+    // of the files are not added again
+    assert!(result.is_ok(), "{:?}", result);
+    assert_eq!(0x08, outvec[0]);
+}
+
+#[test]
+fn include_text_test() {
+    let folder = TESTFOLDER_FAIL.to_string() + "IncludeText/";
+    let (result, outvec) = compile_files(vec![folder.clone() + "Start.twee"]);
+    let errors = result.unwrap_err();
+    assert!(outvec.is_empty());
+
+    assert_eq!(1, errors.len());
+    assert_eq!(Lexer, errors[0].kind);
+    assert_eq!(Some(folder + "Notes.twee"), errors[0].file_name);
+    assert_eq!((1, 1), errors[0].location);
+}
+
+#[test]
+fn versions_test() {
+    for version in vec![3, 4, 5, 8] {
+        let mut cfg = zwreec::config::Config::default_config();
+        cfg.zversion = version;
+        let (result, outvec) = compile_file_with_cfg(TESTFOLDER_PASS.to_string() + "Versions.twee", cfg);
+
+        assert!(result.is_ok(), "{:?}", result);
+        assert_eq!(version, outvec[0]);
+
+        // the length in the header is divided by the packing factor
+        let packing = match version { 3 => 2, 4 | 5 => 4, _ => 8 };
+        let length = (outvec[0x1a] as usize * 256 + outvec[0x1b] as usize) * packing;
+        assert_eq!(outvec.len(), length);
+    }
+}
+
+#[test]
+fn blorb_test() {
+    let mut cfg = zwreec::config::Config::default_config();
+    cfg.output_format = zwreec::config::OutputFormat::Blorb;
+    cfg.cover_image = Some(TESTFOLDER_PASS.to_string() + "Cover.png");
+    let (result, outvec) = compile_file_with_cfg(TESTFOLDER_PASS.to_string() + "Twee3.twee", cfg);
+
+    assert!(result.is_ok(), "{:?}", result);
+    assert_eq!(b"FORM", &outvec[0..4]);
+    assert_eq!(b"IFRS", &outvec[8..12]);
+    assert_eq!(result.unwrap().bytes_written, outvec.len());
+
+    // the metadata contains the ifid of StoryData and the title
+    let blorb = String::from_utf8_lossy(&outvec).into_owned();
+    assert!(blorb.contains("<ifid>D674C58C-DEFA-4F70-B7A2-27742230C0FF</ifid>"));
+    assert!(blorb.contains("<title>A Twee 3 Story</title>"));
+    assert!(blorb.contains("<coverpicture>1</coverpicture>"));
+
+    // the resource index points to the zcode chunk with the story
+    let zcod = outvec[34] as usize * 256 + outvec[35] as usize;
+    assert_eq!(b"ZCOD", &outvec[zcod..zcod + 4]);
+    assert_eq!(0x08, outvec[zcod + 8]);
+}
+
+#[test]
+fn blorb_missing_cover_test() {
+    let mut cfg = zwreec::config::Config::default_config();
+    cfg.output_format = zwreec::config::OutputFormat::Blorb;
+    cfg.cover_image = Some(TESTFOLDER_PASS.to_string() + "Missing.png");
+    let (result, outvec) = compile_file_with_cfg(TESTFOLDER_PASS.to_string() + "Twee3.twee", cfg);
+
+    let errors = result.unwrap_err();
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].kind, Codegen);
+    assert!(outvec.is_empty());
+}
+
+#[test]
+fn silently_test() {
+    test_compile(TESTFOLDER_PASS.to_string() + "Silently.twee");
+}
+
 #[test]
 fn current_status_test() {
     test_compile(TESTFOLDER_PASS.to_string() + "CurrentStatus.twee");
 }
 
 #[test]
-#[should_panic]
+fn arrays_test() {
+    // the array routines are lowered for version 3
+    for version in vec![3, 5, 8] {
+        let mut cfg = zwreec::config::Config::default_config();
+        cfg.zversion = version;
+        let (result, outvec) = compile_file_with_cfg(TESTFOLDER_PASS.to_string() + "Arrays.twee", cfg);
+
+        assert!(result.is_ok(), "{:?}", result);
+        assert_eq!(version, outvec[0]);
+    }
+}
+
+#[test]
+fn fixed_point_test() {
+    // the fixed-point routines are lowered for version 3
+    for version in vec![3, 5, 8] {
+        let mut cfg = zwreec::config::Config::default_config();
+        cfg.zversion = version;
+        cfg.fixed_point = true;
+        let (result, outvec) = compile_file_with_cfg(TESTFOLDER_PASS.to_string() + "FixedPoint.twee", cfg);
+
+        assert!(result.is_ok(), "{:?}", result);
+        assert_eq!(version, outvec[0]);
+    }
+}
+
+#[test]
+fn text_input_test() {
+    // older versions read the input with sread instead of aread
+    for version in vec![3, 5, 8] {
+        let mut cfg = zwreec::config::Config::default_config();
+        cfg.zversion = version;
+        let (result, outvec) = compile_file_with_cfg(TESTFOLDER_PASS.to_string() + "TextInput.twee", cfg);
+
+        assert!(result.is_ok(), "{:?}", result);
+        assert_eq!(version, outvec[0]);
+    }
+}
+
+#[test]
 fn expression_double_operators_test() {
-    test_compile(TESTFOLDER_FAIL.to_string() + "ExpressionDoubleOperators.twee");
+    test_compile_fail(TESTFOLDER_FAIL.to_string() + "ExpressionDoubleOperators.twee", vec![(Parser, 2)]);
 }
 
 #[test]
-#[should_panic]
 fn expression_wrong_parentheses1_test() {
-    test_compile(TESTFOLDER_FAIL.to_string() + "ExpressionsWrongParentheses1.twee");
+    test_compile_fail(TESTFOLDER_FAIL.to_string() + "ExpressionsWrongParentheses1.twee", vec![(Parser, 2)]);
 }
 
 #[test]
-#[should_panic]
 fn expression_wrong_parentheses2_test() {
-    test_compile(TESTFOLDER_FAIL.to_string() + "ExpressionsWrongParentheses2.twee");
+    test_compile_fail(TESTFOLDER_FAIL.to_string() + "ExpressionsWrongParentheses2.twee", vec![(Parser, 2)]);
 }
 
 #[test]
-#[should_panic]
 fn duplicate_passages() {
-    test_compile(TESTFOLDER_FAIL.to_string() + "DuplicatePassage.twee");
+    test_compile_fail(TESTFOLDER_FAIL.to_string() + "DuplicatePassage.twee", vec![(AST, 7)]);
+}
+
+#[test]
+fn duplicate_passages_across_files_test() {
+    let folder = TESTFOLDER_FAIL.to_string() + "DuplicateAcrossFiles/";
+    let (result, outvec) = compile_files(vec![folder.clone() + "First.twee", folder.clone() + "Second.twee"]);
+    let errors = result.unwrap_err();
+    assert!(outvec.is_empty());
+
+    assert_eq!(1, errors.len());
+    assert_eq!(AST, errors[0].kind);
+    assert_eq!(Some(folder.clone() + "Second.twee"), errors[0].file_name);
+    assert_eq!(4, errors[0].location.0);
+
+    let note = errors[0].note.clone().unwrap();
+    assert_eq!(Some(folder + "First.twee"), note.file_name);
+    assert_eq!(4, note.location.0);
+}
+
+#[test]
+fn version3_features_test() {
+    let mut cfg = zwreec::config::Config::default_config();
+    cfg.zversion = 3;
+    let (result, outvec) = compile_file_with_cfg(TESTFOLDER_FAIL.to_string() + "Version3Features.twee", cfg);
+
+    let errors: Vec<(DiagnosticKind, u64)> = result.unwrap_err().iter().map(|error| (error.kind, error.location.0)).collect();
+    assert_eq!(vec![(Codegen, 2), (Codegen, 2), (Codegen, 3)], errors);
+    assert!(outvec.is_empty());
+}
+
+#[test]
+fn array_types_test() {
+    test_compile_fail(TESTFOLDER_FAIL.to_string() + "ArrayTypes.twee", vec![(Codegen, 2), (Codegen, 4), (Codegen, 6), (Codegen, 7),
+        (Codegen, 8), (Codegen, 9), (Codegen, 10), (Codegen, 11), (Codegen, 12)]);
+}
+
+#[test]
+fn fixed_point_disabled_test() {
+    test_compile_fail(TESTFOLDER_FAIL.to_string() + "FixedPointDisabled.twee", vec![(Codegen, 2), (Codegen, 3)]);
+}
+
+#[test]
+fn history_arguments_test() {
+    test_compile_fail(TESTFOLDER_FAIL.to_string() + "HistoryArguments.twee", vec![(Codegen, 2), (Codegen, 3), (Codegen, 4)]);
 }
 
 #[test]
-#[should_panic]
 fn invalid_macro_test() {
-   test_compile(TESTFOLDER_FAIL.to_string() + "InvalidMacro.twee");
+   test_compile_fail(TESTFOLDER_FAIL.to_string() + "InvalidMacro.twee", vec![(Codegen, 2)]);
+}
+
+#[test]
+fn text_input_no_variable_test() {
+    test_compile_fail(TESTFOLDER_FAIL.to_string() + "TextInputNoVariable.twee", vec![(Codegen, 2), (Codegen, 4)]);
 }
 
 #[test]
-#[should_panic]
 fn multiple_else_test() {
-    test_compile(TESTFOLDER_FAIL.to_string() + "MultipleElse.twee");
+    test_compile_fail(TESTFOLDER_FAIL.to_string() + "MultipleElse.twee", vec![(Parser, 2)]);
+}
+
+#[test]
+fn unbalanced_silently_test() {
+    test_compile_fail(TESTFOLDER_FAIL.to_string() + "UnbalancedSilently.twee", vec![(Parser, 2), (Parser, 0)]);
 }
 
 #[test]
-#[should_panic]
 fn multiple_endif_test() {
-    test_compile(TESTFOLDER_FAIL.to_string() + "MultipleEndIf.twee");
+    test_compile_fail(TESTFOLDER_FAIL.to_string() + "MultipleEndIf.twee", vec![(Parser, 2)]);
 }
 
 #[test]
-#[should_panic]
 fn no_start_passage_test() {
-    test_compile(TESTFOLDER_FAIL.to_string() + "NoStartPassage.twee");
+    test_compile_fail(TESTFOLDER_FAIL.to_string() + "NoStartPassage.twee", vec![(AST, 0)]);
 }
 
 #[test]
-#[should_panic]
 fn passage_not_allowed_chars1_test() {
-    test_compile(TESTFOLDER_FAIL.to_string() + "PassageNotAllowedChars1.twee");
+    test_compile_fail(TESTFOLDER_FAIL.to_string() + "PassageNotAllowedChars1.twee", vec![(Lexer, 4)]);
 }
 
 #[test]
-#[should_panic]
 fn passage_not_allowed_chars2_test() {
-    test_compile(TESTFOLDER_FAIL.to_string() + "PassageNotAllowedChars2.twee");
+    test_compile_fail(TESTFOLDER_FAIL.to_string() + "PassageNotAllowedChars2.twee", vec![(Lexer, 4)]);
+}
+
+#[test]
+fn twine2_unknown_link_test() {
+    test_compile_fail(TESTFOLDER_FAIL.to_string() + "Twine2UnknownLink.html", vec![(Codegen, 6)]);
 }
 
 #[test]
-#[should_panic]
 fn wrong_formatting_test() {
-    test_compile(TESTFOLDER_FAIL.to_string() + "WrongFormatting.twee");
+    test_compile_fail(TESTFOLDER_FAIL.to_string() + "WrongFormatting.twee", vec![(Parser, 2)]);
+}
+
+#[test]
+fn multiple_errors_test() {
+    test_compile_fail(TESTFOLDER_FAIL.to_string() + "MultipleErrors.twee",
+        vec![(Parser, 2), (Parser, 3), (Parser, 4), (Parser, 8), (Parser, 11)]);
+}
+
+#[test]
+fn force_test() {
+    // errors found by the code generator are ignored
+    let mut cfg = zwreec::config::Config::default_config();
+    cfg.force = true;
+    let (result, outvec) = compile_file_with_cfg(TESTFOLDER_FAIL.to_string() + "ArrayTypes.twee", cfg);
+    let report = result.unwrap();
+    assert_eq!(9, report.diagnostics.len());
+    assert_eq!(0x08, outvec[0]);
+
+    // syntax errors leave the story incomplete
+    let mut cfg = zwreec::config::Config::default_config();
+    cfg.force = true;
+    let (result, outvec) = compile_file_with_cfg(TESTFOLDER_FAIL.to_string() + "MultipleErrors.twee", cfg);
+    assert_eq!(5, result.unwrap_err().len());
+    assert!(outvec.is_empty());
+}
+
+#[test]
+fn error_messages_test() {
+    let (result, _) = compile_file(TESTFOLDER_FAIL.to_string() + "MultipleElse.twee");
+    let errors = result.unwrap_err();
+    assert_eq!("expected `<<endif>>` to close `<<if>>`, found `<<else>>`", errors[0].message);
+    assert_eq!(Some("<<if true>>Text1<<else>>Text2<<else>>Text3<<endif>>".to_string()), errors[0].source_line);
+
+    let (result, _) = compile_file(TESTFOLDER_FAIL.to_string() + "ExpressionsWrongParentheses1.twee");
+    let errors = result.unwrap_err();
+    assert_eq!("expected `)` to close `(`, found `>>`", errors[0].message);
 }